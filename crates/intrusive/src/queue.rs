@@ -45,6 +45,26 @@ impl<T, U> Queue<T, U> where T: Owner<U>, U: Node {
     pub fn back_mut(&mut self) -> Option<&mut U> {
         self.list.back_mut()
     }
+
+    /// Remove `elem` from the queue wherever it is located
+    ///
+    /// Returns None if `elem` is not part of the queue
+    pub fn remove(&mut self, elem: *const U) -> Option<T> {
+        let mut cursor = self.list.cursor();
+
+        loop {
+            let found = match cursor.next_peek() {
+                None => return None,
+                Some(n) => n as *const U == elem,
+            };
+
+            if found {
+                return cursor.remove();
+            }
+
+            cursor.next();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -126,4 +146,29 @@ mod test {
         assert!(queue.dequeue().is_none());
         assert!(queue.is_empty());
     }
+
+    #[test]
+    fn test_queue_remove() {
+        let mut queue = Queue::<Box<UsizeNode>, UsizeNode>::new();
+        let mut nodes = [0 as *const UsizeNode; 4];
+
+        for i in 0..4 {
+            let node = Box::new(UsizeNode::new(i));
+
+            nodes[i] = &*node;
+
+            queue.enqueue(node);
+        }
+
+        assert_eq!(queue.remove(nodes[2]).unwrap().data, 2);
+        assert_eq!(queue.remove(nodes[0]).unwrap().data, 0);
+        assert_eq!(queue.remove(nodes[3]).unwrap().data, 3);
+        assert!(queue.remove(nodes[3]).is_none());
+
+        assert_eq!(queue.front().unwrap().data, 1);
+        assert_eq!(queue.back().unwrap().data, 1);
+
+        assert_eq!(queue.remove(nodes[1]).unwrap().data, 1);
+        assert!(queue.is_empty());
+    }
 }
@@ -0,0 +1,298 @@
+//! Generic block device abstraction
+//!
+//! Devices of the platform are found with `discover()`.
+
+use core::mem;
+
+use vec::Vec;
+
+use sync::spin::SpinLock;
+
+use thread::WaitQueue;
+
+#[cfg(all(feature = "xen", feature = "blk"))]
+pub use self::hw_imp::{HwBlockDevice, discover};
+
+#[cfg(all(feature = "xen", feature = "blk"))]
+mod hw_imp {
+    use boxed::Box;
+    use vec::Vec;
+
+    use hal::xen::blk;
+
+    /// Type of the hardware block device.
+    pub type HwBlockDevice = blk::XenBlkDevice;
+
+    /// Discover hardware configuration and return a list of existing
+    /// block devices
+    pub fn discover() -> Vec<Box<HwBlockDevice>> {
+        blk::discover()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Error returned by a block device operation
+pub enum Error {
+    /// The device could not be set up with its backend
+    Negotiation,
+    /// The system is out of memory
+    OutOfMemory,
+    /// The device failed to carry out the request
+    Io,
+    /// The device does not support the request
+    NotSupported,
+    /// The buffer is not a multiple of the sector size or is larger than
+    /// what a single request can hold
+    InvalidSize,
+    /// The request goes beyond the end of the device
+    OutOfRange,
+    /// The device is read-only
+    ReadOnly,
+    /// The device was closed before the request completed
+    Closed,
+}
+
+/// Trait implemented by block devices
+///
+/// Devices are addressed by sectors of `sector_size()` bytes. Buffers given to
+/// `read()` and `write()` must hold a whole number of sectors.
+pub trait Device {
+    /// Returns the size of a sector in bytes
+    fn sector_size(&self) -> usize;
+
+    /// Returns the capacity of the device in sectors
+    fn sectors(&self) -> u64;
+
+    /// Returns true if the device cannot be written
+    fn is_read_only(&self) -> bool;
+
+    /// Returns the maximum number of requests the device handles at once
+    fn queue_depth(&self) -> usize;
+
+    /// Returns the queue signaled every time a request completes
+    fn completion_queue(&self) -> &WaitQueue;
+
+    /// Read sectors from `sector` into `buf`
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Write `buf` from `sector`
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), Error>;
+
+    /// Make the writes completed so far durable
+    fn flush(&self) -> Result<(), Error>;
+}
+
+/// Block device backed by memory
+///
+/// This is meant to test storage code without any hardware. Requests
+/// complete right away.
+pub struct RamDisk {
+    sector_size: usize,
+    data: SpinLock<Vec<u8>>,
+    read_only: bool,
+    completion: WaitQueue,
+}
+
+impl RamDisk {
+    /// Create a zeroed RAM disk of `sectors` sectors of `sector_size` bytes
+    ///
+    /// This fails if `sector_size` is 0 or the disk does not fit in memory.
+    pub fn new(sector_size: usize, sectors: u64) -> Result<Self, Error> {
+        if sectors > usize::max_value() as u64 {
+            return Err(Error::OutOfMemory);
+        }
+
+        let size = try!(sector_size.checked_mul(sectors as usize)
+                                   .ok_or(Error::OutOfMemory));
+
+        RamDisk::from_image(sector_size, vec![0; size])
+    }
+
+    /// Create a RAM disk holding `image`
+    ///
+    /// This fails if the size of `image` is not a multiple of `sector_size`.
+    pub fn from_image(sector_size: usize, image: Vec<u8>)
+        -> Result<Self, Error> {
+        if sector_size == 0 || image.len() % sector_size != 0 {
+            return Err(Error::InvalidSize);
+        }
+
+        Ok(RamDisk {
+            sector_size: sector_size,
+            data: SpinLock::new(image),
+            read_only: false,
+            completion: WaitQueue::new(),
+        })
+    }
+
+    #[inline]
+    /// Forbid or allow writes to the disk
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Returns the content of the disk
+    pub fn into_image(self) -> Vec<u8> {
+        mem::replace(&mut *self.data.lock(), Vec::new())
+    }
+
+    /// Returns the byte range of `len` bytes from `sector`
+    fn range(&self, sector: u64, len: usize) -> Result<(usize, usize), Error> {
+        if len % self.sector_size != 0 {
+            return Err(Error::InvalidSize);
+        }
+
+        let count = (len / self.sector_size) as u64;
+
+        match sector.checked_add(count) {
+            Some(end) if end <= self.sectors() => {
+                let start = sector as usize * self.sector_size;
+
+                Ok((start, start + len))
+            }
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
+
+impl Device for RamDisk {
+    #[inline]
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sectors(&self) -> u64 {
+        (self.data.lock().len() / self.sector_size) as u64
+    }
+
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    #[inline]
+    fn queue_depth(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn completion_queue(&self) -> &WaitQueue {
+        &self.completion
+    }
+
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let (start, end) = try!(self.range(sector, buf.len()));
+
+        buf.clone_from_slice(&self.data.lock()[start..end]);
+
+        self.completion.unblock_all();
+
+        Ok(())
+    }
+
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let (start, end) = try!(self.range(sector, buf.len()));
+
+        self.data.lock()[start..end].clone_from_slice(buf);
+
+        self.completion.unblock_all();
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        self.completion.unblock_all();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vec::Vec;
+
+    use super::{Device, Error, RamDisk};
+
+    #[test]
+    fn test_round_trip() {
+        let disk = RamDisk::new(512, 8).unwrap();
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let mut buf = vec![0u8; 1024];
+
+        assert_eq!(disk.sectors(), 8);
+        assert_eq!(disk.write(3, &data), Ok(()));
+        assert_eq!(disk.read(3, &mut buf), Ok(()));
+        assert_eq!(buf, data);
+
+        // The neighbouring sectors are left untouched
+        let mut sector = vec![0xffu8; 512];
+
+        assert_eq!(disk.read(2, &mut sector), Ok(()));
+        assert!(sector.iter().all(|b| *b == 0));
+        assert_eq!(disk.read(5, &mut sector), Ok(()));
+        assert!(sector.iter().all(|b| *b == 0));
+
+        assert_eq!(disk.flush(), Ok(()));
+        assert_eq!(&disk.into_image()[3 * 512..5 * 512], &data[..]);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let disk = RamDisk::new(512, 4).unwrap();
+        let mut buf = vec![0u8; 1024];
+
+        assert_eq!(disk.read(3, &mut buf), Err(Error::OutOfRange));
+        assert_eq!(disk.write(4, &buf[..512]), Err(Error::OutOfRange));
+        assert_eq!(disk.read(!0, &mut buf), Err(Error::OutOfRange));
+        assert_eq!(disk.read(2, &mut buf), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let disk = RamDisk::new(512, 4).unwrap();
+        let mut buf = vec![0u8; 100];
+
+        assert_eq!(disk.read(0, &mut buf), Err(Error::InvalidSize));
+        assert_eq!(disk.write(0, &buf), Err(Error::InvalidSize));
+
+        assert!(RamDisk::new(0, 4).is_err());
+        assert!(RamDisk::new(512, !0).is_err());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut disk = RamDisk::new(512, 4).unwrap();
+        let buf = vec![1u8; 512];
+
+        disk.set_read_only(true);
+
+        assert!(disk.is_read_only());
+        assert_eq!(disk.write(0, &buf), Err(Error::ReadOnly));
+
+        disk.set_read_only(false);
+
+        assert_eq!(disk.write(0, &buf), Ok(()));
+    }
+
+    #[test]
+    fn test_from_image() {
+        let image: Vec<u8> = (0..2048).map(|i| (i / 512) as u8).collect();
+        let disk = RamDisk::from_image(512, image.clone()).unwrap();
+        let mut buf = vec![0u8; 512];
+
+        assert_eq!(disk.sector_size(), 512);
+        assert_eq!(disk.sectors(), 4);
+        assert_eq!(disk.read(2, &mut buf), Ok(()));
+        assert!(buf.iter().all(|b| *b == 2));
+        assert_eq!(disk.into_image(), image);
+
+        assert_eq!(RamDisk::from_image(512, vec![0; 1000]).err(),
+                   Some(Error::InvalidSize));
+        assert_eq!(RamDisk::from_image(0, Vec::new()).err(),
+                   Some(Error::InvalidSize));
+    }
+}
@@ -0,0 +1,193 @@
+//! Stand-in for the platform used when running the unit tests
+//!
+//! The tests run as a regular process of the build machine, so this only
+//! provides what the rest of the library needs to compile and to run code
+//! that does not block: the console discards its output and never has
+//! input, interrupts do not exist and the monotonic clock advances by one
+//! microsecond every time it is read.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use io::{Read, Write, Result};
+
+use thread::WaitQueue;
+
+use super::Console;
+
+/// Nanoseconds the monotonic clock advances on each read
+const CLOCK_STEP_NS: usize = 1000;
+
+static CLOCK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Console of the host stand-in
+pub struct HostConsole<'a> {
+    queue: WaitQueue,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> HostConsole<'a> {
+    #[inline]
+    pub fn has_input(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    pub fn input_queue(&self) -> &WaitQueue {
+        &self.queue
+    }
+}
+
+impl<'a> Read for HostConsole<'a> {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+impl<'a> Write for HostConsole<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Abstracts the actual hardware console type
+pub type HwConsoleType<'a> = HostConsole<'a>;
+
+/// Unprotected access to the console, which discards everything
+pub fn console<'a>() -> Console<'a> {
+    Console(HostConsole {
+        queue: WaitQueue::new(),
+        _marker: PhantomData,
+    })
+}
+
+/// Returns the value of the monotonic clock in nanoseconds
+pub fn monotonic_time() -> u64 {
+    (CLOCK.fetch_add(CLOCK_STEP_NS, Ordering::SeqCst) + CLOCK_STEP_NS) as u64
+}
+
+#[inline]
+/// Returns the wall clock time at which the monotonic clock was 0, the UNIX
+/// epoch
+pub fn wall_clock_origin() -> u64 {
+    0
+}
+
+#[inline]
+/// Returns an empty command line
+pub fn cmd_line() -> &'static str {
+    ""
+}
+
+#[inline]
+pub fn local_irq_disable() {
+}
+
+#[inline]
+pub fn local_irq_enable() {
+}
+
+#[inline]
+pub fn local_irq_save() -> usize {
+    0
+}
+
+#[inline]
+pub fn local_irq_restore(_state: usize) {
+}
+
+/// One-shot timer, which never fires
+pub mod timer {
+    #[inline]
+    pub fn arm(_deadline: u64) {
+    }
+
+    #[inline]
+    pub fn disarm() {
+    }
+}
+
+/// Work with the state of the application
+pub mod app {
+    /// Nothing can interrupt the tests, this returns right away
+    #[inline]
+    pub fn block() {
+    }
+
+    #[inline]
+    pub fn yield_cpu() {
+    }
+
+    pub fn crash() {
+        panic!("Application crashed");
+    }
+
+    pub fn exit(code: isize) {
+        panic!("Application exited with code {}", code);
+    }
+
+    pub fn reboot() {
+        panic!("Application rebooted");
+    }
+}
+
+#[cfg(feature = "net")]
+/// Network device driver abstraction
+pub mod net {
+    use vec::Vec;
+
+    use time::Duration;
+
+    use thread::Scheduler;
+
+    use net::{Instance, Interface, Packet};
+    use net::defs::Device;
+
+    /// Interface of the host stand-in, which keeps the transmitted frames
+    /// for the tests to inspect
+    pub struct HwInterface {
+        pub sent: Vec<Vec<u8>>,
+    }
+
+    impl HwInterface {
+        pub fn new() -> Self {
+            HwInterface {
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Device for HwInterface {
+        fn refresh(&mut self) {
+        }
+
+        fn tx_packet(&mut self, pkt: Packet) {
+            self.sent.push(pkt.as_bytes().to_vec());
+        }
+    }
+
+    /// Interfaces are never added or removed
+    pub struct HotplugWatch;
+
+    impl HotplugWatch {
+        pub fn new() -> Self {
+            HotplugWatch
+        }
+
+        pub fn wait(&self) {
+            Scheduler::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// The host has no interface
+    pub fn discover(_instance: &Instance) -> Vec<Interface> {
+        Vec::new()
+    }
+
+    pub fn rescan(_instance: &Instance) {
+    }
+}
@@ -8,13 +8,30 @@
 
 use io::{Read, Write, Result};
 
+use thread::WaitQueue;
+
 pub mod mmu;
+pub mod block;
 
 pub use self::hw_imp::*;
 
 /// Generic console wrapper
 pub struct Console<'a>(HwConsoleType<'a>);
 
+impl<'a> Console<'a> {
+    #[inline]
+    /// Returns true if input is waiting to be read
+    pub fn has_input(&self) -> bool {
+        self.0.has_input()
+    }
+
+    #[inline]
+    /// Returns the queue signaled when new input is available
+    pub fn input_queue(&self) -> &WaitQueue {
+        self.0.input_queue()
+    }
+}
+
 impl<'a> Read for Console<'a> {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
@@ -60,6 +77,27 @@ mod hw_imp {
         Console(xen::console::console())
     }
 
+    #[inline]
+    /// Returns the value of the monotonic clock in nanoseconds
+    ///
+    /// The origin of the clock is unspecified but it never goes backward.
+    pub fn monotonic_time() -> u64 {
+        xen::time::system_time()
+    }
+
+    #[inline]
+    /// Returns the wall clock time, in nanoseconds since the UNIX epoch, at
+    /// which the monotonic clock was 0
+    pub fn wall_clock_origin() -> u64 {
+        xen::time::wall_clock()
+    }
+
+    #[inline]
+    /// Returns the command line given to the application at boot
+    pub fn cmd_line() -> &'static str {
+        xen::boot::cmd_line()
+    }
+
     #[inline]
     /// Disable local interrupt delivery
     pub fn local_irq_disable() {
@@ -85,9 +123,32 @@ mod hw_imp {
         xen::set_upcalls_state(state as u8);
     }
 
+    /// One-shot timer interrupting the application
+    pub mod timer {
+        use hal::xen;
+
+        /// Raise an interruption once the monotonic clock reaches `deadline`
+        /// (in nanoseconds)
+        ///
+        /// Only one timer exists, any timer previously armed is replaced.
+        #[inline]
+        pub fn arm(deadline: u64) {
+            xen::time::set_timer(deadline);
+        }
+
+        /// Cancel the armed timer
+        #[inline]
+        pub fn disarm() {
+            xen::time::stop_timer();
+        }
+    }
+
     /// Work with the state of the application
     pub mod app {
         use hal::xen;
+        use hal::arch::defs::PAGE_SIZE;
+
+        pub use hal::xen::control::Shutdown;
 
         /// Block the application until an interruption arrives
         #[inline]
@@ -95,6 +156,13 @@ mod hw_imp {
             xen::sched::block();
         }
 
+        /// Give the CPU back to the hypervisor without waiting for an
+        /// interruption
+        #[inline]
+        pub fn yield_cpu() {
+            xen::sched::yield_cpu();
+        }
+
         /// Crash the application
         ///
         /// On certain platform this might be equivalent to
@@ -115,6 +183,34 @@ mod hw_imp {
         pub fn exit(code: isize) {
             xen::sched::poweroff(code as xen::defs::Ulong);
         }
+
+        /// Reboot the system
+        #[inline]
+        pub fn reboot() {
+            xen::sched::reboot();
+        }
+
+        /// Register `hook` to run when the platform asks the application to
+        /// stop, before the system goes down
+        #[inline]
+        pub fn on_shutdown<F>(hook: F) where F: Fn(Shutdown) + Send + 'static {
+            xen::control::on_shutdown(hook)
+        }
+
+        /// Returns the memory currently given to the application in bytes
+        #[inline]
+        pub fn current_memory() -> usize {
+            xen::balloon::current_pages() * PAGE_SIZE
+        }
+
+        /// Returns the memory the platform wants the application to use in
+        /// bytes
+        ///
+        /// The application shrinks or grows towards it on its own.
+        #[inline]
+        pub fn target_memory() -> usize {
+            xen::balloon::target_pages() * PAGE_SIZE
+        }
     }
 
     #[cfg(feature = "net")]
@@ -129,14 +225,27 @@ mod hw_imp {
         /// Type of the hardware interface.
         pub type HwInterface = net::XenNetDevice;
 
+        /// Notifications of interfaces being added or removed
+        pub type HotplugWatch = net::HotplugWatch;
+
         /// Discover hardware configuration and return a list of existing
         /// interfaces
         pub fn discover(instance: &Instance) -> Vec<Interface> {
             net::discover(instance)
         }
+
+        /// Look for interfaces added or removed since the last call and
+        /// update `instance` accordingly
+        pub fn rescan(instance: &Instance) {
+            net::rescan(instance)
+        }
     }
 }
 
+#[cfg(all(test, not(feature = "xen")))]
+#[path = "host.rs"]
+mod hw_imp;
+
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[path="x86"]
 pub mod arch {
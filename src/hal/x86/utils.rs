@@ -53,6 +53,18 @@ pub fn wmb() {
     }
 }
 
+/// Read the time stamp counter
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!("rdtsc" : "={eax}" (low), "={edx}" (high) ::: "volatile");
+    }
+
+    ((high as u64) << 32) | low as u64
+}
+
 #[test]
 pub fn test_set_and_clear() {
     let mut array = [0u32; 4];
@@ -0,0 +1,232 @@
+//! Memory ballooning driver
+//!
+//! The toolstack writes the amount of memory the domain should use to
+//! `memory/target` (in KiB), e.g. with `xl mem-set`. Pages are taken out of
+//! the heap and given back to Xen to shrink the domain, and put back in the
+//! heap once Xen gives frames again.
+//!
+//! The domain never grows beyond the memory it was given at boot, as the P2M
+//! and the mappings of the heap are sized at boot.
+
+use core::{cmp, mem};
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use alloc_uni::{__rust_allocate, __rust_deallocate};
+
+use vec::Vec;
+
+use io::Write;
+
+use sync::spin::InterruptSpinLock;
+
+use hal::arch::defs::PAGE_SIZE;
+use hal::arch::mmu::PageEntry;
+
+use hal::mmu::{Vaddr, Pfn, Mfn};
+
+use hal::xen::{boot, suspend};
+use hal::xen::memory::{self, MapFlags};
+use hal::xen::store::{XenStore, Watch, Path};
+
+use thread::Scheduler;
+
+/// Number of pages moved by a single hypercall
+const BATCH_PAGES: usize = 256;
+
+/// Memory the balloon never takes from the domain, in bytes
+const MIN_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Number of pages the domain should use
+static TARGET: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Pages given back to Xen
+static BALLOONED: InterruptSpinLock<Option<Vec<Pfn>>> =
+    InterruptSpinLock::new(None);
+
+/// Watch on `memory/target`
+static WATCH: InterruptSpinLock<Option<Watch>> = InterruptSpinLock::new(None);
+
+/// Returns the number of pages the domain uses
+pub fn current_pages() -> usize {
+    let ballooned = BALLOONED.lock().as_ref().map_or(0, |b| b.len());
+
+    boot::nr_pages() - ballooned
+}
+
+/// Returns the number of pages the toolstack wants the domain to use
+///
+/// This is the memory of the domain until the toolstack sets a target.
+pub fn target_pages() -> usize {
+    match TARGET.load(Ordering::SeqCst) {
+        0 => boot::nr_pages(),
+        target => target,
+    }
+}
+
+/// Take up to `count` pages out of the heap and give them to Xen
+///
+/// Returns the number of pages released.
+fn decrease(count: usize) -> usize {
+    let mut pfns = Vec::with_capacity(count);
+    let mut mfns = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let page = __rust_allocate(PAGE_SIZE, PAGE_SIZE);
+
+        if page.is_null() {
+            break;
+        }
+
+        let vaddr = Vaddr::from_ptr(page);
+        let pfn = Pfn::from(vaddr);
+        let mfn = Mfn::from(pfn);
+
+        unsafe {
+            // Xen refuses to release a frame that is still mapped
+            if memory::update_va_mapping(vaddr, PageEntry::new(0),
+                                         MapFlags::InvlpgLocal) != 0 {
+                __rust_deallocate(page, PAGE_SIZE, PAGE_SIZE);
+                break;
+            }
+
+            boot::set_p2m(pfn, Mfn::new(boot::INVALID_MFN));
+        }
+
+        pfns.push(pfn);
+        mfns.push(mfn);
+    }
+
+    let ret = unsafe { memory::decrease_reservation(&mut mfns[..]) };
+    let done = cmp::max(ret, 0) as usize;
+
+    // The pages Xen did not take go back to the heap
+    for (pfn, mfn) in pfns.drain(done..).zip(mfns.drain(done..)) {
+        unsafe {
+            restore_page(pfn, mfn);
+        }
+    }
+
+    let mut ballooned = BALLOONED.lock();
+
+    if ballooned.is_none() {
+        *ballooned = Some(Vec::new());
+    }
+
+    ballooned.as_mut().unwrap().extend(pfns);
+
+    done
+}
+
+/// Get up to `count` frames from Xen and put the pages back in the heap
+///
+/// Returns the number of pages added.
+fn increase(count: usize) -> usize {
+    let mut pfns: Vec<Pfn> = match BALLOONED.lock().as_mut() {
+        Some(ballooned) => {
+            let len = ballooned.len();
+
+            ballooned.split_off(len - cmp::min(count, len))
+        },
+        None => Vec::new(),
+    };
+    let mut mfns = vec![Mfn::new(0); pfns.len()];
+
+    let ret = unsafe { memory::increase_reservation(&mut mfns[..]) };
+    let done = cmp::max(ret, 0) as usize;
+
+    for (pfn, mfn) in pfns.drain(..done).zip(mfns.into_iter()) {
+        unsafe {
+            restore_page(pfn, mfn);
+        }
+    }
+
+    // The pages Xen found no frame for stay in the balloon
+    if let Some(ballooned) = BALLOONED.lock().as_mut() {
+        ballooned.extend(pfns);
+    }
+
+    done
+}
+
+/// Back `pfn` with `mfn` again and give the page to the heap
+unsafe fn restore_page(pfn: Pfn, mfn: Mfn) {
+    let vaddr = Vaddr::from(pfn);
+
+    boot::set_p2m(pfn, mfn);
+
+    assert_eq!(memory::update_machphys(mfn, pfn), 0);
+    assert_eq!(memory::update_va_mapping(vaddr, PageEntry::from(mfn),
+                                         MapFlags::InvlpgLocal), 0);
+
+    __rust_deallocate(vaddr.as_mut_ptr(), PAGE_SIZE, PAGE_SIZE);
+}
+
+/// Move pages between the heap and Xen until the target is reached or Xen
+/// stops cooperating
+fn balloon() {
+    loop {
+        let current = current_pages();
+        let target = target_pages();
+
+        let done = if current > target {
+            decrease(cmp::min(current - target, BATCH_PAGES))
+        } else if current < target {
+            increase(cmp::min(target - current, BATCH_PAGES))
+        } else {
+            return;
+        };
+
+        if done == 0 {
+            println!("Warning: Memory target of {} KiB not reached, using {} KiB",
+                     target * PAGE_SIZE / 1024,
+                     current_pages() * PAGE_SIZE / 1024);
+            return;
+        }
+
+        // Let the other threads run between batches
+        Scheduler::schedule();
+    }
+}
+
+/// Handle a change of `memory/target`
+fn handle_target(path: &Path) {
+    let target_kib = match path.read::<usize>() {
+        Ok(target_kib) => target_kib,
+        Err(..) => return,
+    };
+
+    let min = MIN_MEMORY / PAGE_SIZE;
+    let target = target_kib / (PAGE_SIZE / 1024);
+
+    TARGET.store(cmp::min(cmp::max(target, min), boot::nr_pages()),
+                 Ordering::SeqCst);
+
+    balloon();
+}
+
+/// Tell the toolstack that the domain handles `memory/target`
+fn advertise() {
+    if Path::home().join("control/feature-balloon").write(1).is_err() {
+        println!("Warning: Failed to advertise control/feature-balloon");
+    }
+}
+
+/// Advertise the balloon and start watching the memory target
+pub fn init() {
+    advertise();
+
+    // The keys of the domain were recreated by the toolstack
+    mem::forget(suspend::on_resume(advertise));
+
+    let path = Path::home().join("memory/target");
+    let watch = path.to_cstring().and_then(move |cpath| {
+        XenStore::watch_with(cpath, "memory-target", move |_| {
+            handle_target(&path)
+        })
+    });
+
+    match watch {
+        Ok(watch) => *WATCH.lock() = Some(watch),
+        Err(..) => println!("Warning: Failed to watch memory/target"),
+    }
+}
@@ -0,0 +1,1223 @@
+//! Implementation of Xen's block device driver (blkfront)
+//!
+//! Data is transferred through bounce pages owned by the slots of the ring.
+//! Those pages stay granted to the backend for the lifetime of the device, so
+//! a backend supporting persistent grants maps them only once.
+//!
+//! Devices do not survive a restore of the domain: their pending requests
+//! fail with `Error::Closed` and they must be discovered again.
+
+use core::{cmp, mem, ptr, slice};
+use core::str::FromStr;
+
+use alloc_uni::{__rust_allocate, __rust_deallocate};
+
+use boxed::Box;
+
+use vec::Vec;
+use string::{String, ToString};
+
+use sync::Arc;
+use sync::spin::InterruptSpinLock;
+
+use ffi::CString;
+
+use hal::mmu::{Vaddr, Mfn};
+
+use hal::block::{Device, Error};
+
+use hal::xen::store::{self, XenStore, XenbusState};
+use hal::xen::grant::{Table as GrantTable, Ref as GrantRef};
+use hal::xen::ring::{SharedRing, FrontRing};
+
+use hal::xen::defs::EvtchnPort;
+
+use hal::arch::defs::PAGE_SIZE;
+use hal::arch::utils::{rmb, wmb};
+
+use hal::xen::event;
+use hal::xen::suspend::{self, ResumeHook, SuspendHook};
+
+use thread::{Scheduler, WaitQueue, Future, Poll, Waker};
+
+use time::{Duration, Instant};
+
+/// Time given to a backend to connect or to close
+const STATE_TIMEOUT_SECS: u64 = 10;
+
+/// Unit of `sector_number` and of the segment boundaries in requests
+const XEN_SECTOR_SIZE: usize = 512;
+
+/// BLKIF_MAX_SEGMENTS_PER_REQUEST
+const MAX_DIRECT_SEGMENTS: usize = 11;
+
+/// Upper bound of the segments used with indirect descriptors. Every slot of
+/// the ring may hold as many bounce pages.
+const MAX_INDIRECT_SEGMENTS: usize = 32;
+
+/// BLKIF_MAX_INDIRECT_PAGES_PER_REQUEST
+const MAX_INDIRECT_PAGES: usize = 8;
+
+/// VDISK_* flags of the `info` key
+const VDISK_CDROM: u32 = 1;
+const VDISK_REMOVABLE: u32 = 1 << 1;
+const VDISK_READONLY: u32 = 1 << 2;
+
+#[cfg(target_pointer_width = "64")]
+const PROTOCOL: &'static str = "x86_64-abi";
+#[cfg(target_pointer_width = "32")]
+const PROTOCOL: &'static str = "x86_32-abi";
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// BLKIF_OP_*
+enum BlkifOp {
+    Read = 0,
+    Write = 1,
+    WriteBarrier = 2,
+    FlushDiskCache = 3,
+    Indirect = 6,
+}
+
+/// BLKIF_RSP_*
+const BLKIF_RSP_OKAY: i16 = 0;
+const BLKIF_RSP_EOPNOTSUPP: i16 = -2;
+
+#[repr(C)]
+#[derive(Debug)]
+/// struct blkif_request_segment
+struct BlkifSegment {
+    gref: GrantRef,
+    first_sect: u8,
+    last_sect: u8,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// struct blkif_request
+struct BlkifRequest {
+    operation: BlkifOp,
+    nr_segments: u8,
+    handle: u16,
+    id: u64,
+    sector_number: u64,
+    seg: [BlkifSegment; MAX_DIRECT_SEGMENTS],
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// struct blkif_request_indirect
+///
+/// This shares the ring entries of `BlkifRequest`.
+struct BlkifRequestIndirect {
+    operation: BlkifOp,
+    indirect_op: BlkifOp,
+    nr_segments: u16,
+    id: u64,
+    sector_number: u64,
+    handle: u16,
+    indirect_grefs: [GrantRef; MAX_INDIRECT_PAGES],
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// struct blkif_response
+struct BlkifResponse {
+    id: u64,
+    operation: u8,
+    status: i16,
+}
+
+type BlkSharedRing = SharedRing<BlkifRequest, BlkifResponse>;
+type BlkFrontRing = FrontRing<BlkifRequest, BlkifResponse>;
+
+impl From<store::Error> for Error {
+    #[inline]
+    fn from(_: store::Error) -> Self {
+        Error::Negotiation
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Optional features negotiated with the backend
+pub struct Features {
+    /// Flushing the write cache is supported
+    pub flush_cache: bool,
+    /// Write barriers are supported
+    pub barrier: bool,
+    /// The backend maps the granted pages persistently
+    pub persistent: bool,
+    /// Maximum number of segments of an indirect request, 0 if indirect
+    /// descriptors are not supported
+    pub max_indirect_segments: usize,
+}
+
+/// Returns the ids of the vbds (virtual block devices) present in the Xen
+/// Store
+fn vbd_ids() -> Vec<u32> {
+    let path = CString::new("device/vbd").unwrap();
+
+    match XenStore::directory_list(path) {
+        Ok(list) => list.iter().filter_map(|id| u32::from_str(id).ok())
+                               .collect(),
+        Err(..) => Vec::new(),
+    }
+}
+
+/// Read the state of the backend `backend`
+fn backend_state(backend: &str) -> Option<XenbusState> {
+    let path = CString::new(format!("{}/state", backend)).unwrap();
+
+    match XenStore::read_value::<u8>(path) {
+        Ok(state) if state <= XenbusState::Reconfigured as u8 => {
+            Some(unsafe { mem::transmute(state) })
+        }
+        _ => None,
+    }
+}
+
+/// Read the key `key` of the backend `backend`
+fn backend_read<T: FromStr>(backend: &str, key: &str) -> Option<T> {
+    let path = CString::new(format!("{}/{}", backend, key)).unwrap();
+
+    XenStore::read_value::<T>(path).ok()
+}
+
+/// Wait for the backend to reach a state accepted by `done`
+///
+/// Returns the state reached, or None on timeout or if the backend is gone.
+fn wait_backend_state<F>(backend: &str, done: F) -> Option<XenbusState>
+    where F: Fn(&XenbusState) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(STATE_TIMEOUT_SECS);
+    let path = CString::new(format!("{}/state", backend)).unwrap();
+
+    let watch = match XenStore::watch(path, "blkfront-state") {
+        Ok(watch) => watch,
+        Err(..) => return None,
+    };
+
+    loop {
+        let state = match backend_state(backend) {
+            None => return None,
+            Some(state) => state,
+        };
+
+        if done(&state) {
+            return Some(state);
+        }
+
+        if watch.wait_timeout(deadline - Instant::now()).is_none() {
+            return None;
+        }
+    }
+}
+
+/// Bring a backend that went past `InitWait` back to it
+///
+/// The backend is closed first if it is still connected, then the frontend
+/// asks it to wait for a new connection.
+fn reset_backend(vbd_root: &str, backend: &str) -> Result<(), Error> {
+    let state_path = CString::new(format!("{}/state", vbd_root)).unwrap();
+
+    if backend_state(backend).map_or(false, |s| s < XenbusState::Closing) {
+        let mut t = try!(XenStore::start_transaction());
+
+        try!(t.switch_state(state_path.clone(), XenbusState::Closing));
+        try!(t.end());
+
+        try!(wait_backend_state(backend, |s| *s >= XenbusState::Closing)
+             .ok_or(Error::Negotiation));
+    }
+
+    let mut t = try!(XenStore::start_transaction());
+
+    try!(t.switch_state(state_path, XenbusState::Initialising));
+    try!(t.end());
+
+    match wait_backend_state(backend, |s| *s == XenbusState::InitWait) {
+        Some(..) => Ok(()),
+        None => Err(Error::Negotiation),
+    }
+}
+
+/// Returns a list of the block devices that have a xen backend
+pub fn discover() -> Vec<Box<XenBlkDevice>> {
+    vbd_ids().into_iter().filter_map(|id| {
+        match XenBlkDevice::new(id) {
+            Ok(dev) => Some(dev),
+            Err(e) => {
+                println!("Warning: Impossible to initialize xen block device {}: {:?}",
+                         id, e);
+
+                None
+            }
+        }
+    }).collect()
+}
+
+/// Page granted to the backend
+struct Buffer {
+    page: *mut u8,
+    grant_ref: GrantRef,
+}
+
+impl Buffer {
+    fn new(backend_id: u16, readonly: bool) -> Result<Self, Error> {
+        let page = __rust_allocate(PAGE_SIZE, PAGE_SIZE);
+
+        if page.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+
+        match GrantTable::alloc_ref() {
+            None => {
+                __rust_deallocate(page, PAGE_SIZE, PAGE_SIZE);
+
+                Err(Error::OutOfMemory)
+            }
+            Some(grant_ref) => {
+                grant_ref.grant_access(backend_id,
+                                       Mfn::from(Vaddr::from_ptr(page)),
+                                       readonly);
+
+                Ok(Buffer {
+                    page: page,
+                    grant_ref: grant_ref,
+                })
+            }
+        }
+    }
+
+    fn release(mut self) {
+        self.grant_ref.end_access();
+        GrantTable::free_ref(self.grant_ref);
+
+        __rust_deallocate(self.page, PAGE_SIZE, PAGE_SIZE);
+    }
+}
+
+enum SlotState {
+    /// The slot can be used for a new request
+    Free,
+    /// The request was pushed in the ring
+    Pending,
+    /// The request was pushed in the ring but its completion was dropped
+    Abandoned,
+    /// The request completed, its result was not collected yet
+    Done(Result<(), Error>),
+}
+
+struct SlotInner {
+    state: SlotState,
+    /// Bounce pages, allocated on demand
+    buffers: Vec<Buffer>,
+    /// Page holding the segments of indirect requests
+    indirect: Option<Buffer>,
+}
+
+impl SlotInner {
+    fn is_free(&self) -> bool {
+        match self.state {
+            SlotState::Free => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the backend still owns the request
+    fn is_in_flight(&self) -> bool {
+        match self.state {
+            SlotState::Pending | SlotState::Abandoned => true,
+            _ => false,
+        }
+    }
+}
+
+/// Slot of the ring, holding the state and the pages of one request
+struct Slot {
+    inner: InterruptSpinLock<SlotInner>,
+    /// Signaled when the request completes
+    wait: WaitQueue,
+    waker: InterruptSpinLock<Option<Waker>>,
+    /// Signaled when a slot of the device becomes free
+    free: Arc<WaitQueue>,
+}
+
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    fn is_done(&self) -> bool {
+        match self.inner.lock().state {
+            SlotState::Done(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Record the completion of the request
+    ///
+    /// This is called from interrupt context.
+    fn complete(&self, result: Result<(), Error>) {
+        let abandoned = {
+            let mut inner = self.inner.lock();
+
+            let abandoned = match inner.state {
+                SlotState::Pending => false,
+                SlotState::Abandoned => true,
+                _ => return,
+            };
+
+            inner.state = if abandoned {
+                SlotState::Free
+            } else {
+                SlotState::Done(result)
+            };
+
+            abandoned
+        };
+
+        // Nobody waits for the result of an abandoned request
+        if abandoned {
+            self.free.unblock();
+            return;
+        }
+
+        self.wait.unblock_all();
+
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+
+    /// Free the slot without collecting any result
+    fn release(&self) {
+        self.inner.lock().state = SlotState::Free;
+        self.free.unblock();
+    }
+
+    /// Collect the result of a completed request and free the slot
+    ///
+    /// `len` bytes are copied out of the bounce pages on success.
+    fn collect(&self, len: usize) -> Option<Result<Vec<u8>, Error>> {
+        let result = {
+            let mut inner = self.inner.lock();
+
+            let result = match inner.state {
+                SlotState::Done(Ok(())) => {
+                    let mut data = Vec::with_capacity(len);
+
+                    for b in &inner.buffers {
+                        let size = cmp::min(len - data.len(), PAGE_SIZE);
+
+                        if size == 0 {
+                            break;
+                        }
+
+                        data.extend_from_slice(unsafe {
+                            slice::from_raw_parts(b.page, size)
+                        });
+                    }
+
+                    Ok(data)
+                }
+                SlotState::Done(Err(e)) => Err(e),
+                _ => return None,
+            };
+
+            inner.state = SlotState::Free;
+
+            result
+        };
+
+        self.free.unblock();
+
+        Some(result)
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock();
+
+        for b in inner.buffers.drain(..) {
+            b.release();
+        }
+
+        if let Some(b) = inner.indirect.take() {
+            b.release();
+        }
+    }
+}
+
+/// Pending block request
+///
+/// The request is carried out whether or not its completion is awaited. The
+/// result is obtained either by blocking with `wait()` or by polling the
+/// completion as a future. It holds the data read, and is empty for writes and
+/// flushes.
+pub struct Completion {
+    slot: Option<Arc<Slot>>,
+    len: usize,
+}
+
+impl Completion {
+    fn done() -> Self {
+        Completion {
+            slot: None,
+            len: 0,
+        }
+    }
+
+    /// Returns true if the request completed
+    pub fn is_done(&self) -> bool {
+        self.slot.as_ref().map_or(true, |s| s.is_done())
+    }
+
+    /// Block until the request completes and return its result
+    pub fn wait(mut self) -> Result<Vec<u8>, Error> {
+        let slot = match self.slot.take() {
+            None => return Ok(Vec::new()),
+            Some(slot) => slot,
+        };
+
+        wait_event!(slot.wait, slot.is_done());
+
+        slot.collect(self.len).unwrap()
+    }
+}
+
+impl Future for Completion {
+    type Output = Result<Vec<u8>, Error>;
+
+    fn poll(&mut self, waker: &Waker) -> Poll<Self::Output> {
+        let result = match self.slot {
+            None => Some(Ok(Vec::new())),
+            Some(ref slot) => {
+                *slot.waker.lock() = Some(waker.clone());
+
+                slot.collect(self.len)
+            }
+        };
+
+        match result {
+            None => Poll::Pending,
+            Some(result) => {
+                self.slot = None;
+
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if let Some(ref slot) = self.slot {
+            let done = {
+                let mut inner = slot.inner.lock();
+
+                let pending = match inner.state {
+                    SlotState::Pending => true,
+                    SlotState::Done(..) => false,
+                    _ => return,
+                };
+
+                // The slot is freed once the backend is done with the request
+                if pending {
+                    inner.state = SlotState::Abandoned;
+                }
+
+                !pending
+            };
+
+            if done {
+                slot.release();
+            }
+        }
+    }
+}
+
+/// A Xen vbd (virtual block device)
+pub struct XenBlkDevice {
+    id: u32,
+    backend: String,
+    backend_id: u16,
+    evtchn: EvtchnPort,
+    ring: InterruptSpinLock<BlkFrontRing>,
+    slots: Vec<Arc<Slot>>,
+    free: Arc<WaitQueue>,
+    /// Capacity in units of 512 bytes
+    sectors: u64,
+    sector_size: usize,
+    info: u32,
+    features: Features,
+    /// Maximum number of segments of a request
+    segments: usize,
+    /// Value of `suspend::generation()` when the device connected
+    generation: usize,
+    /// Waits for the requests in flight before the domain is suspended
+    suspend_hook: Option<SuspendHook>,
+    /// Fails the requests in flight when the domain is restored
+    resume_hook: Option<ResumeHook>,
+}
+
+unsafe impl Send for XenBlkDevice {}
+unsafe impl Sync for XenBlkDevice {}
+
+impl XenBlkDevice {
+    /// Callback handling Xen block events
+    fn device_callback(_: EvtchnPort, data: *mut u8) {
+        let xen_dev = unsafe { &*(data as *const XenBlkDevice) };
+
+        xen_dev.handle_responses();
+    }
+
+    #[cfg_attr(feature = "clippy", allow(cyclomatic_complexity))]
+    /// Creates a new Xen block device with id `id`
+    pub fn new(id: u32) -> Result<Box<Self>, Error> {
+        // Compute the root path that contains all the information for the
+        // block device with id "id"
+        let vbd_root = format!("device/vbd/{}", id);
+
+        let backend_id_path = CString::new(format!("{}/backend-id", vbd_root))
+                              .unwrap();
+        let backend_id = try!(XenStore::read_value::<u16>(backend_id_path));
+
+        let backend_path = CString::new(format!("{}/backend", vbd_root))
+                           .unwrap();
+        let backend = try!(XenStore::read_value::<String>(backend_path));
+
+        // The backend publishes its features before waiting for us. It might
+        // still be connected to a previous instance of the frontend, e.g.
+        // after a restore or when the device is discovered again
+        match wait_backend_state(&backend, |s| *s >= XenbusState::InitWait) {
+            Some(XenbusState::InitWait) => (),
+            Some(..) => try!(reset_backend(&vbd_root, &backend)),
+            None => return Err(Error::Negotiation),
+        }
+
+        let max_indirect = backend_read::<usize>(
+                               &backend, "feature-max-indirect-segments")
+                           .unwrap_or(0);
+
+        let mut sring = try!(BlkSharedRing::new(PAGE_SIZE)
+                             .ok_or(Error::OutOfMemory));
+        let ring_ref = try!(sring.grant_access(backend_id)
+                            .ok_or(Error::OutOfMemory));
+
+        let ring = BlkFrontRing::new(sring);
+        let free = Arc::new(WaitQueue::new());
+
+        let slots = (0..ring.size()).map(|_| {
+            Arc::new(Slot {
+                inner: InterruptSpinLock::new(SlotInner {
+                    state: SlotState::Free,
+                    buffers: Vec::new(),
+                    indirect: None,
+                }),
+                wait: WaitQueue::new(),
+                waker: InterruptSpinLock::new(None),
+                free: free.clone(),
+            })
+        }).collect();
+
+        let mut xen_dev = Box::new(XenBlkDevice {
+            id: id,
+            backend: backend,
+            backend_id: backend_id,
+            evtchn: 0,
+            ring: InterruptSpinLock::new(ring),
+            slots: slots,
+            free: free,
+            sectors: 0,
+            sector_size: XEN_SECTOR_SIZE,
+            info: 0,
+            features: Features {
+                max_indirect_segments: if max_indirect > MAX_DIRECT_SEGMENTS {
+                    max_indirect
+                } else {
+                    0
+                },
+                ..Features::default()
+            },
+            segments: if max_indirect > MAX_DIRECT_SEGMENTS {
+                cmp::min(max_indirect, MAX_INDIRECT_SEGMENTS)
+            } else {
+                MAX_DIRECT_SEGMENTS
+            },
+            generation: suspend::generation(),
+            suspend_hook: None,
+            resume_hook: None,
+        });
+
+        // No request is submitted while the domain is suspending, give the
+        // backend a chance to complete those it has
+        let slots = xen_dev.slots.clone();
+
+        xen_dev.suspend_hook = Some(suspend::on_suspend(move || {
+            let idle = suspend::wait_idle(|| {
+                slots.iter().all(|s| !s.inner.lock().is_in_flight())
+            });
+
+            if !idle {
+                println!("Warning: Requests of xen block device {} still in flight",
+                         id);
+            }
+        }));
+
+        // The backend of the device is gone once the domain is restored, so
+        // are the requests it was given
+        let slots = xen_dev.slots.clone();
+
+        xen_dev.resume_hook = Some(suspend::on_resume(move || {
+            for slot in &slots {
+                slot.complete(Err(Error::Closed));
+            }
+        }));
+
+        // This is legit as the event will be gone before the XenBlkDevice
+        let xen_dev_ptr = &mut *xen_dev as *mut XenBlkDevice;
+
+        // Create a new event to receive interruptions about this device
+        xen_dev.evtchn = try!(event::dispatcher().alloc_unbound(
+                backend_id, Self::device_callback,
+                xen_dev_ptr as *mut _).map_err(|_| Error::Negotiation));
+
+        // Start a transaction with the Xen store to publish the ring
+        let mut t = try!(XenStore::start_transaction());
+
+        try!(t.write(CString::new(format!("{}/ring-ref", vbd_root)).unwrap(),
+                     CString::new(ring_ref.to_string()).unwrap()));
+        try!(t.write(CString::new(format!("{}/event-channel", vbd_root))
+                     .unwrap(),
+                     CString::new(xen_dev.evtchn.to_string()).unwrap()));
+        try!(t.write(CString::new(format!("{}/protocol", vbd_root)).unwrap(),
+                     CString::new(PROTOCOL).unwrap()));
+
+        // The bounce pages are never ungranted while the device lives
+        try!(t.write(CString::new(format!("{}/feature-persistent", vbd_root))
+                     .unwrap(),
+                     CString::new("1").unwrap()));
+
+        let state_path = CString::new(format!("{}/state", vbd_root)).unwrap();
+
+        try!(t.switch_state(state_path.clone(), XenbusState::Initialised));
+
+        try!(t.end());
+
+        if wait_backend_state(&xen_dev.backend,
+                              |s| *s >= XenbusState::Connected)
+           != Some(XenbusState::Connected) {
+            return Err(Error::Negotiation);
+        }
+
+        // The backend describes the disk once connected
+        {
+            let backend = &xen_dev.backend;
+
+            xen_dev.sectors = try!(backend_read::<u64>(backend, "sectors")
+                                   .ok_or(Error::Negotiation));
+            xen_dev.sector_size = backend_read::<usize>(backend, "sector-size")
+                                  .unwrap_or(XEN_SECTOR_SIZE);
+            xen_dev.info = backend_read::<u32>(backend, "info").unwrap_or(0);
+
+            if backend_read::<String>(backend, "mode").map_or(false,
+                                                              |m| m == "r") {
+                xen_dev.info |= VDISK_READONLY;
+            }
+
+            let flag = |key: &str| {
+                backend_read::<u8>(backend, key).unwrap_or(0) != 0
+            };
+
+            xen_dev.features.flush_cache = flag("feature-flush-cache");
+            xen_dev.features.barrier = flag("feature-barrier");
+            xen_dev.features.persistent = flag("feature-persistent");
+        }
+
+        if xen_dev.sector_size < XEN_SECTOR_SIZE ||
+           xen_dev.sector_size % XEN_SECTOR_SIZE != 0 ||
+           xen_dev.sector_size > PAGE_SIZE {
+            return Err(Error::Negotiation);
+        }
+
+        let mut t = try!(XenStore::start_transaction());
+
+        try!(t.switch_state(state_path, XenbusState::Connected));
+
+        try!(t.end());
+
+        // Unmask the event
+        event::dispatcher().unmask_event(xen_dev.evtchn);
+
+        Ok(xen_dev)
+    }
+
+    #[inline]
+    /// Returns true if the device was connected before the domain was
+    /// restored
+    ///
+    /// Requests to a stale device fail with `Error::Closed`, the device must
+    /// be discovered again.
+    pub fn is_stale(&self) -> bool {
+        self.generation != suspend::generation()
+    }
+
+    #[inline]
+    /// Returns the id of the vbd backing the device
+    pub fn vbd_id(&self) -> u32 {
+        self.id
+    }
+
+    #[inline]
+    /// Returns the size of a sector in bytes
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    #[inline]
+    /// Returns the capacity of the device in sectors
+    pub fn sectors(&self) -> u64 {
+        self.sectors * XEN_SECTOR_SIZE as u64 / self.sector_size as u64
+    }
+
+    #[inline]
+    /// Returns true if the device cannot be written
+    pub fn is_read_only(&self) -> bool {
+        self.info & VDISK_READONLY != 0
+    }
+
+    #[inline]
+    /// Returns true if the device is removable
+    pub fn is_removable(&self) -> bool {
+        self.info & VDISK_REMOVABLE != 0
+    }
+
+    #[inline]
+    /// Returns true if the device is a CD-ROM
+    pub fn is_cdrom(&self) -> bool {
+        self.info & VDISK_CDROM != 0
+    }
+
+    #[inline]
+    /// Returns the features negotiated with the backend
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    #[inline]
+    /// Returns the maximum number of sectors of a single request
+    pub fn max_request_sectors(&self) -> usize {
+        self.segments * PAGE_SIZE / self.sector_size
+    }
+
+    #[inline]
+    /// Returns the maximum number of requests in flight
+    pub fn queue_depth(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns the queue signaled every time a request completes
+    ///
+    /// This can be used to wait for free room in the ring.
+    pub fn completion_queue(&self) -> &WaitQueue {
+        &self.free
+    }
+
+    /// Start reading `count` sectors from `sector`
+    ///
+    /// `count` must not exceed `max_request_sectors()`. This blocks while the
+    /// ring is full.
+    pub fn submit_read(&self, sector: u64, count: usize)
+        -> Result<Completion, Error> {
+        let len = count * self.sector_size;
+
+        self.submit(BlkifOp::Read, sector, len, None)
+    }
+
+    /// Start writing `data` from `sector`
+    ///
+    /// The size of `data` must be a multiple of the sector size and must not
+    /// exceed `max_request_sectors()` sectors. This blocks while the ring is
+    /// full.
+    pub fn submit_write(&self, sector: u64, data: &[u8])
+        -> Result<Completion, Error> {
+        self.submit(BlkifOp::Write, sector, data.len(), Some(data))
+    }
+
+    /// Start flushing the write cache of the device
+    ///
+    /// A write barrier is used if the backend cannot flush its cache. If it
+    /// supports neither, writes are assumed to be durable once completed and
+    /// this completes right away.
+    pub fn submit_flush(&self) -> Result<Completion, Error> {
+        if self.features.flush_cache {
+            self.submit(BlkifOp::FlushDiskCache, 0, 0, None)
+        } else if self.features.barrier {
+            self.submit(BlkifOp::WriteBarrier, 0, 0, None)
+        } else {
+            Ok(Completion::done())
+        }
+    }
+
+    /// Read sectors from `sector` into `buf`
+    ///
+    /// The size of `buf` must be a multiple of the sector size. Large
+    /// buffers are split into several requests.
+    pub fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let chunk = self.max_request_sectors() * self.sector_size;
+        let mut sector = sector;
+
+        if buf.len() % self.sector_size != 0 {
+            return Err(Error::InvalidSize);
+        }
+
+        for part in buf.chunks_mut(chunk) {
+            let count = part.len() / self.sector_size;
+            let data = try!(try!(self.submit_read(sector, count)).wait());
+
+            part.clone_from_slice(&data);
+            sector += count as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Write `buf` from `sector`
+    ///
+    /// The size of `buf` must be a multiple of the sector size. Large
+    /// buffers are split into several requests.
+    pub fn write(&self, sector: u64, buf: &[u8]) -> Result<(), Error> {
+        let chunk = self.max_request_sectors() * self.sector_size;
+        let mut sector = sector;
+
+        if buf.len() % self.sector_size != 0 {
+            return Err(Error::InvalidSize);
+        }
+
+        for part in buf.chunks(chunk) {
+            try!(try!(self.submit_write(sector, part)).wait());
+            sector += (part.len() / self.sector_size) as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the write cache of the device
+    pub fn flush(&self) -> Result<(), Error> {
+        try!(self.submit_flush()).wait().map(|_| ())
+    }
+
+    /// Claim a free slot, blocking while the ring is full or while the
+    /// domain is suspending
+    fn claim_slot(&self) -> (usize, Arc<Slot>) {
+        loop {
+            suspend::wait_running();
+
+            for (i, slot) in self.slots.iter().enumerate() {
+                let mut inner = slot.inner.lock();
+
+                if inner.is_free() {
+                    inner.state = SlotState::Pending;
+
+                    return (i, slot.clone());
+                }
+            }
+
+            wait_event!(self.free,
+                        self.slots.iter().any(|s| s.inner.lock().is_free()));
+        }
+    }
+
+    #[cfg_attr(feature = "clippy", allow(cyclomatic_complexity))]
+    fn submit(&self, op: BlkifOp, sector: u64, len: usize, data: Option<&[u8]>)
+        -> Result<Completion, Error> {
+        if self.is_stale() {
+            return Err(Error::Closed);
+        }
+
+        if len % self.sector_size != 0 ||
+           len > self.max_request_sectors() * self.sector_size {
+            return Err(Error::InvalidSize);
+        }
+
+        if sector + (len / self.sector_size) as u64 > self.sectors() {
+            return Err(Error::OutOfRange);
+        }
+
+        if op != BlkifOp::Read && self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        let nr_segments = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let (id, slot) = self.claim_slot();
+
+        // The domain might have been restored while waiting for the slot
+        if self.is_stale() {
+            slot.release();
+            return Err(Error::Closed);
+        }
+
+        let (segments, indirect) = match self.prepare(&slot, len, data) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                slot.release();
+                return Err(e);
+            }
+        };
+
+        let sector_number = sector *
+                            (self.sector_size / XEN_SECTOR_SIZE) as u64;
+
+        let mut ring = self.ring.lock();
+        let index = ring.req_prod() as usize;
+
+        match indirect {
+            None => {
+                let req = unsafe {
+                    ring.sring_mut().request_from_index(index)
+                };
+
+                req.operation = op;
+                req.nr_segments = nr_segments as u8;
+                req.handle = self.id as u16;
+                req.id = id as u64;
+                req.sector_number = sector_number;
+
+                for (i, seg) in segments.into_iter().enumerate() {
+                    req.seg[i] = seg;
+                }
+            }
+            Some(indirect) => {
+                let req = unsafe {
+                    let req = ring.sring_mut().request_from_index(index)
+                              as *mut BlkifRequest;
+
+                    &mut *(req as *mut BlkifRequestIndirect)
+                };
+
+                req.operation = BlkifOp::Indirect;
+                req.indirect_op = op;
+                req.nr_segments = nr_segments as u16;
+                req.id = id as u64;
+                req.sector_number = sector_number;
+                req.handle = self.id as u16;
+                req.indirect_grefs[0] = indirect;
+            }
+        }
+
+        // Update ring index
+        unsafe {
+            *ring.req_prod_mut() += 1;
+        }
+
+        wmb();
+
+        // Push the request in the shared ring and notify the backend if
+        // necessary
+        if ring.push_requests() {
+            event::send(self.evtchn);
+        }
+
+        mem::drop(ring);
+
+        Ok(Completion {
+            slot: Some(slot),
+            len: if op == BlkifOp::Read { len } else { 0 },
+        })
+    }
+
+    /// Fill the bounce pages of `slot` with `data` and describe the `len`
+    /// bytes of the request as segments
+    ///
+    /// For indirect requests, the segments are written in the indirect page
+    /// whose grant reference is returned instead.
+    fn prepare(&self, slot: &Slot, len: usize, data: Option<&[u8]>)
+        -> Result<(Vec<BlkifSegment>, Option<GrantRef>), Error> {
+        let nr_segments = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut inner = slot.inner.lock();
+
+        // Make sure the slot holds enough bounce pages
+        while inner.buffers.len() < nr_segments {
+            let b = try!(Buffer::new(self.backend_id, false));
+
+            inner.buffers.push(b);
+        }
+
+        if nr_segments > MAX_DIRECT_SEGMENTS && inner.indirect.is_none() {
+            inner.indirect = Some(try!(Buffer::new(self.backend_id, true)));
+        }
+
+        if let Some(data) = data {
+            for (b, part) in inner.buffers.iter().zip(data.chunks(PAGE_SIZE)) {
+                unsafe {
+                    slice::from_raw_parts_mut(b.page, part.len())
+                        .clone_from_slice(part);
+                }
+            }
+        }
+
+        let segments: Vec<BlkifSegment> = (0..nr_segments).map(|i| {
+            let size = cmp::min(len - i * PAGE_SIZE, PAGE_SIZE);
+
+            BlkifSegment {
+                gref: inner.buffers[i].grant_ref.clone(),
+                first_sect: 0,
+                last_sect: (size / XEN_SECTOR_SIZE - 1) as u8,
+            }
+        }).collect();
+
+        if nr_segments <= MAX_DIRECT_SEGMENTS {
+            return Ok((segments, None));
+        }
+
+        let indirect = inner.indirect.as_ref().unwrap();
+
+        unsafe {
+            let page = indirect.page as *mut BlkifSegment;
+
+            for (i, seg) in segments.into_iter().enumerate() {
+                ptr::write(page.offset(i as isize), seg);
+            }
+        }
+
+        Ok((Vec::new(), Some(indirect.grant_ref.clone())))
+    }
+
+    /// Tell the backend we are going away, wait for it to stop using the
+    /// ring and the bounce pages and close the event channel
+    fn disconnect(&self, state_path: &CString) {
+        if let Ok(mut t) = XenStore::start_transaction() {
+            let _ = t.switch_state(state_path.clone(), XenbusState::Closing);
+            let _ = t.end();
+        }
+
+        if wait_backend_state(&self.backend, |s| *s >= XenbusState::Closing)
+           .is_none() {
+            println!("Warning: Backend of xen block device {} did not close",
+                     self.id);
+        }
+
+        // Port 0 is never allocated, it means initialization failed before
+        // the event channel was set up
+        if self.evtchn != 0 {
+            event::dispatcher().unbind_port(self.evtchn);
+            event::close(self.evtchn);
+        }
+    }
+
+    /// Consume the responses pushed by the backend
+    fn handle_responses(&self) {
+        let mut ring = self.ring.lock();
+
+        loop {
+            let prod = unsafe { ring.sring_mut().rsp_prod() };
+            let mut cons = ring.rsp_cons();
+
+            rmb();
+
+            while cons != prod {
+                let (id, status) = {
+                    let resp = unsafe {
+                        ring.sring_mut().response_from_index(cons as usize)
+                    };
+
+                    (resp.id as usize, resp.status)
+                };
+
+                let result = match status {
+                    BLKIF_RSP_OKAY => Ok(()),
+                    BLKIF_RSP_EOPNOTSUPP => Err(Error::NotSupported),
+                    _ => Err(Error::Io),
+                };
+
+                if let Some(slot) = self.slots.get(id) {
+                    slot.complete(result);
+                }
+
+                cons += 1;
+            }
+
+            unsafe {
+                *ring.rsp_cons_mut() = cons;
+            }
+
+            if !ring.final_check_for_responses() {
+                break;
+            }
+        }
+    }
+}
+
+impl Device for XenBlkDevice {
+    #[inline]
+    fn sector_size(&self) -> usize {
+        self.sector_size()
+    }
+
+    #[inline]
+    fn sectors(&self) -> u64 {
+        self.sectors()
+    }
+
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.is_read_only()
+    }
+
+    #[inline]
+    fn queue_depth(&self) -> usize {
+        self.queue_depth()
+    }
+
+    #[inline]
+    fn completion_queue(&self) -> &WaitQueue {
+        self.completion_queue()
+    }
+
+    #[inline]
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.read(sector, buf)
+    }
+
+    #[inline]
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), Error> {
+        self.write(sector, buf)
+    }
+
+    #[inline]
+    fn flush(&self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+impl Drop for XenBlkDevice {
+    fn drop(&mut self) {
+        // The backend and the event channel of a stale device are gone with
+        // the previous domain
+        let stale = self.is_stale();
+        let state_path = CString::new(format!("device/vbd/{}/state", self.id))
+                         .unwrap();
+
+        if !stale {
+            self.disconnect(&state_path);
+        }
+
+        // Requests still in flight will never complete
+        for slot in &self.slots {
+            slot.complete(Err(Error::Closed));
+        }
+
+        unsafe {
+            self.ring.lock().sring_mut().release();
+        }
+
+        if stale {
+            return;
+        }
+
+        if let Ok(mut t) = XenStore::start_transaction() {
+            let _ = t.switch_state(state_path, XenbusState::Closed);
+            let _ = t.end();
+        }
+    }
+}
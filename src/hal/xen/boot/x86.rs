@@ -6,7 +6,7 @@ use hal::arch::mmu::PageEntry;
 
 use hal::mmu::{Vaddr, Maddr, Pfn, Mfn};
 
-use hal::xen::defs::StartInfo;
+use hal::xen::defs::{StartInfo, Ulong, ULONG_SIZE};
 use hal::xen::store::XenStore;
 use hal::xen::memory::MapFlags;
 
@@ -22,6 +22,16 @@ macro_rules! align {
 const MB: usize = 0x100000;
 const PFN_PER_MB: usize = MB / PAGE_SIZE;
 
+/// Number of frame numbers held by a page of the P2M
+const P2M_PER_PAGE: usize = PAGE_SIZE / ULONG_SIZE;
+
+/// Entry of the P2M for a pfn without frame
+pub const INVALID_MFN: usize = !0;
+
+/// Pages listing the frames of the P2M, see `init_p2m()`
+static mut P2M_FRAME_LIST_LIST: *mut Ulong = 0 as *mut Ulong;
+static mut P2M_FRAME_LIST: *mut Ulong = 0 as *mut Ulong;
+
 extern {
     // Start info is not present on all architecture, this is why this
     // was made a global variable only for x86_*
@@ -65,6 +75,17 @@ pub fn init() {
     }
 }
 
+/// Returns the command line given to the domain by the toolstack
+pub fn cmd_line() -> &'static str {
+    unsafe {
+        let cmd_line = &(*start_info).cmd_line;
+        let len = cmd_line.iter().position(|c| *c == 0)
+                                 .unwrap_or(cmd_line.len());
+
+        ::core::str::from_utf8(&cmd_line[..len]).unwrap_or("")
+    }
+}
+
 #[cfg_attr(feature = "clippy", allow(identity_op))]
 pub fn init_memory() -> Vaddr {
     raw_println!("Kernel sections (end @ -> {:p}):", &__uni_end);
@@ -118,6 +139,117 @@ pub fn init_memory() -> Vaddr {
     vaddr_limit
 }
 
+/// Publish the frames of the P2M (the pfn to mfn table) to the toolstack
+///
+/// The toolstack needs them to save the domain. The P2M is listed by pages
+/// whose frames are in turn listed by a single page. Frames move when the
+/// domain is restored so this must be called again on resume.
+pub fn init_p2m() {
+    unsafe {
+        let max_pfn = (*start_info).nr_pages;
+        let p2m = (*start_info).mfn_list as *const u8;
+        let p2m_pages = (max_pfn + P2M_PER_PAGE - 1) / P2M_PER_PAGE;
+        let list_pages = (p2m_pages + P2M_PER_PAGE - 1) / P2M_PER_PAGE;
+
+        if list_pages > P2M_PER_PAGE {
+            panic!("TODO: P2M too big to be listed");
+        }
+
+        if P2M_FRAME_LIST.is_null() {
+            P2M_FRAME_LIST_LIST =
+                alloc_uni::__rust_allocate(PAGE_SIZE, PAGE_SIZE) as *mut _;
+            P2M_FRAME_LIST =
+                alloc_uni::__rust_allocate(list_pages * PAGE_SIZE,
+                                           PAGE_SIZE) as *mut _;
+
+            if P2M_FRAME_LIST_LIST.is_null() || P2M_FRAME_LIST.is_null() {
+                panic!("Cannot allocate the P2M frame list");
+            }
+        }
+
+        for i in 0..p2m_pages {
+            let vaddr = Vaddr::from_ptr(p2m.offset((i * PAGE_SIZE) as isize));
+
+            *P2M_FRAME_LIST.offset(i as isize) = *Mfn::from(vaddr);
+        }
+
+        for i in 0..list_pages {
+            let page = P2M_FRAME_LIST.offset((i * P2M_PER_PAGE) as isize);
+
+            *P2M_FRAME_LIST_LIST.offset(i as isize) =
+                *Mfn::from(Vaddr::from_ptr(page));
+        }
+
+        xen::shared_info.arch.max_pfn = max_pfn;
+        xen::shared_info.arch.pfn_to_mfn_frame_list_list =
+            *Mfn::from(Vaddr::from_ptr(P2M_FRAME_LIST_LIST));
+    }
+}
+
+/// Prepare the start info page and the shared info page for a suspend
+///
+/// The frames of the Xen Store and of the console are given as pfns so that
+/// the toolstack can translate them. Returns the frame of the start info page
+/// to hand to Xen.
+pub unsafe fn pre_suspend() -> Ulong {
+    let info = start_info as *mut StartInfo;
+
+    (*info).store_mfn = *Pfn::from(Mfn::new((*info).store_mfn));
+    (*info).domu_console.mfn = *Pfn::from(Mfn::new((*info).domu_console.mfn));
+
+    // The shared info frame belongs to Xen, put our own frame back under it
+    let shared_info_addr = Vaddr::from_ptr(&xen::shared_info);
+    let own_pte = PageEntry::from(Pfn::from(shared_info_addr));
+
+    assert_eq!(xen::memory::update_va_mapping(shared_info_addr, own_pte,
+                                              MapFlags::InvlpgLocal), 0);
+
+    *Mfn::from(Vaddr::new(start_info as usize))
+}
+
+/// Undo `pre_suspend()` once the domain runs again
+///
+/// Unless the suspend was `cancelled`, the toolstack already wrote the new
+/// frames to the start info page.
+pub unsafe fn post_suspend(cancelled: bool) {
+    let info = start_info as *mut StartInfo;
+
+    if cancelled {
+        (*info).store_mfn = *Mfn::from(Pfn::new((*info).store_mfn));
+        (*info).domu_console.mfn =
+            *Mfn::from(Pfn::new((*info).domu_console.mfn));
+    }
+
+    map_shared_info();
+
+    if !cancelled {
+        init_p2m();
+    }
+}
+
+/// Returns the number of pages given to the domain at boot
+pub fn nr_pages() -> usize {
+    unsafe {
+        (*start_info).nr_pages
+    }
+}
+
+/// Make `pfn` point to `mfn` in the P2M
+///
+/// `INVALID_MFN` tells the toolstack that `pfn` has no frame.
+pub unsafe fn set_p2m(pfn: Pfn, mfn: Mfn) {
+    let p2m = (*start_info).mfn_list as *mut Mfn;
+
+    *p2m.offset(*pfn as isize) = mfn;
+}
+
+/// Returns the event channels of the console and of the Xen Store
+pub fn event_channels() -> (u32, u32) {
+    unsafe {
+        ((*start_info).domu_console.evtchn, (*start_info).store_evtchn)
+    }
+}
+
 unsafe fn map_shared_info() {
     let shared_info_pte = PageEntry::from(Maddr::new((*start_info).shared_info as u64));
     let shared_info_addr = Vaddr::from_ptr(&xen::shared_info);
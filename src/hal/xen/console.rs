@@ -24,6 +24,16 @@ pub unsafe fn init(interface: *mut ConsoleInterface, port: EvtchnPort) {
     CONSOLE.set(Console::new(interface, port));
 }
 
+/// Reconnect the console of a restored domain to its new event channel `port`
+///
+/// The ring stays at the same place in the domain's memory.
+pub unsafe fn resume(port: EvtchnPort) {
+    let mut console = CONSOLE.as_mut();
+
+    console.port = port;
+    console.init_input();
+}
+
 pub struct Console {
     interface: *mut ConsoleInterface,
     port: EvtchnPort,
@@ -61,6 +71,18 @@ impl Console {
         send(self.port);
     }
 
+    #[inline]
+    /// Returns true if input is waiting to be read
+    pub fn has_input(&self) -> bool {
+        self.in_prod != self.in_cons
+    }
+
+    #[inline]
+    /// Returns the queue signaled when new input is available
+    pub fn input_queue(&self) -> &WaitQueue {
+        &self.queue
+    }
+
     fn is_output_full(&self) -> bool {
         let data: ConsRingIdx;
 
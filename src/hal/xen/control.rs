@@ -0,0 +1,155 @@
+//! Handling of the requests sent by the toolstack through `control/`
+//!
+//! `xl shutdown` and `xl reboot` write the action requested to the
+//! `control/shutdown` key of the domain. Registered shutdown hooks run before
+//! the domain goes down. `xl save` and `xl migrate` request a suspend, see
+//! `hal::xen::suspend`.
+
+use core::fmt::{self, Display, Formatter};
+
+use boxed::Box;
+use vec::Vec;
+use string::String;
+
+use io::Write;
+
+use sync::spin::InterruptSpinLock;
+
+use hal;
+
+use hal::xen::{sched, suspend};
+use hal::xen::store::{XenStore, Watch, Path};
+
+/// Hooks run before the domain goes down
+static HOOKS: InterruptSpinLock<Option<Vec<Box<Fn(Shutdown) + Send>>>> =
+    InterruptSpinLock::new(None);
+
+/// Watch on `control/shutdown`
+static WATCH: InterruptSpinLock<Option<Watch>> = InterruptSpinLock::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Action requested by the toolstack
+pub enum Shutdown {
+    /// Power the domain off
+    PowerOff,
+    /// Reboot the domain
+    Reboot,
+    /// Halt the domain, Xen treats it as a power off
+    Halt,
+}
+
+impl Shutdown {
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "poweroff" => Some(Shutdown::PowerOff),
+            "reboot" => Some(Shutdown::Reboot),
+            "halt" => Some(Shutdown::Halt),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Shutdown {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let s = match *self {
+            Shutdown::PowerOff => "poweroff",
+            Shutdown::Reboot => "reboot",
+            Shutdown::Halt => "halt",
+        };
+
+        f.write_str(s)
+    }
+}
+
+/// Register `hook` to run before the domain goes down
+///
+/// Hooks run in the order they were registered, on the thread handling the
+/// request. They are not run when the application exits on its own.
+pub fn on_shutdown<F>(hook: F) where F: Fn(Shutdown) + Send + 'static {
+    let mut hooks = HOOKS.lock();
+
+    if hooks.is_none() {
+        *hooks = Some(Vec::new());
+    }
+
+    hooks.as_mut().unwrap().push(Box::new(hook));
+}
+
+/// Run the shutdown hooks and carry out `action`
+pub fn shutdown(action: Shutdown) -> ! {
+    println!("Shutdown requested by the toolstack: {}", action);
+
+    // Hooks may register other hooks, don't hold the lock while running them
+    let hooks = HOOKS.lock().take().unwrap_or(Vec::new());
+
+    for hook in hooks.iter() {
+        hook(action);
+    }
+
+    hal::local_irq_disable();
+
+    hal::console().flush().unwrap();
+
+    match action {
+        Shutdown::PowerOff | Shutdown::Halt => sched::poweroff(0),
+        Shutdown::Reboot => sched::reboot(),
+    };
+
+    panic!("Failed to shut the machine down !");
+}
+
+/// Handle a change of `control/shutdown`
+fn handle_request(path: &Path) {
+    let request = match path.read::<String>() {
+        Ok(request) => request,
+        Err(..) => return,
+    };
+
+    // The key is cleared once the request is seen, some toolstacks wait for
+    // that acknowledgement
+    if request.is_empty() || path.write("").is_err() {
+        return;
+    }
+
+    if request == "suspend" {
+        // The keys of the domain were recreated by the toolstack
+        if suspend::suspend() {
+            advertise();
+        }
+
+        return;
+    }
+
+    match Shutdown::from_str(&request) {
+        Some(action) => shutdown(action),
+        None => println!("Warning: Unknown shutdown request: {}", request),
+    }
+}
+
+/// Tell the toolstack which requests are handled
+fn advertise() {
+    let control = Path::home().join("control");
+
+    for feature in &["feature-poweroff", "feature-reboot", "feature-suspend"] {
+        if control.clone().join(feature).write(1).is_err() {
+            println!("Warning: Failed to advertise control/{}", feature);
+        }
+    }
+}
+
+/// Advertise the features supported and start watching for requests
+pub fn init() {
+    advertise();
+
+    let path = Path::home().join("control/shutdown");
+    let watch = path.to_cstring().and_then(move |cpath| {
+        XenStore::watch_with(cpath, "control-shutdown", move |_| {
+            handle_request(&path)
+        })
+    });
+
+    match watch {
+        Ok(watch) => *WATCH.lock() = Some(watch),
+        Err(..) => println!("Warning: Failed to watch control/shutdown"),
+    }
+}
@@ -37,6 +37,14 @@ struct EventData {
     pub data: *mut u8,
 }
 
+#[repr(C)]
+/// struct evtchn_bind_virq
+struct BindVirq {
+    virq: u32,
+    vcpu: u32,
+    port: EvtchnPort,
+}
+
 #[repr(C)]
 /// struct evtchn_alloc_unbound
 struct AllocUnbound {
@@ -123,6 +131,17 @@ impl Dispatcher {
         self.handlers[port as usize] = EventData::new(handler, data);
     }
 
+    /// Mask the event `port` and unregister its handler
+    ///
+    /// Once this returns, the handler will not be called anymore so the data
+    /// given to `bind_port` can be released.
+    pub fn unbind_port(&mut self, port: EvtchnPort) {
+        self.mask_event(port);
+
+        self.handlers[port as usize] =
+            EventData::new(Dispatcher::default_handler, null_mut());
+    }
+
     /// Allocate a new event `port` and register an `handler` for it
     ///
     /// This function basically allocates the port through Xen and calls
@@ -146,6 +165,41 @@ impl Dispatcher {
         Ok(op.port)
     }
 
+    /// Bind the virtual interrupt `virq` of vcpu 0 to a new event port and
+    /// register an `handler` for it
+    ///
+    /// The port is left masked.
+    pub fn bind_virq(&mut self, virq: u32, handler: EventHandler,
+                     data: *mut u8) -> Result<EvtchnPort, i32> {
+        let mut op = BindVirq {
+            virq: virq,
+            vcpu: 0,
+            port: 0,
+        };
+
+        let ret = event_channel_op(EventOp::BindVirq, &mut op);
+
+        if ret != 0 {
+            return Err(ret);
+        }
+
+        self.bind_port(op.port, handler, data);
+
+        Ok(op.port)
+    }
+
+    /// Mask all events and unregister all handlers
+    ///
+    /// This is meant for a restored domain, whose event channels are all
+    /// gone.
+    pub fn reset(&mut self) {
+        self.mask_all();
+
+        for handler in self.handlers.iter_mut() {
+            *handler = EventData::new(Dispatcher::default_handler, null_mut());
+        }
+    }
+
     /// Mask all events
     pub fn mask_all(&self) {
         let mut i: EvtchnPort = 0;
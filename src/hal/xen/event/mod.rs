@@ -27,6 +27,14 @@ pub fn init() {
     println!("Event subsystem initialized");
 }
 
+#[doc(hidden)]
+/// Reset the event subsystem in a restored domain
+pub fn resume() {
+    init_callbacks();
+
+    dispatcher().reset();
+}
+
 #[cfg(target_arch = "x86")]
 fn init_callbacks() {
     unsafe {
@@ -70,6 +78,11 @@ struct EvtchnSend {
     port: EvtchnPort,
 }
 
+#[repr(C)]
+struct EvtchnClose {
+    port: EvtchnPort,
+}
+
 #[inline]
 fn event_channel_op<T>(op: EventOp, event: *mut T) -> i32 {
     unsafe {
@@ -87,3 +100,14 @@ pub fn send(port: EvtchnPort) -> i32 {
 
     event_channel_op(EventOp::Send, &mut ev)
 }
+
+/// Close the event channel whose local endpoint is `port`
+///
+/// The port should be unbound from the dispatcher first.
+pub fn close(port: EvtchnPort) -> i32 {
+    let mut ev: EvtchnClose = EvtchnClose {
+        port: port,
+    };
+
+    event_channel_op(EventOp::Close, &mut ev)
+}
@@ -114,10 +114,28 @@ impl Table {
         Ok(())
     }
 
+    #[doc(hidden)]
+    /// Map the grant table of a restored domain
+    ///
+    /// The new table is empty, references allocated beforehand stay
+    /// allocated but grant nothing until access is granted again.
+    pub fn resume() -> Result<(), GntStatus> {
+        let table = TABLE.as_mut();
+
+        TableImpl::map_frames(table.grant_table as *mut u8, table.frame_count)
+    }
+
     /// Allocate a new entry in the Grant table
     pub fn alloc_ref() -> Option<Ref> {
         TABLE.as_mut().alloc_ref()
     }
+
+    /// Give an entry back to the Grant table
+    ///
+    /// Access to the entry *MUST* have been ended beforehand.
+    pub fn free_ref(r: Ref) {
+        TABLE.as_mut().free.lock().push_back(r);
+    }
 }
 
 #[repr(C)]
@@ -181,6 +199,7 @@ struct Entry {
 
 struct TableImpl {
     grant_table: *mut Entry,
+    frame_count: usize,
     free: InterruptSpinLock<VecDeque<Ref>>,
 }
 
@@ -193,8 +212,40 @@ unsafe impl Sync for TableImpl {}
 
 impl TableImpl {
     pub fn new() -> Result<Self, GntStatus> {
-        let ret;
         let frame_count = Self::get_max_frame_count();
+
+        // Allocate enough virtual space to map the grant table
+        //
+        // XXX: There is room for improvement here as this will remove
+        // some pages that could be used as heap memory. When we support
+        // allocating extra virtual memory, this should be used rather than
+        // using heap space.
+        let addr: *mut u8 = __rust_allocate(PAGE_SIZE * frame_count, PAGE_SIZE);
+
+        if addr == ptr::null_mut() {
+            return Err(GntStatus::GeneralError)
+        }
+
+        try!(Self::map_frames(addr, frame_count));
+
+        let count = Self::get_grant_entries_count();
+        let table = TableImpl {
+            grant_table: addr as *mut _,
+            frame_count: frame_count,
+            free: InterruptSpinLock::new(VecDeque::with_capacity(count)),
+        };
+
+        // Initialize free entries queue
+        for i in RESERVED_ENTRIES..count {
+            table.free.lock().push_back(Ref(i as u32));
+        }
+
+        Ok(table)
+    }
+
+    /// Get the frames of the grant table from Xen and map them at `addr`
+    fn map_frames(addr: *mut u8, frame_count: usize) -> Result<(), GntStatus> {
+        let ret;
         let mut frames: Vec<Mfn> = vec![Mfn::new(0); frame_count];
         let mut setup = GnttabSetupTable {
             dom: DOMID_SELF,
@@ -216,39 +267,10 @@ impl TableImpl {
             return Err(setup.status)
         }
 
-        // Allocate enough virtual space to map the grant table
-        //
-        // XXX: There is room for improvement here as this will remove
-        // some pages that could be used as heap memory. When we support
-        // allocating extra virtual memory, this should be used rather than
-        // using heap space.
-        let addr: *mut u8 = __rust_allocate(PAGE_SIZE * frame_count, PAGE_SIZE);
-
-        if addr == ptr::null_mut() {
-            return Err(GntStatus::GeneralError)
-        }
-
-        let vaddr = Vaddr::new(addr as usize);
-
         unsafe {
             // Map the grant table
-            match map_non_contiguous_mfn(vaddr, &frames[..]) {
-                Err(..) => Err(GntStatus::GeneralError),
-                Ok(..) => {
-                    let count = Self::get_grant_entries_count();
-                    let table = TableImpl {
-                        grant_table: addr as *mut _,
-                        free: InterruptSpinLock::new(VecDeque::with_capacity(count)),
-                    };
-
-                    // Initialize free entries queue
-                    for i in RESERVED_ENTRIES..count {
-                        table.free.lock().push_back(Ref(i as u32));
-                    }
-
-                    Ok(table)
-                }
-            }
+            map_non_contiguous_mfn(Vaddr::new(addr as usize), &frames[..])
+                .map_err(|_| GntStatus::GeneralError)
         }
     }
 
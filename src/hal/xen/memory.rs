@@ -1,9 +1,9 @@
-use core::mem;
+use core::{mem, ptr};
 
-use hal::mmu::Vaddr;
+use hal::mmu::{Vaddr, Maddr, Pfn, Mfn};
 use hal::arch::mmu::PageEntry;
 
-use hal::xen::hypercall::{hypercall3, hypercall4};
+use hal::xen::hypercall::{hypercall2, hypercall3, hypercall4};
 use hal::xen::hypercall::HypercallKind;
 
 use hal::xen::defs::{Ulong, DOMID_SELF};
@@ -56,3 +56,68 @@ pub unsafe fn mmu_update(updates: *const MmuUpdate, count: usize,
     hypercall4(HypercallKind::MmuUpdate, updates as Ulong, count as Ulong,
                done_out as Ulong, DOMID_SELF as Ulong) as i32
 }
+
+/// Update an entry of the M2P (the mfn to pfn table), see `mmu_update()`
+const MMU_MACHPHYS_UPDATE: u64 = 1;
+
+/// Record in the M2P that `mfn` backs `pfn`
+pub unsafe fn update_machphys(mfn: Mfn, pfn: Pfn) -> i32 {
+    let maddr = *Maddr::from(mfn) as u64;
+    let update = MmuUpdate::new(maddr | MMU_MACHPHYS_UPDATE, *pfn as u64);
+
+    mmu_update(&update, 1, ptr::null_mut())
+}
+
+#[allow(dead_code)]
+enum MemoryOp {
+    IncreaseReservation = 0,
+    DecreaseReservation = 1,
+    CurrentReservation = 3,
+    MaximumReservation = 4,
+    PopulatePhysmap = 6,
+}
+
+#[repr(C)]
+/// struct xen_memory_reservation
+struct MemoryReservation {
+    extent_start: *mut Mfn,
+    nr_extents: Ulong,
+    extent_order: u32,
+    mem_flags: u32,
+    domid: u16,
+}
+
+impl MemoryReservation {
+    fn new(extents: &mut [Mfn]) -> Self {
+        MemoryReservation {
+            extent_start: extents.as_mut_ptr(),
+            nr_extents: extents.len() as Ulong,
+            extent_order: 0,
+            mem_flags: 0,
+            domid: DOMID_SELF,
+        }
+    }
+}
+
+unsafe fn memory_op<T>(op: MemoryOp, arg: *mut T) -> i32 {
+    hypercall2(HypercallKind::MemoryOp, op as Ulong, arg as Ulong) as i32
+}
+
+/// Give the frames `mfns` back to Xen
+///
+/// The frames must not be mapped anymore. Returns the number of frames
+/// released, they are the first ones of `mfns`, or a negative error code.
+pub unsafe fn decrease_reservation(mfns: &mut [Mfn]) -> i32 {
+    let mut reservation = MemoryReservation::new(mfns);
+
+    memory_op(MemoryOp::DecreaseReservation, &mut reservation)
+}
+
+/// Ask Xen for `mfns.len()` new frames, they are stored in `mfns`
+///
+/// Returns the number of frames obtained or a negative error code.
+pub unsafe fn increase_reservation(mfns: &mut [Mfn]) -> i32 {
+    let mut reservation = MemoryReservation::new(mfns);
+
+    memory_op(MemoryOp::IncreaseReservation, &mut reservation)
+}
@@ -43,9 +43,14 @@ pub mod grant;
 pub mod store;
 pub mod event;
 pub mod sched;
+pub mod time;
 pub mod memory;
 pub mod console;
+pub mod control;
+pub mod balloon;
+pub mod suspend;
 #[cfg(feature = "net")] pub mod net;
+#[cfg(feature = "blk")] pub mod blk;
 
 extern "C" {
     // This symbol must be present in code using libxen
@@ -110,10 +115,16 @@ pub extern "C" fn uni_rust_entry() -> ! {
 
     boot::init_memory();
 
+    boot::init_p2m();
+
     grant::Table::init().expect("Fail to initialize Xen's Grant Table");
 
     event::init();
 
+    time::init();
+
+    ::time::init();
+
     unsafe {
         console::console().init_input();
         XenStore::init_event();
@@ -125,6 +136,10 @@ pub extern "C" fn uni_rust_entry() -> ! {
     Scheduler::spawn(|| {
         println!("Main thread started");
 
+        control::init();
+
+        balloon::init();
+
         net_init();
 
         println!("Uni.rs is now ready");
@@ -8,18 +8,19 @@ use alloc_uni::{__rust_allocate, __rust_deallocate};
 use boxed::Box;
 
 use vec::Vec;
-use string::ToString;
+use string::{String, ToString};
 
 use sync::spin::{InterruptSpinLock, SpinLock};
 
 use ffi::CString;
 
 use net::{Instance, Interface, InterfaceWeak, Packet, Stack as NetStack};
-use net::defs::{Device, HwAddr};
+use net::{V4Configuration, V4Source};
+use net::defs::{Device, HwAddr, Ipv4Addr};
 
 use hal::mmu::{Vaddr, Mfn};
 
-use hal::xen::store::{XenStore, XenbusState};
+use hal::xen::store::{XenStore, XenbusState, Watch};
 use hal::xen::grant::{Table as GrantTable, Ref as GrantRef};
 use hal::xen::ring::{SharedRing, FrontRing, Idx as RingIdx};
 
@@ -29,6 +30,17 @@ use hal::arch::defs::PAGE_SIZE;
 use hal::arch::utils::{rmb, wmb};
 
 use hal::xen::event;
+use hal::xen::suspend;
+
+use thread::Scheduler;
+
+use time::{Duration, Instant};
+
+/// Time given to a backend to close when a vif is removed
+const CLOSE_TIMEOUT_SECS: u64 = 5;
+
+/// Delay between two scans of the vifs when they cannot be watched
+const POLL_PERIOD_MS: u64 = 1000;
 
 #[repr(i16)]
 #[derive(Debug, PartialEq)]
@@ -111,50 +123,295 @@ type RxSharedRing = SharedRing<NetifRxRequest, NetifRxResponse>;
 type TxFrontRing = FrontRing<NetifTxRequest, NetifTxResponse>;
 type RxFrontRing = FrontRing<NetifRxRequest, NetifRxResponse>;
 
-/// Verify if a vif (virtual interface) with id `id` exists
-fn vif_id_exists(id: u32) -> bool {
-    let mut t = XenStore::start_transaction().unwrap();
-    let path = CString::new(format!("device/vif/{}", id)).unwrap();
+/// Returns the ids of the vifs (virtual interfaces) present in the Xen Store
+fn vif_ids() -> Vec<u32> {
+    let path = CString::new("device/vif").unwrap();
+
+    match XenStore::directory_list(path) {
+        Ok(list) => list.iter().filter_map(|id| u32::from_str(id).ok())
+                               .collect(),
+        Err(..) => Vec::new(),
+    }
+}
+
+/// Read the state of the backend of a vif
+fn vif_backend_state(backend: &str) -> Option<XenbusState> {
+    let path = CString::new(format!("{}/state", backend)).unwrap();
+
+    match XenStore::read_value::<u8>(path) {
+        Ok(state) if state <= XenbusState::Reconfigured as u8 => {
+            Some(unsafe { mem::transmute(state) })
+        }
+        _ => None,
+    }
+}
+
+/// Read the key `key` of a vif, first in the frontend directory `vif_root`
+/// then in the backend's one
+fn vif_read_key(vif_root: &str, key: &str) -> Option<String> {
+    let front_path = CString::new(format!("{}/{}", vif_root, key)).unwrap();
+
+    if let Ok(val) = XenStore::read_value::<String>(front_path) {
+        return Some(val);
+    }
+
+    let backend_path = CString::new(format!("{}/backend", vif_root)).unwrap();
+    let backend = match XenStore::read_value::<String>(backend_path) {
+        Ok(backend) => backend,
+        Err(..) => return None,
+    };
+
+    let back_path = CString::new(format!("{}/{}", backend, key)).unwrap();
+
+    XenStore::read_value::<String>(back_path).ok()
+}
 
-    let res = match t.read(path) {
-        Ok(..) => true,
-        Err(..) => false,
+/// Read the static IPv4 configuration published by the toolstack for a vif
+///
+/// The `ip` key may hold a space separated list of addresses, only the first
+/// one is used. It can carry a prefix length (`a.b.c.d/len`) used when no
+/// `netmask` key is present. Without any of them the mask is left to 0.0.0.0
+/// and every destination is considered on link. `gateway` is optional.
+fn vif_v4_configuration(vif_root: &str) -> Option<V4Configuration> {
+    let ip = match vif_read_key(vif_root, "ip") {
+        Some(ip) => ip,
+        None => return None,
     };
 
-    t.end().unwrap();
+    let mut conf = V4Configuration::unconfigured();
+
+    {
+        let first = match ip.split_whitespace().next() {
+            Some(first) => first,
+            None => return None,
+        };
+
+        let mut split = first.splitn(2, '/');
+
+        conf.ipv4 = match split.next().map(Ipv4Addr::from_str) {
+            Some(Ok(addr)) => addr,
+            _ => return None,
+        };
+
+        if let Some(Ok(len)) = split.next().map(u8::from_str) {
+            conf.ipv4_mask = Ipv4Addr::from_prefix_len(len);
+        }
+    }
+
+    if let Some(Ok(mask)) = vif_read_key(vif_root, "netmask")
+                            .map(|m| Ipv4Addr::from_str(m.trim())) {
+        conf.ipv4_mask = mask;
+    }
+
+    if let Some(Ok(gw)) = vif_read_key(vif_root, "gateway")
+                          .map(|g| Ipv4Addr::from_str(g.trim())) {
+        conf.ipv4_gateway = gw;
+    }
+
+    conf.source = V4Source::XenStore;
+
+    Some(conf)
+}
+
+/// Create an interface backed by the vif with id `id`
+fn probe(instance: &Instance, id: u32) -> Option<Interface> {
+    let interface = Interface::new(instance);
+
+    // Instantiate the Xen backend
+    match XenNetDevice::new(id, interface.downgrade()) {
+        Ok(i) => {
+            // Set it as pv_device of the interface
+            interface.write().pv_device_set(i);
+
+            Some(interface)
+        }
+        Err(..) => {
+            println!("Warning: Impossible to initialize xen network interface {}",
+                     id);
+
+            None
+        }
+    }
+}
+
+/// Replace the devices of the interfaces of `instance` that were connected
+/// before the domain was restored by new ones
+///
+/// The interfaces themselves, and thus their configuration, are kept.
+fn reconnect(instance: &Instance) {
+    let interfaces: Vec<Interface> = instance.interfaces().iter()
+                                             .cloned().collect();
+
+    for intf in interfaces {
+        let id = match intf.read().pv_device_ref() {
+            Some(dev) if dev.is_stale() => dev.vif_id(),
+            _ => continue,
+        };
+
+        intf.set_oper_up(false);
+
+        match XenNetDevice::new(id, intf.downgrade()) {
+            // The stale device goes away without talking to any backend
+            Ok(dev) => intf.write().pv_device_set(dev),
+            Err(..) => {
+                println!("Warning: Impossible to reconnect xen network interface {}",
+                         id);
+
+                instance.remove_interface(&intf);
+            }
+        }
+    }
+}
+
+/// Wait for the backends of the interfaces of `instance` to be done with
+/// the packets transmitted before the domain is suspended
+///
+/// Nothing is transmitted meanwhile, see `XenNetDevice::tx_packet()`.
+fn quiesce(instance: &Instance) {
+    let interfaces: Vec<Interface> = instance.interfaces().iter()
+                                             .cloned().collect();
+
+    let idle = suspend::wait_idle(|| {
+        interfaces.iter().all(|intf| {
+            let mut intf = intf.write();
+
+            intf.refresh();
+
+            let in_flight = intf.pv_device_ref().map_or(false, |dev| {
+                dev.tx_in_flight()
+            });
+
+            !in_flight
+        })
+    });
 
-    res
+    if !idle {
+        println!("Warning: Packets of xen network interfaces still in flight");
+    }
 }
 
 /// Returns a list of interfaces that have a xen backend
+///
+/// The interfaces are quiesced before the domain is suspended and
+/// reconnected to their backend whenever it is restored.
 pub fn discover(instance: &Instance) -> Vec<Interface> {
-    let mut id = 0;
-    let mut v = Vec::new();
+    // The network stack lives as long as the domain
+    mem::forget(suspend::on_suspend(|| quiesce(&NetStack::instance())));
+    mem::forget(suspend::on_resume(|| reconnect(&NetStack::instance())));
 
     // Create interface for every `id` valid
-    while vif_id_exists(id) {
-        let interface = Interface::new(instance);
-        let interface_weak = interface.downgrade();
+    vif_ids().into_iter().filter_map(|id| probe(instance, id)).collect()
+}
 
-        v.push(interface);
+/// Watch on the vifs of the domain
+///
+/// Fires when a vif is attached or detached.
+pub struct HotplugWatch(Option<Watch>);
 
-        // Instantiate the Xen backend
-        match XenNetDevice::new(id, interface_weak) {
-            Ok(i) => {
-                // Set it as pv_device of the interface
-                v.last().unwrap().write().pv_device_set(i);
-            }
-            Err(..) => {
-                v.pop();
-                println!("Warning: Impossible to initialize xen network interface {}",
-                         id);
+impl HotplugWatch {
+    pub fn new() -> Self {
+        let path = CString::new("device/vif").unwrap();
+
+        HotplugWatch(XenStore::watch(path, "netfront-hotplug").ok())
+    }
+
+    /// Block until a vif is attached or detached
+    ///
+    /// The vifs are polled every `POLL_PERIOD_MS` if the watch could not be
+    /// registered.
+    pub fn wait(&self) {
+        match self.0 {
+            Some(ref watch) => {
+                watch.wait();
             }
+            None => Scheduler::sleep(Duration::from_millis(POLL_PERIOD_MS)),
         }
+    }
+}
+
+/// Follow the state of the backend of the interface `intf`
+///
+/// The link is up as long as the backend is connected. The interface is
+/// removed once the backend closes, which the toolstack does before removing
+/// the vif.
+fn backend_state_changed(intf: &InterfaceWeak, backend: &str) {
+    let intf = match intf.upgrade() {
+        Some(intf) => intf,
+        None => return,
+    };
 
-        id += 1;
+    match vif_backend_state(backend) {
+        Some(XenbusState::Connected) => intf.set_oper_up(true),
+        Some(XenbusState::Closing) |
+        Some(XenbusState::Closed) |
+        None => {
+            intf.set_oper_up(false);
+            NetStack::instance().remove_interface(&intf);
+        }
+        Some(..) => intf.set_oper_up(false),
     }
+}
 
-    v
+/// Add interfaces for vifs attached since the last scan and remove those
+/// whose vif was detached
+pub fn rescan(instance: &Instance) {
+    let ids = vif_ids();
+    let mut known = Vec::new();
+    let mut removed = Vec::new();
+
+    // Querying the Xen Store yields the CPU, which must not happen while
+    // holding locks other threads spin on
+    let devices: Vec<(Interface, String, u32)> = {
+        instance.interfaces().iter().filter_map(|intf| {
+            intf.read().pv_device_ref().map(|dev| {
+                (intf.clone(), dev.backend.clone(), dev.vif_id())
+            })
+        }).collect()
+    };
+
+    for (intf, backend, id) in devices {
+        known.push(id);
+
+        let state = vif_backend_state(&backend);
+
+        // The toolstack closes the backend before removing the vif
+        let closing = match state {
+            None => true,
+            Some(ref state) => *state == XenbusState::Closing ||
+                               *state == XenbusState::Closed,
+        };
+
+        if closing || !ids.contains(&id) {
+            removed.push(intf);
+        }
+    }
+
+    for intf in &removed {
+        instance.remove_interface(intf);
+    }
+
+    for id in ids {
+        if known.contains(&id) {
+            continue;
+        }
+
+        // Only pick vifs whose backend waits for us. This also skips vifs
+        // being removed
+        let backend_path = CString::new(format!("device/vif/{}/backend", id))
+                           .unwrap();
+
+        let ready = match XenStore::read_value::<String>(backend_path) {
+            Ok(backend) => vif_backend_state(&backend) ==
+                           Some(XenbusState::InitWait),
+            Err(..) => false,
+        };
+
+        if ready {
+            if let Some(intf) = probe(instance, id) {
+                instance.add_interface(intf);
+            }
+        }
+    }
 }
 
 /// Buffer allocated for packet reception
@@ -173,6 +430,8 @@ struct TxBuffer {
 
 /// A Xen vif (virtual interface)
 pub struct XenNetDevice {
+    id: u32,
+    backend: String,
     evtchn: EvtchnPort,
     backend_id: u16,
     tx_ring: TxFrontRing,
@@ -180,16 +439,33 @@ pub struct XenNetDevice {
     tx_buffer: SpinLock<Vec<TxBuffer>>,
     rx_buffer: InterruptSpinLock<Vec<RxBuffer>>,
     intf: InterfaceWeak,
+    /// Value of `suspend::generation()` when the device connected
+    generation: usize,
+    /// Watch on the state of the backend, driving the link state
+    state_watch: Option<Watch>,
+    /// True once the device reached the connected state. The backend has
+    /// nothing to close before that
+    connected: bool,
 }
 
 impl Device for XenNetDevice {
     fn refresh(&mut self) {
+        if self.is_stale() {
+            return;
+        }
+
         self.refresh_tx_ring();
         self.refresh_rx_ring();
     }
 
     /// Transmit a packet over the network
     fn tx_packet(&mut self, pkt: Packet) {
+        // The packet is dropped while the domain is suspending and until the
+        // device is reconnected
+        if self.is_stale() || suspend::is_suspending() {
+            return;
+        }
+
         // Find a free TX buffer
         for b in &mut *self.tx_buffer.lock() {
             // Nope that one is taken
@@ -242,6 +518,61 @@ impl Device for XenNetDevice {
 }
 
 impl XenNetDevice {
+    #[inline]
+    /// Returns the id of the vif backing the device
+    pub fn vif_id(&self) -> u32 {
+        self.id
+    }
+
+    #[inline]
+    /// Returns true if the device was connected in a previous incarnation
+    /// of the domain, i.e. before it was restored
+    fn is_stale(&self) -> bool {
+        self.generation != suspend::generation()
+    }
+
+    /// Returns true if the backend still owns transmitted packets
+    fn tx_in_flight(&self) -> bool {
+        self.tx_buffer.lock().iter().any(|b| b.pkt.is_some())
+    }
+
+    /// Tell the backend we are going away and wait for it to stop using the
+    /// rings and buffers
+    fn disconnect(&self) {
+        let state_path = CString::new(format!("device/vif/{}/state", self.id))
+                         .unwrap();
+
+        if let Ok(mut t) = XenStore::start_transaction() {
+            let _ = t.switch_state(state_path.clone(), XenbusState::Closing);
+            let _ = t.end();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(CLOSE_TIMEOUT_SECS);
+        let backend_path = CString::new(format!("{}/state", self.backend))
+                           .unwrap();
+        let watch = XenStore::watch(backend_path, "netfront-state").ok();
+
+        loop {
+            match vif_backend_state(&self.backend) {
+                Some(XenbusState::Closing) |
+                Some(XenbusState::Closed) |
+                None => break,
+                Some(..) => {
+                    let timeout = deadline - Instant::now();
+                    let changed = watch.as_ref().and_then(|w| {
+                        w.wait_timeout(timeout)
+                    });
+
+                    if changed.is_none() {
+                        println!("Warning: Backend of xen network interface {} did not close",
+                                 self.id);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
     /// Callback handling Xen network events
     fn device_callback(_: EvtchnPort, data: *mut u8) {
         let xen_dev = unsafe { &mut (*(data as *mut XenNetDevice)) };
@@ -263,6 +594,10 @@ impl XenNetDevice {
         let backend_path = try!(CString::new(format!("{}/backend-id", vif_root)));
         let backend_id = try!(XenStore::read_value::<u16>(backend_path));
 
+        // Path of the backend's directory, used to follow its state
+        let backend_dir_path = try!(CString::new(format!("{}/backend", vif_root)));
+        let backend = try!(XenStore::read_value::<String>(backend_dir_path));
+
         // Create RX and TX shared ring
         let mut tx_sring = try!(TxSharedRing::new(PAGE_SIZE).ok_or(()));
         let mut rx_sring = try!(RxSharedRing::new(PAGE_SIZE).ok_or(()));
@@ -272,6 +607,8 @@ impl XenNetDevice {
         let rx_ref = try!(rx_sring.grant_access(backend_id).ok_or(()));
 
         let mut xen_dev = Box::new(XenNetDevice {
+            id: id,
+            backend: backend,
             evtchn: 0,
             backend_id: backend_id,
             tx_ring: TxFrontRing::new(tx_sring),
@@ -279,6 +616,9 @@ impl XenNetDevice {
             tx_buffer: SpinLock::new(Vec::new()),
             rx_buffer: InterruptSpinLock::new(Vec::new()),
             intf: intf,
+            generation: suspend::generation(),
+            state_watch: None,
+            connected: false,
         });
 
         // This is legit as the event will be gone before the XenNetDevice
@@ -332,16 +672,18 @@ impl XenNetDevice {
         try!(t.end());
 
         // Wait for the device to be connected
-        // XXX: Add xen store watch implementation to avoid busy wait
+        let watch = try!(XenStore::watch(state_path.clone(), "netfront-state"));
+
         loop {
             let state: XenbusState = unsafe {
                 mem::transmute(try!(XenStore::read_value::<u8>(state_path.clone())))
             };
 
             if state == XenbusState::Connected {
+                xen_dev.connected = true;
                 break;
             } else if state < XenbusState::Connected {
-                ::hal::xen::sched::yield_cpu();
+                watch.wait();
                 continue;
             } else {
                 return Err(());
@@ -361,11 +703,38 @@ impl XenNetDevice {
         *parent.write().name_mut() = format!("xen{}", id);
         *parent.write().hw_addr_mut() = hw_addr;
 
+        // The backend is connected, so is the link
+        parent.set_oper_up(true);
+
+        // From now on the link follows the state of the backend
+        let backend_state_path = try!(CString::new(format!("{}/state",
+                                                           xen_dev.backend)));
+        let (weak, backend) = (xen_dev.intf.clone(), xen_dev.backend.clone());
+
+        xen_dev.state_watch = {
+            Some(try!(XenStore::watch_with(backend_state_path, "netback-state",
+                                           move |_| {
+                backend_state_changed(&weak, &backend)
+            })))
+        };
+
+        // Use the static configuration published by the toolstack if any.
+        // The command line may still override it afterward, see V4Source
+        if let Some(conf) = vif_v4_configuration(&vif_root) {
+            parent.write().v4_configuration_mut().update(conf);
+        }
+
         Ok(xen_dev)
     }
 
+    /// Give every rx buffer to the backend
+    ///
+    /// On failure, the buffers set up so far are kept in `rx_buffer` to be
+    /// freed when the device is dropped.
     fn init_rx(&mut self) -> Result<(), ()> {
-        let mut v: Vec<RxBuffer> = Vec::with_capacity(self.rx_ring.size());
+        let mut v = self.rx_buffer.lock();
+
+        v.reserve(self.rx_ring.size());
 
         // We populate the rx ring with RxRequest. These requests give to the
         // backend some pages to work with in order to send us some packets
@@ -394,7 +763,13 @@ impl XenNetDevice {
             }
 
             // Grant access to the page to the backend
-            let grant = try!(GrantTable::alloc_ref().ok_or(()));
+            let grant = match GrantTable::alloc_ref() {
+                Some(grant) => grant,
+                None => {
+                    __rust_deallocate(page, PAGE_SIZE, PAGE_SIZE);
+                    return Err(());
+                }
+            };
 
             grant.grant_access(self.backend_id,
                                Mfn::from(Vaddr::from_ptr(page)), false);
@@ -413,6 +788,8 @@ impl XenNetDevice {
             });
         }
 
+        mem::drop(v);
+
         // Update ring's request production index
         unsafe {
             *self.rx_ring.req_prod_mut() += self.rx_ring.size() as RingIdx;
@@ -431,13 +808,17 @@ impl XenNetDevice {
             self.rx_ring.sring_mut().rsp_event_set(rsp_event);
         }
 
-        self.rx_buffer = InterruptSpinLock::new(v);
-
         Ok(())
     }
 
+    /// Set up the tx buffers
+    ///
+    /// On failure, the buffers set up so far are kept in `tx_buffer` to be
+    /// freed when the device is dropped.
     fn init_tx(&mut self) -> Result<(), ()> {
-        let mut v: Vec<TxBuffer> = Vec::with_capacity(self.tx_ring.size());
+        let mut v = self.tx_buffer.lock();
+
+        v.reserve(self.tx_ring.size());
 
         // We internally initialize empty buffers.
         // The way TX works with Xen is as follow:
@@ -467,8 +848,6 @@ impl XenNetDevice {
             v.push(buffer);
         }
 
-        self.tx_buffer = SpinLock::new(v);
-
         Ok(())
     }
 
@@ -618,3 +997,64 @@ impl XenNetDevice {
         }
     }
 }
+
+impl Drop for XenNetDevice {
+    fn drop(&mut self) {
+        // The state changes of the backend caused by the disconnection are
+        // none of the interface's business
+        mem::drop(self.state_watch.take());
+
+        // The backend and the event channel of a stale device are gone with
+        // the previous domain
+        let stale = self.is_stale();
+
+        if !stale && self.connected {
+            self.disconnect();
+        }
+
+        // Port 0 is never allocated, it means initialization failed before
+        // the event channel was set up
+        if self.evtchn != 0 && !stale {
+            event::dispatcher().unbind_port(self.evtchn);
+            event::close(self.evtchn);
+        }
+
+        for b in self.rx_buffer.lock().drain(..) {
+            let mut grant_ref = b.grant_ref;
+
+            grant_ref.end_access();
+            GrantTable::free_ref(grant_ref);
+
+            if !b.page.is_null() {
+                __rust_deallocate(b.page, PAGE_SIZE, PAGE_SIZE);
+            }
+        }
+
+        for b in self.tx_buffer.lock().drain(..) {
+            let mut grant_ref = b.grant_ref;
+
+            grant_ref.end_access();
+            GrantTable::free_ref(grant_ref);
+
+            mem::drop(b.pkt);
+        }
+
+        unsafe {
+            self.tx_ring.sring_mut().release();
+            self.rx_ring.sring_mut().release();
+        }
+
+        // The vif of a stale device belongs to the previous domain
+        if stale {
+            return;
+        }
+
+        let state_path = CString::new(format!("device/vif/{}/state", self.id))
+                         .unwrap();
+
+        if let Ok(mut t) = XenStore::start_transaction() {
+            let _ = t.switch_state(state_path, XenbusState::Closed);
+            let _ = t.end();
+        }
+    }
+}
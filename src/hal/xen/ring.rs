@@ -1,12 +1,12 @@
 //! Implementation of Xen's ring mechanism
 
-use core::{mem, cmp};
+use core::{mem, cmp, ptr};
 
 use core::marker::PhantomData;
 
 use rlibc::memset;
 
-use alloc_uni::__rust_allocate;
+use alloc_uni::{__rust_allocate, __rust_deallocate};
 
 use hal::mmu::{Vaddr, Mfn};
 
@@ -85,7 +85,7 @@ struct XenSharedRing {
     ring: u8, // Variable length
 }
 
-// XXX: deallocate/end grant access on Drop ?
+// XXX: deallocate/end grant access on Drop ? For now see `release()`
 /// Xen's shared ring
 ///
 /// `Req` is the type of the request
@@ -241,6 +241,22 @@ impl<Req, Resp> SharedRing<Req, Resp> {
         }
     }
 
+    /// End the access granted to the foreign domain and free the ring
+    ///
+    /// This *MUST* only be called on rings allocated with `new()`, once the
+    /// foreign domain stopped using it. The ring cannot be used afterward.
+    pub unsafe fn release(&mut self) {
+        if let Some(mut r) = self.grant_ref.take() {
+            r.end_access();
+            GrantTable::free_ref(r);
+        }
+
+        if !self.sring.is_null() {
+            __rust_deallocate(self.sring as *mut u8, self.size, PAGE_SIZE);
+            self.sring = ptr::null_mut();
+        }
+    }
+
     #[inline]
     fn ptr_from_index(&mut self, mut idx: usize) -> *mut u8 {
         idx = idx & (self.size() - 1);
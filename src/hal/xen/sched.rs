@@ -1,4 +1,4 @@
-use hal::xen::hypercall::hypercall2;
+use hal::xen::hypercall::{hypercall2, hypercall3};
 use hal::xen::hypercall::HypercallKind;
 
 use hal::xen::defs::Ulong;
@@ -59,6 +59,26 @@ pub fn crash() -> i32 {
     shutdown(ShutdownReason::Crash)
 }
 
+/// Suspend the domain, `start_info_mfn` names the frame of the start info
+/// page which the toolstack updates on resume
+///
+/// Returns 0 when the domain resumes in a new domain (e.g. after a migration)
+/// and a positive value when the suspend was cancelled.
+pub fn suspend(start_info_mfn: Ulong) -> i32 {
+    let r = SchedShutdown {
+        reason: ShutdownReason::Suspend as u32,
+    };
+
+    unsafe {
+        hypercall3(HypercallKind::SchedOp, SchedOp::Shutdown as Ulong,
+                   &r as *const SchedShutdown as Ulong, start_info_mfn) as i32
+    }
+}
+
+pub fn reboot() -> i32 {
+    shutdown(ShutdownReason::Reboot)
+}
+
 pub fn poweroff(status: Ulong) -> i32 {
     let ret: i32 = shutdown_code(status);
 
@@ -1,12 +1,16 @@
 use core::mem;
 use core::slice;
+use core::str::FromStr;
 
 use vec::Vec;
-use string::String;
+use string::{String, ToString};
+
+use ffi::CString;
 
 use thread::{Scheduler, WaitQueue};
 
-use sync::spin::InterruptSpinLock;
+use sync::Arc;
+use sync::spin::{InterruptSpinLock, SpinLock};
 
 use hal::arch::utils::wmb;
 
@@ -15,7 +19,9 @@ use hal::xen::event::{dispatcher, send};
 use hal::xen::defs::XENSTORE_RING_SIZE;
 use hal::xen::defs::{XenstoreInterface, EvtchnPort};
 
-use hal::xen::store::{Result, Error};
+use hal::xen::store::{Result, Error, Permission};
+
+use super::watch::WatchInner;
 
 const REQ_ID_COUNT: usize = 20;
 
@@ -72,6 +78,7 @@ pub struct XenStoreImpl {
     req_lock: InterruptSpinLock<()>,
     id_pool: RequestIdPool,
     req_pool: [Request; REQ_ID_COUNT],
+    watches: SpinLock<Vec<Arc<WatchInner>>>,
 }
 
 unsafe impl Sync for XenStoreImpl {}
@@ -104,7 +111,8 @@ impl XenStoreImpl {
                        Request::empty(),
                        Request::empty(),
                        Request::empty(),
-                       Request::empty()]
+                       Request::empty()],
+            watches: SpinLock::new(Vec::new()),
         }
     }
 
@@ -149,10 +157,6 @@ impl XenStoreImpl {
             let req_id = resp.req_id as usize;
             let size = resp.len as usize;
 
-            // Store the message in the pool to be fetched by the waiting
-            // thread
-            store.req_pool[req_id].reply_msg = resp;
-
             let mut data = vec![0; size];
 
             if size > 0 {
@@ -160,16 +164,67 @@ impl XenStoreImpl {
                 store.read_response_bytes(&mut data[..]);
             }
 
-            // Store the data in the pool to be fetched by the waiting thread
-            store.req_pool[req_id].reply_data = data;
-
-            // Unblock the thread that was waiting for a response
-            store.req_pool[req_id].wait.unblock();
+            match resp.msg_type {
+                // Watch events are not replies to any request
+                XsdSockmsgType::WatchEvent => store.fire_watches(data),
+                _ => {
+                    // Store the message and its data in the pool to be
+                    // fetched by the waiting thread
+                    store.req_pool[req_id].reply_msg = resp;
+                    store.req_pool[req_id].reply_data = data;
+
+                    // Unblock the thread that was waiting for a response
+                    store.req_pool[req_id].wait.unblock();
+                }
+            }
 
             send(store.port);
         }
     }
 
+    /// Deliver a watch event to the watches it belongs to
+    ///
+    /// The payload of the event is the path that changed followed by the
+    /// token of the watch, both nul terminated.
+    fn fire_watches(&self, data: Vec<u8>) {
+        let to_string = |s: &[u8]| String::from_utf8(s.to_vec()).ok();
+        let mut parts = data.split(|c| *c == 0);
+
+        let path = parts.next().and_then(&to_string);
+        let token = parts.next().and_then(&to_string);
+
+        if let (Some(path), Some(token)) = (path, token) {
+            for watch in self.watches.lock().iter() {
+                if watch.matches(&path, &token) {
+                    watch.push(path.clone());
+                }
+            }
+        }
+    }
+
+    pub fn add_watch(&self, watch: Arc<WatchInner>) {
+        self.watches.lock().push(watch);
+    }
+
+    pub fn remove_watch(&self, watch: &Arc<WatchInner>) {
+        let ptr = &**watch as *const WatchInner;
+
+        self.watches.lock().retain(|w| &**w as *const WatchInner != ptr);
+    }
+
+    /// Register again all the watches to the Xen Store daemon
+    ///
+    /// The daemon forgets about the watches of a domain when it is resumed.
+    pub fn restore_watches(&self) -> Result<()> {
+        let watches = self.watches.lock().clone();
+
+        for watch in watches.iter() {
+            try!(watch.register());
+        }
+
+        Ok(())
+    }
+
     fn write_request_bytes(&mut self, bytes: &[u8]) {
         let mut lock;
         let mut prod;
@@ -238,6 +293,17 @@ impl XenStoreImpl {
         });
     }
 
+    /// Reconnect to the Xen Store of a restored domain through the event
+    /// channel `port`
+    pub fn resume(&mut self, port: EvtchnPort) {
+        self.port = port;
+
+        dispatcher().bind_port(self.port, Self::xen_store_callback,
+                               self as *mut _ as *mut u8);
+        dispatcher().unmask_event(self.port);
+        send(self.port);
+    }
+
     pub fn send(&mut self, mut req: RequestBuilder) -> Result<Vec<u8>> {
         let req_id = self.id_pool.alloc() as usize;
 
@@ -273,6 +339,87 @@ impl XenStoreImpl {
     }
 }
 
+/// Operations on the nodes of the store
+///
+/// `tx_id` is the transaction the operation belongs to, 0 if none.
+impl XenStoreImpl {
+    fn node_request(&mut self, tx_id: u32, msg_type: XsdSockmsgType,
+                    path: &CString) -> Result<Vec<u8>> {
+        let req = {
+            RequestBuilder::new(tx_id).set_msg_type(msg_type)
+                                      .append_data(path.as_bytes_with_nul())
+        };
+
+        self.send(req)
+    }
+
+    pub fn directory(&mut self, tx_id: u32, path: &CString)
+        -> Result<Vec<String>> {
+        self.node_request(tx_id, XsdSockmsgType::Directory, path)
+            .and_then(split_strings)
+    }
+
+    pub fn read(&mut self, tx_id: u32, path: &CString) -> Result<String> {
+        self.node_request(tx_id, XsdSockmsgType::Read, path)
+            .and_then(|data| String::from_utf8(data)
+                                    .map_err(|_| Error::Conversion))
+    }
+
+    pub fn write(&mut self, tx_id: u32, path: &CString,
+                 value: &CString) -> Result<()> {
+        let req = {
+            RequestBuilder::new(tx_id).set_msg_type(XsdSockmsgType::Write)
+                                      .append_data(path.as_bytes_with_nul())
+                                      .append_data(value.as_bytes())
+        };
+
+        self.send(req).map(|_| ())
+    }
+
+    pub fn mkdir(&mut self, tx_id: u32, path: &CString) -> Result<()> {
+        self.node_request(tx_id, XsdSockmsgType::Mkdir, path).map(|_| ())
+    }
+
+    pub fn rm(&mut self, tx_id: u32, path: &CString) -> Result<()> {
+        self.node_request(tx_id, XsdSockmsgType::Remove, path).map(|_| ())
+    }
+
+    pub fn get_perms(&mut self, tx_id: u32, path: &CString)
+        -> Result<Vec<Permission>> {
+        let list = try!(self.node_request(tx_id, XsdSockmsgType::GetPerms,
+                                          path).and_then(split_strings));
+
+        list.iter().map(|p| Permission::from_str(p)).collect()
+    }
+
+    pub fn set_perms(&mut self, tx_id: u32, path: &CString,
+                     perms: &[Permission]) -> Result<()> {
+        let mut req = {
+            RequestBuilder::new(tx_id).set_msg_type(XsdSockmsgType::SetPerms)
+                                      .append_data(path.as_bytes_with_nul())
+        };
+
+        for perm in perms {
+            req = req.append_data(perm.to_string().as_bytes())
+                     .append_data(&[0]);
+        }
+
+        self.send(req).map(|_| ())
+    }
+}
+
+/// Split a reply made of nul separated strings
+fn split_strings(data: Vec<u8>) -> Result<Vec<String>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The trailing nul was already stripped by `send()`
+    data.split(|c| *c == 0).map(|s| {
+        String::from_utf8(s.to_vec()).map_err(|_| Error::Conversion)
+    }).collect()
+}
+
 #[repr(u32)]
 #[derive(Clone, Copy)]
 #[allow(dead_code)]
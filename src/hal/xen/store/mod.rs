@@ -3,20 +3,33 @@
 use core::result;
 use core::str::FromStr;
 
+use vec::Vec;
+use string::{String, ToString};
+
 use ffi::CString;
 
 use cell::GlobalCell;
 
+use thread::{Blocking, spawn_blocking};
+
 use self::imp::XenStoreImpl;
 
 use hal::xen::defs::{XenstoreInterface, EvtchnPort};
 
+use self::imp::{RequestBuilder, XsdSockmsgType};
+
 pub use self::transaction::Transaction;
+pub use self::watch::{Watch, Events};
+pub use self::perms::{Permission, Access};
+pub use self::path::Path;
 
 static STORE: GlobalCell<XenStoreImpl> = GlobalCell::new();
 
 mod imp;
 mod transaction;
+mod watch;
+mod perms;
+mod path;
 
 /// Result of an operation within the Xen Store
 pub type Result<T> = result::Result<T, Error>;
@@ -94,19 +107,164 @@ impl XenStore {
         STORE.as_mut().init_event();
     }
 
+    #[doc(hidden)]
+    pub unsafe fn resume(port: EvtchnPort) {
+        STORE.as_mut().resume(port);
+    }
+
+    #[doc(hidden)]
+    pub fn restore_watches() -> Result<()> {
+        STORE.as_mut().restore_watches()
+    }
+
+    /// Watch `path` and its children for changes
+    ///
+    /// `token` is handed back by the Xen Store daemon with every event and
+    /// helps telling watches apart.
+    pub fn watch(path: CString, token: &str) -> Result<Watch> {
+        Watch::new(path, token)
+    }
+
+    /// Same as `watch()` but calls `callback` with the path that changed on
+    /// every event instead
+    ///
+    /// The callback is run on a dedicated thread until the returned watch is
+    /// dropped.
+    pub fn watch_with<F>(path: CString, token: &str, callback: F)
+        -> Result<Watch> where F: FnMut(&str) + Send + 'static {
+        Self::watch(path, token).map(|w| w.spawn_callback(callback))
+    }
+
     /// Start a new transaction within the Xen Store
     pub fn start_transaction<'a>() -> Result<Transaction<'a>> {
         Transaction::new(STORE.as_mut())
     }
 
-    /// Read a value from the xen store and convert it
-    pub fn read_value<T: FromStr>(path: CString) -> Result<T> {
-        let mut t = try!(Self::start_transaction());
+    /// Run `f` within a transaction, again and again as long as the
+    /// transaction fails to commit because of a concurrent change
+    ///
+    /// The transaction is aborted if `f` fails.
+    pub fn transaction<F, T>(mut f: F) -> Result<T>
+        where F: FnMut(&mut Transaction) -> Result<T> {
+        loop {
+            let mut t = try!(Self::start_transaction());
+
+            // Dropping the transaction aborts it
+            let val = try!(f(&mut t));
+
+            match t.end() {
+                Ok(()) => return Ok(val),
+                Err(Error::Again) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
 
-        let val = try!(t.read(path));
+    /// List a directory in the Xen Store
+    pub fn directory_list(directory: CString) -> Result<Vec<String>> {
+        STORE.as_mut().directory(0, &directory)
+    }
+
+    /// Read the value pointed by `key`
+    pub fn read(key: CString) -> Result<String> {
+        STORE.as_mut().read(0, &key)
+    }
 
-        try!(t.end());
+    /// Write the value of `key`
+    pub fn write(key: CString, value: CString) -> Result<()> {
+        STORE.as_mut().write(0, &key, &value)
+    }
+
+    /// Create the directory `path` along with its missing parents
+    pub fn mkdir(path: CString) -> Result<()> {
+        STORE.as_mut().mkdir(0, &path)
+    }
+
+    /// Remove `path` and all of its children
+    pub fn rm(path: CString) -> Result<()> {
+        STORE.as_mut().rm(0, &path)
+    }
+
+    /// Returns the permissions of `path`
+    pub fn get_perms(path: CString) -> Result<Vec<Permission>> {
+        STORE.as_mut().get_perms(0, &path)
+    }
+
+    /// Set the permissions of `path`, the first one names its owner
+    pub fn set_perms(path: CString, perms: &[Permission]) -> Result<()> {
+        STORE.as_mut().set_perms(0, &path, perms)
+    }
+
+    /// Returns the home directory of the domain `domid`
+    pub fn domain_path(domid: u16) -> Result<Path> {
+        let req = {
+            RequestBuilder::new(0).set_msg_type(XsdSockmsgType::GetDomainPath)
+                                  .append_data(domid.to_string().as_bytes())
+                                  .append_data(&[0])
+        };
+
+        STORE.as_mut().send(req).and_then(|data| {
+            match String::from_utf8(data) {
+                Err(..) => Err(Error::Conversion),
+                Ok(s) => Ok(Path::root().join(s.trim_left_matches('/'))),
+            }
+        })
+    }
+
+    /// Returns the absolute path of the home directory of the current domain
+    pub fn home_path() -> Result<Path> {
+        let domid = try!(Path::home().join("domid").read::<u16>());
+
+        Self::domain_path(domid)
+    }
+
+    /// Tell the Xen Store daemon about the domain `domid`, whose store
+    /// interface lives in `mfn` and is notified through `port`
+    ///
+    /// This is only allowed to privileged domains.
+    pub fn introduce(domid: u16, mfn: u64, port: EvtchnPort) -> Result<()> {
+        let req = {
+            RequestBuilder::new(0).set_msg_type(XsdSockmsgType::Introduce)
+                                  .append_data(domid.to_string().as_bytes())
+                                  .append_data(&[0])
+                                  .append_data(mfn.to_string().as_bytes())
+                                  .append_data(&[0])
+                                  .append_data(port.to_string().as_bytes())
+                                  .append_data(&[0])
+        };
+
+        STORE.as_mut().send(req).map(|_| ())
+    }
+
+    /// Returns true if the domain `domid` was introduced to the Xen Store
+    /// daemon
+    pub fn is_domain_introduced(domid: u16) -> Result<bool> {
+        let req = {
+            RequestBuilder::new(0)
+                .set_msg_type(XsdSockmsgType::IsDomainIntroduced)
+                .append_data(domid.to_string().as_bytes())
+                .append_data(&[0])
+        };
+
+        STORE.as_mut().send(req).and_then(|data| {
+            match &data[..] {
+                b"T" => Ok(true),
+                b"F" => Ok(false),
+                _ => Err(Error::Conversion),
+            }
+        })
+    }
+
+    /// Read a value from the xen store and convert it
+    pub fn read_value<T: FromStr>(path: CString) -> Result<T> {
+        let val = try!(Self::read(path));
 
         T::from_str(val.as_str()).or_else(|_| Err(Error::Conversion))
     }
+
+    /// Same as `read_value()` but returns a future completing with the value
+    pub fn read_value_async<T>(path: CString) -> Blocking<Result<T>>
+        where T: FromStr + Send + 'static {
+        spawn_blocking(move || Self::read_value(path))
+    }
 }
@@ -0,0 +1,92 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use string::{String, ToString};
+
+use ffi::CString;
+
+use hal::xen::store::{Result, Error, XenStore};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Path of a node of the Xen Store
+///
+/// Paths built from `Path::home()` are relative, the Xen Store resolves them
+/// from the home directory of the domain (i.e. `/local/domain/<domid>`).
+pub struct Path(String);
+
+impl Path {
+    #[inline]
+    /// Returns the home directory of the domain
+    pub fn home() -> Self {
+        Path(String::new())
+    }
+
+    #[inline]
+    /// Returns the root of the Xen Store
+    pub fn root() -> Self {
+        Path(String::from("/"))
+    }
+
+    /// Returns the path of the child `component` of this node
+    ///
+    /// `component` may itself hold several components separated by `/`.
+    pub fn join<T: Display>(mut self, component: T) -> Self {
+        if !self.0.is_empty() && !self.0.ends_with('/') {
+            self.0.push('/');
+        }
+
+        self.0.push_str(&component.to_string());
+        self
+    }
+
+    /// Returns the path of the parent of this node, None for the home
+    /// directory and the root
+    pub fn parent(&self) -> Option<Path> {
+        let s = self.0.trim_right_matches('/');
+
+        if s.is_empty() {
+            return None;
+        }
+
+        match s.rfind('/') {
+            Some(0) => Some(Path::root()),
+            Some(i) => Some(Path(String::from(&s[..i]))),
+            None => Some(Path::home()),
+        }
+    }
+
+    #[inline]
+    /// Returns true if the path does not depend on the home directory
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with('/')
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the path in the form taken by `XenStore` and `Transaction`
+    pub fn to_cstring(&self) -> Result<CString> {
+        CString::new(self.0.as_bytes()).map_err(|_| Error::Inval)
+    }
+
+    /// Read the value of the node and convert it
+    pub fn read<T: FromStr>(&self) -> Result<T> {
+        XenStore::read_value(try!(self.to_cstring()))
+    }
+
+    /// Write `value` to the node
+    pub fn write<T: Display>(&self, value: T) -> Result<()> {
+        let value = try!(CString::new(value.to_string())
+                                 .map_err(|_| Error::Inval));
+
+        XenStore::write(try!(self.to_cstring()), value)
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,69 @@
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+use hal::xen::store::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Access granted to a domain on a node
+pub enum Access {
+    /// No access
+    None,
+    /// Read only access
+    Read,
+    /// Write only access
+    Write,
+    /// Read and write access
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Permission of a domain on a node of the Xen Store
+///
+/// The first permission of a node names its owner, which has full access,
+/// and the access given to the domains not listed afterwards.
+pub struct Permission {
+    /// The domain concerned
+    pub domid: u16,
+    /// The access given to the domain
+    pub access: Access,
+}
+
+impl Permission {
+    #[inline]
+    pub fn new(domid: u16, access: Access) -> Self {
+        Permission {
+            domid: domid,
+            access: access,
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let access = match s.as_bytes().first() {
+            Some(&b'n') => Access::None,
+            Some(&b'r') => Access::Read,
+            Some(&b'w') => Access::Write,
+            Some(&b'b') => Access::Both,
+            _ => return Err(Error::Conversion),
+        };
+
+        u16::from_str(&s[1..]).map(|domid| Permission::new(domid, access))
+                              .map_err(|_| Error::Conversion)
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let c = match self.access {
+            Access::None => 'n',
+            Access::Read => 'r',
+            Access::Write => 'w',
+            Access::Both => 'b',
+        };
+
+        f.write_fmt(format_args!("{}{}", c, self.domid))
+    }
+}
@@ -10,7 +10,7 @@ use ffi::CString;
 
 use cell::GlobalCellMutRef;
 
-use hal::xen::store::{Result, Error, XenbusState};
+use hal::xen::store::{Result, Error, XenbusState, Permission};
 
 use super::imp::{XenStoreImpl, RequestBuilder, XsdSockmsgType};
 
@@ -51,26 +51,7 @@ impl<'a> Transaction<'a> {
 
     /// List a directory in the Xen Store
     pub fn directory_list(&mut self, directory: CString) -> Result<Vec<String>> {
-        let req = {
-            RequestBuilder::new(self.tx_id).set_msg_type(XsdSockmsgType::Directory)
-                                           .append_data(directory.as_bytes_with_nul())
-        };
-
-        self.imp.send(req).and_then(|data| {
-            let mut v = Vec::<String>::new();
-            let mut s = String::new();
-
-            for b in data {
-                if b == 0 {
-                    v.push(s);
-                    s = String::new();
-                } else {
-                    s.push(b as char);
-                }
-            }
-
-            Ok(v)
-        })
+        self.imp.directory(self.tx_id, &directory)
     }
 
     /// Switch the state of the XenBus
@@ -100,29 +81,40 @@ impl<'a> Transaction<'a> {
 
     /// Read the value pointed by `key`
     pub fn read(&mut self, key: CString) -> Result<String> {
-        let req = RequestBuilder::new(self.tx_id).set_msg_type(XsdSockmsgType::Read)
-                                                 .append_data(key.as_bytes_with_nul());
-
-        self.imp.send(req).and_then(|data| {
-            match String::from_utf8(data) {
-                Err(..) => Err(Error::Conversion),
-                Ok(s) => Ok(s),
-            }
-        })
+        self.imp.read(self.tx_id, &key)
     }
 
     /// Write the value of `key`
     pub fn write(&mut self, key: CString, value: CString) -> Result<()> {
-        let req = {
-            RequestBuilder::new(self.tx_id).set_msg_type(XsdSockmsgType::Write)
-                                           .append_data(key.as_bytes_with_nul())
-                                           .append_data(value.as_bytes())
-        };
+        self.imp.write(self.tx_id, &key, &value)
+    }
+
+    /// Create the directory `path` along with its missing parents
+    pub fn mkdir(&mut self, path: CString) -> Result<()> {
+        self.imp.mkdir(self.tx_id, &path)
+    }
+
+    /// Remove `path` and all of its children
+    pub fn rm(&mut self, path: CString) -> Result<()> {
+        self.imp.rm(self.tx_id, &path)
+    }
 
-        self.imp.send(req).map(|_| ())
+    /// Returns the permissions of `path`
+    pub fn get_perms(&mut self, path: CString) -> Result<Vec<Permission>> {
+        self.imp.get_perms(self.tx_id, &path)
+    }
+
+    /// Set the permissions of `path`, the first one names its owner
+    pub fn set_perms(&mut self, path: CString,
+                     perms: &[Permission]) -> Result<()> {
+        self.imp.set_perms(self.tx_id, &path, perms)
     }
 
     fn _end(&mut self, data: CString) -> Result<()> {
+        // The transaction is over whatever the outcome, e.g. the daemon
+        // forgets it when failing to commit with `Error::Again`
+        self.ended = true;
+
         let req = {
             RequestBuilder::new(self.tx_id).set_msg_type(XsdSockmsgType::TransactionEnd)
                                            .append_data(data.as_bytes_with_nul())
@@ -133,10 +125,7 @@ impl<'a> Transaction<'a> {
                 Err(..) => Err(Error::Conversion),
                 Ok(s) => {
                     match s.as_ref() {
-                        "OK" => {
-                            self.ended = true;
-                            Ok(())
-                        }
+                        "OK" => Ok(()),
                         _ => Err(Error::Unknown),
                     }
                 }
@@ -0,0 +1,233 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use vec_deque::VecDeque;
+use string::String;
+
+use ffi::CString;
+
+use sync::Arc;
+use sync::spin::SpinLock;
+
+use thread::{Scheduler, WaitQueue};
+
+use time::{Duration, Instant};
+
+use hal::xen::store::{Result, STORE};
+
+use super::imp::{RequestBuilder, XsdSockmsgType};
+
+#[doc(hidden)]
+/// State of a watch shared with the Xen Store thread
+pub struct WatchInner {
+    path: CString,
+    token: String,
+    events: SpinLock<VecDeque<String>>,
+    closed: AtomicBool,
+    wait: WaitQueue,
+}
+
+impl WatchInner {
+    #[inline]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns true if an event on `path` with `token` is meant for this watch
+    pub fn matches(&self, path: &str, token: &str) -> bool {
+        let root = self.path.as_bytes();
+        let path = path.as_bytes();
+
+        token == self.token && path.starts_with(root) &&
+        (path.len() == root.len() || path[root.len()] == b'/')
+    }
+
+    /// Queue a notification that `path` changed
+    ///
+    /// A path already waiting to be consumed is not queued twice.
+    pub fn push(&self, path: String) {
+        {
+            let mut events = self.events.lock();
+
+            if !events.iter().any(|p| *p == path) {
+                events.push_back(path);
+            }
+        }
+
+        self.wait.unblock_all();
+    }
+
+    fn is_ready(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) || !self.events.lock().is_empty()
+    }
+
+    /// Mark the watch as closed, returns false if it already was
+    fn close(&self) -> bool {
+        let was_closed = self.closed.swap(true, Ordering::SeqCst);
+
+        self.wait.unblock_all();
+
+        !was_closed
+    }
+
+    /// Returns the next notification, blocking until `deadline` if none is
+    /// waiting
+    ///
+    /// None is returned on timeout or once the watch is closed.
+    fn next_until(&self, deadline: Option<Instant>) -> Option<String> {
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            if let Some(path) = self.events.lock().pop_front() {
+                return Some(path);
+            }
+
+            match deadline {
+                None => wait_event!(self.wait, self.is_ready()),
+                Some(deadline) => {
+                    if !wait_event_timeout!(self.wait, self.is_ready(),
+                                            deadline - Instant::now()) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Register the watch to the Xen Store daemon
+    pub fn register(&self) -> Result<()> {
+        self.send(XsdSockmsgType::Watch)
+    }
+
+    fn send(&self, msg_type: XsdSockmsgType) -> Result<()> {
+        let req = {
+            RequestBuilder::new(0).set_msg_type(msg_type)
+                                  .append_data(self.path.as_bytes_with_nul())
+                                  .append_data(self.token.as_bytes())
+                                  .append_data(&[0])
+        };
+
+        STORE.as_mut().send(req).map(|_| ())
+    }
+}
+
+/// A watch on a path of the Xen Store
+///
+/// The Xen Store notifies a watch every time the path or one of its children
+/// changes, and once right after the watch is registered. Notifications carry
+/// the path that changed.
+///
+/// The watch is unregistered on `Drop`.
+pub struct Watch {
+    inner: Arc<WatchInner>,
+}
+
+impl Watch {
+    #[doc(hidden)]
+    pub fn new(path: CString, token: &str) -> Result<Self> {
+        let inner = Arc::new(WatchInner {
+            path: path,
+            token: String::from(token),
+            events: SpinLock::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            wait: WaitQueue::new(),
+        });
+
+        // Register locally first, the daemon fires the watch right away
+        STORE.as_mut().add_watch(inner.clone());
+
+        if let Err(e) = inner.register() {
+            STORE.as_mut().remove_watch(&inner);
+
+            return Err(e);
+        }
+
+        Ok(Watch {
+            inner: inner,
+        })
+    }
+
+    #[inline]
+    /// Returns the token of the watch
+    pub fn token(&self) -> &str {
+        self.inner.token()
+    }
+
+    /// Returns the next notification if one is waiting
+    pub fn try_next(&self) -> Option<String> {
+        self.inner.events.lock().pop_front()
+    }
+
+    /// Block until the next notification and return the path that changed
+    pub fn wait(&self) -> Option<String> {
+        self.inner.next_until(None)
+    }
+
+    /// Same as `wait()` but gives up after `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<String> {
+        self.inner.next_until(Some(Instant::now() + timeout))
+    }
+
+    /// Returns a blocking iterator over the notifications
+    ///
+    /// The iterator ends once the watch is unregistered.
+    pub fn iter(&self) -> Events {
+        Events {
+            inner: &self.inner,
+        }
+    }
+
+    /// Call `callback` with the path that changed on every notification
+    ///
+    /// The callback runs on its own thread, so it may access the Xen Store.
+    /// It stops being called once the returned watch is dropped.
+    pub fn spawn_callback<F>(self, mut callback: F) -> Watch
+        where F: FnMut(&str) + Send + 'static {
+        let inner = self.inner.clone();
+
+        Scheduler::spawn(move || {
+            while let Some(path) = inner.next_until(None) {
+                callback(&path);
+            }
+        });
+
+        self
+    }
+
+    /// Unregister the watch
+    pub fn unwatch(self) -> Result<()> {
+        self.close()
+    }
+
+    fn close(&self) -> Result<()> {
+        if !self.inner.close() {
+            return Ok(());
+        }
+
+        STORE.as_mut().remove_watch(&self.inner);
+
+        self.inner.send(XsdSockmsgType::Unwatch)
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// Blocking iterator over the notifications of a watch
+///
+/// See `Watch::iter()`.
+pub struct Events<'a> {
+    inner: &'a WatchInner,
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.inner.next_until(None)
+    }
+}
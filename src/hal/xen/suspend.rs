@@ -0,0 +1,263 @@
+//! Suspend and resume of the domain
+//!
+//! A suspended domain is either resumed in place when the suspend is
+//! cancelled (e.g. after a checkpoint), or restored in a new domain, possibly
+//! on another host. In the latter case everything shared with Xen and the
+//! backends is gone: shared info, event channels, grant table and the
+//! connections of the frontends.
+//!
+//! Before suspending, frontends are quiesced by their suspend hooks: they
+//! stop submitting new requests and wait for the backends to complete those
+//! in flight, while interrupts still run. Requests still pending after that
+//! are failed if the domain is restored.
+//!
+//! Requests sent to the Xen Store by other threads and not answered by the
+//! time the domain is suspended are lost.
+
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use vec::Vec;
+
+use io::Write;
+
+use sync::Arc;
+use sync::spin::InterruptSpinLock;
+
+use thread::Scheduler;
+
+use time::{Duration, Instant};
+
+use hal;
+
+use hal::xen::{boot, console, event, sched, time};
+use hal::xen::grant::Table as GrantTable;
+use hal::xen::store::XenStore;
+
+/// Time given to the backends to complete the requests in flight
+const DRAIN_TIMEOUT_SECS: u64 = 5;
+/// Period at which frontends are polled while the domain is suspending
+const POLL_PERIOD_MS: u64 = 10;
+
+/// Number of times the domain was restored
+static GENERATION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set from the time the suspend hooks run until the domain runs again
+static SUSPENDING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Identifier given to the next hook
+static NEXT_HOOK_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+type Hook = Arc<Fn() + Send + Sync>;
+
+type Hooks = InterruptSpinLock<Option<Vec<(usize, Hook)>>>;
+
+/// Hooks run before the domain is suspended
+static SUSPEND_HOOKS: Hooks = InterruptSpinLock::new(None);
+
+/// Hooks run once the domain was restored
+static RESUME_HOOKS: Hooks = InterruptSpinLock::new(None);
+
+fn register(hooks: &Hooks, hook: Hook) -> usize {
+    let id = NEXT_HOOK_ID.fetch_add(1, Ordering::SeqCst);
+    let mut hooks = hooks.lock();
+
+    if hooks.is_none() {
+        *hooks = Some(Vec::new());
+    }
+
+    hooks.as_mut().unwrap().push((id, hook));
+
+    id
+}
+
+fn unregister(hooks: &Hooks, id: usize) {
+    if let Some(hooks) = hooks.lock().as_mut() {
+        hooks.retain(|&(i, _)| i != id);
+    }
+}
+
+/// Run `hooks`
+///
+/// Hooks may register other hooks, the lock is not held while running them.
+fn run(hooks: &Hooks) {
+    let hooks: Vec<Hook> = match *hooks.lock() {
+        Some(ref hooks) => hooks.iter().map(|&(_, ref h)| h.clone()).collect(),
+        None => Vec::new(),
+    };
+
+    for hook in hooks {
+        hook();
+    }
+}
+
+/// Handle on a hook registered with `on_suspend()`
+///
+/// The hook is unregistered on `Drop`.
+pub struct SuspendHook(usize);
+
+impl Drop for SuspendHook {
+    fn drop(&mut self) {
+        unregister(&SUSPEND_HOOKS, self.0);
+    }
+}
+
+/// Handle on a hook registered with `on_resume()`
+///
+/// The hook is unregistered on `Drop`.
+pub struct ResumeHook(usize);
+
+impl Drop for ResumeHook {
+    fn drop(&mut self) {
+        unregister(&RESUME_HOOKS, self.0);
+    }
+}
+
+/// Register `hook` to run every time the domain is about to be suspended
+///
+/// Hooks run on the thread suspending the domain, with interrupts enabled,
+/// once `is_suspending()` returns true. They are meant to quiesce frontends:
+/// new requests are held back or dropped from then on, and the hooks wait
+/// for the backends to complete those in flight, see `wait_idle()`.
+pub fn on_suspend<F>(hook: F) -> SuspendHook
+    where F: Fn() + Send + Sync + 'static {
+    SuspendHook(register(&SUSPEND_HOOKS, Arc::new(hook)))
+}
+
+/// Register `hook` to run every time the domain is restored in a new domain
+///
+/// Hooks run on the thread that suspended the domain, once the core services
+/// (events, grant table, Xen Store) work again. They are meant to reconnect
+/// frontends.
+pub fn on_resume<F>(hook: F) -> ResumeHook
+    where F: Fn() + Send + Sync + 'static {
+    ResumeHook(register(&RESUME_HOOKS, Arc::new(hook)))
+}
+
+#[inline]
+/// Returns true while the domain is being suspended
+///
+/// Frontends must not give new requests to their backend meanwhile.
+pub fn is_suspending() -> bool {
+    SUSPENDING.load(Ordering::SeqCst)
+}
+
+/// Block while the domain is being suspended
+///
+/// The domain might have been restored when this returns, in which case the
+/// frontends connected before are stale.
+pub fn wait_running() {
+    while is_suspending() {
+        Scheduler::sleep(Duration::from_millis(POLL_PERIOD_MS));
+    }
+}
+
+/// Poll `idle` until it returns true, for `DRAIN_TIMEOUT_SECS` at most
+///
+/// Returns false if the timeout expired first. Suspend hooks use this to
+/// wait for the backends to complete the requests in flight.
+pub fn wait_idle<F: FnMut() -> bool>(mut idle: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(DRAIN_TIMEOUT_SECS);
+
+    loop {
+        if idle() {
+            return true;
+        }
+
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        Scheduler::sleep(Duration::from_millis(POLL_PERIOD_MS));
+    }
+}
+
+#[inline]
+/// Returns the number of times the domain was restored
+///
+/// Frontends compare it with the value seen when they connected to find out
+/// whether their backend is still there.
+pub fn generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Suspend the domain
+///
+/// The suspend hooks run first, the resume hooks once the domain is restored.
+/// Returns true if the domain was restored in a new domain and false if the
+/// suspend was cancelled.
+pub fn suspend() -> bool {
+    println!("Suspending the domain");
+
+    SUSPENDING.store(true, Ordering::SeqCst);
+
+    // Frontends are quiesced while their backends can still answer
+    run(&SUSPEND_HOOKS);
+
+    hal::console().flush().unwrap();
+
+    // Nothing else runs until the domain is resumed
+    hal::local_irq_disable();
+
+    time::suspend();
+
+    let cancelled = unsafe {
+        let start_info_mfn = boot::pre_suspend();
+        let ret = sched::suspend(start_info_mfn);
+
+        boot::post_suspend(ret != 0);
+
+        ret != 0
+    };
+
+    time::resume();
+
+    if !cancelled {
+        resume_core();
+    }
+
+    hal::local_irq_enable();
+
+    if cancelled {
+        println!("Suspend cancelled, resuming");
+    } else {
+        println!("Domain restored");
+
+        restore();
+    }
+
+    // Frontends of a cancelled suspend carry on with the same backends
+    SUSPENDING.store(false, Ordering::SeqCst);
+
+    !cancelled
+}
+
+/// Restore what relies on the Xen Store and run the resume hooks
+fn restore() {
+    if XenStore::restore_watches().is_err() {
+        println!("Warning: Failed to restore the Xen Store watches");
+    }
+
+    ::time::resume();
+
+    run(&RESUME_HOOKS);
+}
+
+/// Reconnect the services of the domain to Xen
+fn resume_core() {
+    event::resume();
+
+    GrantTable::resume().expect("Fail to restore Xen's Grant Table");
+
+    // The new domain starts with a periodic timer and no timer event
+    time::init();
+
+    let (console_port, store_port) = boot::event_channels();
+
+    unsafe {
+        console::resume(console_port);
+        XenStore::resume(store_port);
+    }
+
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,202 @@
+//! Implementation of Xen's PV clock and timer
+
+use core::intrinsics::volatile_load;
+use core::ptr::null_mut;
+
+use hal::xen::shared_info;
+use hal::xen::defs::{EvtchnPort, Ulong};
+use hal::xen::event::dispatcher;
+use hal::xen::hypercall::{hypercall3, HypercallKind};
+
+use hal::arch::utils::{rdtsc, rmb};
+
+/// Virtual interrupt raised when the timer of a vcpu fires
+const VIRQ_TIMER: u32 = 0;
+
+/// Correction applied to Xen's system time so that it does not jump when the
+/// domain is restored, possibly on another host
+static mut SYSTEM_TIME_OFFSET: i64 = 0;
+
+/// System time at which the domain was suspended
+static mut SUSPEND_TIME: u64 = 0;
+
+enum VcpuOp {
+    StopPeriodicTimer = 7,
+    SetSingleshotTimer = 8,
+    StopSingleshotTimer = 9,
+}
+
+#[repr(C)]
+/// struct vcpu_set_singleshot_timer
+struct SingleshotTimer {
+    timeout_abs_ns: u64,
+    flags: u32,
+}
+
+#[inline]
+fn vcpu_op<T>(op: VcpuOp, arg: *mut T) -> i32 {
+    unsafe {
+        hypercall3(HypercallKind::VcpuOp, op as Ulong, 0, arg as Ulong) as i32
+    }
+}
+
+/// Snapshot of the time information published by Xen for a vcpu
+struct TimeInfo {
+    tsc_timestamp: u64,
+    system_time: u64,
+    tsc_to_system_mul: u32,
+    tsc_shift: i8,
+}
+
+impl TimeInfo {
+    /// Read a consistent snapshot of the time information of vcpu 0
+    ///
+    /// Xen increments the version before and after an update, so an odd
+    /// version or a version that changed during the read means that the
+    /// snapshot might be torn and has to be read again.
+    fn read() -> Self {
+        unsafe {
+            let time = &shared_info.vcpu_info[0].time;
+
+            loop {
+                let version = volatile_load(&time.version);
+
+                rmb();
+
+                let info = TimeInfo {
+                    tsc_timestamp: volatile_load(&time.tsc_timestamp),
+                    system_time: volatile_load(&time.system_time),
+                    tsc_to_system_mul: volatile_load(&time.tsc_to_system_mul),
+                    tsc_shift: volatile_load(&time.tsc_shift),
+                };
+
+                rmb();
+
+                if version & 1 == 0 && version == volatile_load(&time.version) {
+                    return info;
+                }
+            }
+        }
+    }
+
+    /// Convert a TSC delta to nanoseconds
+    fn scale_delta(&self, mut delta: u64) -> u64 {
+        if self.tsc_shift < 0 {
+            delta >>= -self.tsc_shift as u32;
+        } else {
+            delta <<= self.tsc_shift as u32;
+        }
+
+        // (delta * mul) >> 32 computed without a 128 bits intermediate
+        let mul = self.tsc_to_system_mul as u64;
+
+        (delta >> 32) * mul + (((delta & 0xFFFFFFFF) * mul) >> 32)
+    }
+}
+
+/// Returns Xen's raw system time, i.e. the number of nanoseconds since the
+/// host booted
+fn host_time() -> u64 {
+    let info = TimeInfo::read();
+    let delta = rdtsc().wrapping_sub(info.tsc_timestamp);
+
+    info.system_time + info.scale_delta(delta)
+}
+
+/// Returns the system time, i.e. the number of nanoseconds since the host
+/// booted
+///
+/// Once the domain was restored, the system time continues from the time it
+/// was suspended at instead.
+pub fn system_time() -> u64 {
+    unsafe {
+        (host_time() as i64 + SYSTEM_TIME_OFFSET) as u64
+    }
+}
+
+/// Returns the wall clock time, in nanoseconds since the UNIX epoch, at
+/// which the system time was 0
+///
+/// Like the vcpu time information, the wall clock is versioned and read again
+/// until a consistent snapshot is found.
+pub fn wall_clock() -> u64 {
+    unsafe {
+        loop {
+            let version = volatile_load(&shared_info.wc_version);
+
+            rmb();
+
+            let sec = volatile_load(&shared_info.wc_sec);
+            let nsec = volatile_load(&shared_info.wc_nsec);
+
+            rmb();
+
+            if version & 1 == 0 &&
+               version == volatile_load(&shared_info.wc_version) {
+                let wc = sec as u64 * 1_000_000_000 + nsec as u64;
+
+                return (wc as i64 - SYSTEM_TIME_OFFSET) as u64;
+            }
+        }
+    }
+}
+
+fn timer_handler(_port: EvtchnPort, _data: *mut u8) {
+    // Nothing to do, the event only has to wake up the vcpu. The scheduler
+    // looks at the expired deadlines on its next run
+}
+
+/// Bind the timer virtual interrupt and stop the periodic timer
+///
+/// Xen starts vcpus with a periodic timer ticking every 10ms. Only one-shot
+/// timers armed with `set_timer()` are used instead.
+pub fn init() {
+    vcpu_op(VcpuOp::StopPeriodicTimer, null_mut::<u8>());
+
+    bind_timer();
+}
+
+fn bind_timer() {
+    let port = dispatcher().bind_virq(VIRQ_TIMER, timer_handler, null_mut())
+                           .expect("Fail to bind the timer virtual interrupt");
+
+    dispatcher().unmask_event(port);
+}
+
+/// Raise a timer event once system time reaches `deadline` (see
+/// `system_time()`)
+///
+/// Any timer previously armed is replaced. A deadline in the past raises the
+/// event right away.
+pub fn set_timer(deadline: u64) -> i32 {
+    // Xen expects a deadline in its own system time
+    let deadline = unsafe { (deadline as i64 - SYSTEM_TIME_OFFSET) as u64 };
+    let mut timer = SingleshotTimer {
+        timeout_abs_ns: deadline,
+        flags: 0,
+    };
+
+    vcpu_op(VcpuOp::SetSingleshotTimer, &mut timer)
+}
+
+/// Cancel the timer armed with `set_timer()`
+pub fn stop_timer() -> i32 {
+    vcpu_op(VcpuOp::StopSingleshotTimer, null_mut::<u8>())
+}
+
+/// Record the time at which the domain is suspended
+pub fn suspend() {
+    unsafe {
+        SUSPEND_TIME = system_time();
+    }
+}
+
+/// Make the system time resume from the time the domain was suspended at
+///
+/// A restored domain starts with a periodic timer and without any event
+/// channel, `init()` must be called again once events work.
+pub fn resume() {
+    unsafe {
+        SYSTEM_TIME_OFFSET = SUSPEND_TIME as i64 - host_time() as i64;
+    }
+}
@@ -3,20 +3,41 @@
 use core::fmt;
 use core::result;
 
+use sync::spin::InterruptSpinLock;
+
 mod lazy;
 mod stdio;
 
 pub use self::stdio::{stdin, stdout};
 pub use self::stdio::{Stdin, Stdout, StdinLock, StdoutLock};
 
+static PRINT_MIRROR: InterruptSpinLock<Option<fn(fmt::Arguments)>> =
+    InterruptSpinLock::new(None);
+
+/// Mirror everything printed on the standard output to `mirror`
+///
+/// `mirror` is called after the output has been written to the console. It
+/// *MUST NOT* block nor print. Passing None removes the mirror.
+pub fn set_print_mirror(mirror: Option<fn(fmt::Arguments)>) {
+    *PRINT_MIRROR.lock() = mirror;
+}
+
 #[doc(hidden)]
 pub fn _print(fmt: fmt::Arguments) {
-    let out = stdout();
-    let mut out_locked = out.lock();
-    let res = out_locked.write_fmt(fmt);
+    {
+        let out = stdout();
+        let mut out_locked = out.lock();
+        let res = out_locked.write_fmt(fmt);
+
+        if let Err(..) = res {
+            panic!("Fail to print on the Xen console");
+        }
+    }
+
+    let mirror = *PRINT_MIRROR.lock();
 
-    if let Err(..) = res {
-        panic!("Fail to print on the Xen console");
+    if let Some(mirror) = mirror {
+        mirror(fmt);
     }
 }
 
@@ -1,6 +1,7 @@
 #![feature(asm)]
 #![feature(alloc)]
 #![feature(fnbox)]
+#![feature(heap_api)]
 #![feature(unique)]
 #![feature(const_fn)]
 #![feature(lang_items)]
@@ -40,7 +41,8 @@ pub mod ffi;
 pub mod num;
 pub mod cell;
 pub mod sync;
+pub mod time;
 #[doc(hidden)] pub mod utils;
-#[cfg(not(test))] pub mod thread;
+pub mod thread;
 
 #[cfg(feature = "net")] pub mod net;
@@ -47,3 +47,41 @@ macro_rules! wait_event {
         }
     )
 }
+
+#[macro_export]
+/// Wait for an event to occur, for `timeout` at most
+///
+/// This behaves like [`wait_event!`][wait_event] except that the thread
+/// gives up waiting once `timeout` (a [`Duration`][duration]) elapsed. The
+/// macro evaluates to the last value of the condition: true if the event
+/// occurred, false if the timeout expired first.
+///
+/// [wait_event]: macro.wait_event.html
+/// [duration]: time/struct.Duration.html
+macro_rules! wait_event_timeout {
+    ($queue:expr, $cond:expr, $timeout:expr) => ({
+        let deadline = $crate::time::Instant::now() + $timeout;
+        let ret;
+
+        loop {
+            $crate::hal::local_irq_disable();
+
+            let locked_queue = $queue.lock();
+
+            if $cond {
+                $crate::hal::local_irq_enable();
+                ret = true;
+                break;
+            }
+
+            if !$crate::thread::Scheduler::block_timeout(locked_queue,
+                                                         &$queue, deadline) {
+                // The event might have occurred right before the deadline
+                ret = $cond;
+                break;
+            }
+        }
+
+        ret
+    })
+}
@@ -0,0 +1,282 @@
+//! Address Resolution Protocol (RFC 826) over ethernet for IPv4
+
+use vec::Vec;
+use btree_map::BTreeMap;
+
+use sync::spin::SpinLock;
+
+use hal::{local_irq_disable, local_irq_enable};
+
+use thread::{Scheduler, WaitQueue};
+
+use time::{Duration, Instant};
+
+use net::{Interface, PacketBuilder};
+use net::defs::{be16, HwAddr, Ipv4Addr, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use net::defs::ETH_HDR_SIZE;
+
+const ARP_SIZE: usize = 28;
+
+const ARP_HW_ETHERNET: u16 = 1;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+/// Lifetime of a cache entry
+const ENTRY_TIMEOUT_SECS: u64 = 300;
+/// Number of requests sent before giving up a resolution
+const REQUEST_RETRIES: usize = 3;
+/// Time to wait for a reply to a request
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+
+struct Entry {
+    hw_addr: HwAddr,
+    expires: Instant,
+}
+
+/// Cache of IPv4 to hardware address mappings
+pub struct ArpCache {
+    entries: SpinLock<BTreeMap<Ipv4Addr, Entry>>,
+    /// Signaled every time a mapping is learnt
+    wait: WaitQueue,
+}
+
+fn frame(dest: &HwAddr, src: &HwAddr, op: u16, sender_ip: &Ipv4Addr,
+         target_hw: &HwAddr, target_ip: &Ipv4Addr) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HDR_SIZE + ARP_SIZE);
+
+    frame.extend_from_slice(dest.as_bytes());
+    frame.extend_from_slice(src.as_bytes());
+    frame.extend_from_slice(&[(ETHERTYPE_ARP >> 8) as u8, ETHERTYPE_ARP as u8]);
+
+    frame.extend_from_slice(&[0, ARP_HW_ETHERNET as u8,
+                              (ETHERTYPE_IPV4 >> 8) as u8, ETHERTYPE_IPV4 as u8,
+                              6, 4, 0, op as u8]);
+    frame.extend_from_slice(src.as_bytes());
+    frame.extend_from_slice(&sender_ip.octets());
+    frame.extend_from_slice(target_hw.as_bytes());
+    frame.extend_from_slice(&target_ip.octets());
+
+    frame
+}
+
+fn send(intf: &Interface, frame: &[u8]) -> Result<(), ()> {
+    let mut builder = try!(PacketBuilder::new());
+
+    try!(builder.write(frame));
+
+    intf.tx_frame(builder.finalize_raw())
+}
+
+impl ArpCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        ArpCache {
+            entries: SpinLock::new(BTreeMap::new()),
+            wait: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the hardware address mapped to `ip` if known
+    pub fn lookup(&self, ip: &Ipv4Addr) -> Option<HwAddr> {
+        let entries = self.entries.lock();
+
+        entries.get(ip).and_then(|e| {
+            if e.expires > Instant::now() {
+                Some(e.hw_addr.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Record that `ip` is reachable at `hw_addr`
+    pub fn insert(&self, ip: Ipv4Addr, hw_addr: HwAddr) {
+        self.entries.lock().insert(ip, Entry {
+            hw_addr: hw_addr,
+            expires: Instant::now() + Duration::from_secs(ENTRY_TIMEOUT_SECS),
+        });
+
+        self.wait.unblock_all();
+    }
+
+    /// Send a request for the hardware address of `ip` on `intf`
+    ///
+    /// This does not wait for the reply, which is learnt when received.
+    pub fn request(&self, intf: &Interface, ip: &Ipv4Addr) -> Result<(), ()> {
+        let (hw_addr, our_ip) = {
+            let intf = intf.read();
+
+            (intf.hw_addr_ref().clone(),
+             intf.v4_configuration_ref().ipv4.clone())
+        };
+
+        let request = frame(&HwAddr::broadcast(), &hw_addr, ARP_OP_REQUEST,
+                            &our_ip, &HwAddr::empty(), ip);
+
+        send(intf, &request)
+    }
+
+    /// Resolve the hardware address of `ip` reachable through `intf`
+    ///
+    /// Requests are sent if the mapping is not known yet. This blocks until
+    /// a reply is received or all retries timed out, so this *MUST NOT* be
+    /// called from the network thread.
+    pub fn resolve(&self, intf: &Interface, ip: &Ipv4Addr) -> Option<HwAddr> {
+        if let Some(hw_addr) = self.lookup(ip) {
+            return Some(hw_addr);
+        }
+
+        for _ in 0..REQUEST_RETRIES {
+            if self.request(intf, ip).is_err() {
+                return None;
+            }
+
+            let deadline = Instant::now() +
+                           Duration::from_millis(REQUEST_TIMEOUT_MS);
+
+            loop {
+                local_irq_disable();
+
+                let locked_queue = self.wait.lock();
+
+                if let Some(hw_addr) = self.lookup(ip) {
+                    local_irq_enable();
+                    return Some(hw_addr);
+                }
+
+                if !Scheduler::block_timeout(locked_queue, &self.wait,
+                                             deadline) {
+                    break;
+                }
+            }
+        }
+
+        self.lookup(ip)
+    }
+
+    /// Handle an ARP frame received on `intf`
+    ///
+    /// The sender is learnt and requests for the address of `intf` are
+    /// answered.
+    pub fn rx(&self, intf: &Interface, frame_bytes: &[u8]) {
+        if frame_bytes.len() < ETH_HDR_SIZE + ARP_SIZE {
+            return;
+        }
+
+        let arp = &frame_bytes[ETH_HDR_SIZE..];
+
+        if be16(arp, 0) != ARP_HW_ETHERNET || be16(arp, 2) != ETHERTYPE_IPV4 ||
+           arp[4] != 6 || arp[5] != 4 {
+            return;
+        }
+
+        let op = be16(arp, 6);
+        let sender_hw = unsafe { HwAddr::from_bytes(&arp[8..14]) };
+        let sender_ip = Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]);
+        let target_ip = Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]);
+
+        if !sender_ip.is_unspecified() {
+            self.insert(sender_ip.clone(), sender_hw.clone());
+        }
+
+        if op != ARP_OP_REQUEST {
+            return;
+        }
+
+        let (hw_addr, our_ip) = {
+            let intf = intf.read();
+
+            (intf.hw_addr_ref().clone(),
+             intf.v4_configuration_ref().ipv4.clone())
+        };
+
+        if our_ip.is_unspecified() || target_ip != our_ip {
+            return;
+        }
+
+        let reply = frame(&sender_hw, &hw_addr, ARP_OP_REPLY, &our_ip,
+                          &sender_hw, &sender_ip);
+
+        let _ = send(intf, &reply);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use net::Instance;
+    use net::defs::{HwAddr, Ipv4Addr, ETH_HDR_SIZE};
+    use net::test_support::{hw, interface, sent};
+
+    use super::{frame, ArpCache, ARP_OP_REPLY, ARP_OP_REQUEST, ARP_SIZE};
+
+    #[test]
+    fn test_request() {
+        let instance = Instance::new();
+        let intf = interface(&instance, 1);
+        let cache = ArpCache::new();
+
+        intf.write().v4_configuration_mut().ipv4 = Ipv4Addr::new(10, 0, 0, 2);
+
+        cache.request(&intf, &Ipv4Addr::new(10, 0, 0, 1)).unwrap();
+
+        let frames = sent(&intf);
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0], frame(&HwAddr::broadcast(), &hw(1),
+                                    ARP_OP_REQUEST, &Ipv4Addr::new(10, 0, 0, 2),
+                                    &HwAddr::empty(),
+                                    &Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(frames[0].len(), ETH_HDR_SIZE + ARP_SIZE);
+    }
+
+    #[test]
+    fn test_resolution() {
+        let instance = Instance::new();
+        let intf = interface(&instance, 1);
+        let cache = ArpCache::new();
+        let (ours, theirs) = (Ipv4Addr::new(10, 0, 0, 2),
+                              Ipv4Addr::new(10, 0, 0, 1));
+
+        intf.write().v4_configuration_mut().ipv4 = ours.clone();
+
+        assert!(cache.lookup(&theirs).is_none());
+
+        // Replies to our requests are learnt, and the mapping is then used
+        // without asking again
+        cache.rx(&intf, &frame(&hw(1), &hw(9), ARP_OP_REPLY, &theirs, &hw(1),
+                               &ours));
+
+        assert!(cache.lookup(&theirs) == Some(hw(9)));
+        assert!(cache.resolve(&intf, &theirs) == Some(hw(9)));
+        assert!(sent(&intf).is_empty());
+
+        // Requests for our address are answered, and teach us the sender
+        let other = Ipv4Addr::new(10, 0, 0, 3);
+
+        cache.rx(&intf, &frame(&HwAddr::broadcast(), &hw(8), ARP_OP_REQUEST,
+                               &other, &HwAddr::empty(), &ours));
+
+        assert!(cache.lookup(&other) == Some(hw(8)));
+        assert_eq!(sent(&intf), vec![frame(&hw(8), &hw(1), ARP_OP_REPLY,
+                                           &ours, &hw(8), &other)]);
+
+        // Requests for other addresses are not
+        cache.rx(&intf, &frame(&HwAddr::broadcast(), &hw(7), ARP_OP_REQUEST,
+                               &Ipv4Addr::new(10, 0, 0, 4), &HwAddr::empty(),
+                               &theirs));
+
+        assert_eq!(sent(&intf).len(), 1);
+
+        // Truncated and non ethernet/IPv4 frames are ignored
+        let mut bad = frame(&hw(1), &hw(6), ARP_OP_REPLY,
+                            &Ipv4Addr::new(10, 0, 0, 5), &hw(1), &ours);
+
+        cache.rx(&intf, &bad[..ETH_HDR_SIZE + ARP_SIZE - 1]);
+
+        bad[ETH_HDR_SIZE + 1] = 6;
+
+        cache.rx(&intf, &bad);
+
+        assert!(cache.lookup(&Ipv4Addr::new(10, 0, 0, 5)).is_none());
+    }
+}
@@ -0,0 +1,358 @@
+//! Software ethernet bridge
+//!
+//! A bridge forwards frames between its member interfaces. Source addresses
+//! are learnt so that frames are only sent to the member behind which their
+//! destination lives. Frames to unknown, multicast or broadcast destinations
+//! are flooded to every other member.
+//!
+//! Forwarded frames do not go through the local IP stack. Frames addressed
+//! to a member, as well as multicast and broadcast ones, are still delivered
+//! locally so that the bridge itself stays reachable.
+
+use vec::Vec;
+use btree_map::BTreeMap;
+
+use sync::spin::SpinLock;
+
+use time::{Duration, Instant};
+
+use net::{Interface, InterfaceWeak, Packet, PacketBuilder};
+use net::defs::HwAddr;
+use net::eth::Header;
+
+#[derive(Clone)]
+/// Configuration of a bridge
+pub struct BridgeConfig {
+    /// Time after which a learnt address is forgotten if not seen again
+    pub ageing: Duration,
+    /// Maximum number of learnt addresses
+    pub max_entries: usize,
+}
+
+impl BridgeConfig {
+    /// Default configuration, with an ageing time of 300 seconds as
+    /// recommended by IEEE 802.1D
+    pub fn new() -> Self {
+        BridgeConfig {
+            ageing: Duration::from_secs(300),
+            max_entries: 1024,
+        }
+    }
+}
+
+struct FdbEntry {
+    /// Index of the member the address lives behind
+    member: usize,
+    expires: Instant,
+}
+
+/// A bridge between interfaces of a network stack
+pub struct Bridge {
+    config: BridgeConfig,
+    members: Vec<InterfaceWeak>,
+    /// Forwarding database
+    fdb: SpinLock<BTreeMap<HwAddr, FdbEntry>>,
+}
+
+impl Bridge {
+    /// Create a bridge between `members`
+    ///
+    /// Members are put in promiscuous mode. Fails if less than two
+    /// interfaces are given.
+    pub fn new(members: &[Interface], config: BridgeConfig) -> Result<Self, ()> {
+        if members.len() < 2 {
+            return Err(());
+        }
+
+        for m in members {
+            m.write().set_promiscuous(true);
+        }
+
+        Ok(Bridge {
+            config: config,
+            members: members.iter().map(|m| m.downgrade()).collect(),
+            fdb: SpinLock::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the member interfaces that are still alive
+    pub fn members(&self) -> Vec<Interface> {
+        self.members.iter().filter_map(|m| m.upgrade()).collect()
+    }
+
+    fn member_index(&self, intf: &Interface) -> Option<usize> {
+        self.members.iter().position(|m| {
+            m.upgrade().map_or(false, |m| m == *intf)
+        })
+    }
+
+    /// Returns the number of addresses currently learnt
+    pub fn fdb_len(&self) -> usize {
+        let now = Instant::now();
+
+        self.fdb.lock().values().filter(|e| e.expires > now).count()
+    }
+
+    /// Forget every learnt address
+    pub fn flush(&self) {
+        self.fdb.lock().clear();
+    }
+
+    fn learn(&self, addr: HwAddr, member: usize) {
+        let now = Instant::now();
+        let mut fdb = self.fdb.lock();
+
+        if fdb.len() >= self.config.max_entries && !fdb.contains_key(&addr) {
+            let expired: Vec<HwAddr> = fdb.iter()
+                                          .filter(|&(_, e)| e.expires <= now)
+                                          .map(|(a, _)| a.clone())
+                                          .collect();
+
+            for a in expired {
+                fdb.remove(&a);
+            }
+
+            if fdb.len() >= self.config.max_entries {
+                return;
+            }
+        }
+
+        fdb.insert(addr, FdbEntry {
+            member: member,
+            expires: now + self.config.ageing,
+        });
+    }
+
+    fn lookup(&self, addr: &HwAddr) -> Option<usize> {
+        let now = Instant::now();
+
+        self.fdb.lock().get(addr).and_then(|e| {
+            if e.expires > now { Some(e.member) } else { None }
+        })
+    }
+
+    /// Forward a frame received on a member
+    ///
+    /// Returns true if the frame must also be delivered to the local stack.
+    /// Frames received on an interface that is not a member are left to the
+    /// local stack.
+    pub fn rx(&self, pkt: &Packet) -> bool {
+        let ingress = match pkt.interface() {
+            Some(intf) => intf,
+            None => return false,
+        };
+
+        let ingress_idx = match self.member_index(&ingress) {
+            Some(idx) => idx,
+            None => return true,
+        };
+
+        let (dest, src) = match pkt.link_header::<Header>() {
+            Some(hdr) => (hdr.dest.clone(), hdr.src.clone()),
+            None => return false,
+        };
+
+        let frame = pkt.as_bytes();
+
+        let members = self.members.iter()
+                                  .map(|m| m.upgrade())
+                                  .collect::<Vec<Option<Interface>>>();
+
+        let is_own = |addr: &HwAddr| {
+            members.iter().any(|m| {
+                m.as_ref().map_or(false, |m| m.read().hw_addr_ref() == addr)
+            })
+        };
+
+        // Our own frames looping back are dropped
+        if is_own(&src) {
+            return false;
+        }
+
+        if !src.is_multicast() {
+            self.learn(src, ingress_idx);
+        }
+
+        if dest.is_multicast() {
+            self.flood(&members, ingress_idx, frame);
+            return true;
+        }
+
+        if is_own(&dest) {
+            return true;
+        }
+
+        match self.lookup(&dest) {
+            // The destination is on the segment the frame comes from
+            Some(idx) if idx == ingress_idx => {}
+            Some(idx) => {
+                if let Some(&Some(ref egress)) = members.get(idx) {
+                    forward(egress, frame);
+                }
+            }
+            None => self.flood(&members, ingress_idx, frame),
+        }
+
+        false
+    }
+
+    fn flood(&self, members: &[Option<Interface>], ingress: usize,
+             frame: &[u8]) {
+        for (idx, m) in members.iter().enumerate() {
+            if idx == ingress {
+                continue;
+            }
+
+            if let Some(ref m) = *m {
+                forward(m, frame);
+            }
+        }
+    }
+
+    /// Leave promiscuous mode on the members
+    pub fn release(&self) {
+        for m in self.members() {
+            m.write().set_promiscuous(false);
+        }
+    }
+}
+
+/// Transmit a copy of `frame` on `egress`
+///
+/// Members that are down are skipped silently.
+fn forward(egress: &Interface, frame: &[u8]) {
+    if !egress.read().is_up() {
+        return;
+    }
+
+    if let Ok(mut builder) = PacketBuilder::new() {
+        if builder.write(frame).is_ok() {
+            let _ = egress.tx_frame(builder.finalize_raw());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vec::Vec;
+
+    use time::Duration;
+
+    use net::{Instance, Interface, Packet};
+    use net::defs::{HwAddr, ETHERTYPE_IPV4};
+    use net::test_support::{self, eth, hw, interface, packet};
+
+    use super::{Bridge, BridgeConfig};
+
+    fn new_bridge(config: BridgeConfig) -> (Bridge, Vec<Interface>) {
+        let instance = Instance::new();
+        let members: Vec<Interface> = (1..4).map(|i| {
+            interface(&instance, i)
+        }).collect();
+
+        (Bridge::new(&members, config).unwrap(), members)
+    }
+
+    /// Frame received on `intf`
+    fn frame(intf: &Interface, dest: &HwAddr, src: &HwAddr) -> Packet {
+        let mut pkt = packet(&eth(dest, src, ETHERTYPE_IPV4, &[0; 46]));
+
+        pkt.set_interface(intf.downgrade());
+
+        pkt
+    }
+
+    /// Returns how many frames were transmitted on each member
+    fn sent(members: &[Interface]) -> Vec<usize> {
+        members.iter().map(|m| test_support::sent(m).len()).collect()
+    }
+
+    #[test]
+    fn test_learning() {
+        let (bridge, m) = new_bridge(BridgeConfig::new());
+        let (x, y) = (hw(0x10), hw(0x20));
+
+        assert!(m.iter().all(|m| m.read().is_promiscuous()));
+
+        // Unknown destinations are flooded, the source is learnt
+        assert!(!bridge.rx(&frame(&m[0], &y, &x)));
+        assert_eq!(sent(&m), vec![0, 1, 1]);
+        assert_eq!(bridge.fdb_len(), 1);
+
+        // Known destinations are only sent to their member
+        assert!(!bridge.rx(&frame(&m[1], &x, &y)));
+        assert_eq!(sent(&m), vec![1, 1, 1]);
+        assert!(!bridge.rx(&frame(&m[0], &y, &x)));
+        assert_eq!(sent(&m), vec![1, 2, 1]);
+        assert_eq!(bridge.fdb_len(), 2);
+
+        // Nothing to do when the destination lives behind the ingress
+        assert!(!bridge.rx(&frame(&m[1], &y, &hw(0x30))));
+        assert_eq!(sent(&m), vec![1, 2, 1]);
+
+        bridge.flush();
+
+        assert_eq!(bridge.fdb_len(), 0);
+
+        bridge.release();
+
+        assert!(m.iter().all(|m| !m.read().is_promiscuous()));
+    }
+
+    #[test]
+    fn test_ageing() {
+        let mut config = BridgeConfig::new();
+
+        config.ageing = Duration::from_secs(0);
+
+        let (bridge, m) = new_bridge(config);
+        let (x, y) = (hw(0x10), hw(0x20));
+
+        // Learnt addresses expire right away, so frames keep being flooded
+        assert!(!bridge.rx(&frame(&m[0], &y, &x)));
+        assert_eq!(bridge.fdb_len(), 0);
+        assert!(!bridge.rx(&frame(&m[1], &x, &y)));
+        assert_eq!(sent(&m), vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn test_flooding() {
+        let (bridge, m) = new_bridge(BridgeConfig::new());
+        let multicast = unsafe {
+            HwAddr::from_bytes(&[0x01, 0, 0x5e, 0, 0, 1])
+        };
+
+        // Broadcasts and multicasts reach every other member and the local
+        // stack
+        assert!(bridge.rx(&frame(&m[0], &HwAddr::broadcast(), &hw(0x10))));
+        assert_eq!(sent(&m), vec![0, 1, 1]);
+        assert!(bridge.rx(&frame(&m[2], &multicast, &hw(0x30))));
+        assert_eq!(sent(&m), vec![1, 2, 1]);
+
+        // Members that are down are skipped
+        m[1].set_oper_up(false);
+
+        assert!(!bridge.rx(&frame(&m[0], &hw(0x20), &hw(0x10))));
+        assert_eq!(sent(&m), vec![1, 2, 2]);
+    }
+
+    #[test]
+    fn test_own_address() {
+        let (bridge, m) = new_bridge(BridgeConfig::new());
+
+        // Frames to a member are delivered locally only
+        assert!(bridge.rx(&frame(&m[0], &hw(2), &hw(0x10))));
+        assert_eq!(sent(&m), vec![0, 0, 0]);
+
+        // Our own frames looping back are dropped
+        assert!(!bridge.rx(&frame(&m[0], &HwAddr::broadcast(), &hw(3))));
+        assert_eq!(sent(&m), vec![0, 0, 0]);
+        assert_eq!(bridge.fdb_len(), 1);
+
+        // Frames of other interfaces are none of the bridge's business
+        let other = interface(&Instance::new(), 9);
+
+        assert!(bridge.rx(&frame(&other, &hw(0x10), &hw(0x20))));
+        assert_eq!(sent(&m), vec![0, 0, 0]);
+    }
+}
@@ -5,10 +5,13 @@
 //! - Generic parameter: ether type, protocol id, ...
 //! - Specific parameter: hardware address, ip address, ...
 
+use core::hash::{Hash, Hasher};
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use sync::Arc;
 
+use vec::Vec;
 use boxed::Box;
 use btree_map::BTreeMap;
 
@@ -16,13 +19,112 @@ use net::Packet;
 
 use net::defs::Rule;
 
-use net::conn::{UniConn, MultiConn};
+use net::conn::{UniConn, MultiConn, Sharing};
 
 enum ConnChoice<C, F: GenericFilterTrait + ?Sized> {
-    Conn(Arc<C>),
+    Conn(C),
     Filter(Box<F>),
 }
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a hasher used to compute flow hashes
+struct FlowHasher(u64);
+
+impl FlowHasher {
+    fn new() -> Self {
+        FlowHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FlowHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Multi connexions registered for the same rule
+///
+/// Incoming packets are spread across the connexions of the group according
+/// to their sharing policy.
+struct MultiGroup {
+    conns: Vec<Arc<MultiConn>>,
+    next: AtomicUsize,
+}
+
+impl MultiGroup {
+    fn new(conn: Arc<MultiConn>) -> Self {
+        MultiGroup {
+            conns: vec![conn],
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    /// Returns the sharing policy of the group
+    fn sharing(&self) -> Sharing {
+        self.conns[0].sharing()
+    }
+
+    /// Add a connexion to the group
+    ///
+    /// This fails if the group is exclusive or if the connexion does not use
+    /// the same sharing policy as the group.
+    fn insert(&mut self, conn: Arc<MultiConn>) -> Result<(), ()> {
+        let sharing = self.sharing();
+
+        if sharing == Sharing::Exclusive || conn.sharing() != sharing {
+            return Err(());
+        }
+
+        self.conns.push(conn);
+
+        Ok(())
+    }
+
+    /// Remove a connexion from the group
+    ///
+    /// Returns true if the group is now empty.
+    fn remove(&mut self, conn: &Arc<MultiConn>) -> bool {
+        let ptr = &**conn as *const MultiConn;
+
+        self.conns.retain(|c| &**c as *const MultiConn != ptr);
+
+        self.conns.is_empty()
+    }
+
+    /// Elect the connexion that receives a packet sent by the endpoint
+    /// described by `rule`
+    fn elect(&self, rule: &Rule) -> &Arc<MultiConn> {
+        let idx = match self.sharing() {
+            Sharing::Exclusive => 0,
+            Sharing::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            Sharing::FlowHash => {
+                let mut hasher = FlowHasher::new();
+
+                rule.hash(&mut hasher);
+
+                hasher.finish() as usize
+            }
+        };
+
+        &self.conns[idx % self.conns.len()]
+    }
+
+    #[inline]
+    fn rx(&self, pkt: Packet, rule: Rule) {
+        self.elect(&rule).rx(pkt, rule);
+    }
+}
+
 /// Callbacks used by `SpecificFilter`.
 ///
 /// **FIXME**: I feel like this is somehow hacky
@@ -88,6 +190,12 @@ pub trait GenericFilterTrait {
     fn insert_multi(&mut self, conn: Arc<MultiConn>,
                     rule: &Rule) -> Result<(), ()>;
 
+    /// Remove a multi connexion inserted with the same rule.
+    fn remove_multi(&mut self, conn: &Arc<MultiConn>, rule: &Rule);
+
+    /// Returns true if no connexion is registered in the filter.
+    fn is_empty(&self) -> bool;
+
     /// Filter and route an incoming packet to a connexion (uni or multi).
     fn rx(&self, pkt: Packet);
 
@@ -113,6 +221,12 @@ pub trait SpecificFilterTrait<T> {
     fn insert_multi(&mut self, conn: Arc<MultiConn>,
                     rule: &Rule) -> Result<(), ()>;
 
+    /// Remove a multi connexion inserted with the same rule.
+    fn remove_multi(&mut self, conn: &Arc<MultiConn>, rule: &Rule);
+
+    /// Returns true if no connexion is registered in the filter.
+    fn is_empty(&self) -> bool;
+
     /// Filter and route an incoming packet to a connexion (uni or multi).
     fn rx(&self, pkt: Packet);
 
@@ -177,6 +291,28 @@ impl<T, U, E, S> GenericFilterTrait for GenericFilter<T, U, E, S>
         }
     }
 
+    fn remove_multi(&mut self, conn: &Arc<MultiConn>, rule: &Rule) {
+        if let Some(key) = E::from_rule(rule) {
+            let empty = match self.filters.get_mut(&key) {
+                None => return,
+                Some(filter) => {
+                    filter.remove_multi(conn, rule);
+                    filter.is_empty()
+                }
+            };
+
+            // Release specific filters that are not used anymore
+            if empty {
+                self.filters.remove(&key);
+            }
+        }
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
     fn rx(&self, mut pkt: Packet) {
         // Sanitize the packet
         if S::sanitize(&mut pkt).is_ok() {
@@ -221,8 +357,8 @@ pub struct SpecificFilter<T, P, E, F> where T: Ord + Clone,
                                             E: Extractor<T>,
                                             F: SpecificCallbacks<P> {
     generic_param: P,
-    filters: BTreeMap<T, ConnChoice<UniConn, GenericFilterTrait>>,
-    multi: Option<ConnChoice<MultiConn, GenericFilterTrait>>,
+    filters: BTreeMap<T, ConnChoice<Arc<UniConn>, GenericFilterTrait>>,
+    multi: Option<ConnChoice<MultiGroup, GenericFilterTrait>>,
     _extractor: PhantomData<E>,
     _factory: PhantomData<F>,
 }
@@ -272,16 +408,46 @@ impl<T, P, E, F> SpecificFilterTrait<P> for SpecificFilter<T, P, E, F>
             }
         } else {
             // If there is no upper layer try to insert the multi in our filter
-            if let None = self.multi {
-                self.multi = Some(ConnChoice::Conn(conn));
-                Ok(())
-            } else {
-                // A multi already exist, cannot insert this new one
-                Err(())
+            match self.multi {
+                None => {
+                    self.multi = Some(ConnChoice::Conn(MultiGroup::new(conn)));
+                    Ok(())
+                }
+                // Other multi connexions already exist, the new one can join
+                // them only if they all agreed to share the rule
+                Some(ConnChoice::Conn(ref mut group)) => group.insert(conn),
+                Some(ConnChoice::Filter(..)) => Err(()),
             }
         }
     }
 
+    fn remove_multi(&mut self, conn: &Arc<MultiConn>, rule: &Rule) {
+        let empty = match self.multi {
+            None => return,
+            Some(ConnChoice::Conn(ref mut group)) => {
+                if F::has_upper_filter(rule) {
+                    return;
+                }
+
+                group.remove(conn)
+            }
+            Some(ConnChoice::Filter(ref mut filter)) => {
+                filter.remove_multi(conn, rule);
+                filter.is_empty()
+            }
+        };
+
+        // Release the group or the upper filter once it is not used anymore
+        if empty {
+            self.multi = None;
+        }
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.multi.is_none()
+    }
+
     fn rx(&self, pkt: Packet) {
         // Extract the specific parameter from the packet
         if let Some(key) = E::from_packet(&pkt) {
@@ -306,7 +472,7 @@ impl<T, P, E, F> SpecificFilterTrait<P> for SpecificFilter<T, P, E, F>
                 // Receive the packet (pass it to an upper filter if one exists,
                 // or push the packet to the connexion otherwise).
                 match *multi {
-                    ConnChoice::Conn(ref conn) => conn.rx(pkt, rule),
+                    ConnChoice::Conn(ref group) => group.rx(pkt, rule),
                     ConnChoice::Filter(ref filter) => {
                         filter.rx_multi(pkt, rule)
                     }
@@ -321,9 +487,104 @@ impl<T, P, E, F> SpecificFilterTrait<P> for SpecificFilter<T, P, E, F>
             F::set_layer_rule(&mut rule, &pkt);
 
             match *multi {
-                ConnChoice::Conn(ref conn) => conn.rx(pkt, rule),
+                ConnChoice::Conn(ref group) => group.rx(pkt, rule),
                 ConnChoice::Filter(ref filter) => filter.rx_multi(pkt, rule),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use sync::Arc;
+
+    use vec::Vec;
+
+    use net::conn::{MultiConn, Sharing};
+
+    use net::defs::{Rule, TransportRule};
+
+    use super::MultiGroup;
+
+    fn rule(port: u16) -> Rule {
+        Rule {
+            eth_rule: None,
+            net_rule: None,
+            tspt_rule: Some(TransportRule {
+                port: port,
+            }),
+        }
+    }
+
+    fn new_group(sharing: Sharing,
+                 count: usize) -> (MultiGroup, Vec<Arc<MultiConn>>) {
+        let conns: Vec<Arc<MultiConn>> = (0..count).map(|_| {
+            Arc::new(MultiConn::any_interface(sharing))
+        }).collect();
+
+        let mut group = MultiGroup::new(conns[0].clone());
+
+        for conn in &conns[1..] {
+            assert_eq!(group.insert(conn.clone()), Ok(()));
+        }
+
+        (group, conns)
+    }
+
+    fn index(conns: &[Arc<MultiConn>], conn: &Arc<MultiConn>) -> usize {
+        conns.iter().position(|c| {
+            &**c as *const MultiConn == &**conn as *const MultiConn
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let (group, conns) = new_group(Sharing::RoundRobin, 3);
+
+        // Every connexion is elected in turn, whoever sent the packet
+        for i in 0..9 {
+            assert_eq!(index(&conns, group.elect(&rule(i as u16))), i % 3);
+        }
+    }
+
+    #[test]
+    fn test_flow_hash() {
+        let (group, conns) = new_group(Sharing::FlowHash, 3);
+        let mut elected = vec![0; 3];
+
+        for port in 1000..1064 {
+            let first = index(&conns, group.elect(&rule(port)));
+
+            // Packets of a given flow always reach the same connexion
+            for _ in 0..4 {
+                assert_eq!(index(&conns, group.elect(&rule(port))), first);
+            }
+
+            elected[first] += 1;
+        }
+
+        // Flows are spread across the connexions
+        assert!(elected.iter().filter(|count| **count > 0).count() > 1);
+    }
+
+    #[test]
+    fn test_sharing_policies() {
+        let (mut group, _) = new_group(Sharing::Exclusive, 1);
+
+        // Exclusive connexions never share
+        let conn = Arc::new(MultiConn::any_interface(Sharing::Exclusive));
+
+        assert_eq!(group.insert(conn), Err(()));
+
+        // Every connexion of a group uses the same policy
+        let (mut group, _) = new_group(Sharing::RoundRobin, 2);
+        let conn = Arc::new(MultiConn::any_interface(Sharing::FlowHash));
+
+        assert_eq!(group.insert(conn), Err(()));
+
+        let conn = Arc::new(MultiConn::any_interface(Sharing::RoundRobin));
+
+        assert_eq!(group.insert(conn.clone()), Ok(()));
+        assert!(!group.remove(&conn));
+    }
+}
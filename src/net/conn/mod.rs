@@ -7,10 +7,26 @@ mod multi;
 pub mod filter;
 
 pub use self::uni::UniConn;
-pub use self::multi::MultiConn;
+pub use self::multi::{MultiConn, PopPacket};
 
 /// A connexion, either uni or multi
 pub enum Connexion {
     Uni(Arc<UniConn>),
     Multi(Arc<MultiConn>),
 }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// Policy used when several multi connexions register for the same rule
+///
+/// This is similar to what `SO_REUSEPORT` offers on other systems. Every
+/// connexion registered for a given rule must use the same policy, and
+/// `Exclusive` connexions can never share a rule.
+pub enum Sharing {
+    /// The connexion is the only one allowed to serve the rule
+    Exclusive,
+    /// Incoming packets are given to every connexion in turn
+    RoundRobin,
+    /// Incoming packets are dispatched based on a hash of the endpoint that
+    /// sent them. Packets of a given flow always reach the same connexion.
+    FlowHash,
+}
@@ -1,21 +1,38 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use sync::Arc;
+
 use vec_deque::VecDeque;
 
 use sync::spin::SpinLock;
 
-use thread::WaitQueue;
+use hal::{local_irq_disable, local_irq_enable};
+
+use thread::{Scheduler, WaitQueue, Notify, Pollable, Ready};
+use thread::{Future, Poll, Waker};
+
+use time::{Duration, Instant};
 
 use net::{InterfaceWeak, Packet, PacketBuilder};
 
 use net::defs::Rule;
 
+use net::conn::Sharing;
+
+/// Maximum number of packets queued on a connexion
+const MAX_QUEUE_SIZE: usize = 64;
+
 /// Connexion that can receive packets from multiple endpoints.
 ///
 /// This is just a placeholder object. In order for it to receive packets it
 /// must be added to a `Manager`.
 pub struct MultiConn {
     queue: SpinLock<VecDeque<(Packet, Rule)>>,
-    wait: WaitQueue,
-    parent: InterfaceWeak,
+    wait: Arc<WaitQueue>,
+    /// None if the connexion receives packets from every interface
+    parent: Option<InterfaceWeak>,
+    sharing: Sharing,
+    nonblocking: AtomicBool,
 }
 
 unsafe impl Sync for MultiConn {}
@@ -26,20 +43,73 @@ impl MultiConn {
     /// This should not be used directly. Instead, to create a new multi
     /// connexion, use `Interface::create_multi()`.
     pub fn new(parent: InterfaceWeak) -> Self {
+        Self::with_sharing(parent, Sharing::Exclusive)
+    }
+
+    /// Create a new multi connexion object that can share its rule with
+    /// other connexions.
+    ///
+    /// When several connexions using the same `sharing` policy register for
+    /// the same rule, incoming packets are spread across them.
+    pub fn with_sharing(parent: InterfaceWeak, sharing: Sharing) -> Self {
+        Self::create(Some(parent), sharing)
+    }
+
+    /// Create a new multi connexion object receiving packets from every
+    /// interface of the network stack.
+    ///
+    /// Such a connexion never detaches and cannot transmit packets, which
+    /// protocols send on their own (e.g. `UdpSocket`).
+    pub fn any_interface(sharing: Sharing) -> Self {
+        Self::create(None, sharing)
+    }
+
+    fn create(parent: Option<InterfaceWeak>, sharing: Sharing) -> Self {
+        let wait = Arc::new(WaitQueue::new());
+
+        // Waiters are woken up when the interface goes away or its link state
+        // changes
+        if let Some(intf) = parent.as_ref().and_then(|p| p.upgrade()) {
+            intf.write().watch_detach(&(wait.clone() as Arc<Notify>));
+            intf.watch(wait.clone());
+        }
+
         MultiConn {
             queue: SpinLock::new(VecDeque::new()),
-            wait: WaitQueue::new(),
+            wait: wait,
             parent: parent,
+            sharing: sharing,
+            nonblocking: AtomicBool::new(false),
         }
     }
 
+    /// Returns true if the interface of the connexion went away
+    ///
+    /// Packets already received can still be popped.
+    pub fn is_detached(&self) -> bool {
+        match self.parent {
+            None => false,
+            Some(ref parent) => match parent.upgrade() {
+                None => true,
+                Some(intf) => intf.read().is_detached(),
+            },
+        }
+    }
+
+    #[inline]
+    /// Returns the sharing policy of the connexion
+    pub fn sharing(&self) -> Sharing {
+        self.sharing
+    }
+
     /// Pop a packet from the connexion.
     ///
     /// It returns a tuple that contains the packet and a rule that contains
     /// information to reply to the sender.
     ///
     /// Note that if no packets are available, this function will block until
-    /// one is received.
+    /// one is received. If the interface might go away, use `receive()`
+    /// instead.
     pub fn pop_packet(&self) -> (Packet, Rule) {
         loop {
             let res = self.queue.lock().pop_front();
@@ -51,13 +121,98 @@ impl MultiConn {
         }
     }
 
+    /// Pop a packet from the connexion without blocking.
+    ///
+    /// Returns None if no packet is available.
+    pub fn try_pop_packet(&self) -> Option<(Packet, Rule)> {
+        self.queue.lock().pop_front()
+    }
+
+    /// Pop a packet from the connexion, waiting at most `timeout` for one to
+    /// be received.
+    ///
+    /// Returns None if no packet was received before the timeout expired or
+    /// if the interface went away.
+    pub fn pop_packet_timeout(&self, timeout: Duration) -> Option<(Packet, Rule)> {
+        self.wait_packet(Some(Instant::now() + timeout))
+    }
+
+    /// Pop a packet from the connexion honouring its blocking mode.
+    ///
+    /// In blocking mode this behaves like `pop_packet()`, otherwise like
+    /// `try_pop_packet()`. In both cases, None is returned if no packet is
+    /// available and the interface went away.
+    pub fn receive(&self) -> Option<(Packet, Rule)> {
+        if self.is_nonblocking() {
+            self.try_pop_packet()
+        } else {
+            self.wait_packet(None)
+        }
+    }
+
+    fn wait_packet(&self, deadline: Option<Instant>) -> Option<(Packet, Rule)> {
+        loop {
+            if let Some(res) = self.try_pop_packet() {
+                return Some(res);
+            }
+
+            if self.is_detached() {
+                return None;
+            }
+
+            local_irq_disable();
+
+            let locked_queue = self.wait.lock();
+
+            if !self.queue.lock().is_empty() || self.is_detached() {
+                local_irq_enable();
+                continue;
+            }
+
+            match deadline {
+                None => Scheduler::block(locked_queue),
+                Some(deadline) => {
+                    if !Scheduler::block_timeout(locked_queue, &self.wait,
+                                                 deadline) {
+                        // Deadline expired, give a last chance to a packet
+                        // that might have arrived in the meantime
+                        return self.try_pop_packet();
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    /// Set the blocking mode of the connexion
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::SeqCst);
+    }
+
+    #[inline]
+    /// Returns true if the connexion is in non-blocking mode
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::SeqCst)
+    }
+
     /// Insert a packet inside the connexion
     ///
     /// `pkt` is the received packet
     ///
     /// `rule` is information about the endpoint that sent the packet
+    ///
+    /// The packet is dropped if too many packets are already waiting.
     pub fn rx(&self, pkt: Packet, rule: Rule) {
-        self.queue.lock().push_back((pkt, rule));
+        {
+            let mut queue = self.queue.lock();
+
+            if queue.len() == MAX_QUEUE_SIZE {
+                return;
+            }
+
+            queue.push_back((pkt, rule));
+        }
+
         self.wait.unblock();
     }
 
@@ -84,17 +239,102 @@ impl MultiConn {
     /// be sent to.
     pub fn tx_packet(&self, builder: PacketBuilder,
                      rule: &Rule) -> Result<(), ()> {
-        let intf = try!(self.parent.upgrade().ok_or(()));
+        let intf = try!(self.parent.as_ref().and_then(|p| p.upgrade())
+                                            .ok_or(()));
 
         intf.tx_packet(builder, rule)
     }
 }
 
+impl Pollable for MultiConn {
+    fn readiness(&self) -> Ready {
+        let mut ready = Ready::empty();
+
+        if !self.queue.lock().is_empty() {
+            ready = ready | Ready::readable();
+        }
+
+        let parent = match self.parent {
+            None => return ready | Ready::writable(),
+            Some(ref parent) => parent.upgrade(),
+        };
+
+        match parent {
+            None => ready = ready | Ready::hangup(),
+            Some(intf) => {
+                let intf = intf.read();
+
+                if intf.is_detached() {
+                    ready = ready | Ready::hangup();
+                } else if intf.is_up() {
+                    ready = ready | Ready::writable();
+                }
+            }
+        }
+
+        ready
+    }
+
+    fn watch(&self, notifier: Arc<Notify>) {
+        self.wait.watch(notifier);
+    }
+
+    fn unwatch(&self, notifier: &Notify) {
+        self.wait.unwatch(notifier);
+    }
+}
+
+/// Future that completes with the next packet received on a connexion
+///
+/// It completes with None if the interface of the connexion went away.
+pub struct PopPacket {
+    conn: Arc<MultiConn>,
+    watcher: Option<Arc<Notify>>,
+}
+
+impl PopPacket {
+    /// Create a future popping the next packet received on `conn`
+    pub fn new(conn: Arc<MultiConn>) -> Self {
+        PopPacket {
+            conn: conn,
+            watcher: None,
+        }
+    }
+}
+
+impl Future for PopPacket {
+    type Output = Option<(Packet, Rule)>;
+
+    fn poll(&mut self, waker: &Waker) -> Poll<Option<(Packet, Rule)>> {
+        if self.watcher.is_none() {
+            let watcher = waker.as_notify();
+
+            self.conn.watch(watcher.clone());
+            self.watcher = Some(watcher);
+        }
+
+        match self.conn.try_pop_packet() {
+            Some(res) => Poll::Ready(Some(res)),
+            None if self.conn.is_detached() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for PopPacket {
+    fn drop(&mut self) {
+        if let Some(ref watcher) = self.watcher {
+            self.conn.unwatch(&**watcher);
+        }
+    }
+}
+
 impl Drop for MultiConn {
     fn drop(&mut self) {
-        // XXX: The way multi connexion should go down is not defined yet.
-        // This will eventually trigger when it's time to seriously think
-        // about it
-        unimplemented!();
+        // Stop following the interface
+        if let Some(intf) = self.parent.as_ref().and_then(|p| p.upgrade()) {
+            intf.write().unwatch_detach(&*self.wait);
+            intf.unwatch(&*self.wait);
+        }
     }
 }
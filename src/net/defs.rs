@@ -26,27 +26,46 @@ pub type ProtocolIdType = u8;
 /// Type that represent a port
 pub type PortType = u16;
 
-#[derive(Clone)]
+/// Size of an ethernet header
+pub const ETH_HDR_SIZE: usize = 14;
+
+/// Ether type of IPv4
+pub const ETHERTYPE_IPV4: EtherType = 0x0800;
+/// Ether type of ARP
+pub const ETHERTYPE_ARP: EtherType = 0x0806;
+/// Ether type of IPv6
+pub const ETHERTYPE_IPV6: EtherType = 0x86DD;
+
+/// Protocol id of ICMP
+pub const PROTOCOL_ICMP: ProtocolIdType = 1;
+/// Protocol id of TCP
+pub const PROTOCOL_TCP: ProtocolIdType = 6;
+/// Protocol id of UDP
+pub const PROTOCOL_UDP: ProtocolIdType = 17;
+/// Protocol id of ICMPv6
+pub const PROTOCOL_ICMPV6: ProtocolIdType = 58;
+
+#[derive(Clone, Hash)]
 /// Ethernet layer part of the rule
 pub struct EthernetRule {
     pub ether_type: EtherType,
     pub hw_in: Option<HwAddr>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 /// Network layer part of the rule
 pub struct NetworkRule {
     pub protocol_id: ProtocolIdType,
     pub ip_in: Option<IpAddr>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 /// Transport layer part of the rule
 pub struct TransportRule {
     pub port: PortType,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 /// Represent a rule that a packet must match to be enqueued in a connexion
 pub struct Rule {
     pub eth_rule: Option<EthernetRule>,
@@ -54,6 +73,38 @@ pub struct Rule {
     pub tspt_rule: Option<TransportRule>,
 }
 
+/// Compute the internet checksum (RFC 1071) of `data`
+///
+/// The result uses host's endianness.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+
+    for chunk in data.chunks(2) {
+        let hi = (chunk[0] as u32) << 8;
+
+        sum += if chunk.len() == 2 { hi | chunk[1] as u32 } else { hi };
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[inline]
+/// Read the big endian 16 bits word at `offset` in `bytes`
+pub fn be16(bytes: &[u8], offset: usize) -> u16 {
+    ((bytes[offset] as u16) << 8) | bytes[offset + 1] as u16
+}
+
+#[inline]
+/// Write `v` as a big endian 16 bits word at `offset` in `bytes`
+pub fn put16(bytes: &mut [u8], offset: usize, v: u16) {
+    bytes[offset] = (v >> 8) as u8;
+    bytes[offset + 1] = v as u8;
+}
+
 /// Trait implemented by hardware interfaces
 pub trait Device {
     /// Periodically called by the network thread to let the interface
@@ -69,7 +120,7 @@ pub trait Device {
 /// This type stores an integer using network's endianness and let the user
 /// manipulates it using host's endianness.
 #[repr(C, packed)]
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Int<T: PrimInt + Clone>(T);
 
 impl<T> Int<T> where T: PrimInt + Clone {
@@ -120,7 +171,7 @@ impl<T> Display for Int<T> where T: PrimInt + Clone + Display {
 
 const COUNT_HWADDR_BYTES: usize = 6;
 
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 /// An IP address, either V4 or V6
 pub enum IpAddr {
     /// An IPv4 address
@@ -130,7 +181,7 @@ pub enum IpAddr {
 }
 
 #[repr(C, packed)]
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 /// An IPv4 address
 pub struct Ipv4Addr {
     a: u8,
@@ -151,6 +202,57 @@ impl Ipv4Addr {
             d: d,
         }
     }
+
+    /// Creates the subnet mask matching a prefix of `len` bits
+    ///
+    /// `len` is capped to 32.
+    pub fn from_prefix_len(len: u8) -> Self {
+        let mask = if len == 0 {
+            0
+        } else {
+            !0u32 << (32 - ::core::cmp::min(len, 32) as u32)
+        };
+
+        Ipv4Addr::new((mask >> 24) as u8, (mask >> 16) as u8,
+                      (mask >> 8) as u8, mask as u8)
+    }
+
+    #[inline]
+    /// Returns true if the address is 0.0.0.0
+    pub fn is_unspecified(&self) -> bool {
+        self.a == 0 && self.b == 0 && self.c == 0 && self.d == 0
+    }
+
+    #[inline]
+    /// Returns the four bytes of the address
+    pub fn octets(&self) -> [u8; 4] {
+        [self.a, self.b, self.c, self.d]
+    }
+}
+
+impl FromStr for Ipv4Addr {
+    type Err = ();
+
+    /// Convert a string with format a.b.c.d to an IPv4 address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = [0u8; 4];
+        let mut count = 0;
+
+        for b in s.split('.') {
+            if count == bytes.len() || b.is_empty() {
+                return Err(());
+            }
+
+            bytes[count] = try!(u8::from_str(b).map_err(|_| ()));
+            count += 1;
+        }
+
+        if count != bytes.len() {
+            return Err(());
+        }
+
+        Ok(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+    }
 }
 
 impl Display for Ipv4Addr {
@@ -160,8 +262,14 @@ impl Display for Ipv4Addr {
     }
 }
 
+impl Debug for Ipv4Addr {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Display::fmt(self, f)
+    }
+}
+
 #[repr(C, packed)]
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 /// An IPv6 address
 pub struct Ipv6Addr {
     a: Int<u16>,
@@ -191,6 +299,13 @@ impl Ipv6Addr {
             h: Int::from_host(h),
         }
     }
+
+    /// Returns the eight 16-bit segments of the address
+    pub fn segments(&self) -> [u16; 8] {
+        [self.a.as_host(), self.b.as_host(), self.c.as_host(),
+         self.d.as_host(), self.e.as_host(), self.f.as_host(),
+         self.g.as_host(), self.h.as_host()]
+    }
 }
 
 impl Display for Ipv6Addr {
@@ -201,8 +316,14 @@ impl Display for Ipv6Addr {
     }
 }
 
+impl Debug for Ipv6Addr {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Display::fmt(self, f)
+    }
+}
+
 #[repr(C, packed)]
-#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 /// A MAC address
 pub struct HwAddr {
     bytes: [u8; COUNT_HWADDR_BYTES],
@@ -229,6 +350,13 @@ impl HwAddr {
         *self == Self::broadcast()
     }
 
+    #[inline]
+    /// Is the current hardware address a multicast address, broadcast
+    /// included
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] & 1 != 0
+    }
+
     /// Create an hardware address from bytes.
     ///
     /// This method is unsafe because the slice *MUST* contain at least 6
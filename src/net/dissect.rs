@@ -0,0 +1,475 @@
+//! Human readable description of packets
+//!
+//! Packets are summarized on a single line, in the spirit of tcpdump:
+//!
+//! ```text
+//! 00:16:3e:00:00:01 > ff:ff:ff:ff:ff:ff ARP who-has 10.0.0.1 tell 10.0.0.2
+//! 00:16:3e:00:00:01 > 00:16:3e:00:00:02 IPv4 10.0.0.2.49152 > 10.0.0.1.53: UDP, length 32
+//! ```
+//!
+//! Headers whose size was set by the protocol handlers (see
+//! `Packet::link_hdr_size()` and friends) are walked using these sizes,
+//! otherwise they are parsed from the frame. Truncated headers are reported
+//! with `[|proto]`.
+
+use core::fmt::{Display, Formatter, Error};
+
+use io::Write;
+
+use hal::console;
+
+use net::Packet;
+use net::defs::{be16, HwAddr, Ipv4Addr, Ipv6Addr, ETH_HDR_SIZE};
+use net::defs::{ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use net::defs::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP, PROTOCOL_ICMPV6};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Packets printed by an interface
+pub struct TraceMode {
+    /// Print received packets
+    pub rx: bool,
+    /// Print transmitted packets
+    pub tx: bool,
+    /// Follow the summary with a hexdump of the frame
+    pub hexdump: bool,
+}
+
+impl TraceMode {
+    /// Nothing is printed
+    pub fn off() -> Self {
+        TraceMode::default()
+    }
+
+    /// Returns true if nothing is printed
+    pub fn is_off(&self) -> bool {
+        !self.rx && !self.tx
+    }
+}
+
+#[inline]
+fn be32(b: &[u8], offset: usize) -> u32 {
+    ((be16(b, offset) as u32) << 16) | be16(b, offset + 2) as u32
+}
+
+#[inline]
+fn ipv4(b: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(b[offset], b[offset + 1], b[offset + 2], b[offset + 3])
+}
+
+/// One line summary of a packet, obtained with `Packet::summary()`
+pub struct Summary<'a> {
+    pkt: &'a Packet,
+}
+
+impl<'a> Summary<'a> {
+    fn arp(&self, f: &mut Formatter, arp: &[u8]) -> Result<(), Error> {
+        if arp.len() < 28 {
+            return f.write_str("ARP [|arp]");
+        }
+
+        let sender_hw = unsafe { HwAddr::from_bytes(&arp[8..14]) };
+
+        match be16(arp, 6) {
+            1 => f.write_fmt(format_args!("ARP who-has {} tell {}", ipv4(arp, 24),
+                                          ipv4(arp, 14))),
+            2 => f.write_fmt(format_args!("ARP reply {} is-at {}", ipv4(arp, 14), sender_hw)),
+            op => f.write_fmt(format_args!("ARP op {}", op)),
+        }
+    }
+
+    fn ipv4(&self, f: &mut Formatter, ip: &[u8]) -> Result<(), Error> {
+        if ip.len() < 20 {
+            return f.write_str("IPv4 [|ip]");
+        }
+
+        let hdr_size = match self.pkt.net_hdr_size() {
+            0 => ((ip[0] & 0x0f) as usize) * 4,
+            size => size,
+        };
+
+        let total = be16(ip, 2) as usize;
+        let (src, dst) = (ipv4(ip, 12), ipv4(ip, 16));
+        let protocol = ip[9];
+
+        // Only the first fragment carries the transport header
+        if be16(ip, 6) & 0x1fff != 0 {
+            return f.write_fmt(format_args!("IPv4 {} > {}: fragment offset {}, proto {}",
+                                            src, dst, (be16(ip, 6) & 0x1fff) * 8, protocol));
+        }
+
+        let end = if total >= hdr_size && total <= ip.len() { total } else { ip.len() };
+        let tspt = if hdr_size <= end { &ip[hdr_size..end] } else { &[][..] };
+
+        self.transport(f, "IPv4", &src, &dst, protocol, tspt)
+    }
+
+    fn ipv6(&self, f: &mut Formatter, ip: &[u8]) -> Result<(), Error> {
+        if ip.len() < 40 {
+            return f.write_str("IPv6 [|ip6]");
+        }
+
+        let seg = |o: usize| {
+            let s: [u16; 8] = [be16(ip, o), be16(ip, o + 2), be16(ip, o + 4),
+                               be16(ip, o + 6), be16(ip, o + 8), be16(ip, o + 10),
+                               be16(ip, o + 12), be16(ip, o + 14)];
+
+            Ipv6Addr::new(s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7])
+        };
+
+        let (src, dst) = (seg(8), seg(24));
+        let end = ::core::cmp::min(40 + be16(ip, 4) as usize, ip.len());
+
+        self.transport(f, "IPv6", &src, &dst, ip[6], &ip[40..end])
+    }
+
+    fn transport<A: Display>(&self, f: &mut Formatter, net: &str, src: &A,
+                             dst: &A, protocol: u8,
+                             tspt: &[u8]) -> Result<(), Error> {
+        match protocol {
+            PROTOCOL_UDP => {
+                if tspt.len() < 8 {
+                    return f.write_fmt(format_args!("{} {} > {}: [|udp]", net, src, dst));
+                }
+
+                f.write_fmt(format_args!("{} {}.{} > {}.{}: UDP, length {}", net, src,
+                                         be16(tspt, 0), dst, be16(tspt, 2),
+                                         (be16(tspt, 4) as usize).saturating_sub(8)))
+            }
+            PROTOCOL_TCP => {
+                if tspt.len() < 20 {
+                    return f.write_fmt(format_args!("{} {} > {}: [|tcp]", net, src, dst));
+                }
+
+                let hdr_size = match self.pkt.tspt_hdr_size() {
+                    0 => ((tspt[12] >> 4) as usize) * 4,
+                    size => size,
+                };
+
+                let flags = tspt[13];
+                let mut names = [0u8; 6];
+                let mut count = 0;
+
+                for &(bit, name) in &[(0x02, b'S'), (0x01, b'F'), (0x04, b'R'),
+                                      (0x08, b'P'), (0x20, b'U'), (0x10, b'.')] {
+                    if flags & bit != 0 {
+                        names[count] = name;
+                        count += 1;
+                    }
+                }
+
+                let names = ::core::str::from_utf8(&names[..count]).unwrap_or("");
+
+                try!(f.write_fmt(format_args!("{} {}.{} > {}.{}: Flags [{}], seq {}", net,
+                                              src, be16(tspt, 0), dst, be16(tspt, 2), names,
+                                              be32(tspt, 4))));
+
+                if flags & 0x10 != 0 {
+                    try!(f.write_fmt(format_args!(", ack {}", be32(tspt, 8))));
+                }
+
+                f.write_fmt(format_args!(", win {}, length {}", be16(tspt, 14),
+                                         tspt.len().saturating_sub(hdr_size)))
+            }
+            PROTOCOL_ICMP | PROTOCOL_ICMPV6 => {
+                let name = if protocol == PROTOCOL_ICMP { "ICMP" } else { "ICMP6" };
+
+                if tspt.len() < 4 {
+                    return f.write_fmt(format_args!("{} {} > {}: [|icmp]", net, src, dst));
+                }
+
+                let echo = match (protocol, tspt[0]) {
+                    (PROTOCOL_ICMP, 8) | (PROTOCOL_ICMPV6, 128) => Some("request"),
+                    (PROTOCOL_ICMP, 0) | (PROTOCOL_ICMPV6, 129) => Some("reply"),
+                    _ => None,
+                };
+
+                match echo {
+                    Some(kind) if tspt.len() >= 8 => {
+                        f.write_fmt(format_args!("{} {} > {}: {} echo {}, id {}, seq {}", net,
+                                                 src, dst, name, kind, be16(tspt, 4),
+                                                 be16(tspt, 6)))
+                    }
+                    _ => f.write_fmt(format_args!("{} {} > {}: {} type {}, code {}", net, src,
+                                                  dst, name, tspt[0], tspt[1])),
+                }
+            }
+            _ => f.write_fmt(format_args!("{} {} > {}: proto {}, length {}", net, src, dst,
+                                          protocol, tspt.len())),
+        }
+    }
+}
+
+impl<'a> Display for Summary<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let frame = self.pkt.as_bytes();
+
+        if frame.len() < ETH_HDR_SIZE {
+            return f.write_fmt(format_args!("[|ether] length {}", frame.len()));
+        }
+
+        let dest = unsafe { HwAddr::from_bytes(&frame[0..6]) };
+        let src = unsafe { HwAddr::from_bytes(&frame[6..12]) };
+        let ether_type = be16(frame, 12);
+
+        let hdr_size = match self.pkt.link_hdr_size() {
+            0 => ETH_HDR_SIZE,
+            size => ::core::cmp::min(size, frame.len()),
+        };
+
+        let net = &frame[hdr_size..];
+
+        try!(f.write_fmt(format_args!("{} > {} ", src, dest)));
+
+        match ether_type {
+            ETHERTYPE_ARP => self.arp(f, net),
+            ETHERTYPE_IPV4 => self.ipv4(f, net),
+            ETHERTYPE_IPV6 => self.ipv6(f, net),
+            _ => f.write_fmt(format_args!("ethertype 0x{:04x}, length {}", ether_type,
+                                          frame.len())),
+        }
+    }
+}
+
+/// Hexdump of a packet, obtained with `Packet::hexdump()`
+///
+/// Each line shows the offset, 16 bytes in hexadecimal and their ASCII
+/// representation.
+pub struct Hexdump<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Display for Hexdump<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        for (line, chunk) in self.bytes.chunks(16).enumerate() {
+            if line != 0 {
+                try!(f.write_str("\r\n"));
+            }
+
+            try!(f.write_fmt(format_args!("  0x{:04x}: ", line * 16)));
+
+            for i in 0..16 {
+                match chunk.get(i) {
+                    Some(b) => try!(f.write_fmt(format_args!("{:02x}", b))),
+                    None => try!(f.write_str("  ")),
+                }
+
+                if i % 2 == 1 {
+                    try!(f.write_str(" "));
+                }
+            }
+
+            try!(f.write_str(" "));
+
+            for &b in chunk {
+                let c = if b >= 0x20 && b < 0x7f { b as char } else { '.' };
+
+                try!(f.write_fmt(format_args!("{}", c)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Packet {
+    /// Returns a one line summary of the packet
+    pub fn summary(&self) -> Summary {
+        Summary {
+            pkt: self,
+        }
+    }
+
+    /// Returns a hexdump of the packet
+    pub fn hexdump(&self) -> Hexdump {
+        Hexdump {
+            bytes: self.as_bytes(),
+        }
+    }
+}
+
+impl Display for Packet {
+    /// Format the summary of the packet, followed by a hexdump with the
+    /// alternate flag (`{:#}`)
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        try!(self.summary().fmt(f));
+
+        if f.alternate() {
+            try!(f.write_str("\r\n"));
+            try!(self.hexdump().fmt(f));
+        }
+
+        Ok(())
+    }
+}
+
+/// Print a traced packet of interface `name`
+///
+/// `event` describes what happened to the packet (e.g. "rx"). The console is
+/// written directly so that traces are not mirrored to remote loggers, whose
+/// own packets would be traced in turn.
+pub fn trace(name: &str, event: &str, pkt: &Packet, mode: TraceMode) {
+    let mut console = console();
+
+    let _ = if mode.hexdump {
+        console.write_fmt(format_args!("{} {}: {:#}\r\n", name, event, pkt))
+    } else {
+        console.write_fmt(format_args!("{} {}: {}\r\n", name, event, pkt))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use string::String;
+    use vec::Vec;
+
+    use net::defs::{Ipv4Addr, Ipv6Addr, ETHERTYPE_ARP, ETHERTYPE_IPV6};
+    use net::defs::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+    use net::test_support::{self, hw, ipv4_frame, packet};
+
+    const SRC: &'static str = "00:16:3e:00:00:01 > 00:16:3e:00:00:02";
+
+    fn summary(frame: &[u8]) -> String {
+        format!("{}", packet(frame).summary())
+    }
+
+    /// Build a frame from 00:16:3e:00:00:01 to 00:16:3e:00:00:02
+    fn eth(ether_type: u16, payload: &[u8]) -> Vec<u8> {
+        test_support::eth(&hw(2), &hw(1), ether_type, payload)
+    }
+
+    /// Build a frame carrying an IPv4 datagram from 10.0.0.2 to 10.0.0.1
+    fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        ipv4_frame(&Ipv4Addr::new(10, 0, 0, 2), &Ipv4Addr::new(10, 0, 0, 1),
+                   protocol, payload)
+    }
+
+    #[test]
+    fn test_arp() {
+        let mut arp = vec![0, 1, 0x08, 0, 6, 4, 0, 1,
+                           0x00, 0x16, 0x3e, 0, 0, 1, 10, 0, 0, 2,
+                           0, 0, 0, 0, 0, 0, 10, 0, 0, 1];
+
+        assert_eq!(summary(&eth(ETHERTYPE_ARP, &arp)),
+                   format!("{} ARP who-has 10.0.0.1 tell 10.0.0.2", SRC));
+
+        arp[7] = 2;
+
+        assert_eq!(summary(&eth(ETHERTYPE_ARP, &arp)),
+                   format!("{} ARP reply 10.0.0.2 is-at 00:16:3e:00:00:01",
+                           SRC));
+
+        arp[7] = 9;
+
+        assert_eq!(summary(&eth(ETHERTYPE_ARP, &arp)),
+                   format!("{} ARP op 9", SRC));
+        assert_eq!(summary(&eth(ETHERTYPE_ARP, &arp[..20])),
+                   format!("{} ARP [|arp]", SRC));
+    }
+
+    #[test]
+    fn test_udp() {
+        let mut udp = vec![0xc0, 0, 0, 53, 0, 40, 0, 0];
+
+        udp.extend_from_slice(&[0; 32]);
+
+        assert_eq!(summary(&ipv4(PROTOCOL_UDP, &udp)),
+                   format!("{} IPv4 10.0.0.2.49152 > 10.0.0.1.53: UDP, \
+                            length 32", SRC));
+        assert_eq!(summary(&ipv4(PROTOCOL_UDP, &udp[..6])),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: [|udp]", SRC));
+    }
+
+    #[test]
+    fn test_tcp() {
+        let mut tcp = vec![0x1f, 0x90, 0xc0, 0, 0, 0, 0, 100, 0, 0, 0, 42, 0x50,
+                           0x12, 0x72, 0x10, 0, 0, 0, 0];
+
+        assert_eq!(summary(&ipv4(PROTOCOL_TCP, &tcp)),
+                   format!("{} IPv4 10.0.0.2.8080 > 10.0.0.1.49152: Flags \
+                            [S.], seq 100, ack 42, win 29200, length 0", SRC));
+
+        // Push without ACK, followed by data
+        tcp[13] = 0x08;
+        tcp.extend_from_slice(b"hello");
+
+        assert_eq!(summary(&ipv4(PROTOCOL_TCP, &tcp)),
+                   format!("{} IPv4 10.0.0.2.8080 > 10.0.0.1.49152: Flags \
+                            [P], seq 100, win 29200, length 5", SRC));
+    }
+
+    #[test]
+    fn test_icmp() {
+        assert_eq!(summary(&ipv4(PROTOCOL_ICMP, &[8, 0, 0, 0, 0, 7, 0, 1])),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: ICMP echo request, \
+                            id 7, seq 1", SRC));
+        assert_eq!(summary(&ipv4(PROTOCOL_ICMP, &[3, 3, 0, 0, 0, 0, 0, 0])),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: ICMP type 3, code 3",
+                           SRC));
+        assert_eq!(summary(&ipv4(PROTOCOL_ICMP, &[0, 0])),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: [|icmp]", SRC));
+        assert_eq!(summary(&ipv4(47, &[0; 12])),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: proto 47, length 12",
+                           SRC));
+    }
+
+    #[test]
+    fn test_ipv4() {
+        // Fragments other than the first are not dissected further
+        let mut frame = ipv4(PROTOCOL_UDP, &[0; 16]);
+
+        frame[20] = 0x20;
+        frame[21] = 0x02;
+
+        assert_eq!(summary(&frame),
+                   format!("{} IPv4 10.0.0.2 > 10.0.0.1: fragment offset 16, \
+                            proto 17", SRC));
+
+        // The padding of short frames is not part of the datagram
+        let mut frame = ipv4(PROTOCOL_UDP, &[0xc0, 0, 0, 53, 0, 10, 0, 0, 1,
+                                             2]);
+
+        frame.extend_from_slice(&[0; 8]);
+
+        assert_eq!(summary(&frame),
+                   format!("{} IPv4 10.0.0.2.49152 > 10.0.0.1.53: UDP, \
+                            length 2", SRC));
+        assert_eq!(summary(&frame[..30]), format!("{} IPv4 [|ip]", SRC));
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let mut ip = vec![0x60, 0, 0, 0, 0, 8, PROTOCOL_UDP, 64];
+
+        ip.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0,
+                               0, 0, 0, 0, 0, 0, 0, 1]);
+        ip.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0,
+                               0, 0, 0, 0, 0, 0, 0, 2]);
+        ip.extend_from_slice(&[0x02, 0x22, 0x02, 0x23, 0, 8, 0, 0]);
+
+        let src = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+
+        assert_eq!(summary(&eth(ETHERTYPE_IPV6, &ip)),
+                   format!("{} IPv6 {}.546 > {}.547: UDP, length 0", SRC, src,
+                           dst));
+        assert_eq!(summary(&eth(ETHERTYPE_IPV6, &ip[..30])),
+                   format!("{} IPv6 [|ip6]", SRC));
+    }
+
+    #[test]
+    fn test_ethernet() {
+        assert_eq!(summary(&eth(0x88cc, &[0; 46])),
+                   format!("{} ethertype 0x88cc, length 60", SRC));
+        assert_eq!(summary(&[0; 10]), "[|ether] length 10");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let pkt = packet(b"0123456789abcdef\x00\x01\x7f");
+
+        assert_eq!(format!("{}", pkt.hexdump()),
+                   "  0x0000: 3031 3233 3435 3637 3839 6162 6364 6566  \
+                    0123456789abcdef\r\n  \
+                    0x0010: 0001 7f                                  ...");
+    }
+}
@@ -7,3 +7,26 @@ mod defs;
 mod sanitizer;
 mod extractor;
 mod callbacks;
+
+use net::defs::{HwAddr, EtherType};
+
+use net::conn::filter::{GenericFilter, SpecificFilter};
+
+use self::sanitizer::EthernetPacketSanitizer;
+use self::extractor::{EtherTypeExtractor, SourceHwExtractor};
+use self::callbacks::EthernetCallbacks;
+
+pub use self::defs::Header;
+
+/// Filter routing frames on their source hardware address
+pub type EthernetSpecificFilter = SpecificFilter<HwAddr, EtherType,
+                                                 SourceHwExtractor,
+                                                 EthernetCallbacks>;
+
+/// Filter routing frames on their ether type
+///
+/// This is the entry point of received frames in the filters.
+pub type EthernetGenericFilter = GenericFilter<EtherType,
+                                               EthernetSpecificFilter,
+                                               EtherTypeExtractor,
+                                               EthernetPacketSanitizer>;
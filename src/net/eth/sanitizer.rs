@@ -16,8 +16,9 @@ impl PacketSanitizer for EthernetPacketSanitizer {
     /// accept it
     fn sanitize(pkt: &mut Packet) -> Result<(), ()> {
         // Verify the packet
-        // For now we only accept packets that targets directly the interface
-        // the packet was received on. The only exception is broadcast packets
+        // We only accept packets that targets directly the interface the
+        // packet was received on, unless it is in promiscuous mode. The only
+        // exception is broadcast packets
         {
             // Incoming packet *MUST* have an interface set
             let intf = try!(pkt.interface().ok_or(()));
@@ -25,9 +26,11 @@ impl PacketSanitizer for EthernetPacketSanitizer {
             // Get a reference over the ethernet header
             let hdr = try!(pkt.link_header::<Header>().ok_or(()));
 
-            // For now we only accept packets that are either for us or
-            // broadcast
-            if *intf.read().hw_addr_ref() != hdr.dest &&
+            let intf = intf.read();
+
+            // Promiscuous interfaces accept everything, the others only
+            // packets that are either for us or broadcast
+            if !intf.is_promiscuous() && *intf.hw_addr_ref() != hdr.dest &&
                 !hdr.dest.is_broadcast() {
                 return Err(())
             }
@@ -0,0 +1,376 @@
+//! Connection tracking of TCP and UDP flows
+//!
+//! The table is generic over the key identifying flows so that it is shared
+//! by the firewall and the address translation.
+
+use vec::Vec;
+use btree_map::BTreeMap;
+
+use time::{Duration, Instant};
+
+use net::defs::{IpAddr, PortType, ProtocolIdType, PROTOCOL_ICMP};
+use net::defs::PROTOCOL_TCP;
+
+use super::{Direction, Flow};
+use super::flow::{TCP_FIN, TCP_RST};
+
+/// Timeout of a flow seen in one direction only
+const NEW_TIMEOUT_SECS: u64 = 30;
+/// Timeout of an established UDP flow
+const UDP_ESTABLISHED_TIMEOUT_SECS: u64 = 180;
+/// Timeout of an established TCP connection
+const TCP_ESTABLISHED_TIMEOUT_SECS: u64 = 7200;
+/// Timeout of a TCP connection being closed
+const TCP_CLOSING_TIMEOUT_SECS: u64 = 10;
+
+/// Key identifying a tracked flow
+pub trait TrackedKey: Ord + Clone {
+    /// Returns the transport protocol of the flow
+    fn protocol(&self) -> ProtocolIdType;
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+/// Identify a flow from the point of view of the unikernel
+pub struct FlowKey {
+    /// Transport protocol of the flow
+    pub protocol: ProtocolIdType,
+    /// Local address
+    pub local: IpAddr,
+    /// Local port
+    pub local_port: PortType,
+    /// Remote address
+    pub remote: IpAddr,
+    /// Remote port
+    pub remote_port: PortType,
+}
+
+impl FlowKey {
+    /// Build the key of a flow going in direction `dir`
+    ///
+    /// Returns None if the flow cannot be tracked.
+    pub fn from_flow(flow: &Flow, dir: Direction) -> Option<Self> {
+        if !flow.is_trackable() {
+            return None;
+        }
+
+        let (src, dst) = (flow.src.clone().unwrap(), flow.dst.clone().unwrap());
+        let (sport, dport) = (flow.src_port.unwrap(), flow.dst_port.unwrap());
+
+        let (local, local_port, remote, remote_port) = match dir {
+            Direction::In => (dst, dport, src, sport),
+            Direction::Out => (src, sport, dst, dport),
+        };
+
+        Some(FlowKey {
+            protocol: flow.protocol.unwrap(),
+            local: local,
+            local_port: local_port,
+            remote: remote,
+            remote_port: remote_port,
+        })
+    }
+}
+
+impl TrackedKey for FlowKey {
+    #[inline]
+    fn protocol(&self) -> ProtocolIdType {
+        self.protocol
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// State of a tracked flow
+pub enum ConnState {
+    /// Packets were only seen in the direction that opened the flow
+    New,
+    /// Packets were seen in both directions
+    Established,
+    /// The TCP connection is being closed
+    Closing,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Lifetimes of idle tracked flows
+///
+/// The UDP ones also apply to protocols other than TCP and ICMP.
+pub struct Timeouts {
+    /// TCP connections being opened
+    pub tcp_new: Duration,
+    /// Established TCP connections
+    pub tcp_established: Duration,
+    /// TCP connections being closed
+    pub tcp_closing: Duration,
+    /// UDP flows seen in one direction only
+    pub udp_new: Duration,
+    /// UDP flows seen in both directions
+    pub udp_established: Duration,
+    /// ICMP flows, e.g. echo requests and their replies
+    pub icmp: Duration,
+}
+
+impl Timeouts {
+    /// Lifetimes used by the firewall
+    pub fn new() -> Self {
+        Timeouts {
+            tcp_new: Duration::from_secs(NEW_TIMEOUT_SECS),
+            tcp_established: Duration::from_secs(TCP_ESTABLISHED_TIMEOUT_SECS),
+            tcp_closing: Duration::from_secs(TCP_CLOSING_TIMEOUT_SECS),
+            udp_new: Duration::from_secs(NEW_TIMEOUT_SECS),
+            udp_established: Duration::from_secs(UDP_ESTABLISHED_TIMEOUT_SECS),
+            icmp: Duration::from_secs(NEW_TIMEOUT_SECS),
+        }
+    }
+
+    fn get(&self, protocol: ProtocolIdType, state: ConnState) -> Duration {
+        match (protocol, state) {
+            (PROTOCOL_TCP, ConnState::New) => self.tcp_new,
+            (PROTOCOL_TCP, ConnState::Established) => self.tcp_established,
+            (_, ConnState::Closing) => self.tcp_closing,
+            (PROTOCOL_ICMP, _) => self.icmp,
+            (_, ConnState::New) => self.udp_new,
+            (_, ConnState::Established) => self.udp_established,
+        }
+    }
+}
+
+struct Entry {
+    state: ConnState,
+    origin: Direction,
+    expires: Instant,
+}
+
+/// Table of tracked flows
+pub struct Conntrack<K: TrackedKey = FlowKey> {
+    entries: BTreeMap<K, Entry>,
+    max_entries: usize,
+    timeouts: Timeouts,
+}
+
+impl<K: TrackedKey> Conntrack<K> {
+    /// Create an empty table holding at most `max_entries` flows, with the
+    /// lifetimes of the firewall
+    pub fn new(max_entries: usize) -> Self {
+        Self::with_timeouts(max_entries, Timeouts::new())
+    }
+
+    /// Create an empty table holding at most `max_entries` flows that
+    /// expire after `timeouts`
+    pub fn with_timeouts(max_entries: usize, timeouts: Timeouts) -> Self {
+        Conntrack {
+            entries: BTreeMap::new(),
+            max_entries: max_entries,
+            timeouts: timeouts,
+        }
+    }
+
+    #[inline]
+    /// Returns the number of tracked flows
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    /// Returns true if no flow is tracked
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the state of a flow if it is tracked
+    pub fn state(&self, key: &K) -> Option<ConnState> {
+        self.entries.get(key).map(|e| e.state)
+    }
+
+    /// Returns true if a flow is tracked and did not expire at `now`
+    pub fn is_live(&self, key: &K, now: Instant) -> bool {
+        self.entries.get(key).map_or(false, |e| e.expires > now)
+    }
+
+    /// Update a tracked flow with a packet going in direction `dir`
+    ///
+    /// Returns the new state of the flow, or None if the flow is not tracked
+    /// (or expired).
+    pub fn update(&mut self, key: &K, dir: Direction, tcp_flags: u8,
+                  now: Instant) -> Option<ConnState> {
+        let protocol = key.protocol();
+        let timeouts = self.timeouts;
+
+        let (state, remove) = match self.entries.get_mut(key) {
+            None => return None,
+            Some(entry) => {
+                if entry.expires <= now {
+                    (None, true)
+                } else if protocol == PROTOCOL_TCP && tcp_flags & TCP_RST != 0 {
+                    // The connection is aborted, let the packet go through
+                    (Some(entry.state), true)
+                } else {
+                    if protocol == PROTOCOL_TCP && tcp_flags & TCP_FIN != 0 {
+                        entry.state = ConnState::Closing;
+                    } else if entry.state == ConnState::New &&
+                              dir != entry.origin {
+                        entry.state = ConnState::Established;
+                    }
+
+                    entry.expires = now + timeouts.get(protocol, entry.state);
+
+                    (Some(entry.state), false)
+                }
+            }
+        };
+
+        if remove {
+            self.entries.remove(key);
+        }
+
+        state
+    }
+
+    /// Start tracking a flow opened by a packet going in direction `dir`
+    ///
+    /// Returns false if the table is full.
+    pub fn track(&mut self, key: K, dir: Direction, now: Instant) -> bool {
+        if self.entries.len() >= self.max_entries {
+            self.expire(now);
+
+            if self.entries.len() >= self.max_entries {
+                return false;
+            }
+        }
+
+        let expires = now + self.timeouts.get(key.protocol(), ConnState::New);
+
+        self.entries.insert(key, Entry {
+            state: ConnState::New,
+            origin: dir,
+            expires: expires,
+        });
+
+        true
+    }
+
+    /// Stop tracking flows whose timeout expired
+    pub fn expire(&mut self, now: Instant) {
+        let expired: Vec<K> = self.entries.iter()
+                                  .filter(|&(_, e)| e.expires <= now)
+                                  .map(|(k, _)| k.clone())
+                                  .collect();
+
+        for key in expired {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Stop tracking a flow
+    ///
+    /// Returns false if the flow was not tracked.
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    /// Stop tracking every flow
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::{Duration, Instant};
+
+    use net::defs::{IpAddr, Ipv4Addr, PROTOCOL_TCP, PROTOCOL_UDP};
+
+    use super::{Conntrack, ConnState, FlowKey};
+    use super::super::Direction;
+    use super::super::flow::{TCP_ACK, TCP_FIN, TCP_RST, TCP_SYN};
+
+    fn flow_key(protocol: u8, remote_port: u16) -> FlowKey {
+        FlowKey {
+            protocol: protocol,
+            local: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            local_port: 4000,
+            remote: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            remote_port: remote_port,
+        }
+    }
+
+    #[test]
+    fn test_udp() {
+        let mut ct = Conntrack::new(16);
+        let key = flow_key(PROTOCOL_UDP, 53);
+        let now = Instant::now();
+
+        assert_eq!(ct.update(&key, Direction::In, 0, now), None);
+        assert!(ct.track(key.clone(), Direction::Out, now));
+        assert_eq!(ct.state(&key), Some(ConnState::New));
+
+        // More packets in the opening direction keep the flow new, an answer
+        // establishes it
+        assert_eq!(ct.update(&key, Direction::Out, 0, now),
+                   Some(ConnState::New));
+        assert_eq!(ct.update(&key, Direction::In, 0, now),
+                   Some(ConnState::Established));
+        assert_eq!(ct.update(&key, Direction::Out, 0, now),
+                   Some(ConnState::Established));
+
+        // Established flows live longer than new ones
+        let later = now + Duration::from_secs(60);
+
+        assert_eq!(ct.update(&key, Direction::In, 0, later),
+                   Some(ConnState::Established));
+        assert_eq!(ct.update(&key, Direction::In, 0,
+                             later + Duration::from_secs(180)), None);
+        assert!(ct.is_empty());
+    }
+
+    #[test]
+    fn test_tcp() {
+        let mut ct = Conntrack::new(16);
+        let key = flow_key(PROTOCOL_TCP, 80);
+        let now = Instant::now();
+
+        assert!(ct.track(key.clone(), Direction::Out, now));
+        assert_eq!(ct.update(&key, Direction::In, TCP_SYN | TCP_ACK, now),
+                   Some(ConnState::Established));
+
+        // Closing connections expire quickly
+        assert_eq!(ct.update(&key, Direction::Out, TCP_FIN | TCP_ACK, now),
+                   Some(ConnState::Closing));
+        assert_eq!(ct.update(&key, Direction::In, TCP_ACK, now),
+                   Some(ConnState::Closing));
+        assert_eq!(ct.update(&key, Direction::In, TCP_ACK,
+                             now + Duration::from_secs(10)), None);
+
+        // A reset goes through and ends the tracking
+        assert!(ct.track(key.clone(), Direction::In, now));
+        assert_eq!(ct.update(&key, Direction::Out, TCP_RST, now),
+                   Some(ConnState::New));
+        assert_eq!(ct.state(&key), None);
+
+        // Flags are ignored for other protocols
+        let udp = flow_key(PROTOCOL_UDP, 80);
+
+        assert!(ct.track(udp.clone(), Direction::Out, now));
+        assert_eq!(ct.update(&udp, Direction::Out, TCP_RST | TCP_FIN, now),
+                   Some(ConnState::New));
+    }
+
+    #[test]
+    fn test_full() {
+        let mut ct = Conntrack::new(2);
+        let now = Instant::now();
+
+        assert!(ct.track(flow_key(PROTOCOL_UDP, 1), Direction::Out, now));
+        assert!(ct.track(flow_key(PROTOCOL_UDP, 2), Direction::Out, now));
+        assert!(!ct.track(flow_key(PROTOCOL_UDP, 3), Direction::Out, now));
+        assert_eq!(ct.len(), 2);
+
+        // Expired flows make room for new ones
+        let later = now + Duration::from_secs(30);
+
+        assert!(ct.track(flow_key(PROTOCOL_UDP, 3), Direction::Out, later));
+        assert_eq!(ct.len(), 1);
+
+        ct.clear();
+
+        assert!(ct.is_empty());
+    }
+}
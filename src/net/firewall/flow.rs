@@ -0,0 +1,255 @@
+//! Extraction of the fields the firewall matches on
+
+use net::defs::{IpAddr, Ipv4Addr, Ipv6Addr, EtherType, ProtocolIdType};
+use net::defs::{PortType, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use net::defs::{PROTOCOL_TCP, PROTOCOL_UDP};
+use net::defs::{be16, ETH_HDR_SIZE};
+
+const IPV4_MIN_HDR_SIZE: usize = 20;
+const IPV6_HDR_SIZE: usize = 40;
+
+/// TCP FIN flag
+pub const TCP_FIN: u8 = 1 << 0;
+/// TCP SYN flag
+pub const TCP_SYN: u8 = 1 << 1;
+/// TCP RST flag
+pub const TCP_RST: u8 = 1 << 2;
+/// TCP ACK flag
+pub const TCP_ACK: u8 = 1 << 4;
+
+#[derive(Clone, Debug)]
+/// Fields of a frame the firewall can match on
+///
+/// Fields of upper layers are None if the frame is too short or if the
+/// protocol is not understood.
+pub struct Flow {
+    /// Ether type of the frame
+    pub ether_type: EtherType,
+    /// Source address of the network layer
+    pub src: Option<IpAddr>,
+    /// Destination address of the network layer
+    pub dst: Option<IpAddr>,
+    /// Protocol carried by the network layer
+    pub protocol: Option<ProtocolIdType>,
+    /// Source port (TCP and UDP only)
+    pub src_port: Option<PortType>,
+    /// Destination port (TCP and UDP only)
+    pub dst_port: Option<PortType>,
+    /// TCP flags, 0 for other protocols
+    pub tcp_flags: u8,
+    /// Offset of the network header in the frame
+    pub net_offset: usize,
+    /// Offset of the transport header in the frame
+    pub tspt_offset: usize,
+}
+
+impl Flow {
+    /// Parse an ethernet frame
+    ///
+    /// Returns None if the frame is shorter than an ethernet header.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ETH_HDR_SIZE {
+            return None;
+        }
+
+        let mut flow = Flow {
+            ether_type: be16(frame, 12),
+            src: None,
+            dst: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            tcp_flags: 0,
+            net_offset: ETH_HDR_SIZE,
+            tspt_offset: 0,
+        };
+
+        match flow.ether_type {
+            ETHERTYPE_IPV4 => flow.parse_ipv4(frame),
+            ETHERTYPE_IPV6 => flow.parse_ipv6(frame),
+            _ => (),
+        }
+
+        Some(flow)
+    }
+
+    fn parse_ipv4(&mut self, frame: &[u8]) {
+        let hdr = &frame[ETH_HDR_SIZE..];
+
+        if hdr.len() < IPV4_MIN_HDR_SIZE || hdr[0] >> 4 != 4 {
+            return;
+        }
+
+        let hdr_size = ((hdr[0] & 0xf) as usize) * 4;
+
+        self.src = Some(IpAddr::V4(Ipv4Addr::new(hdr[12], hdr[13], hdr[14],
+                                                 hdr[15])));
+        self.dst = Some(IpAddr::V4(Ipv4Addr::new(hdr[16], hdr[17], hdr[18],
+                                                 hdr[19])));
+        self.protocol = Some(hdr[9]);
+
+        // Only the first fragment carries the transport header
+        if be16(hdr, 6) & 0x1fff != 0 || hdr_size < IPV4_MIN_HDR_SIZE {
+            return;
+        }
+
+        self.parse_transport(frame, ETH_HDR_SIZE + hdr_size);
+    }
+
+    fn parse_ipv6(&mut self, frame: &[u8]) {
+        let hdr = &frame[ETH_HDR_SIZE..];
+
+        if hdr.len() < IPV6_HDR_SIZE || hdr[0] >> 4 != 6 {
+            return;
+        }
+
+        let addr = |offset: usize| {
+            IpAddr::V6(Ipv6Addr::new(be16(hdr, offset), be16(hdr, offset + 2),
+                                     be16(hdr, offset + 4), be16(hdr, offset + 6),
+                                     be16(hdr, offset + 8), be16(hdr, offset + 10),
+                                     be16(hdr, offset + 12), be16(hdr, offset + 14)))
+        };
+
+        self.src = Some(addr(8));
+        self.dst = Some(addr(24));
+
+        // XXX: Extension headers are not walked through
+        self.protocol = Some(hdr[6]);
+
+        self.parse_transport(frame, ETH_HDR_SIZE + IPV6_HDR_SIZE);
+    }
+
+    fn parse_transport(&mut self, frame: &[u8], offset: usize) {
+        let min_size = match self.protocol {
+            Some(PROTOCOL_TCP) => 14,
+            Some(PROTOCOL_UDP) => 8,
+            _ => return,
+        };
+
+        if frame.len() < offset + min_size {
+            return;
+        }
+
+        self.tspt_offset = offset;
+        self.src_port = Some(be16(frame, offset));
+        self.dst_port = Some(be16(frame, offset + 2));
+
+        if self.protocol == Some(PROTOCOL_TCP) {
+            self.tcp_flags = frame[offset + 13];
+        }
+    }
+
+    #[inline]
+    /// Returns true if the flow can be tracked (i.e. TCP or UDP)
+    pub fn is_trackable(&self) -> bool {
+        self.src.is_some() && self.src_port.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vec::Vec;
+
+    use net::defs::{IpAddr, Ipv4Addr, Ipv6Addr, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use net::defs::{ETHERTYPE_IPV6, PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+    use net::test_support::{eth, hw, ipv4_frame};
+
+    use super::{Flow, TCP_SYN, TCP_ACK};
+
+    /// Build an ethernet frame carrying an IPv4 datagram from 10.0.0.1 to
+    /// 10.0.0.2
+    fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        ipv4_frame(&Ipv4Addr::new(10, 0, 0, 1), &Ipv4Addr::new(10, 0, 0, 2),
+                   protocol, payload)
+    }
+
+    fn tcp(flags: u8) -> Vec<u8> {
+        let mut hdr = vec![0x0f, 0xa0, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50];
+
+        hdr.extend_from_slice(&[flags, 0xff, 0xff, 0, 0, 0, 0]);
+
+        hdr
+    }
+
+    #[test]
+    fn test_parse_ipv4() {
+        let flow = Flow::parse(&ipv4(PROTOCOL_UDP, &[0, 53, 0x10, 0,
+                                                     0, 8, 0, 0])).unwrap();
+
+        assert_eq!(flow.ether_type, ETHERTYPE_IPV4);
+        assert_eq!(flow.src, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert_eq!(flow.dst, Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+        assert_eq!(flow.protocol, Some(PROTOCOL_UDP));
+        assert_eq!((flow.src_port, flow.dst_port), (Some(53), Some(4096)));
+        assert_eq!((flow.net_offset, flow.tspt_offset), (14, 34));
+        assert_eq!(flow.tcp_flags, 0);
+        assert!(flow.is_trackable());
+
+        let flow = Flow::parse(&ipv4(PROTOCOL_TCP, &tcp(TCP_SYN | TCP_ACK)))
+                       .unwrap();
+
+        assert_eq!((flow.src_port, flow.dst_port), (Some(4000), Some(80)));
+        assert_eq!(flow.tcp_flags, TCP_SYN | TCP_ACK);
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        // Truncated transport header
+        let flow = Flow::parse(&ipv4(PROTOCOL_TCP, &tcp(0)[..10])).unwrap();
+
+        assert_eq!(flow.protocol, Some(PROTOCOL_TCP));
+        assert_eq!(flow.src_port, None);
+        assert!(!flow.is_trackable());
+
+        // Fragments other than the first do not carry the transport header
+        let mut frame = ipv4(PROTOCOL_UDP, &[0, 53, 0x10, 0, 0, 8, 0, 0]);
+
+        frame[21] = 0x10;
+
+        let flow = Flow::parse(&frame).unwrap();
+
+        assert!(flow.src.is_some() && flow.dst_port.is_none());
+
+        // No ports for ICMP
+        let flow = Flow::parse(&ipv4(PROTOCOL_ICMP, &[8, 0, 0, 0])).unwrap();
+
+        assert_eq!(flow.protocol, Some(PROTOCOL_ICMP));
+        assert!(!flow.is_trackable());
+
+        // Truncated network header and unknown ether types
+        let frame = ipv4(PROTOCOL_UDP, &[]);
+        let flow = Flow::parse(&frame[..30]).unwrap();
+
+        assert!(flow.src.is_none() && flow.protocol.is_none());
+
+        let frame = eth(&hw(2), &hw(1), ETHERTYPE_ARP, &[0; 46]);
+        let flow = Flow::parse(&frame).unwrap();
+
+        assert_eq!(flow.ether_type, ETHERTYPE_ARP);
+        assert!(flow.src.is_none());
+
+        assert!(Flow::parse(&frame[..13]).is_none());
+    }
+
+    #[test]
+    fn test_parse_ipv6() {
+        let mut ip = vec![0x60, 0, 0, 0, 0, 8, PROTOCOL_UDP, 64];
+
+        ip.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0,
+                               0, 0, 0, 0, 0, 0, 0, 1]);
+        ip.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0,
+                               0, 0, 0, 0, 0, 0, 0, 2]);
+        ip.extend_from_slice(&[0x02, 0x22, 0x02, 0x23, 0, 8, 0, 0]);
+
+        let frame = eth(&hw(2), &hw(1), ETHERTYPE_IPV6, &ip);
+        let flow = Flow::parse(&frame).unwrap();
+        let addr = |last| IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0,
+                                                   last));
+
+        assert_eq!(flow.ether_type, ETHERTYPE_IPV6);
+        assert_eq!(flow.src, Some(addr(1)));
+        assert_eq!(flow.dst, Some(addr(2)));
+        assert_eq!((flow.src_port, flow.dst_port), (Some(546), Some(547)));
+        assert_eq!(flow.tspt_offset, 54);
+    }
+}
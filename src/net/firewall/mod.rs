@@ -0,0 +1,659 @@
+//! Rule based packet filter
+//!
+//! The firewall sits in the rx and tx path of an `Instance`. Every frame is
+//! matched against an ordered list of rules and the first matching rule
+//! decides what happens to it. If no rule matches, the default action of the
+//! direction applies.
+//!
+//! When the firewall is stateful, TCP and UDP flows accepted once are tracked
+//! so that the following packets of the flow, in both directions, are
+//! accepted without going through the rules.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use string::String;
+use vec::Vec;
+
+use sync::spin::{SpinLock, SpinGuard, RwLock};
+
+use time::Instant;
+
+use net::defs::{IpAddr, EtherType, ProtocolIdType, PortType, HwAddr};
+use net::defs::{ETHERTYPE_IPV4, PROTOCOL_ICMP, checksum};
+
+mod flow;
+mod conntrack;
+
+pub use self::flow::Flow;
+pub use self::conntrack::{Conntrack, ConnState, FlowKey, Timeouts};
+pub use self::conntrack::TrackedKey;
+
+/// Maximum number of flows tracked at the same time
+const MAX_TRACKED_FLOWS: usize = 4096;
+
+/// Number of bytes of the offending datagram quoted in ICMP errors after
+/// its IP header
+const ICMP_QUOTE_SIZE: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+/// Direction of a frame
+pub enum Direction {
+    /// Received from the network
+    In,
+    /// Transmitted to the network
+    Out,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// What to do with a frame
+pub enum Action {
+    /// Let the frame go through
+    Accept,
+    /// Silently discard the frame
+    Drop,
+    /// Discard the frame and tell the sender.
+    ///
+    /// Rejected incoming IPv4 frames are answered with an ICMP
+    /// "communication administratively prohibited" error. Other frames are
+    /// dropped. Rejected outgoing frames make the transmission fail.
+    Reject,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Inclusive range of ports
+pub struct PortRange {
+    pub start: PortType,
+    pub end: PortType,
+}
+
+impl PortRange {
+    /// Range of ports from `start` to `end` included
+    pub fn new(start: PortType, end: PortType) -> Self {
+        PortRange {
+            start: start,
+            end: end,
+        }
+    }
+
+    /// Range made of a single `port`
+    pub fn single(port: PortType) -> Self {
+        PortRange::new(port, port)
+    }
+
+    #[inline]
+    /// Returns true if `port` is within the range
+    pub fn contains(&self, port: PortType) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// An address prefix, e.g. 10.0.0.0/8
+pub struct IpPrefix {
+    pub addr: IpAddr,
+    pub len: u8,
+}
+
+impl IpPrefix {
+    /// Prefix made of the first `len` bits of `addr`
+    pub fn new(addr: IpAddr, len: u8) -> Self {
+        IpPrefix {
+            addr: addr,
+            len: len,
+        }
+    }
+
+    /// Returns true if `addr` belongs to the prefix
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (&self.addr, addr) {
+            (&IpAddr::V4(ref prefix), &IpAddr::V4(ref addr)) => {
+                prefix_match(&prefix.octets(), &addr.octets(), self.len)
+            }
+            (&IpAddr::V6(ref prefix), &IpAddr::V6(ref addr)) => {
+                prefix_match(&v6_bytes(prefix.segments()),
+                             &v6_bytes(addr.segments()), self.len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn v6_bytes(segments: [u16; 8]) -> [u8; 16] {
+    let mut bytes = [0; 16];
+
+    for (i, s) in segments.iter().enumerate() {
+        bytes[2 * i] = (s >> 8) as u8;
+        bytes[2 * i + 1] = *s as u8;
+    }
+
+    bytes
+}
+
+fn prefix_match(prefix: &[u8], addr: &[u8], len: u8) -> bool {
+    let len = ::core::cmp::min(len as usize, prefix.len() * 8);
+    let full = len / 8;
+    let rem = len % 8;
+
+    if prefix[..full] != addr[..full] {
+        return false;
+    }
+
+    if rem == 0 {
+        return true;
+    }
+
+    let mask = !0u8 << (8 - rem);
+
+    prefix[full] & mask == addr[full] & mask
+}
+
+#[derive(Clone, Debug)]
+/// A firewall rule
+///
+/// Every criterion set to None matches any frame. A frame matches the rule
+/// if it matches all of the criteria.
+pub struct FirewallRule {
+    /// Direction of the frame
+    pub direction: Option<Direction>,
+    /// Name of the interface the frame goes through
+    pub interface: Option<String>,
+    /// Ether type of the frame
+    pub ether_type: Option<EtherType>,
+    /// Prefix the source address belongs to
+    pub src: Option<IpPrefix>,
+    /// Prefix the destination address belongs to
+    pub dst: Option<IpPrefix>,
+    /// Protocol carried by the network layer
+    pub protocol: Option<ProtocolIdType>,
+    /// Range the source port belongs to
+    pub src_ports: Option<PortRange>,
+    /// Range the destination port belongs to
+    pub dst_ports: Option<PortRange>,
+    /// Action taken on matching frames
+    pub action: Action,
+}
+
+impl FirewallRule {
+    /// Create a rule matching every frame
+    pub fn new(action: Action) -> Self {
+        FirewallRule {
+            direction: None,
+            interface: None,
+            ether_type: None,
+            src: None,
+            dst: None,
+            protocol: None,
+            src_ports: None,
+            dst_ports: None,
+            action: action,
+        }
+    }
+
+    /// Returns true if a frame going in direction `dir` through interface
+    /// `intf` matches the rule
+    pub fn matches(&self, dir: Direction, intf: &str, flow: &Flow) -> bool {
+        fn check<T, U, F>(criterion: &Option<T>, value: Option<U>, f: F) -> bool
+            where F: Fn(&T, U) -> bool {
+            match (criterion, value) {
+                (&None, _) => true,
+                (&Some(..), None) => false,
+                (&Some(ref c), Some(v)) => f(c, v),
+            }
+        }
+
+        check(&self.direction, Some(dir), |d, v| *d == v) &&
+        check(&self.interface, Some(intf), |i, v| i == v) &&
+        check(&self.ether_type, Some(flow.ether_type), |e, v| *e == v) &&
+        check(&self.src, flow.src.as_ref(), |p, v| p.contains(v)) &&
+        check(&self.dst, flow.dst.as_ref(), |p, v| p.contains(v)) &&
+        check(&self.protocol, flow.protocol, |p, v| *p == v) &&
+        check(&self.src_ports, flow.src_port, |r, v| r.contains(v)) &&
+        check(&self.dst_ports, flow.dst_port, |r, v| r.contains(v))
+    }
+}
+
+struct RuleEntry {
+    rule: FirewallRule,
+    hits: AtomicUsize,
+}
+
+struct Table {
+    rules: Vec<RuleEntry>,
+    default_in: Action,
+    default_out: Action,
+}
+
+impl Table {
+    #[inline]
+    fn default_action(&self, dir: Direction) -> Action {
+        match dir {
+            Direction::In => self.default_in,
+            Direction::Out => self.default_out,
+        }
+    }
+}
+
+/// Rule based packet filter of a network stack
+///
+/// By default, the firewall has no rule, accepts everything and is
+/// stateless.
+pub struct Firewall {
+    table: RwLock<Table>,
+    default_hits: AtomicUsize,
+    established_hits: AtomicUsize,
+    stateful: AtomicBool,
+    conntrack: SpinLock<Conntrack>,
+}
+
+impl Firewall {
+    /// Create a firewall accepting everything
+    pub fn new() -> Self {
+        Firewall {
+            table: RwLock::new(Table {
+                rules: Vec::new(),
+                default_in: Action::Accept,
+                default_out: Action::Accept,
+            }),
+            default_hits: AtomicUsize::new(0),
+            established_hits: AtomicUsize::new(0),
+            stateful: AtomicBool::new(false),
+            conntrack: SpinLock::new(Conntrack::new(MAX_TRACKED_FLOWS)),
+        }
+    }
+
+    /// Append a rule and return its index
+    pub fn push_rule(&self, rule: FirewallRule) -> usize {
+        let mut table = self.table.write();
+
+        table.rules.push(RuleEntry {
+            rule: rule,
+            hits: AtomicUsize::new(0),
+        });
+
+        table.rules.len() - 1
+    }
+
+    /// Insert a rule at `index`, shifting the following ones
+    ///
+    /// Panics if `index` is greater than the number of rules.
+    pub fn insert_rule(&self, index: usize, rule: FirewallRule) {
+        self.table.write().rules.insert(index, RuleEntry {
+            rule: rule,
+            hits: AtomicUsize::new(0),
+        });
+    }
+
+    /// Remove the rule at `index`
+    pub fn remove_rule(&self, index: usize) -> Option<FirewallRule> {
+        let mut table = self.table.write();
+
+        if index < table.rules.len() {
+            Some(table.rules.remove(index).rule)
+        } else {
+            None
+        }
+    }
+
+    /// Remove every rule
+    pub fn clear_rules(&self) {
+        self.table.write().rules.clear();
+    }
+
+    /// Returns a copy of the rules
+    pub fn rules(&self) -> Vec<FirewallRule> {
+        self.table.read().rules.iter().map(|e| e.rule.clone()).collect()
+    }
+
+    /// Returns the number of frames that matched the rule at `index`
+    pub fn hits(&self, index: usize) -> Option<usize> {
+        self.table.read().rules.get(index)
+                                .map(|e| e.hits.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of frames that matched no rule
+    pub fn default_hits(&self) -> usize {
+        self.default_hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames accepted because they belong to a
+    /// tracked flow
+    pub fn established_hits(&self) -> usize {
+        self.established_hits.load(Ordering::Relaxed)
+    }
+
+    /// Reset every hit counter
+    pub fn reset_counters(&self) {
+        for e in &self.table.read().rules {
+            e.hits.store(0, Ordering::Relaxed);
+        }
+
+        self.default_hits.store(0, Ordering::Relaxed);
+        self.established_hits.store(0, Ordering::Relaxed);
+    }
+
+    /// Set the action taken on frames going in direction `dir` that match no
+    /// rule
+    pub fn set_default_action(&self, dir: Direction, action: Action) {
+        let mut table = self.table.write();
+
+        match dir {
+            Direction::In => table.default_in = action,
+            Direction::Out => table.default_out = action,
+        }
+    }
+
+    /// Enable or disable connection tracking
+    ///
+    /// Disabling it forgets every tracked flow.
+    pub fn set_stateful(&self, stateful: bool) {
+        self.stateful.store(stateful, Ordering::SeqCst);
+
+        if !stateful {
+            self.conntrack.lock().clear();
+        }
+    }
+
+    #[inline]
+    /// Returns true if connection tracking is enabled
+    pub fn is_stateful(&self) -> bool {
+        self.stateful.load(Ordering::SeqCst)
+    }
+
+    /// Access the connection tracking table
+    pub fn conntrack(&self) -> SpinGuard<Conntrack> {
+        self.conntrack.lock()
+    }
+
+    /// Decide what to do with `frame` going in direction `dir` through the
+    /// interface named `intf`
+    ///
+    /// Frames too short to be parsed match no rule and get the default
+    /// action of `dir`.
+    pub fn filter(&self, dir: Direction, intf: &str, frame: &[u8]) -> Action {
+        let flow = match Flow::parse(frame) {
+            Some(flow) => flow,
+            None => {
+                self.default_hits.fetch_add(1, Ordering::Relaxed);
+
+                return self.table.read().default_action(dir);
+            }
+        };
+
+        let stateful = self.is_stateful();
+        let key = if stateful { FlowKey::from_flow(&flow, dir) } else { None };
+        let now = Instant::now();
+
+        if let Some(ref key) = key {
+            if self.conntrack.lock().update(key, dir, flow.tcp_flags,
+                                            now).is_some() {
+                self.established_hits.fetch_add(1, Ordering::Relaxed);
+                return Action::Accept;
+            }
+        }
+
+        let action = {
+            let table = self.table.read();
+
+            match table.rules.iter().find(|e| e.rule.matches(dir, intf, &flow)) {
+                Some(entry) => {
+                    entry.hits.fetch_add(1, Ordering::Relaxed);
+                    entry.rule.action
+                }
+                None => {
+                    self.default_hits.fetch_add(1, Ordering::Relaxed);
+
+                    table.default_action(dir)
+                }
+            }
+        };
+
+        if action == Action::Accept {
+            if let Some(key) = key {
+                self.conntrack.lock().track(key, dir, now);
+            }
+        }
+
+        action
+    }
+}
+
+/// Build the ICMP error answering a rejected incoming IPv4 `frame`
+///
+/// `hw_addr` is the hardware address of the interface that received the
+/// frame. Returns None if no error must be sent, e.g. for non IPv4 frames,
+/// broadcasts or ICMP messages.
+pub fn reject_reply(frame: &[u8], hw_addr: &HwAddr) -> Option<Vec<u8>> {
+    let flow = match Flow::parse(frame) {
+        Some(flow) => flow,
+        None => return None,
+    };
+
+    // Never answer to broadcasts/multicasts or to ICMP to avoid storms
+    if flow.ether_type != ETHERTYPE_IPV4 || frame[0] & 1 != 0 ||
+       flow.protocol == Some(PROTOCOL_ICMP) {
+        return None;
+    }
+
+    let (src, dst) = match (flow.src, flow.dst) {
+        (Some(IpAddr::V4(src)), Some(IpAddr::V4(dst))) => (src, dst),
+        _ => return None,
+    };
+
+    let ip_hdr_size = ((frame[flow.net_offset] & 0xf) as usize) * 4;
+    let quote_end = ::core::cmp::min(frame.len(),
+                                     flow.net_offset + ip_hdr_size +
+                                     ICMP_QUOTE_SIZE);
+    let quote = &frame[flow.net_offset..quote_end];
+
+    let icmp_size = 8 + quote.len();
+    let total_size = 20 + icmp_size;
+
+    let mut reply = Vec::with_capacity(14 + total_size);
+
+    // Ethernet header
+    reply.extend_from_slice(&frame[6..12]);
+    reply.extend_from_slice(hw_addr.as_bytes());
+    reply.extend_from_slice(&[(ETHERTYPE_IPV4 >> 8) as u8, ETHERTYPE_IPV4 as u8]);
+
+    // IPv4 header
+    reply.extend_from_slice(&[0x45, 0, (total_size >> 8) as u8,
+                              total_size as u8, 0, 0, 0, 0, 64,
+                              PROTOCOL_ICMP, 0, 0]);
+    reply.extend_from_slice(&dst.octets());
+    reply.extend_from_slice(&src.octets());
+
+    let ip_csum = checksum(&reply[14..34]);
+
+    reply[24] = (ip_csum >> 8) as u8;
+    reply[25] = ip_csum as u8;
+
+    // ICMP destination unreachable, communication administratively
+    // prohibited
+    reply.extend_from_slice(&[3, 13, 0, 0, 0, 0, 0, 0]);
+    reply.extend_from_slice(quote);
+
+    let icmp_csum = checksum(&reply[34..]);
+
+    reply[36] = (icmp_csum >> 8) as u8;
+    reply[37] = icmp_csum as u8;
+
+    Some(reply)
+}
+
+#[cfg(test)]
+mod test {
+    use vec::Vec;
+
+    use net::defs::{IpAddr, Ipv4Addr, Ipv6Addr, PROTOCOL_ICMP, checksum};
+    use net::test_support::{hw, udp_frame};
+
+    use super::{prefix_match, reject_reply, Action, Direction, Firewall};
+    use super::{FirewallRule, IpPrefix, PortRange};
+
+    fn v4(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    /// Build an ethernet frame carrying an UDP datagram from 10.0.0.1 to
+    /// 10.0.0.2
+    fn udp(src_port: u16, dst_port: u16) -> Vec<u8> {
+        udp_frame(&Ipv4Addr::new(10, 0, 0, 1), src_port,
+                  &Ipv4Addr::new(10, 0, 0, 2), dst_port, &[])
+    }
+
+    #[test]
+    fn test_prefix_match() {
+        let addr = [192, 168, 37, 12];
+
+        assert!(prefix_match(&[192, 168, 0, 0], &addr, 16));
+        assert!(prefix_match(&[192, 168, 32, 0], &addr, 19));
+        assert!(!prefix_match(&[192, 168, 32, 0], &addr, 20));
+        assert!(!prefix_match(&[10, 0, 0, 0], &addr, 8));
+
+        // Every address matches an empty prefix, a full prefix only matches
+        // itself
+        assert!(prefix_match(&[0, 0, 0, 0], &addr, 0));
+        assert!(prefix_match(&addr, &addr, 32));
+        assert!(!prefix_match(&[192, 168, 37, 13], &addr, 32));
+
+        // Lengths beyond the size of the address are clamped
+        assert!(prefix_match(&addr, &addr, 200));
+    }
+
+    #[test]
+    fn test_ip_prefix() {
+        let prefix = IpPrefix::new(v4(10, 0, 0, 0), 8);
+
+        assert!(prefix.contains(&v4(10, 1, 2, 3)));
+        assert!(!prefix.contains(&v4(11, 1, 2, 3)));
+
+        let v6 = |s: u16| {
+            IpAddr::V6(Ipv6Addr::new(0xfd00, s, 0, 0, 0, 0, 0, 1))
+        };
+        let prefix = IpPrefix::new(v6(0), 24);
+
+        assert!(prefix.contains(&v6(0x00ff)));
+        assert!(!prefix.contains(&v6(0x0100)));
+
+        // Address families never match each other
+        assert!(!prefix.contains(&v4(253, 0, 0, 1)));
+        assert!(!IpPrefix::new(v4(0, 0, 0, 0), 0).contains(&v6(0)));
+    }
+
+    #[test]
+    fn test_port_range() {
+        let range = PortRange::new(1000, 1999);
+
+        assert!(range.contains(1000) && range.contains(1500));
+        assert!(range.contains(1999));
+        assert!(!range.contains(999) && !range.contains(2000));
+
+        let single = PortRange::single(53);
+
+        assert!(single.contains(53));
+        assert!(!single.contains(52) && !single.contains(54));
+
+        // An inverted range is empty
+        assert!(!PortRange::new(10, 5).contains(7));
+    }
+
+    #[test]
+    fn test_filter() {
+        let fw = Firewall::new();
+        let mut rule = FirewallRule::new(Action::Accept);
+
+        rule.direction = Some(Direction::In);
+        rule.dst_ports = Some(PortRange::single(53));
+
+        assert_eq!(fw.push_rule(rule), 0);
+
+        let mut rule = FirewallRule::new(Action::Reject);
+
+        rule.src = Some(IpPrefix::new(v4(10, 0, 0, 0), 8));
+
+        assert_eq!(fw.push_rule(rule), 1);
+
+        fw.set_default_action(Direction::Out, Action::Drop);
+
+        // The first matching rule decides
+        assert_eq!(fw.filter(Direction::In, "eth0", &udp(4000, 53)),
+                   Action::Accept);
+        assert_eq!(fw.filter(Direction::In, "eth0", &udp(4000, 54)),
+                   Action::Reject);
+        assert_eq!(fw.hits(0), Some(1));
+        assert_eq!(fw.hits(1), Some(1));
+
+        // Frames matching no rule get the default action of their direction
+        let mut frame = udp(53, 4000);
+
+        frame[26] = 192;
+
+        assert_eq!(fw.filter(Direction::Out, "eth0", &frame), Action::Drop);
+        assert_eq!(fw.filter(Direction::In, "eth0", &frame), Action::Accept);
+        assert_eq!(fw.default_hits(), 2);
+
+        // So do frames too short for an ethernet header
+        assert_eq!(fw.filter(Direction::In, "eth0", &frame[..10]),
+                   Action::Accept);
+        assert_eq!(fw.filter(Direction::Out, "eth0", &frame[..10]),
+                   Action::Drop);
+        assert_eq!(fw.default_hits(), 4);
+    }
+
+    #[test]
+    fn test_stateful() {
+        let fw = Firewall::new();
+
+        fw.set_default_action(Direction::In, Action::Drop);
+        fw.set_stateful(true);
+
+        // Answers are only accepted once the flow was opened from inside
+        let mut answer = udp(53, 4000);
+
+        answer[26..30].clone_from_slice(&[10, 0, 0, 2]);
+        answer[30..34].clone_from_slice(&[10, 0, 0, 1]);
+
+        assert_eq!(fw.filter(Direction::In, "eth0", &answer), Action::Drop);
+        assert_eq!(fw.filter(Direction::Out, "eth0", &udp(4000, 53)),
+                   Action::Accept);
+        assert_eq!(fw.filter(Direction::In, "eth0", &answer), Action::Accept);
+        assert_eq!(fw.established_hits(), 1);
+        assert_eq!(fw.conntrack().len(), 1);
+
+        fw.set_stateful(false);
+
+        assert!(fw.conntrack().is_empty());
+        assert_eq!(fw.filter(Direction::In, "eth0", &answer), Action::Drop);
+    }
+
+    #[test]
+    fn test_reject_reply() {
+        let hw_addr = hw(2);
+        let frame = udp(4000, 53);
+        let reply = reject_reply(&frame, &hw_addr).unwrap();
+
+        // Sent back to the sender, quoting its IP header and 8 bytes
+        assert_eq!(&reply[..6], &frame[6..12]);
+        assert_eq!(&reply[6..12], hw_addr.as_bytes());
+        assert_eq!(&reply[26..30], &frame[30..34]);
+        assert_eq!(&reply[30..34], &frame[26..30]);
+        assert_eq!(reply[23], PROTOCOL_ICMP);
+        assert_eq!(&reply[34..36], &[3, 13][..]);
+        assert_eq!(&reply[42..], &frame[14..]);
+        assert_eq!(checksum(&reply[14..34]), 0);
+        assert_eq!(checksum(&reply[34..]), 0);
+
+        // No error about broadcasts and ICMP messages
+        let mut broadcast = frame.clone();
+
+        broadcast[0] = 0xff;
+
+        assert!(reject_reply(&broadcast, &hw_addr).is_none());
+
+        let mut icmp = frame.clone();
+
+        icmp[23] = PROTOCOL_ICMP;
+
+        assert!(reject_reply(&icmp, &hw_addr).is_none());
+    }
+}
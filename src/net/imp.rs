@@ -1,22 +1,117 @@
+use core::str::FromStr;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use sync::{Arc, Weak};
 
+use string::String;
 use vec::Vec;
 use vec_deque::VecDeque;
 
 use sync::spin::{InterruptSpinLock, RwLock, RwLockReadGuard};
 
-use thread::WaitQueue;
+use thread::{Scheduler, WaitQueue};
 
-use net::Interface;
+use net::{Interface, V4Configuration, V4Source};
 
-use hal::net::discover;
+use hal::{cmd_line, local_irq_disable, local_irq_enable};
+use hal::net::{discover, rescan, HotplugWatch};
 
-use net::Packet;
-use net::defs::Device;
+use net::{Packet, PacketBuilder, MultiConn};
+use net::arp::ArpCache;
+use net::udp::UdpTable;
+use net::bridge::{Bridge, BridgeConfig};
+use net::nat::{Nat, NatConfig};
+use net::ipv4;
+use net::dissect;
+use net::eth::{self, EthernetGenericFilter};
+use net::defs::{Device, Rule, Ipv4Addr, ETHERTYPE_ARP};
+use net::conn::filter::GenericFilterTrait;
+use net::firewall::{Firewall, Direction, Action, reject_reply};
 
 
 const MAX_QUEUE_SIZE: usize = 512;
 
+/// Parse an `ip=` option of the command line
+///
+/// The format is a subset of the one used by Linux:
+/// `ip=<client-ip>:<server-ip>:<gw-ip>:<netmask>:<hostname>:<device>`.
+/// Only the client address is mandatory, `server-ip` and `hostname` are
+/// ignored. Returns the configuration and the device name, which is empty if
+/// not specified.
+fn parse_ip_option(opt: &str) -> Option<(V4Configuration, &str)> {
+    let mut fields = opt.split(':');
+    let mut conf = V4Configuration::unconfigured();
+
+    conf.ipv4 = match fields.next().map(Ipv4Addr::from_str) {
+        Some(Ok(addr)) => addr,
+        _ => return None,
+    };
+
+    // Server address
+    fields.next();
+
+    if let Some(Ok(gw)) = fields.next().map(Ipv4Addr::from_str) {
+        conf.ipv4_gateway = gw;
+    }
+
+    if let Some(Ok(mask)) = fields.next().map(Ipv4Addr::from_str) {
+        conf.ipv4_mask = mask;
+    }
+
+    // Hostname
+    fields.next();
+
+    let device = fields.next().unwrap_or("");
+
+    conf.source = V4Source::CmdLine;
+
+    Some((conf, device))
+}
+
+/// Apply the `ip=` options of the command line to the interfaces
+///
+/// An option without device applies to the first interface.
+fn apply_cmd_line(intfs: &[Interface]) {
+    for opt in cmd_line().split(' ').filter(|o| o.starts_with("ip=")) {
+        let (conf, device) = match parse_ip_option(&opt[3..]) {
+            Some(res) => res,
+            None => {
+                println!("Warning: Invalid option {}", opt);
+                continue;
+            }
+        };
+
+        let intf = if device.is_empty() {
+            intfs.first()
+        } else {
+            intfs.iter().find(|i| i.read().name_ref() == device)
+        };
+
+        match intf {
+            Some(intf) => {
+                intf.write().v4_configuration_mut().update(conf);
+            }
+            None => println!("Warning: No interface matching {}", opt),
+        }
+    }
+}
+
+/// Print a received packet if its interface traces received packets
+fn trace_rx(pkt: &Packet, event: &str) {
+    if let Some(intf) = pkt.interface() {
+        let (name, mode) = {
+            let intf = intf.read();
+
+            (String::from(intf.name_ref()), intf.trace_mode())
+        };
+
+        if mode.rx {
+            dissect::trace(&name, event, pkt, mode);
+        }
+    }
+}
+
 #[derive(Clone)]
 /// A network stack
 ///
@@ -34,11 +129,27 @@ struct InstanceRaw {
     rx_queue: InterruptSpinLock<VecDeque<Packet>>,
     /// Used to wait for packet to arrive in the rx_queue
     rx_wait: WaitQueue,
+    /// Filter applied to received and transmitted packets
+    firewall: Firewall,
+    /// IPv4 to hardware address mappings
+    arp: ArpCache,
+    /// Route received packets to connexions
+    filter: RwLock<EthernetGenericFilter>,
+    /// Bound UDP ports
+    udp: UdpTable,
+    /// Bridge between interfaces, if enabled
+    bridge: RwLock<Option<Bridge>>,
+    /// Forward IPv4 packets between interfaces
+    forwarding: AtomicBool,
+    /// Address translation of forwarded packets, if enabled
+    nat: RwLock<Option<Nat>>,
 }
 
 // rx_queue is protected by a spin lock
 // rx_wait is Sync
+// firewall, arp, filter, udp, bridge and nat are protected by locks
 unsafe impl Sync for InstanceRaw {}
+unsafe impl Send for InstanceRaw {}
 
 impl Instance {
     /// Network thread linked to an instance
@@ -52,19 +163,79 @@ impl Instance {
             match pkt_opt {
                 None => {
                     instance.refresh_interfaces();
-                    // No packet to process => wait for one to come
-                    wait_event!(instance.0.rx_wait,
-                                !instance.0.rx_queue.lock().is_empty());
+                    // No packet to process => wait for one to come or for
+                    // shaped packets to be released
+                    instance.wait_rx();
                     instance.refresh_interfaces();
                 }
                 Some(pkt) => {
-                    // Treat the packet
-                    println!("New incoming packet!");
+                    trace_rx(&pkt, "rx");
+
+                    if !instance.bridge_rx(&pkt) {
+                        continue;
+                    }
+
+                    if !instance.filter_rx(&pkt) {
+                        continue;
+                    }
+
+                    let pkt = match instance.forward_rx(pkt) {
+                        Some(pkt) => pkt,
+                        None => continue,
+                    };
+
+                    instance.dispatch_rx(pkt);
                 }
             }
         }
     }
 
+    /// Wait for a packet to be received or for the next packet held by a
+    /// shaper to be releasable
+    fn wait_rx(&self) {
+        let deadline = self.interfaces().iter()
+                                        .filter_map(|i| i.read().next_tx_release())
+                                        .min();
+
+        local_irq_disable();
+
+        let locked_queue = self.0.rx_wait.lock();
+
+        if !self.0.rx_queue.lock().is_empty() {
+            local_irq_enable();
+            return;
+        }
+
+        match deadline {
+            None => Scheduler::block(locked_queue),
+            Some(deadline) => {
+                Scheduler::block_timeout(locked_queue, &self.0.rx_wait,
+                                         deadline);
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    /// Wake up the network thread so that it refreshes the interfaces
+    pub fn wake_network_thread(&self) {
+        self.0.rx_wait.unblock();
+    }
+
+    /// Hotplug thread linked to an instance
+    ///
+    /// This function looks for interfaces that were added or removed at
+    /// runtime and refreshes the link state of the others, every time the
+    /// hardware reports a change.
+    pub fn hotplug_thread(instance: Instance) {
+        let watch = HotplugWatch::new();
+
+        loop {
+            watch.wait();
+
+            rescan(&instance);
+        }
+    }
+
     /// Create a new network stack
     ///
     /// TODO: This cannot really be used more than once for now.
@@ -73,20 +244,40 @@ impl Instance {
             interfaces: RwLock::new(Vec::new()),
             rx_queue: InterruptSpinLock::new(VecDeque::with_capacity(MAX_QUEUE_SIZE)),
             rx_wait: WaitQueue::new(),
+            firewall: Firewall::new(),
+            arp: ArpCache::new(),
+            filter: RwLock::new(EthernetGenericFilter::new()),
+            udp: UdpTable::new(),
+            bridge: RwLock::new(None),
+            forwarding: AtomicBool::new(false),
+            nat: RwLock::new(None),
         });
 
         let instance = Instance(inner);
 
         let intfs = discover(&instance);
 
+        // Static configurations from the command line take precedence over
+        // the ones found during discovery
+        apply_cmd_line(&intfs);
+
         if intfs.is_empty() {
             println!("Warning: Uni.rs is built with network capabilities but no interface found");
         } else {
             println!("{} interface(s) discovered:", intfs.len());
 
             for i in &intfs {
-                println!("  - {} ({})", i.read().name_ref(),
-                         i.read().hw_addr_ref());
+                let intf = i.read();
+                let conf = intf.v4_configuration_ref();
+
+                if conf.source == V4Source::Unconfigured {
+                    println!("  - {} ({})", intf.name_ref(),
+                             intf.hw_addr_ref());
+                } else {
+                    println!("  - {} ({}) {} mask {} gw {} ({:?})",
+                             intf.name_ref(), intf.hw_addr_ref(), conf.ipv4,
+                             conf.ipv4_mask, conf.ipv4_gateway, conf.source);
+                }
             }
         }
 
@@ -105,6 +296,227 @@ impl Instance {
         self.0.interfaces.read()
     }
 
+    #[inline]
+    /// Returns the firewall of the network stack
+    pub fn firewall(&self) -> &Firewall {
+        &self.0.firewall
+    }
+
+    #[inline]
+    /// Returns the ARP cache of the network stack
+    pub fn arp(&self) -> &ArpCache {
+        &self.0.arp
+    }
+
+    #[doc(hidden)]
+    /// Register a multi connexion receiving the packets that match `rule`
+    /// on every interface
+    pub fn insert_multi(&self, conn: Arc<MultiConn>,
+                        rule: &Rule) -> Result<(), ()> {
+        self.0.filter.write().insert_multi(conn, rule)
+    }
+
+    #[doc(hidden)]
+    /// Unregister a multi connexion registered with `insert_multi()`
+    pub fn remove_multi(&self, conn: &Arc<MultiConn>, rule: &Rule) {
+        self.0.filter.write().remove_multi(conn, rule);
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    /// Returns the UDP ports bound on the network stack
+    pub fn udp(&self) -> &UdpTable {
+        &self.0.udp
+    }
+
+    /// Bridge `members` together
+    ///
+    /// Frames received on a member are forwarded to the others. Fails if a
+    /// bridge is already enabled or less than two members are given.
+    pub fn enable_bridge(&self, members: &[Interface],
+                         config: BridgeConfig) -> Result<(), ()> {
+        let mut bridge = self.0.bridge.write();
+
+        if bridge.is_some() {
+            return Err(());
+        }
+
+        *bridge = Some(try!(Bridge::new(members, config)));
+
+        Ok(())
+    }
+
+    /// Stop bridging interfaces
+    pub fn disable_bridge(&self) {
+        if let Some(bridge) = self.0.bridge.write().take() {
+            bridge.release();
+        }
+    }
+
+    /// Returns the bridge of the network stack if enabled
+    pub fn bridge(&self) -> RwLockReadGuard<Option<Bridge>> {
+        self.0.bridge.read()
+    }
+
+    /// Forward a received packet if it was received on a bridge member
+    ///
+    /// Returns true if the packet must be processed by the local stack.
+    fn bridge_rx(&self, pkt: &Packet) -> bool {
+        match *self.0.bridge.read() {
+            None => true,
+            Some(ref bridge) => bridge.rx(pkt),
+        }
+    }
+
+    #[inline]
+    /// Enable or disable forwarding of IPv4 packets between interfaces
+    pub fn set_forwarding(&self, forwarding: bool) {
+        self.0.forwarding.store(forwarding, Ordering::SeqCst);
+    }
+
+    #[inline]
+    /// Returns true if IPv4 packets are forwarded between interfaces
+    pub fn is_forwarding(&self) -> bool {
+        self.0.forwarding.load(Ordering::SeqCst)
+    }
+
+    /// Masquerade the inside interface of `config` behind the outside one
+    ///
+    /// Forwarding is enabled as well. Fails if a translation is already
+    /// enabled.
+    pub fn enable_nat(&self, config: NatConfig) -> Result<(), ()> {
+        let mut nat = self.0.nat.write();
+
+        if nat.is_some() {
+            return Err(());
+        }
+
+        *nat = Some(Nat::new(config));
+
+        self.set_forwarding(true);
+
+        Ok(())
+    }
+
+    /// Stop translating addresses
+    ///
+    /// Translations are forgotten. Forwarding stays enabled.
+    pub fn disable_nat(&self) {
+        *self.0.nat.write() = None;
+    }
+
+    /// Returns the address translator of the network stack if enabled
+    pub fn nat(&self) -> RwLockReadGuard<Option<Nat>> {
+        self.0.nat.read()
+    }
+
+    /// Forward a received packet if forwarding is enabled
+    ///
+    /// Returns the packet if it must be processed by the local stack.
+    fn forward_rx(&self, pkt: Packet) -> Option<Packet> {
+        if !self.is_forwarding() {
+            return Some(pkt);
+        }
+
+        let nat = self.0.nat.read();
+
+        ipv4::forward(self, nat.as_ref(), pkt)
+    }
+
+    /// Hand a received packet to the protocol handling it
+    ///
+    /// ARP packets are handled by the stack, the others go through the
+    /// filters to the connexions they match. Packets matching no connexion
+    /// are dropped.
+    fn dispatch_rx(&self, pkt: Packet) {
+        let is_arp = pkt.link_header::<eth::Header>().map_or(false, |hdr| {
+            hdr.ether_type.as_host() == ETHERTYPE_ARP
+        });
+
+        if is_arp {
+            if let Some(intf) = pkt.interface() {
+                self.0.arp.rx(&intf, pkt.as_bytes());
+            }
+        } else {
+            self.0.filter.read().rx(pkt);
+        }
+    }
+
+    /// Run a received packet through the firewall
+    ///
+    /// Returns true if the packet is accepted. Rejected packets are answered
+    /// when possible.
+    fn filter_rx(&self, pkt: &Packet) -> bool {
+        let intf = match pkt.interface() {
+            Some(intf) => intf,
+            None => return false,
+        };
+
+        let (name, hw_addr) = {
+            let intf = intf.read();
+
+            (String::from(intf.name_ref()), intf.hw_addr_ref().clone())
+        };
+
+        match self.0.firewall.filter(Direction::In, &name, pkt.as_bytes()) {
+            Action::Accept => true,
+            Action::Drop => {
+                trace_rx(pkt, "rx dropped by firewall");
+                false
+            }
+            Action::Reject => {
+                trace_rx(pkt, "rx rejected by firewall");
+
+                if let Some(reply) = reject_reply(pkt.as_bytes(), &hw_addr) {
+                    if let Ok(mut builder) = PacketBuilder::new() {
+                        if builder.write(&reply).is_ok() {
+                            let _ = intf.tx_frame(builder.finalize_raw());
+                        }
+                    }
+                }
+
+                false
+            }
+        }
+    }
+
+    /// Register a new interface within the network stack
+    ///
+    /// Static configurations from the command line are applied to it.
+    pub fn add_interface(&self, intf: Interface) {
+        apply_cmd_line(&[intf.clone()]);
+
+        println!("Interface {} ({}) added", intf.read().name_ref(),
+                 intf.read().hw_addr_ref());
+
+        self.0.interfaces.write().push(intf);
+    }
+
+    /// Remove an interface from the network stack and detach it
+    ///
+    /// Returns false if the interface was not registered.
+    pub fn remove_interface(&self, intf: &Interface) -> bool {
+        let removed = {
+            let mut interfaces = self.0.interfaces.write();
+
+            match interfaces.iter().position(|i| i == intf) {
+                None => None,
+                Some(idx) => Some(interfaces.remove(idx)),
+            }
+        };
+
+        match removed {
+            None => false,
+            Some(intf) => {
+                println!("Interface {} removed", intf.read().name_ref());
+
+                intf.detach();
+
+                true
+            }
+        }
+    }
+
     /// Call refresh on every registered interface
     fn refresh_interfaces(&self) {
         for intf in self.interfaces().iter() {
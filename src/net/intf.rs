@@ -1,17 +1,46 @@
+use core::mem;
+
 use boxed::Box;
 use string::String;
+use vec::Vec;
 
 use sync::{Arc, Weak};
 
 use sync::spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+use thread::{WaitQueue, Notify, Pollable, Ready};
+
 use net::{Instance, InstanceWeak, Packet, PacketBuilder};
 
 use net::defs::{Rule, HwAddr, Ipv4Addr, Device};
+use net::firewall::{Direction, Action};
+use net::shaper::{Shaper, ShaperConfig};
+use net::dissect::{self, TraceMode};
+
+use time::Instant;
 
 use hal::net::HwInterface;
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+/// Origin of the IPv4 configuration of an interface
+///
+/// Sources are ordered by precedence: a configuration is only replaced by one
+/// coming from a source at least as strong. From weakest to strongest:
+///
+/// - `Unconfigured`: the interface has no address
+/// - `Dhcp`: obtained dynamically, a DHCP client must not override any static
+///   configuration
+/// - `XenStore`: published by the toolstack under the vif's directory
+/// - `CmdLine`: given explicitly on the command line of the domain
+pub enum V4Source {
+    Unconfigured,
+    Dhcp,
+    XenStore,
+    CmdLine,
+}
+
 // XXX: Should this be in net::defs ?
+#[derive(Clone)]
 /// IPv4 configuration of an interface
 pub struct V4Configuration {
     /// Main IPv4 address
@@ -20,6 +49,41 @@ pub struct V4Configuration {
     pub ipv4_mask: Ipv4Addr,
     /// Gateway IPv4 address
     pub ipv4_gateway: Ipv4Addr,
+    /// Where the configuration comes from
+    pub source: V4Source,
+}
+
+impl V4Configuration {
+    /// Creates an empty configuration
+    pub fn unconfigured() -> Self {
+        V4Configuration {
+            ipv4: Ipv4Addr::new(0, 0, 0, 0),
+            ipv4_mask: Ipv4Addr::new(0, 0, 0, 0),
+            ipv4_gateway: Ipv4Addr::new(0, 0, 0, 0),
+            source: V4Source::Unconfigured,
+        }
+    }
+
+    /// Replace the configuration by `conf` if its source has a precedence
+    /// greater or equal to the current one
+    ///
+    /// Returns true if the configuration was replaced.
+    pub fn update(&mut self, conf: V4Configuration) -> bool {
+        if conf.source < self.source {
+            return false;
+        }
+
+        *self = conf;
+
+        true
+    }
+
+    #[inline]
+    /// Returns true if the configuration was set statically (i.e. neither
+    /// unconfigured nor obtained via DHCP)
+    pub fn is_static(&self) -> bool {
+        self.source > V4Source::Dhcp
+    }
 }
 
 #[derive(Clone)]
@@ -49,6 +113,22 @@ pub struct InterfaceRaw {
     conf: V4Configuration,
     /// Underlying driver
     pv_device: Option<Box<HwInterface>>,
+    /// True once the interface was removed from its network stack
+    detached: bool,
+    /// Notified when the interface is detached
+    watchers: Vec<Weak<Notify>>,
+    /// Administrative state, set by the application
+    admin_up: bool,
+    /// Operational state, set by the driver
+    oper_up: bool,
+    /// Signaled every time the link state changes
+    link_wait: Arc<WaitQueue>,
+    /// Optional transmit shaper
+    shaper: Option<Shaper>,
+    /// Frames not addressed to the interface are received
+    promiscuous: bool,
+    /// Packets printed when received or transmitted
+    trace: TraceMode,
 }
 
 impl Interface {
@@ -58,12 +138,16 @@ impl Interface {
             instance: instance.downgrade(),
             name: String::new(),
             hw_addr: HwAddr::empty(),
-            conf: V4Configuration {
-                ipv4: Ipv4Addr::new(0, 0, 0, 0),
-                ipv4_mask: Ipv4Addr::new(0, 0, 0, 0),
-                ipv4_gateway: Ipv4Addr::new(0, 0, 0, 0),
-            },
+            conf: V4Configuration::unconfigured(),
             pv_device: None,
+            detached: false,
+            watchers: Vec::new(),
+            admin_up: true,
+            oper_up: false,
+            link_wait: Arc::new(WaitQueue::new()),
+            shaper: None,
+            promiscuous: false,
+            trace: TraceMode::off(),
         };
 
         Interface(Arc::new(RwLock::new(inner)))
@@ -88,19 +172,216 @@ impl Interface {
         self.0.write()
     }
 
+    /// Detach the interface from the hardware
+    ///
+    /// This is called when the underlying device goes away. The driver is
+    /// released and watchers (e.g. connexions bound to the interface) are
+    /// notified.
+    pub fn detach(&self) {
+        let (pv_device, watchers, link_wait) = {
+            let mut intf = self.write();
+
+            intf.detached = true;
+            intf.oper_up = false;
+
+            (intf.pv_device.take(), mem::replace(&mut intf.watchers, Vec::new()),
+             intf.link_wait.clone())
+        };
+
+        // The driver is released outside of the lock
+        mem::drop(pv_device);
+
+        for watcher in watchers {
+            if let Some(watcher) = watcher.upgrade() {
+                watcher.notify();
+            }
+        }
+
+        // The link is now down for good
+        link_wait.unblock_all();
+    }
+
+    /// Set the administrative state of the interface
+    ///
+    /// An interface administratively down does not transmit packets, whatever
+    /// the state of the underlying link.
+    pub fn set_admin_up(&self, up: bool) {
+        self.update_link(|intf| intf.admin_up = up);
+    }
+
+    #[doc(hidden)]
+    /// Set the operational state of the interface
+    ///
+    /// This is used by drivers to report the state of the underlying link.
+    pub fn set_oper_up(&self, up: bool) {
+        self.update_link(|intf| intf.oper_up = up);
+    }
+
+    /// Apply `f` to the interface and notify link watchers if the link state
+    /// changed
+    fn update_link<F: FnOnce(&mut InterfaceRaw)>(&self, f: F) {
+        let link_wait = {
+            let mut intf = self.write();
+            let before = (intf.admin_up, intf.oper_up);
+
+            f(&mut *intf);
+
+            if before == (intf.admin_up, intf.oper_up) {
+                return;
+            }
+
+            println!("Interface {} link is {}", intf.name_ref(),
+                     if intf.is_up() { "up" } else { "down" });
+
+            intf.link_wait.clone()
+        };
+
+        // Notify outside of the lock as watchers might look at the interface
+        link_wait.unblock_all();
+    }
+
     /// Receive a packet on the interface
     pub fn rx_packet(&self, _pkt: Packet) {
         unimplemented!();
     }
 
     /// Transmit a packet through the interface
-    pub fn tx_packet(&self, _builder: PacketBuilder,
+    ///
+    /// This fails if the interface is down.
+    pub fn tx_packet(&self, builder: PacketBuilder,
                      _rule: &Rule) -> Result<(), ()> {
-        unimplemented!();
+        if !self.read().is_up() {
+            return Err(());
+        }
+
+        // Formatters might need to lock the interface
+        let pkt = try!(builder.finalize(self));
+
+        self.tx_frame(pkt)
+    }
+
+    /// Transmit an already formatted frame through the interface
+    ///
+    /// The frame goes through the firewall of the network stack. This fails
+    /// if the interface is down or if the frame is not accepted.
+    pub fn tx_frame(&self, pkt: Packet) -> Result<(), ()> {
+        let (instance, name, trace) = {
+            let intf = self.read();
+
+            if !intf.is_up() {
+                return Err(());
+            }
+
+            (intf.instance.upgrade(), intf.name.clone(), intf.trace)
+        };
+
+        if let Some(instance) = instance {
+            let action = instance.firewall().filter(Direction::Out, &name,
+                                                    pkt.as_bytes());
+
+            if action != Action::Accept {
+                if trace.tx {
+                    dissect::trace(&name, "tx dropped by firewall", &pkt, trace);
+                }
+
+                return Err(());
+            }
+        }
+
+        if trace.tx {
+            dissect::trace(&name, "tx", &pkt, trace);
+        }
+
+        let mut intf = self.write();
+        let intf = &mut *intf;
+
+        let pv = try!(intf.pv_device.as_mut().ok_or(()));
+
+        match intf.shaper {
+            None => pv.tx_packet(pkt),
+            Some(ref mut shaper) => {
+                try!(shaper.enqueue(pkt));
+
+                shaper.release(Instant::now(), |pkt| pv.tx_packet(pkt));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Limit the transmit rate of the interface
+    ///
+    /// Packets exceeding the rate are queued by traffic class and released
+    /// later by the network thread. Any packet queued by a previous shaper is
+    /// dropped. Fails if the rate is 0.
+    pub fn set_shaper(&self, config: ShaperConfig) -> Result<(), ()> {
+        if config.rate == 0 {
+            return Err(());
+        }
+
+        self.write().shaper = Some(Shaper::new(config));
+
+        // Let the network thread know about the new deadline
+        if let Some(instance) = self.read().instance.upgrade() {
+            instance.wake_network_thread();
+        }
+
+        Ok(())
+    }
+
+    /// Stop limiting the transmit rate of the interface
+    ///
+    /// Queued packets are sent right away.
+    pub fn clear_shaper(&self) {
+        let mut intf = self.write();
+        let intf = &mut *intf;
+
+        if let Some(mut shaper) = intf.shaper.take() {
+            if let Some(pv) = intf.pv_device.as_mut() {
+                // Release everything
+                shaper.release_all(|pkt| pv.tx_packet(pkt));
+            }
+        }
+    }
+}
+
+impl Pollable for Interface {
+    /// An interface is writable when its link is up and hung up once
+    /// detached. Watchers are notified on every link state change.
+    fn readiness(&self) -> Ready {
+        let intf = self.read();
+
+        if intf.is_detached() {
+            Ready::hangup()
+        } else if intf.is_up() {
+            Ready::writable()
+        } else {
+            Ready::empty()
+        }
+    }
+
+    fn watch(&self, notifier: Arc<Notify>) {
+        self.read().link_wait.watch(notifier);
+    }
+
+    fn unwatch(&self, notifier: &Notify) {
+        self.read().link_wait.unwatch(notifier);
+    }
+}
+
+impl PartialEq for Interface {
+    /// Two interfaces are equal if they are the same object
+    fn eq(&self, rhs: &Self) -> bool {
+        &*self.0 as *const _ == &*rhs.0 as *const _
     }
 }
 
 impl InterfaceWeak {
+    /// Create a weak reference that refers to no interface
+    pub fn new() -> Self {
+        InterfaceWeak(Weak::new())
+    }
+
     /// Upgrade the weak reference to a real reference
     pub fn upgrade(&self) -> Option<Interface> {
         self.0.upgrade().map(Interface)
@@ -150,9 +431,110 @@ impl InterfaceRaw {
         self.pv_device = Some(pv);
     }
 
+    #[inline]
+    #[doc(hidden)]
+    pub fn pv_device_ref(&self) -> Option<&HwInterface> {
+        self.pv_device.as_ref().map(|pv| &**pv)
+    }
+
+    #[inline]
+    /// Returns true if the interface was detached from the hardware
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    #[inline]
+    /// Returns true if the interface is administratively up
+    pub fn is_admin_up(&self) -> bool {
+        self.admin_up
+    }
+
+    #[inline]
+    /// Returns true if the underlying link is up
+    pub fn is_oper_up(&self) -> bool {
+        self.oper_up
+    }
+
+    #[inline]
+    /// Returns true if the interface can be used to transmit packets, that is
+    /// if it's both administratively and operationally up
+    pub fn is_up(&self) -> bool {
+        self.admin_up && self.oper_up && !self.detached
+    }
+
+    /// Notify `watcher` when the interface is detached
+    ///
+    /// If the interface is already detached, `watcher` is notified right
+    /// away.
+    pub fn watch_detach(&mut self, watcher: &Arc<Notify>) {
+        if self.detached {
+            watcher.notify();
+        } else {
+            self.watchers.retain(|w| w.upgrade().is_some());
+            self.watchers.push(Arc::downgrade(watcher));
+        }
+    }
+
+    /// Stop notifying `watcher` when the interface is detached
+    pub fn unwatch_detach<T: Notify + ?Sized>(&mut self, watcher: &T) {
+        let ptr = watcher as *const T as *const u8;
+
+        self.watchers.retain(|w| {
+            match w.upgrade() {
+                None => false,
+                Some(w) => &*w as *const Notify as *const u8 != ptr,
+            }
+        });
+    }
+
+    #[inline]
+    /// Set whether frames not addressed to the interface are received
+    ///
+    /// Such frames are otherwise dropped when they reach the ethernet layer.
+    /// Xen backends deliver every frame switched to the vif, so drivers have
+    /// nothing to change.
+    pub fn set_promiscuous(&mut self, promiscuous: bool) {
+        self.promiscuous = promiscuous;
+    }
+
+    #[inline]
+    /// Returns true if the interface is in promiscuous mode
+    pub fn is_promiscuous(&self) -> bool {
+        self.promiscuous
+    }
+
+    #[inline]
+    /// Set which packets of the interface are printed on the console
+    pub fn set_trace(&mut self, mode: TraceMode) {
+        self.trace = mode;
+    }
+
+    #[inline]
+    /// Returns which packets of the interface are printed on the console
+    pub fn trace_mode(&self) -> TraceMode {
+        self.trace
+    }
+
+    #[inline]
+    /// Returns the transmit shaper of the interface if any
+    pub fn shaper_ref(&self) -> Option<&Shaper> {
+        self.shaper.as_ref()
+    }
+
+    /// Returns when packets held by the shaper can be released, if any
+    pub fn next_tx_release(&self) -> Option<Instant> {
+        self.shaper.as_ref().and_then(|s| s.next_release())
+    }
+
     #[inline]
     /// Refresh underlying driver
     pub fn refresh(&mut self) {
-        self.pv_device.as_mut().unwrap().refresh();
+        if let Some(pv) = self.pv_device.as_mut() {
+            if let Some(ref mut shaper) = self.shaper {
+                shaper.release(Instant::now(), |pkt| pv.tx_packet(pkt));
+            }
+
+            pv.refresh();
+        }
     }
 }
@@ -0,0 +1,42 @@
+//! Useful callbacks used by IPv4's specific filter
+
+use boxed::Box;
+
+use net::Packet;
+
+use net::defs::{Rule, NetworkRule, IpAddr, ProtocolIdType, PROTOCOL_UDP};
+
+use net::conn::filter::{SpecificCallbacks, GenericFilterTrait};
+
+use net::udp::UdpGenericFilter;
+
+use super::defs::Header;
+
+/// Defines specific callbacks for IPv4 protocol
+pub struct Ipv4Callbacks;
+
+impl SpecificCallbacks<ProtocolIdType> for Ipv4Callbacks {
+    /// Create a transport filter based on the protocol id
+    fn filter_from_generic_parameter(protocol: ProtocolIdType) -> Option<Box<GenericFilterTrait>> {
+        match protocol {
+            PROTOCOL_UDP => Some(Box::new(UdpGenericFilter::new())),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// Does the rule has a transport rule component
+    fn has_upper_filter(rule: &Rule) -> bool {
+        rule.tspt_rule.is_some()
+    }
+
+    /// Set IPv4 part of the rule with information gathered from the packet
+    fn set_layer_rule(rule: &mut Rule, pkt: &Packet) {
+        let hdr = pkt.net_header::<Header>().unwrap();
+
+        rule.net_rule = Some(NetworkRule {
+            protocol_id: hdr.protocol,
+            ip_in: Some(IpAddr::V4(hdr.src.clone())),
+        });
+    }
+}
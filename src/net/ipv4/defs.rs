@@ -0,0 +1,43 @@
+use net::defs::{Ipv4Addr, Int as NetInt};
+
+/// Mask of the fragment offset in `flags_fragment`
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+/// "More fragments" flag in `flags_fragment`
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+
+#[repr(C, packed)]
+/// IPv4 header, without options
+pub struct Header {
+    pub version_ihl: u8,
+    pub tos: u8,
+    pub length: NetInt<u16>,
+    pub id: NetInt<u16>,
+    pub flags_fragment: NetInt<u16>,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub checksum: NetInt<u16>,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+}
+
+impl Header {
+    #[inline]
+    /// Returns the version of the IP protocol
+    pub fn version(&self) -> u8 {
+        self.version_ihl >> 4
+    }
+
+    #[inline]
+    /// Returns the size of the header, options included
+    pub fn header_size(&self) -> usize {
+        ((self.version_ihl & 0x0f) as usize) * 4
+    }
+
+    #[inline]
+    /// Returns true if the datagram is a fragment of a larger one
+    pub fn is_fragment(&self) -> bool {
+        let flags_fragment = self.flags_fragment.as_host();
+
+        flags_fragment & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET_MASK) != 0
+    }
+}
@@ -0,0 +1,42 @@
+//! Implementation of IPv4 related Extractors
+
+use net::Packet;
+
+use net::defs::{Rule, IpAddr, Ipv4Addr, ProtocolIdType};
+
+use net::conn::filter::Extractor;
+
+use super::defs::Header;
+
+/// Type responsible for protocol id extraction
+pub struct ProtocolExtractor;
+
+impl Extractor<ProtocolIdType> for ProtocolExtractor {
+    /// Extract the protocol id from a rule
+    fn from_rule(rule: &Rule) -> Option<ProtocolIdType> {
+        rule.net_rule.as_ref().map(|net_rule| net_rule.protocol_id)
+    }
+
+    /// Extract the protocol id from a packet
+    fn from_packet(pkt: &Packet) -> Option<ProtocolIdType> {
+        pkt.net_header::<Header>().map(|hdr| hdr.protocol)
+    }
+}
+
+/// Type responsible for source IPv4 address extraction
+pub struct SourceIpv4Extractor;
+
+impl Extractor<Ipv4Addr> for SourceIpv4Extractor {
+    /// Extract the source IPv4 address from a rule
+    fn from_rule(rule: &Rule) -> Option<Ipv4Addr> {
+        match rule.net_rule.as_ref().and_then(|r| r.ip_in.as_ref()) {
+            Some(&IpAddr::V4(ref addr)) => Some(addr.clone()),
+            _ => None,
+        }
+    }
+
+    /// Extract the source IPv4 address from a packet
+    fn from_packet(pkt: &Packet) -> Option<Ipv4Addr> {
+        pkt.net_header::<Header>().map(|hdr| hdr.src.clone())
+    }
+}
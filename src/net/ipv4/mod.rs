@@ -0,0 +1,421 @@
+//! Minimal IPv4 layer: routing, datagram transmission and reception
+//!
+//! Received datagrams are routed to the proper connexion by two filters,
+//! based on the protocol id and on the source address.
+
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use vec::Vec;
+
+use net::{Instance, Interface, Packet, PacketBuilder};
+use net::defs::{checksum, HwAddr, Ipv4Addr, ProtocolIdType, ETHERTYPE_IPV4};
+use net::defs::ETH_HDR_SIZE;
+use net::nat::{self, Nat};
+use net::eth;
+
+use net::conn::filter::{GenericFilter, SpecificFilter};
+
+pub mod defs;
+mod sanitizer;
+mod extractor;
+mod callbacks;
+
+use self::sanitizer::Ipv4PacketSanitizer;
+use self::extractor::{ProtocolExtractor, SourceIpv4Extractor};
+use self::callbacks::Ipv4Callbacks;
+
+/// Filter routing IPv4 datagrams on their source address
+pub type Ipv4SpecificFilter = SpecificFilter<Ipv4Addr, ProtocolIdType,
+                                             SourceIpv4Extractor,
+                                             Ipv4Callbacks>;
+
+/// Filter routing IPv4 datagrams on their protocol id
+pub type Ipv4GenericFilter = GenericFilter<ProtocolIdType, Ipv4SpecificFilter,
+                                           ProtocolExtractor,
+                                           Ipv4PacketSanitizer>;
+
+/// Size of an IPv4 header without options
+pub const HDR_SIZE: usize = 20;
+
+/// Time to live of transmitted datagrams
+const DEFAULT_TTL: u8 = 64;
+
+static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Where to send a datagram to reach a destination
+pub struct Route {
+    /// Interface to transmit on
+    pub intf: Interface,
+    /// Source address to use
+    pub src: Ipv4Addr,
+    /// Address whose hardware address is the ethernet destination
+    pub next_hop: Ipv4Addr,
+    /// The destination is a broadcast address
+    pub broadcast: bool,
+}
+
+fn masked(addr: &Ipv4Addr, mask: &Ipv4Addr) -> [u8; 4] {
+    let (a, m) = (addr.octets(), mask.octets());
+
+    [a[0] & m[0], a[1] & m[1], a[2] & m[2], a[3] & m[3]]
+}
+
+/// Returns true if `addr` is the limited broadcast address or the directed
+/// broadcast address of the subnet of `intf`
+pub fn is_broadcast(intf: &Interface, addr: &Ipv4Addr) -> bool {
+    let intf = intf.read();
+    let conf = intf.v4_configuration_ref();
+    let (a, m) = (addr.octets(), conf.ipv4_mask.octets());
+
+    if a == [255, 255, 255, 255] {
+        return true;
+    }
+
+    !conf.ipv4.is_unspecified() && !conf.ipv4_mask.is_unspecified() &&
+    masked(addr, &conf.ipv4_mask) == masked(&conf.ipv4, &conf.ipv4_mask) &&
+    (0..4).all(|i| a[i] | m[i] == 0xff)
+}
+
+/// Returns true if `addr` is the address of `intf` or a broadcast address it
+/// receives
+pub fn is_local(intf: &Interface, addr: &Ipv4Addr) -> bool {
+    *addr == intf.read().v4_configuration_ref().ipv4 || is_broadcast(intf, addr)
+}
+
+/// Find how to reach `dst`
+///
+/// Destinations on the subnet of an interface are reached directly, the
+/// others through the gateway of the first interface having one. Interfaces
+/// that are down or not configured are ignored.
+pub fn route(instance: &Instance, dst: &Ipv4Addr) -> Option<Route> {
+    let intfs: Vec<Interface> = instance.interfaces().iter().cloned().collect();
+    let mut gateway = None;
+
+    for intf in intfs {
+        let (conf, up) = {
+            let raw = intf.read();
+
+            (raw.v4_configuration_ref().clone(), raw.is_up())
+        };
+
+        if !up || conf.ipv4.is_unspecified() {
+            continue;
+        }
+
+        if is_broadcast(&intf, dst) {
+            return Some(Route {
+                intf: intf,
+                src: conf.ipv4,
+                next_hop: dst.clone(),
+                broadcast: true,
+            });
+        }
+
+        if masked(dst, &conf.ipv4_mask) == masked(&conf.ipv4, &conf.ipv4_mask) {
+            return Some(Route {
+                intf: intf,
+                src: conf.ipv4,
+                next_hop: dst.clone(),
+                broadcast: false,
+            });
+        }
+
+        if gateway.is_none() && !conf.ipv4_gateway.is_unspecified() {
+            gateway = Some(Route {
+                intf: intf,
+                src: conf.ipv4,
+                next_hop: conf.ipv4_gateway,
+                broadcast: false,
+            });
+        }
+    }
+
+    gateway
+}
+
+/// Write an IPv4 header for a datagram carrying `payload_len` bytes
+pub fn header(src: &Ipv4Addr, dst: &Ipv4Addr, protocol: ProtocolIdType,
+              payload_len: usize) -> [u8; HDR_SIZE] {
+    let len = HDR_SIZE + payload_len;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) as u16;
+    let (s, d) = (src.octets(), dst.octets());
+
+    let mut hdr = [0x45, 0, (len >> 8) as u8, len as u8,
+                   (id >> 8) as u8, id as u8, 0x40, 0,
+                   DEFAULT_TTL, protocol, 0, 0,
+                   s[0], s[1], s[2], s[3],
+                   d[0], d[1], d[2], d[3]];
+
+    let sum = checksum(&hdr);
+
+    hdr[10] = (sum >> 8) as u8;
+    hdr[11] = sum as u8;
+
+    hdr
+}
+
+/// Send `payload` to `dst` in an IPv4 datagram of protocol `protocol`
+///
+/// The hardware address of the next hop is resolved if needed, which might
+/// block. This *MUST NOT* be called from the network thread.
+pub fn send(instance: &Instance, dst: &Ipv4Addr, protocol: ProtocolIdType,
+            payload: &[u8]) -> Result<(), ()> {
+    let route = try!(route(instance, dst).ok_or(()));
+
+    let dest_hw = if route.broadcast {
+        HwAddr::broadcast()
+    } else {
+        try!(instance.arp().resolve(&route.intf, &route.next_hop).ok_or(()))
+    };
+
+    let src_hw = route.intf.read().hw_addr_ref().clone();
+
+    let mut frame = Vec::with_capacity(ETH_HDR_SIZE + HDR_SIZE +
+                                       payload.len());
+
+    frame.extend_from_slice(dest_hw.as_bytes());
+    frame.extend_from_slice(src_hw.as_bytes());
+    frame.extend_from_slice(&[(ETHERTYPE_IPV4 >> 8) as u8,
+                              ETHERTYPE_IPV4 as u8]);
+    frame.extend_from_slice(&header(&route.src, dst, protocol, payload.len()));
+    frame.extend_from_slice(payload);
+
+    let mut builder = try!(PacketBuilder::new());
+
+    try!(builder.write(&frame));
+
+    route.intf.tx_frame(builder.finalize_raw())
+}
+
+/// Returns the IPv4 address of `intf`
+fn intf_addr(intf: &Interface) -> Ipv4Addr {
+    intf.read().v4_configuration_ref().ipv4.clone()
+}
+
+/// Forward a received packet that is not addressed to the local stack
+///
+/// Packets going from the inside to the outside interface of `nat` are
+/// masqueraded, and packets received on the outside interface that belong
+/// to a translation are sent back to the inside. The frame is rewritten in
+/// place before being transmitted.
+///
+/// Returns the packet back if it must be processed by the local stack.
+/// Packets that cannot be forwarded are dropped, including when the hardware
+/// address of the next hop is not known yet: a request is sent so that the
+/// following packets go through.
+pub fn forward(instance: &Instance, nat: Option<&Nat>,
+               mut pkt: Packet) -> Option<Packet> {
+    let ingress = match pkt.interface() {
+        Some(intf) => intf,
+        None => return None,
+    };
+
+    let is_ipv4 = pkt.link_header::<eth::Header>().map_or(false, |hdr| {
+        hdr.ether_type.as_host() == ETHERTYPE_IPV4
+    });
+
+    if !is_ipv4 || pkt.size() < ETH_HDR_SIZE + HDR_SIZE ||
+       pkt.as_bytes()[ETH_HDR_SIZE] >> 4 != 4 {
+        return Some(pkt);
+    }
+
+    let mut translated = false;
+
+    if let Some(nat) = nat {
+        if nat.is_outside(&ingress) {
+            let addr = intf_addr(&ingress);
+
+            translated = nat.translate_in(pkt.as_mut_bytes(), &addr);
+        }
+    }
+
+    let dst = {
+        let ip = &pkt.as_bytes()[ETH_HDR_SIZE..];
+
+        Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19])
+    };
+
+    if !translated &&
+       instance.interfaces().iter().any(|intf| is_local(intf, &dst)) {
+        return Some(pkt);
+    }
+
+    // Multicast destinations are not forwarded
+    if dst.octets()[0] >= 224 {
+        return Some(pkt);
+    }
+
+    if pkt.as_bytes()[ETH_HDR_SIZE + 8] <= 1 {
+        return None;
+    }
+
+    let route = match route(instance, &dst) {
+        Some(route) => route,
+        None => return None,
+    };
+
+    if route.broadcast || (route.intf == ingress && !translated) {
+        return None;
+    }
+
+    if let Some(nat) = nat {
+        if !translated && nat.is_outside(&route.intf) && nat.is_inside(&ingress) {
+            if !nat.translate_out(pkt.as_mut_bytes(), &route.src) {
+                return None;
+            }
+        }
+    }
+
+    let dest_hw = match instance.arp().lookup(&route.next_hop) {
+        Some(hw_addr) => hw_addr,
+        None => {
+            let _ = instance.arp().request(&route.intf, &route.next_hop);
+            return None;
+        }
+    };
+
+    let src_hw = route.intf.read().hw_addr_ref().clone();
+
+    {
+        let frame = pkt.as_mut_bytes();
+
+        // Decrement the TTL and update the header checksum
+        let ip = ETH_HDR_SIZE;
+        let old = ((frame[ip + 8] as u16) << 8) | frame[ip + 9] as u16;
+
+        frame[ip + 8] -= 1;
+
+        nat::fix16(frame, ip + 10, old, old - 0x100);
+
+        for i in 0..6 {
+            frame[i] = dest_hw.as_bytes()[i];
+            frame[6 + i] = src_hw.as_bytes()[i];
+        }
+    }
+
+    let _ = route.intf.tx_frame(pkt);
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use net::{Instance, Interface, V4Configuration, V4Source};
+    use net::defs::{checksum, HwAddr, Ipv4Addr, ETH_HDR_SIZE, PROTOCOL_UDP};
+    use net::test_support::{hw, interface, sent};
+
+    use super::{header, is_broadcast, route, send, HDR_SIZE};
+
+    /// Interface registered in `instance` with a static configuration
+    fn configured(instance: &Instance, last: u8, ipv4: Ipv4Addr,
+                  prefix_len: u8, gateway: Ipv4Addr) -> Interface {
+        let intf = interface(instance, last);
+
+        *intf.write().v4_configuration_mut() = V4Configuration {
+            ipv4: ipv4,
+            ipv4_mask: Ipv4Addr::from_prefix_len(prefix_len),
+            ipv4_gateway: gateway,
+            source: V4Source::CmdLine,
+        };
+
+        instance.add_interface(intf.clone());
+
+        intf
+    }
+
+    #[test]
+    fn test_is_broadcast() {
+        let instance = Instance::new();
+        let intf = configured(&instance, 1, Ipv4Addr::new(10, 0, 0, 2), 24,
+                              Ipv4Addr::new(0, 0, 0, 0));
+
+        assert!(is_broadcast(&intf, &Ipv4Addr::new(255, 255, 255, 255)));
+        assert!(is_broadcast(&intf, &Ipv4Addr::new(10, 0, 0, 255)));
+        assert!(!is_broadcast(&intf, &Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(!is_broadcast(&intf, &Ipv4Addr::new(10, 0, 1, 255)));
+    }
+
+    #[test]
+    fn test_route() {
+        let instance = Instance::new();
+        let lan = configured(&instance, 1, Ipv4Addr::new(10, 0, 0, 2), 24,
+                             Ipv4Addr::new(0, 0, 0, 0));
+        let wan = configured(&instance, 2, Ipv4Addr::new(192, 168, 1, 2), 24,
+                             Ipv4Addr::new(192, 168, 1, 1));
+
+        // Destinations on a subnet are reached directly
+        let direct = route(&instance, &Ipv4Addr::new(10, 0, 0, 7)).unwrap();
+
+        assert!(direct.intf == lan && !direct.broadcast);
+        assert!(direct.src == Ipv4Addr::new(10, 0, 0, 2));
+        assert!(direct.next_hop == Ipv4Addr::new(10, 0, 0, 7));
+
+        let broadcast = route(&instance,
+                              &Ipv4Addr::new(10, 0, 0, 255)).unwrap();
+
+        assert!(broadcast.intf == lan && broadcast.broadcast);
+
+        // The others through the gateway
+        let remote = route(&instance, &Ipv4Addr::new(8, 8, 8, 8)).unwrap();
+
+        assert!(remote.intf == wan && !remote.broadcast);
+        assert!(remote.src == Ipv4Addr::new(192, 168, 1, 2));
+        assert!(remote.next_hop == Ipv4Addr::new(192, 168, 1, 1));
+
+        // Interfaces that are down are ignored
+        wan.set_oper_up(false);
+
+        assert!(route(&instance, &Ipv4Addr::new(8, 8, 8, 8)).is_none());
+        assert!(route(&instance, &Ipv4Addr::new(10, 0, 0, 7)).is_some());
+    }
+
+    #[test]
+    fn test_header() {
+        let hdr = header(&Ipv4Addr::new(10, 0, 0, 2),
+                         &Ipv4Addr::new(10, 0, 0, 1), PROTOCOL_UDP, 12);
+
+        assert_eq!(hdr[0], 0x45);
+        assert_eq!(((hdr[2] as usize) << 8) | hdr[3] as usize, HDR_SIZE + 12);
+        assert_eq!(hdr[9], PROTOCOL_UDP);
+        assert_eq!(&hdr[12..], &[10, 0, 0, 2, 10, 0, 0, 1][..]);
+        assert_eq!(checksum(&hdr), 0);
+    }
+
+    #[test]
+    fn test_send() {
+        let instance = Instance::new();
+        let intf = configured(&instance, 1, Ipv4Addr::new(10, 0, 0, 2), 24,
+                              Ipv4Addr::new(0, 0, 0, 0));
+
+        // Broadcasts need no resolution
+        send(&instance, &Ipv4Addr::new(10, 0, 0, 255), PROTOCOL_UDP,
+             b"ping").unwrap();
+
+        // Known neighbours neither
+        instance.arp().insert(Ipv4Addr::new(10, 0, 0, 1), hw(9));
+
+        send(&instance, &Ipv4Addr::new(10, 0, 0, 1), PROTOCOL_UDP,
+             b"pong").unwrap();
+
+        let frames = sent(&intf);
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(&frames[0][..6], HwAddr::broadcast().as_bytes());
+        assert_eq!(&frames[1][..6], hw(9).as_bytes());
+
+        for frame in &frames {
+            let ip = &frame[ETH_HDR_SIZE..];
+
+            assert_eq!(&frame[6..12], hw(1).as_bytes());
+            assert_eq!(checksum(&ip[..HDR_SIZE]), 0);
+            assert_eq!(ip.len(), HDR_SIZE + 4);
+        }
+
+        assert_eq!(&frames[1][ETH_HDR_SIZE + HDR_SIZE..], b"pong");
+
+        // Unreachable destinations are not sent anywhere
+        assert!(send(&instance, &Ipv4Addr::new(8, 8, 8, 8), PROTOCOL_UDP,
+                     b"ping").is_err());
+        assert_eq!(sent(&intf).len(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+//! Sanitize incoming packets at the IPv4 layer
+
+use core::mem;
+
+use net::Packet;
+
+use net::defs::checksum;
+
+use net::conn::filter::PacketSanitizer;
+
+use super::defs::Header;
+use super::is_local;
+
+/// Sanitize a packet at the IPv4 level
+pub struct Ipv4PacketSanitizer;
+
+impl PacketSanitizer for Ipv4PacketSanitizer {
+    /// Determine if the packet is a valid IPv4 datagram addressed to the
+    /// interface it was received on
+    fn sanitize(pkt: &mut Packet) -> Result<(), ()> {
+        let hdr_size = {
+            // Incoming packet *MUST* have an interface set
+            let intf = try!(pkt.interface().ok_or(()));
+
+            let hdr = try!(pkt.net_header::<Header>().ok_or(()));
+            let hdr_size = hdr.header_size();
+            let length = hdr.length.as_host() as usize;
+
+            if hdr.version() != 4 || hdr_size < mem::size_of::<Header>() ||
+               length < hdr_size ||
+               length > pkt.size() - pkt.link_hdr_size() {
+                return Err(());
+            }
+
+            // Fragments are not reassembled
+            if hdr.is_fragment() {
+                return Err(());
+            }
+
+            // The checksum of a valid header, checksum included, is 0
+            let start = pkt.link_hdr_size();
+
+            if checksum(&pkt.as_bytes()[start..start + hdr_size]) != 0 {
+                return Err(());
+            }
+
+            if !is_local(&intf, &hdr.dst) {
+                return Err(());
+            }
+
+            hdr_size
+        };
+
+        unsafe {
+            // Set the size of the network layer header, options included
+            *pkt.net_hdr_size_mut() = hdr_size;
+        }
+
+        Ok(())
+    }
+}
@@ -9,11 +9,24 @@ mod imp;
 mod pkt;
 mod intf;
 pub mod conn;
+pub mod firewall;
+pub mod shaper;
+pub mod arp;
+pub mod ipv4;
+pub mod udp;
+pub mod sntp;
+pub mod syslog;
+pub mod tftp;
+pub mod bridge;
+pub mod nat;
+pub mod dissect;
 
 mod eth;
 
 pub mod defs;
 
+#[cfg(test)] mod test_support;
+
 pub use self::imp::{Instance, InstanceWeak};
 
 pub use self::pkt::{
@@ -22,9 +35,14 @@ pub use self::pkt::{
     Formatter as PacketFormatter,
 };
 
-pub use self::intf::{Interface, InterfaceWeak, InterfaceRaw, V4Configuration};
+pub use self::intf::{Interface, InterfaceWeak, InterfaceRaw};
+pub use self::intf::{V4Configuration, V4Source};
+
+pub use self::conn::{UniConn, MultiConn, Sharing};
 
-pub use self::conn::{UniConn, MultiConn};
+pub use self::udp::{UdpSocket, Datagram};
+
+pub use self::dissect::TraceMode;
 
 static STACK: GlobalCell<Instance> = GlobalCell::new();
 
@@ -40,6 +58,21 @@ impl Stack {
         Scheduler::spawn(|| {
             Instance::network_thread(STACK.as_ref().clone());
         });
+
+        // Spawn the thread handling interface hotplug
+        Scheduler::spawn(|| {
+            Instance::hotplug_thread(STACK.as_ref().clone());
+        });
+
+        // Mirror logs to a remote collector if one is configured
+        if let Some(config) = syslog::RemoteLogConfig::from_cmd_line() {
+            let _ = syslog::start(STACK.as_ref().clone(), config);
+        }
+
+        // Synchronize the wall clock if NTP servers are configured
+        if let Some(config) = sntp::SntpConfig::from_cmd_line() {
+            sntp::start(STACK.as_ref().clone(), config);
+        }
     }
 
     /// Returns interfaces registered in the network stack
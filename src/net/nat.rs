@@ -0,0 +1,972 @@
+//! IPv4 network address translation
+//!
+//! Connexions initiated from the inside interface are masqueraded behind the
+//! address of the outside interface (source NAT with port translation, also
+//! known as NAPT). TCP and UDP ports and ICMP echo identifiers are
+//! translated. Static port forwards expose inside servers on the outside.
+//!
+//! ICMP errors received on the outside about a translated flow (e.g. port
+//! unreachable or time exceeded) are translated back to the inside host,
+//! datagram quoted in the error included (RFC 5508). ICMP errors sent by
+//! inside hosts are not translated and dropped.
+//!
+//! Translations are done in place in the frame and checksums are updated
+//! incrementally (RFC 1624). Their lifetime follows the state of the flow
+//! they belong to, tracked as the firewall does.
+
+use core::cmp;
+
+use vec::Vec;
+use btree_map::BTreeMap;
+use btree_set::BTreeSet;
+
+use sync::spin::SpinLock;
+
+use time::{Duration, Instant};
+
+use net::{Interface, InterfaceWeak};
+use net::defs::{be16, checksum, put16, Ipv4Addr, PortType, ProtocolIdType};
+use net::defs::ETH_HDR_SIZE;
+use net::defs::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+use net::firewall::{Conntrack, Direction, PortRange, Timeouts, TrackedKey};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_PARAMETER_PROBLEM: u8 = 12;
+
+/// Size of an ICMP header, and of the part of a transport header quoted in
+/// ICMP errors
+const ICMP_HDR_SIZE: usize = 8;
+
+#[derive(Clone)]
+/// Configuration of the translation
+pub struct NatConfig {
+    /// Interface of the private network
+    pub inside: InterfaceWeak,
+    /// Interface whose address is shared
+    pub outside: InterfaceWeak,
+    /// External ports and identifiers allocated to translations
+    pub ports: PortRange,
+    /// Maximum number of translations
+    pub max_entries: usize,
+    /// Lifetimes of idle translations
+    pub timeouts: Timeouts,
+}
+
+impl NatConfig {
+    /// Create a configuration masquerading `inside` behind `outside` with
+    /// the timeouts recommended by the IETF BEHAVE RFCs
+    pub fn new(inside: &Interface, outside: &Interface) -> Self {
+        NatConfig {
+            inside: inside.downgrade(),
+            outside: outside.downgrade(),
+            ports: PortRange::new(20000, 60999),
+            max_entries: 4096,
+            timeouts: Timeouts {
+                // RFC 5382
+                tcp_new: Duration::from_secs(240),
+                tcp_established: Duration::from_secs(7440),
+                tcp_closing: Duration::from_secs(240),
+                // RFC 4787
+                udp_new: Duration::from_secs(120),
+                udp_established: Duration::from_secs(120),
+                // RFC 5508
+                icmp: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+/// A static port forward
+pub struct PortForward {
+    /// TCP or UDP
+    pub protocol: ProtocolIdType,
+    /// Port on the outside address
+    pub external_port: PortType,
+    /// Address of the inside server
+    pub internal: Ipv4Addr,
+    /// Port of the inside server
+    pub internal_port: PortType,
+}
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+/// Identifies a translation from the inside
+struct InsideKey {
+    protocol: ProtocolIdType,
+    addr: Ipv4Addr,
+    port: PortType,
+    remote: Ipv4Addr,
+    remote_port: PortType,
+}
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq)]
+/// Identifies a translation from the outside
+struct OutsideKey {
+    protocol: ProtocolIdType,
+    port: PortType,
+    remote: Ipv4Addr,
+    remote_port: PortType,
+}
+
+impl TrackedKey for OutsideKey {
+    #[inline]
+    fn protocol(&self) -> ProtocolIdType {
+        self.protocol
+    }
+}
+
+struct Mapping {
+    inside: InsideKey,
+    /// Created by a port forward, the external port is not allocated
+    forwarded: bool,
+}
+
+struct Table {
+    inside: BTreeMap<InsideKey, OutsideKey>,
+    outside: BTreeMap<OutsideKey, Mapping>,
+    /// State of the flow of each translation. A translation is expired
+    /// once its flow is no longer tracked
+    flows: Conntrack<OutsideKey>,
+    /// External ports allocated per protocol
+    used: BTreeSet<(ProtocolIdType, PortType)>,
+    next_port: PortType,
+}
+
+/// Fields of a frame relevant to the translation
+struct Header {
+    /// Offset of the IPv4 header
+    ip: usize,
+    /// Offset of the transport header
+    tspt: usize,
+    protocol: ProtocolIdType,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    /// Ports, or the identifier twice for ICMP echo
+    src_port: PortType,
+    dst_port: PortType,
+    icmp_type: u8,
+    tcp_flags: u8,
+}
+
+/// Update the checksum `sum` after a 16 bits word changed from `old` to
+/// `new` (RFC 1624 equation 3)
+pub fn csum_update(sum: u16, old: u16, new: u16) -> u16 {
+    let mut s = (!sum as u32) + (!old as u32 & 0xffff) + new as u32;
+
+    while s >> 16 != 0 {
+        s = (s & 0xffff) + (s >> 16);
+    }
+
+    !(s as u16)
+}
+
+/// Update the checksum at `offset` after a 16 bits word changed
+pub fn fix16(frame: &mut [u8], offset: usize, old: u16, new: u16) {
+    let sum = csum_update(be16(frame, offset), old, new);
+
+    put16(frame, offset, sum);
+}
+
+/// Update the checksum at `offset` after an address changed
+fn fix_addr(frame: &mut [u8], offset: usize, old: &Ipv4Addr, new: &Ipv4Addr) {
+    let (o, n) = (old.octets(), new.octets());
+
+    fix16(frame, offset, ((o[0] as u16) << 8) | o[1] as u16,
+          ((n[0] as u16) << 8) | n[1] as u16);
+    fix16(frame, offset, ((o[2] as u16) << 8) | o[3] as u16,
+          ((n[2] as u16) << 8) | n[3] as u16);
+}
+
+#[inline]
+fn is_icmp_error(icmp_type: u8) -> bool {
+    icmp_type == ICMP_DEST_UNREACHABLE || icmp_type == ICMP_TIME_EXCEEDED ||
+    icmp_type == ICMP_PARAMETER_PROBLEM
+}
+
+/// Parse the frame, or the datagram quoted in an ICMP error, whose IPv4
+/// header starts at `ip`
+///
+/// ICMP errors are accepted, with both ports set to 0. Only the first bytes
+/// of the transport header of a quoted datagram are available, `tcp_flags`
+/// is left to 0 for them.
+fn parse_at(frame: &[u8], ip: usize, quoted: bool) -> Option<Header> {
+    if frame.len() < ip + 20 || frame[ip] >> 4 != 4 {
+        return None;
+    }
+
+    let ihl = ((frame[ip] & 0x0f) as usize) * 4;
+    let tspt = ip + ihl;
+
+    // Fragments other than the first do not carry the transport header
+    if ihl < 20 || be16(frame, ip + 6) & 0x1fff != 0 {
+        return None;
+    }
+
+    let mut hdr = Header {
+        ip: ip,
+        tspt: tspt,
+        protocol: frame[ip + 9],
+        src: Ipv4Addr::new(frame[ip + 12], frame[ip + 13], frame[ip + 14],
+                           frame[ip + 15]),
+        dst: Ipv4Addr::new(frame[ip + 16], frame[ip + 17], frame[ip + 18],
+                           frame[ip + 19]),
+        src_port: 0,
+        dst_port: 0,
+        icmp_type: 0,
+        tcp_flags: 0,
+    };
+
+    match hdr.protocol {
+        PROTOCOL_TCP if quoted && frame.len() >= tspt + ICMP_HDR_SIZE => {
+            hdr.src_port = be16(frame, tspt);
+            hdr.dst_port = be16(frame, tspt + 2);
+        }
+        PROTOCOL_TCP if frame.len() >= tspt + 20 => {
+            hdr.src_port = be16(frame, tspt);
+            hdr.dst_port = be16(frame, tspt + 2);
+            hdr.tcp_flags = frame[tspt + 13];
+        }
+        PROTOCOL_UDP if frame.len() >= tspt + 8 => {
+            hdr.src_port = be16(frame, tspt);
+            hdr.dst_port = be16(frame, tspt + 2);
+        }
+        PROTOCOL_ICMP if frame.len() >= tspt + ICMP_HDR_SIZE => {
+            hdr.icmp_type = frame[tspt];
+
+            if hdr.icmp_type == ICMP_ECHO_REQUEST ||
+               hdr.icmp_type == ICMP_ECHO_REPLY {
+                hdr.src_port = be16(frame, tspt + 4);
+                hdr.dst_port = hdr.src_port;
+            } else if quoted || !is_icmp_error(hdr.icmp_type) {
+                // Errors are never sent about errors
+                return None;
+            }
+        }
+        _ => return None,
+    }
+
+    Some(hdr)
+}
+
+#[inline]
+fn parse(frame: &[u8]) -> Option<Header> {
+    parse_at(frame, ETH_HDR_SIZE, false)
+}
+
+/// Rewrite an address of the IPv4 header and fix the checksums
+///
+/// `field` is the offset of the address in the IPv4 header.
+fn rewrite_addr(frame: &mut [u8], hdr: &Header, field: usize,
+                old: &Ipv4Addr, new: &Ipv4Addr) {
+    let octets = new.octets();
+
+    for i in 0..4 {
+        frame[hdr.ip + field + i] = octets[i];
+    }
+
+    fix_addr(frame, hdr.ip + 10, old, new);
+
+    // TCP and UDP checksums cover the addresses through the pseudo header
+    match hdr.protocol {
+        PROTOCOL_TCP => fix_addr(frame, hdr.tspt + 16, old, new),
+        PROTOCOL_UDP if be16(frame, hdr.tspt + 6) != 0 => {
+            fix_addr(frame, hdr.tspt + 6, old, new);
+
+            if be16(frame, hdr.tspt + 6) == 0 {
+                put16(frame, hdr.tspt + 6, 0xffff);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rewrite a port, or the ICMP echo identifier, and fix the checksum
+///
+/// `field` is the offset of the port in the transport header.
+fn rewrite_port(frame: &mut [u8], hdr: &Header, field: usize, old: PortType,
+                new: PortType) {
+    let (field, sum) = match hdr.protocol {
+        PROTOCOL_TCP => (field, 16),
+        PROTOCOL_UDP => (field, 6),
+        _ => (4, 2),
+    };
+
+    put16(frame, hdr.tspt + field, new);
+
+    if hdr.protocol == PROTOCOL_UDP && be16(frame, hdr.tspt + sum) == 0 {
+        return;
+    }
+
+    fix16(frame, hdr.tspt + sum, old, new);
+
+    if hdr.protocol == PROTOCOL_UDP && be16(frame, hdr.tspt + sum) == 0 {
+        put16(frame, hdr.tspt + sum, 0xffff);
+    }
+}
+
+/// Address translator between two interfaces
+pub struct Nat {
+    config: NatConfig,
+    forwards: SpinLock<Vec<PortForward>>,
+    table: SpinLock<Table>,
+}
+
+impl Nat {
+    /// Create a translator with no translation nor port forward
+    pub fn new(config: NatConfig) -> Self {
+        let first = config.ports.start;
+        let flows = Conntrack::with_timeouts(config.max_entries,
+                                             config.timeouts);
+
+        Nat {
+            config: config,
+            forwards: SpinLock::new(Vec::new()),
+            table: SpinLock::new(Table {
+                inside: BTreeMap::new(),
+                outside: BTreeMap::new(),
+                flows: flows,
+                used: BTreeSet::new(),
+                next_port: first,
+            }),
+        }
+    }
+
+    #[inline]
+    /// Returns the configuration of the translator
+    pub fn config(&self) -> &NatConfig {
+        &self.config
+    }
+
+    /// Returns true if `intf` is the inside interface
+    pub fn is_inside(&self, intf: &Interface) -> bool {
+        self.config.inside.upgrade().map_or(false, |i| i == *intf)
+    }
+
+    /// Returns true if `intf` is the outside interface
+    pub fn is_outside(&self, intf: &Interface) -> bool {
+        self.config.outside.upgrade().map_or(false, |i| i == *intf)
+    }
+
+    /// Add a static port forward
+    ///
+    /// Fails if the protocol is neither TCP nor UDP or if the external port
+    /// is already forwarded.
+    pub fn add_port_forward(&self, fwd: PortForward) -> Result<(), ()> {
+        if fwd.protocol != PROTOCOL_TCP && fwd.protocol != PROTOCOL_UDP {
+            return Err(());
+        }
+
+        let mut forwards = self.forwards.lock();
+
+        if forwards.iter().any(|f| f.protocol == fwd.protocol &&
+                                   f.external_port == fwd.external_port) {
+            return Err(());
+        }
+
+        forwards.push(fwd);
+
+        Ok(())
+    }
+
+    /// Remove the port forward of `external_port`
+    ///
+    /// Returns false if no such forward exists. Translations already
+    /// established through it are kept until they expire.
+    pub fn remove_port_forward(&self, protocol: ProtocolIdType,
+                               external_port: PortType) -> bool {
+        let mut forwards = self.forwards.lock();
+        let len = forwards.len();
+
+        forwards.retain(|f| f.protocol != protocol ||
+                            f.external_port != external_port);
+
+        forwards.len() != len
+    }
+
+    /// Returns the static port forwards
+    pub fn port_forwards(&self) -> Vec<PortForward> {
+        self.forwards.lock().clone()
+    }
+
+    /// Returns the number of translations, expired ones included
+    pub fn len(&self) -> usize {
+        self.table.lock().outside.len()
+    }
+
+    /// Returns true if there is no translation
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove expired translations
+    ///
+    /// Expired translations are ignored anyway, and removed when room is
+    /// needed for new ones.
+    pub fn expire(&self) {
+        self.table.lock().sweep(Instant::now());
+    }
+
+    fn allocate_port(&self, table: &mut Table,
+                     protocol: ProtocolIdType) -> Option<PortType> {
+        let range = &self.config.ports;
+        let count = (range.end - range.start) as usize + 1;
+        let forwards = self.forwards.lock();
+
+        for _ in 0..count {
+            let port = table.next_port;
+
+            table.next_port = if port >= range.end { range.start } else { port + 1 };
+
+            let reserved = forwards.iter().any(|f| f.protocol == protocol &&
+                                                   f.external_port == port);
+
+            if !reserved && !table.used.contains(&(protocol, port)) {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+
+    /// Translate a frame going from the inside to the outside
+    ///
+    /// The source is replaced with `outside_addr` and an external port, a
+    /// translation being created if needed. Returns false if the frame
+    /// cannot be translated and must be dropped.
+    pub fn translate_out(&self, frame: &mut [u8], outside_addr: &Ipv4Addr) -> bool {
+        let hdr = match parse(frame) {
+            Some(hdr) => hdr,
+            None => return false,
+        };
+
+        if hdr.protocol == PROTOCOL_ICMP && is_icmp_error(hdr.icmp_type) {
+            return false;
+        }
+
+        let remote_port = if hdr.protocol == PROTOCOL_ICMP { 0 } else { hdr.dst_port };
+
+        let inside = InsideKey {
+            protocol: hdr.protocol,
+            addr: hdr.src.clone(),
+            port: hdr.src_port,
+            remote: hdr.dst.clone(),
+            remote_port: remote_port,
+        };
+
+        let now = Instant::now();
+        let mut table = self.table.lock();
+
+        let existing = table.inside.get(&inside).cloned();
+
+        let live = match existing {
+            Some(outside) => {
+                if table.flows.update(&outside, Direction::Out, hdr.tcp_flags,
+                                      now).is_some() {
+                    Some(outside)
+                } else {
+                    // Expired translations are not revived
+                    table.remove(&outside);
+                    None
+                }
+            }
+            None => None,
+        };
+
+        let outside = match live {
+            Some(outside) => outside,
+            None => {
+                // ICMP echo replies do not open translations
+                if hdr.protocol == PROTOCOL_ICMP && hdr.icmp_type != ICMP_ECHO_REQUEST {
+                    return false;
+                }
+
+                if table.outside.len() >= self.config.max_entries {
+                    table.sweep(now);
+
+                    if table.outside.len() >= self.config.max_entries {
+                        return false;
+                    }
+                }
+
+                let port = match self.allocate_port(&mut table, hdr.protocol) {
+                    Some(port) => port,
+                    None => return false,
+                };
+
+                let outside = OutsideKey {
+                    protocol: hdr.protocol,
+                    port: port,
+                    remote: hdr.dst.clone(),
+                    remote_port: remote_port,
+                };
+
+                if !table.flows.track(outside.clone(), Direction::Out, now) {
+                    return false;
+                }
+
+                table.used.insert((hdr.protocol, port));
+                table.inside.insert(inside.clone(), outside.clone());
+                table.outside.insert(outside.clone(), Mapping {
+                    inside: inside,
+                    forwarded: false,
+                });
+
+                outside
+            }
+        };
+
+        drop(table);
+
+        rewrite_addr(frame, &hdr, 12, &hdr.src, outside_addr);
+
+        if outside.port != hdr.src_port {
+            rewrite_port(frame, &hdr, 0, hdr.src_port, outside.port);
+        }
+
+        true
+    }
+
+    /// Translate a frame received on the outside for `outside_addr`
+    ///
+    /// The destination is replaced with the inside endpoint of the
+    /// translation, which is created if a port forward matches. Returns
+    /// false if the frame does not belong to a translation, in which case it
+    /// is left untouched for the local stack.
+    pub fn translate_in(&self, frame: &mut [u8], outside_addr: &Ipv4Addr) -> bool {
+        let hdr = match parse(frame) {
+            Some(hdr) => hdr,
+            None => return false,
+        };
+
+        if hdr.dst != *outside_addr {
+            return false;
+        }
+
+        if hdr.protocol == PROTOCOL_ICMP && is_icmp_error(hdr.icmp_type) {
+            return self.translate_error_in(frame, &hdr, outside_addr);
+        }
+
+        if hdr.protocol == PROTOCOL_ICMP && hdr.icmp_type != ICMP_ECHO_REPLY {
+            return false;
+        }
+
+        let key = OutsideKey {
+            protocol: hdr.protocol,
+            port: hdr.dst_port,
+            remote: hdr.src.clone(),
+            remote_port: if hdr.protocol == PROTOCOL_ICMP { 0 } else { hdr.src_port },
+        };
+
+        let now = Instant::now();
+        let mut table = self.table.lock();
+
+        let live = table.flows.update(&key, Direction::In, hdr.tcp_flags,
+                                      now).is_some();
+
+        if !live {
+            if table.outside.contains_key(&key) {
+                table.remove(&key);
+            }
+
+            let fwd = self.forwards.lock()
+                                   .iter()
+                                   .find(|f| f.protocol == hdr.protocol &&
+                                             f.external_port == hdr.dst_port)
+                                   .cloned();
+
+            let fwd = match fwd {
+                Some(fwd) => fwd,
+                None => return false,
+            };
+
+            if table.outside.len() >= self.config.max_entries {
+                table.sweep(now);
+
+                if table.outside.len() >= self.config.max_entries {
+                    return false;
+                }
+            }
+
+            let inside = InsideKey {
+                protocol: hdr.protocol,
+                addr: fwd.internal,
+                port: fwd.internal_port,
+                remote: hdr.src.clone(),
+                remote_port: hdr.src_port,
+            };
+
+            // The initiator of forwarded translations is on the outside
+            if !table.flows.track(key.clone(), Direction::In, now) {
+                return false;
+            }
+
+            table.inside.insert(inside.clone(), key.clone());
+            table.outside.insert(key.clone(), Mapping {
+                inside: inside,
+                forwarded: true,
+            });
+        }
+
+        let inside = table.outside.get(&key).unwrap().inside.clone();
+
+        drop(table);
+
+        rewrite_addr(frame, &hdr, 16, &hdr.dst, &inside.addr);
+
+        if inside.port != hdr.dst_port {
+            rewrite_port(frame, &hdr, 2, hdr.dst_port, inside.port);
+        }
+
+        true
+    }
+
+    /// Translate an ICMP error received on the outside about a datagram sent
+    /// through a translation
+    ///
+    /// Both the destination of the error and the source of the quoted
+    /// datagram are replaced with the inside endpoint. Errors do not refresh
+    /// translations. Returns false if the error does not belong to a
+    /// translation.
+    fn translate_error_in(&self, frame: &mut [u8], hdr: &Header,
+                          outside_addr: &Ipv4Addr) -> bool {
+        // The ICMP message ends with the IPv4 datagram, before any padding
+        let end = cmp::min(hdr.ip + be16(frame, hdr.ip + 2) as usize,
+                           frame.len());
+
+        let quoted = match parse_at(&frame[..end], hdr.tspt + ICMP_HDR_SIZE,
+                                    true) {
+            Some(quoted) => quoted,
+            None => return false,
+        };
+
+        if quoted.src != *outside_addr {
+            return false;
+        }
+
+        let key = OutsideKey {
+            protocol: quoted.protocol,
+            port: quoted.src_port,
+            remote: quoted.dst.clone(),
+            remote_port: match quoted.protocol {
+                PROTOCOL_ICMP => 0,
+                _ => quoted.dst_port,
+            },
+        };
+
+        let inside = {
+            let table = self.table.lock();
+
+            match table.outside.get(&key) {
+                Some(mapping) if table.flows.is_live(&key, Instant::now()) => {
+                    mapping.inside.clone()
+                }
+                _ => return false,
+            }
+        };
+
+        rewrite_addr(frame, hdr, 16, &hdr.dst, &inside.addr);
+
+        // The quoted datagram is truncated, its transport checksum is only
+        // fixed if present
+        let octets = inside.addr.octets();
+
+        for i in 0..4 {
+            frame[quoted.ip + 12 + i] = octets[i];
+        }
+
+        fix_addr(frame, quoted.ip + 10, &quoted.src, &inside.addr);
+
+        let (port, sum) = match quoted.protocol {
+            PROTOCOL_TCP => (0, Some(16)),
+            PROTOCOL_UDP if be16(frame, quoted.tspt + 6) != 0 => (0, Some(6)),
+            PROTOCOL_UDP => (0, None),
+            _ => (4, Some(2)),
+        };
+
+        put16(frame, quoted.tspt + port, inside.port);
+
+        if let Some(sum) = sum {
+            if end >= quoted.tspt + sum + 2 {
+                if quoted.protocol != PROTOCOL_ICMP {
+                    fix_addr(frame, quoted.tspt + sum, &quoted.src,
+                             &inside.addr);
+                }
+
+                fix16(frame, quoted.tspt + sum, quoted.src_port, inside.port);
+
+                if quoted.protocol == PROTOCOL_UDP &&
+                   be16(frame, quoted.tspt + sum) == 0 {
+                    put16(frame, quoted.tspt + sum, 0xffff);
+                }
+            }
+        }
+
+        // The ICMP checksum covers the whole quoted datagram
+        put16(frame, hdr.tspt + 2, 0);
+
+        let sum = checksum(&frame[hdr.tspt..end]);
+
+        put16(frame, hdr.tspt + 2, sum);
+
+        true
+    }
+}
+
+impl Table {
+    fn sweep(&mut self, now: Instant) {
+        self.flows.expire(now);
+
+        let expired: Vec<OutsideKey> = {
+            let flows = &self.flows;
+
+            self.outside.keys()
+                        .filter(|k| flows.state(k).is_none())
+                        .cloned()
+                        .collect()
+        };
+
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &OutsideKey) {
+        self.flows.remove(key);
+
+        if let Some(mapping) = self.outside.remove(key) {
+            self.inside.remove(&mapping.inside);
+
+            if !mapping.forwarded {
+                self.used.remove(&(key.protocol, key.port));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vec::Vec;
+
+    use net::InterfaceWeak;
+    use net::defs::{be16, checksum, put16, Ipv4Addr, ETH_HDR_SIZE};
+    use net::defs::{PROTOCOL_ICMP, PROTOCOL_UDP};
+    use net::firewall::{PortRange, Timeouts};
+    use net::test_support::{ipv4_frame, udp_checksum, udp_frame};
+
+    use time::Duration;
+
+    use super::{csum_update, parse, Nat, NatConfig};
+
+    fn config() -> NatConfig {
+        NatConfig {
+            inside: InterfaceWeak::new(),
+            outside: InterfaceWeak::new(),
+            ports: PortRange::new(20000, 20009),
+            max_entries: 16,
+            timeouts: Timeouts {
+                tcp_new: Duration::from_secs(60),
+                tcp_established: Duration::from_secs(60),
+                tcp_closing: Duration::from_secs(60),
+                udp_new: Duration::from_secs(60),
+                udp_established: Duration::from_secs(60),
+                icmp: Duration::from_secs(60),
+            },
+        }
+    }
+
+    fn inside() -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 0, 2)
+    }
+
+    fn outside() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 1)
+    }
+
+    fn remote() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 53)
+    }
+
+    /// Build an ethernet frame carrying an UDP datagram with valid checksums
+    fn udp(src: &Ipv4Addr, sport: u16, dst: &Ipv4Addr, dport: u16) -> Vec<u8> {
+        udp_frame(src, sport, dst, dport, b"ping")
+    }
+
+    fn addr(frame: &[u8], offset: usize) -> Ipv4Addr {
+        Ipv4Addr::new(frame[offset], frame[offset + 1], frame[offset + 2],
+                      frame[offset + 3])
+    }
+
+    fn ip_valid(frame: &[u8], ip: usize) -> bool {
+        checksum(&frame[ip..ip + 20]) == 0
+    }
+
+    fn udp_valid(frame: &[u8]) -> bool {
+        let ip = ETH_HDR_SIZE;
+
+        udp_checksum(&addr(frame, ip + 12), &addr(frame, ip + 16),
+                     &frame[ip + 20..]) == 0
+    }
+
+    #[test]
+    fn test_csum_update() {
+        let mut data = vec![0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 17, 0, 0];
+        let sum = checksum(&data);
+
+        // Changing a word and updating the checksum gives the checksum of
+        // the new data
+        data[8] = 63;
+
+        assert_eq!(csum_update(sum, 0x4011, 0x3f11), checksum(&data));
+        assert_eq!(csum_update(sum, 0x4011, 0x4011), sum);
+    }
+
+    #[test]
+    fn test_parse() {
+        let frame = udp(&inside(), 1234, &remote(), 53);
+        let hdr = parse(&frame).unwrap();
+
+        assert_eq!(hdr.protocol, PROTOCOL_UDP);
+        assert!(hdr.src == inside() && hdr.dst == remote());
+        assert_eq!((hdr.src_port, hdr.dst_port), (1234, 53));
+        assert_eq!(hdr.tspt, ETH_HDR_SIZE + 20);
+
+        // Truncated datagrams, fragments and ICMP messages other than echo
+        // and errors are not translated
+        assert!(parse(&frame[..ETH_HDR_SIZE + 24]).is_none());
+
+        let mut fragment = frame.clone();
+
+        fragment[ETH_HDR_SIZE + 7] = 1;
+
+        assert!(parse(&fragment).is_none());
+
+        let mut icmp = ipv4_frame(&remote(), &outside(), PROTOCOL_ICMP,
+                                  &[13, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(parse(&icmp).is_none());
+
+        icmp[ETH_HDR_SIZE + 20] = 8;
+
+        assert_eq!(parse(&icmp).unwrap().icmp_type, 8);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let nat = Nat::new(config());
+        let mut frame = udp(&inside(), 1234, &remote(), 53);
+        let ip = ETH_HDR_SIZE;
+
+        assert!(nat.translate_out(&mut frame, &outside()));
+
+        // The source is masqueraded behind the outside address
+        let external = be16(&frame, ip + 20);
+
+        assert!(addr(&frame, ip + 12) == outside());
+        assert!(external >= 20000 && external <= 20009);
+        assert!(ip_valid(&frame, ip) && udp_valid(&frame));
+        assert_eq!(nat.len(), 1);
+
+        // The reply goes back to the inside host
+        let mut reply = udp(&remote(), 53, &outside(), external);
+
+        assert!(nat.translate_in(&mut reply, &outside()));
+        assert!(addr(&reply, ip + 16) == inside());
+        assert_eq!(be16(&reply, ip + 22), 1234);
+        assert!(ip_valid(&reply, ip) && udp_valid(&reply));
+
+        // The same flow keeps its translation
+        let mut again = udp(&inside(), 1234, &remote(), 53);
+
+        assert!(nat.translate_out(&mut again, &outside()));
+        assert_eq!(be16(&again, ip + 20), external);
+        assert_eq!(nat.len(), 1);
+
+        // Unknown flows are left to the local stack
+        let mut unknown = udp(&remote(), 53, &outside(), external + 1);
+
+        assert!(!nat.translate_in(&mut unknown, &outside()));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut conf = config();
+
+        conf.timeouts.udp_new = Duration::from_secs(0);
+
+        let nat = Nat::new(conf);
+        let ip = ETH_HDR_SIZE;
+        let mut frame = udp(&inside(), 1234, &remote(), 53);
+
+        assert!(nat.translate_out(&mut frame, &outside()));
+
+        // Unanswered flows expire, and so do their translations
+        let external = be16(&frame, ip + 20);
+        let mut reply = udp(&remote(), 53, &outside(), external);
+
+        assert!(!nat.translate_in(&mut reply, &outside()));
+
+        let mut again = udp(&inside(), 1234, &remote(), 53);
+
+        assert!(nat.translate_out(&mut again, &outside()));
+        assert!(be16(&again, ip + 20) != external);
+        assert_eq!(nat.len(), 1);
+
+        nat.expire();
+
+        assert!(nat.is_empty());
+    }
+
+    /// Build a port unreachable error from the remote host quoting the IPv4
+    /// and UDP headers of `frame`
+    fn unreachable(frame: &[u8]) -> Vec<u8> {
+        let ip = ETH_HDR_SIZE;
+        let mut icmp = vec![3, 3, 0, 0, 0, 0, 0, 0];
+
+        icmp.extend_from_slice(&frame[ip..ip + 28]);
+
+        let sum = checksum(&icmp);
+
+        put16(&mut icmp, 2, sum);
+
+        ipv4_frame(&remote(), &outside(), PROTOCOL_ICMP, &icmp)
+    }
+
+    #[test]
+    fn test_icmp_error() {
+        let nat = Nat::new(config());
+        let mut frame = udp(&inside(), 1234, &remote(), 53);
+        let ip = ETH_HDR_SIZE;
+
+        assert!(nat.translate_out(&mut frame, &outside()));
+
+        let mut error = unreachable(&frame);
+
+        assert!(nat.translate_in(&mut error, &outside()));
+
+        // The error and the quoted datagram are about the inside host
+        let quoted = ip + 28;
+
+        assert!(addr(&error, ip + 16) == inside());
+        assert!(addr(&error, quoted + 12) == inside());
+        assert_eq!(be16(&error, quoted + 20), 1234);
+        assert!(ip_valid(&error, ip) && ip_valid(&error, quoted));
+        assert_eq!(checksum(&error[ip + 20..]), 0);
+
+        // Errors about unknown flows are dropped
+        let external = be16(&frame, ip + 20);
+
+        put16(&mut frame, ip + 20, external + 1);
+
+        let mut unknown = unreachable(&frame);
+
+        assert!(!nat.translate_in(&mut unknown, &outside()));
+
+        // Inside hosts do not send errors through the translation
+        let mut outgoing = unreachable(&frame);
+
+        assert!(!nat.translate_out(&mut outgoing, &outside()));
+    }
+}
@@ -5,8 +5,9 @@ use core::{mem, slice};
 use sync::Arc;
 
 use net::{Interface, InterfaceWeak};
+use net::shaper::TrafficClass;
 
-use alloc_uni::{__rust_allocate, __rust_deallocate};
+use alloc::heap::{allocate, deallocate};
 
 use hal::arch::defs::PAGE_SIZE;
 
@@ -35,6 +36,8 @@ pub struct Builder {
     /// Was the packet generated yet ? Used in the destructor to determine if
     /// deallocation is necessary
     finalized: bool,
+    /// Traffic class of the packet, guessed when transmitted if not set
+    class: Option<TrafficClass>,
 }
 
 impl Builder {
@@ -43,7 +46,7 @@ impl Builder {
     /// This does an allocation under the hood, which is why this method can
     /// fail.
     pub fn new() -> Result<Self, ()> {
-        let page = __rust_allocate(PAGE_SIZE, PAGE_SIZE);
+        let page = allocate(PAGE_SIZE, PAGE_SIZE);
 
         if page.is_null() {
             Err(())
@@ -55,6 +58,7 @@ impl Builder {
                 link_fmt: None,
                 net_fmt: None,
                 finalized: false,
+                class: None,
             })
         }
     }
@@ -73,6 +77,20 @@ impl Builder {
         }
     }
 
+    #[inline]
+    /// Set the traffic class of the packet
+    ///
+    /// This is used to prioritize packets when the interface is shaped.
+    pub fn set_traffic_class(&mut self, class: TrafficClass) {
+        self.class = Some(class);
+    }
+
+    #[inline]
+    /// Returns the traffic class of the packet if it was set
+    pub fn traffic_class(&self) -> Option<TrafficClass> {
+        self.class
+    }
+
     #[inline]
     /// Set the formatter for the link layer
     pub fn set_link_fmt(&mut self, fmt: Arc<Formatter>) {
@@ -122,20 +140,38 @@ impl Builder {
         }
 
         let offset = self.data as usize - self.page as usize;
-        let pkt = unsafe {
+        let mut pkt = unsafe {
             Packet::new(self.page, offset, self.size)
         };
 
+        pkt.class = self.class;
+
         self.finalized = true;
 
         Ok(pkt)
     }
+
+    /// Generate the packet as is, without formatting it
+    ///
+    /// This is meant for frames that were entirely written by hand.
+    pub fn finalize_raw(mut self) -> Packet {
+        let offset = self.data as usize - self.page as usize;
+        let mut pkt = unsafe {
+            Packet::new(self.page, offset, self.size)
+        };
+
+        pkt.class = self.class;
+
+        self.finalized = true;
+
+        pkt
+    }
 }
 
 impl Drop for Builder {
     fn drop(&mut self) {
         if !self.finalized {
-            __rust_deallocate(self.page, PAGE_SIZE, PAGE_SIZE);
+            deallocate(self.page, PAGE_SIZE, PAGE_SIZE);
         }
     }
 }
@@ -143,7 +179,7 @@ impl Drop for Builder {
 /// A network packet
 pub struct Packet {
     /// The page that contains the packet. This is aligned on PAGE_SIZE and
-    /// must be allocated by __rust_allocate(PAGE_SIZE, PAGE_SIZE)
+    /// must be allocated by `alloc::heap::allocate(PAGE_SIZE, PAGE_SIZE)`
     page: *mut u8,
     /// Pointer to the start of the data.
     data: *mut u8,
@@ -157,6 +193,8 @@ pub struct Packet {
     net_hdr_size: usize,
     /// Size of the transport header
     tspt_hdr_size: usize,
+    /// Traffic class of the packet
+    class: Option<TrafficClass>,
 }
 
 impl Packet {
@@ -169,7 +207,8 @@ impl Packet {
     ///
     /// This method is unsafe because a few requirements are necessary:
     ///
-    /// * `page` must be allocated with `__rust_allocate(PAGE_SIZE, PAGE_SIZE)`
+    /// * `page` must be allocated with
+    ///   `alloc::heap::allocate(PAGE_SIZE, PAGE_SIZE)`
     /// * `page` ownership is transferred to this packet (i.e. it will be
     ///   deallocated on `Drop`)
     pub unsafe fn new(page: *mut u8, offset: usize, size: usize) -> Self {
@@ -181,6 +220,7 @@ impl Packet {
             link_hdr_size: 0,
             net_hdr_size: 0,
             tspt_hdr_size: 0,
+            class: None,
         }
     }
 
@@ -208,6 +248,28 @@ impl Packet {
         self.data
     }
 
+    #[inline]
+    /// Get the data contained in the packet as a slice
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self.data, self.size)
+        }
+    }
+
+    #[inline]
+    /// Get the data contained in the packet as a mutable slice
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.data, self.size)
+        }
+    }
+
+    #[inline]
+    /// Returns the traffic class of the packet if it was set
+    pub fn traffic_class(&self) -> Option<TrafficClass> {
+        self.class
+    }
+
     #[inline]
     /// Return the interface the packet is linked to
     pub fn interface(&self) -> Option<Interface> {
@@ -323,6 +385,6 @@ impl Packet {
 
 impl Drop for Packet {
     fn drop(&mut self) {
-        __rust_deallocate(self.page, PAGE_SIZE, PAGE_SIZE);
+        deallocate(self.page, PAGE_SIZE, PAGE_SIZE);
     }
 }
@@ -0,0 +1,329 @@
+//! Token bucket traffic shaper
+//!
+//! Packets waiting for tokens are kept in one queue per traffic class. Queues
+//! are served by strict priority: a packet is only released when every queue
+//! of a more important class is empty.
+
+use vec_deque::VecDeque;
+
+use time::{Duration, Instant};
+
+use net::Packet;
+use net::defs::{ETHERTYPE_ARP, PROTOCOL_ICMP, PROTOCOL_ICMPV6, PROTOCOL_TCP};
+use net::firewall::Flow;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Number of traffic classes
+const CLASS_COUNT: usize = 4;
+
+/// TCP flags other than ACK, PSH and ECN ones
+const TCP_NON_ACK_FLAGS: u8 = 0x07 | 0x20;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+/// Traffic class of a packet, from the most to the least important
+pub enum TrafficClass {
+    /// Control traffic, e.g. ARP or TCP acknowledgements
+    Control = 0,
+    /// Latency sensitive traffic
+    Interactive = 1,
+    /// Default class
+    BestEffort = 2,
+    /// Bulk data transfers
+    Bulk = 3,
+}
+
+impl TrafficClass {
+    /// Guess the class of a frame
+    ///
+    /// ARP and TCP segments without payload nor control flags other than ACK
+    /// are `Control`, ICMP is `Interactive` and everything else is
+    /// `BestEffort`.
+    pub fn classify(frame: &[u8]) -> Self {
+        let flow = match Flow::parse(frame) {
+            Some(flow) => flow,
+            None => return TrafficClass::BestEffort,
+        };
+
+        if flow.ether_type == ETHERTYPE_ARP {
+            return TrafficClass::Control;
+        }
+
+        match flow.protocol {
+            Some(PROTOCOL_ICMP) | Some(PROTOCOL_ICMPV6) => {
+                TrafficClass::Interactive
+            }
+            Some(PROTOCOL_TCP) if flow.tspt_offset != 0 => {
+                let data_offset = ((frame[flow.tspt_offset + 12] >> 4) as usize) * 4;
+                let pure_ack = flow.tcp_flags & TCP_NON_ACK_FLAGS == 0 &&
+                               frame.len() <= flow.tspt_offset + data_offset;
+
+                if pure_ack {
+                    TrafficClass::Control
+                } else {
+                    TrafficClass::BestEffort
+                }
+            }
+            _ => TrafficClass::BestEffort,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Configuration of a shaper
+pub struct ShaperConfig {
+    /// Sustained rate in bytes per second. Nothing is released if this is 0
+    pub rate: u64,
+    /// Maximum number of bytes that can be sent at once after an idle period
+    pub burst: u64,
+    /// Maximum number of packets waiting in each class queue
+    pub queue_len: usize,
+}
+
+/// Token bucket shaper
+pub struct Shaper {
+    config: ShaperConfig,
+    /// Available bytes. This goes negative when a packet bigger than the
+    /// remaining tokens is released so that large packets cannot starve.
+    tokens: i64,
+    /// Part of a token earned since the last refill, in billionths of a
+    /// token
+    fraction: u64,
+    last_refill: Instant,
+    queues: [VecDeque<Packet>; CLASS_COUNT],
+    dropped: usize,
+}
+
+impl Shaper {
+    /// Create a shaper with a full bucket
+    pub fn new(config: ShaperConfig) -> Self {
+        Shaper {
+            config: config,
+            tokens: config.burst as i64,
+            fraction: 0,
+            last_refill: Instant::now(),
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new(),
+                     VecDeque::new()],
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    /// Returns the configuration of the shaper
+    pub fn config(&self) -> &ShaperConfig {
+        &self.config
+    }
+
+    #[inline]
+    /// Returns the number of packets dropped because their queue was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the number of packets waiting for tokens
+    pub fn backlog(&self) -> usize {
+        self.queues.iter().map(|q| q.len()).sum()
+    }
+
+    /// Queue a packet to be released once enough tokens are available
+    ///
+    /// Fails if the queue of the packet's class is full.
+    pub fn enqueue(&mut self, pkt: Packet) -> Result<(), ()> {
+        let class = pkt.traffic_class()
+                       .unwrap_or_else(|| TrafficClass::classify(pkt.as_bytes()));
+        let queue = &mut self.queues[class as usize];
+
+        if queue.len() >= self.config.queue_len {
+            self.dropped += 1;
+            return Err(());
+        }
+
+        queue.push_back(pkt);
+
+        Ok(())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_nanos();
+        let burst = self.config.burst as i64;
+
+        self.last_refill = now;
+
+        if self.tokens >= burst || self.config.rate == 0 {
+            return;
+        }
+
+        // Past the time needed to fill the bucket, the elapsed time does not
+        // matter and could overflow the count of earned tokens
+        let missing = (burst - self.tokens) as u64;
+
+        if elapsed >= delay(missing, self.config.rate, self.fraction) {
+            self.tokens = burst;
+            self.fraction = 0;
+        } else {
+            let (fill, fraction) = earn(elapsed, self.config.rate,
+                                        self.fraction);
+
+            self.tokens += fill as i64;
+            self.fraction = fraction;
+        }
+    }
+
+    /// Release packets as long as tokens are available, most important
+    /// classes first
+    pub fn release<F: FnMut(Packet)>(&mut self, now: Instant, mut tx: F) {
+        self.refill(now);
+
+        while self.tokens > 0 {
+            let pkt = match self.queues.iter_mut().filter_map(|q| q.pop_front())
+                                                  .next() {
+                Some(pkt) => pkt,
+                None => break,
+            };
+
+            self.tokens -= pkt.size() as i64;
+
+            tx(pkt);
+        }
+    }
+
+    /// Release every queued packet whatever the available tokens
+    pub fn release_all<F: FnMut(Packet)>(&mut self, mut tx: F) {
+        for queue in self.queues.iter_mut() {
+            while let Some(pkt) = queue.pop_front() {
+                tx(pkt);
+            }
+        }
+    }
+
+    /// Returns when packets waiting for tokens can be released, None if no
+    /// packet is waiting
+    pub fn next_release(&self) -> Option<Instant> {
+        if self.backlog() == 0 {
+            return None;
+        }
+
+        if self.config.rate == 0 {
+            return None;
+        }
+
+        if self.tokens > 0 {
+            return Some(self.last_refill);
+        }
+
+        let missing = (1 - self.tokens) as u64;
+
+        Some(self.last_refill +
+             Duration::from_nanos(delay(missing, self.config.rate,
+                                        self.fraction)))
+    }
+}
+
+/// Returns the tokens earned in `ns` nanoseconds at `rate` bytes per second
+/// on top of `fraction` billionths of a token, along with the billionths of
+/// a token left over
+///
+/// The number of tokens earned must fit in a u64.
+fn earn(ns: u64, rate: u64, fraction: u64) -> (u64, u64) {
+    let (secs, rem) = (ns / NS_PER_SEC, ns % NS_PER_SEC);
+    let (rate_secs, rate_rem) = (rate / NS_PER_SEC, rate % NS_PER_SEC);
+
+    // ns * rate split so that no product overflows
+    let billionths = rem * rate_rem + fraction;
+
+    (secs * rate + rem * rate_secs + billionths / NS_PER_SEC,
+     billionths % NS_PER_SEC)
+}
+
+/// Returns the nanoseconds needed to earn `tokens` tokens at `rate` bytes per
+/// second when `fraction` billionths of a token are already earned
+///
+/// The delay is rounded up so that the tokens are available once it elapsed.
+fn delay(tokens: u64, rate: u64, fraction: u64) -> u64 {
+    let billionths = tokens.saturating_mul(NS_PER_SEC)
+                           .saturating_sub(fraction);
+
+    (billionths + rate - 1) / rate
+}
+
+#[cfg(test)]
+mod test {
+    use time::Duration;
+
+    use super::{earn, delay, Shaper, ShaperConfig, NS_PER_SEC};
+
+    #[test]
+    fn test_earn() {
+        assert_eq!(earn(NS_PER_SEC, 1000, 0), (1000, 0));
+        assert_eq!(earn(NS_PER_SEC / 2, 3, 0), (1, NS_PER_SEC / 2));
+        assert_eq!(earn(NS_PER_SEC / 2, 3, NS_PER_SEC / 2), (2, 0));
+
+        // High rates do not overflow
+        assert_eq!(earn(10 * NS_PER_SEC, 40 * NS_PER_SEC, 0),
+                   (400 * NS_PER_SEC, 0));
+        assert_eq!(earn(1, 40 * NS_PER_SEC + 1, 0), (40, 1));
+    }
+
+    #[test]
+    fn test_earn_small_steps() {
+        let (mut tokens, mut fraction) = (0, 0);
+
+        // At a low rate, every step is too short to earn a whole token. The
+        // leftover must not be lost
+        for _ in 0..1000 {
+            let (fill, rem) = earn(1000, 1000, fraction);
+
+            tokens += fill;
+            fraction = rem;
+        }
+
+        assert_eq!(tokens, 1);
+        assert_eq!(fraction, 0);
+    }
+
+    #[test]
+    fn test_delay() {
+        assert_eq!(delay(1, 1000, 0), 1000_000);
+        assert_eq!(delay(1, 1000, 400_000_000), 600_000);
+        assert_eq!(delay(1, 3, 0), 333_333_334);
+
+        // Never rounded down to 0 at high rates
+        assert_eq!(delay(1, 10 * NS_PER_SEC, 0), 1);
+
+        // The tokens are available once the delay elapsed
+        for &(tokens, rate, fraction) in &[(1, 7, 0), (1500, 12345, 99),
+                                          (4096, 1_000_000_007, 5)] {
+            let (fill, _) = earn(delay(tokens, rate, fraction), rate,
+                                 fraction);
+
+            assert!(fill >= tokens);
+        }
+    }
+
+    #[test]
+    fn test_refill() {
+        let mut shaper = Shaper::new(ShaperConfig {
+            rate: 1000,
+            burst: 100_000,
+            queue_len: 8,
+        });
+        let start = shaper.last_refill;
+
+        // A bucket bigger than what is earned in a few seconds keeps
+        // filling for as long as needed
+        shaper.tokens = -500;
+        shaper.refill(start + Duration::from_secs(50));
+
+        assert_eq!(shaper.tokens, 49_500);
+
+        shaper.refill(start + Duration::from_secs(100));
+
+        assert_eq!(shaper.tokens, 99_500);
+
+        // And never beyond the burst, however long it stays idle
+        shaper.refill(start + Duration::from_secs(1_000_000_000));
+
+        assert_eq!(shaper.tokens, 100_000);
+        assert_eq!(shaper.fraction, 0);
+    }
+}
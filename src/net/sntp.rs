@@ -0,0 +1,241 @@
+//! Simple Network Time Protocol client (RFC 4330)
+//!
+//! Servers are queried periodically and the wall clock is corrected using the
+//! answer with the lowest round trip delay.
+
+use core::str::FromStr;
+
+use vec::Vec;
+
+use hal::cmd_line;
+
+use thread::Scheduler;
+
+use time::{Duration, Instant, SyncInfo, SyncStatus};
+use time::{wall_time, adjust_wall_time, set_sync_status};
+
+use net::{Instance, UdpSocket};
+use net::defs::{Ipv4Addr, PortType};
+
+/// Port of NTP servers
+pub const NTP_PORT: PortType = 123;
+
+const PACKET_SIZE: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the UNIX epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const NS_PER_SEC: i64 = 1_000_000_000;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_ALARM: u8 = 3;
+
+/// Smallest poll interval allowed by RFC 4330
+const MIN_POLL_INTERVAL_SECS: u64 = 15;
+
+#[derive(Clone)]
+/// Configuration of the SNTP client
+pub struct SntpConfig {
+    /// Servers to query
+    pub servers: Vec<Ipv4Addr>,
+    /// Delay between two synchronizations
+    pub poll_interval: Duration,
+    /// Time to wait for the answer of a server
+    pub timeout: Duration,
+}
+
+impl SntpConfig {
+    /// Create a configuration querying `servers` every 64 seconds
+    pub fn new(servers: Vec<Ipv4Addr>) -> Self {
+        SntpConfig {
+            servers: servers,
+            poll_interval: Duration::from_secs(64),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Create a configuration from the `ntp=` option of the command line
+    ///
+    /// The option is a comma separated list of server addresses. Returns None
+    /// if no valid server is given.
+    pub fn from_cmd_line() -> Option<Self> {
+        let mut servers = Vec::new();
+
+        for opt in cmd_line().split(' ').filter(|o| o.starts_with("ntp=")) {
+            for server in opt[4..].split(',') {
+                match Ipv4Addr::from_str(server) {
+                    Ok(addr) => servers.push(addr),
+                    Err(_) => println!("Warning: Invalid NTP server {}", server),
+                }
+            }
+        }
+
+        if servers.is_empty() {
+            None
+        } else {
+            Some(Self::new(servers))
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Result of a query to a server
+pub struct Sample {
+    /// Server that answered
+    pub server: Ipv4Addr,
+    /// Offset of the server clock relative to the wall clock, in nanoseconds
+    pub offset: i64,
+    /// Round trip delay to the server
+    pub delay: Duration,
+    /// Stratum of the server
+    pub stratum: u8,
+}
+
+/// Convert nanoseconds since the UNIX epoch to a NTP timestamp
+fn to_ntp(ns: i64) -> [u8; 8] {
+    let secs = (ns / NS_PER_SEC) as u64 + NTP_UNIX_OFFSET;
+    let frac = (((ns % NS_PER_SEC) as u64) << 32) / NS_PER_SEC as u64;
+    let mut ts = [0; 8];
+
+    for i in 0..4 {
+        ts[i] = (secs >> (24 - 8 * i)) as u8;
+        ts[4 + i] = (frac >> (24 - 8 * i)) as u8;
+    }
+
+    ts
+}
+
+/// Convert a NTP timestamp to nanoseconds since the UNIX epoch
+///
+/// Timestamps whose seconds are below the UNIX epoch are taken to be in the
+/// NTP era starting in 2036.
+fn from_ntp(ts: &[u8]) -> i64 {
+    let be32 = |b: &[u8]| ((b[0] as u64) << 24) | ((b[1] as u64) << 16) |
+                          ((b[2] as u64) << 8) | b[3] as u64;
+
+    let mut secs = be32(&ts[0..4]);
+    let frac = be32(&ts[4..8]);
+
+    if secs < NTP_UNIX_OFFSET {
+        secs += 1 << 32;
+    }
+
+    (secs - NTP_UNIX_OFFSET) as i64 * NS_PER_SEC +
+    ((frac * NS_PER_SEC as u64) >> 32) as i64
+}
+
+/// Query `server` and estimate the offset of its clock
+pub fn query(instance: &Instance, server: &Ipv4Addr,
+             timeout: Duration) -> Result<Sample, ()> {
+    let sock = try!(UdpSocket::bind(instance, 0));
+    let mut request = [0u8; PACKET_SIZE];
+
+    let t1 = wall_time().as_nanos() as i64;
+    let origin = to_ntp(t1);
+
+    request[0] = (VERSION << 3) | MODE_CLIENT;
+    for (dst, src) in request[40..48].iter_mut().zip(origin.iter()) {
+        *dst = *src;
+    }
+
+    try!(sock.send_to(&request, server, NTP_PORT));
+
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let now = Instant::now();
+
+        if now >= deadline {
+            return Err(());
+        }
+
+        let dgram = match sock.recv_from_timeout(deadline.duration_since(now)) {
+            Some(dgram) => dgram,
+            None => return Err(()),
+        };
+
+        let t4 = wall_time().as_nanos() as i64;
+        let reply = &dgram.data;
+
+        if dgram.src != *server || dgram.src_port != NTP_PORT ||
+           reply.len() < PACKET_SIZE {
+            continue;
+        }
+
+        // Replies to another request are ignored
+        if reply[24..32] != origin[..] {
+            continue;
+        }
+
+        let leap = reply[0] >> 6;
+        let mode = reply[0] & 0x7;
+        let stratum = reply[1];
+
+        // A stratum of 0 is a kiss-o'-death, the server should not be used
+        if leap == LEAP_ALARM || mode != MODE_SERVER || stratum == 0 ||
+           stratum > 15 || reply[40..48].iter().all(|&b| b == 0) {
+            return Err(());
+        }
+
+        let t2 = from_ntp(&reply[32..40]);
+        let t3 = from_ntp(&reply[40..48]);
+
+        let offset = ((t2 - t1) + (t3 - t4)) / 2;
+        let delay = (t4 - t1) - (t3 - t2);
+
+        return Ok(Sample {
+            server: server.clone(),
+            offset: offset,
+            delay: Duration::from_nanos(if delay < 0 { 0 } else { delay as u64 }),
+            stratum: stratum,
+        });
+    }
+}
+
+/// Query every server of `config` and correct the wall clock
+///
+/// The answer with the lowest round trip delay is used. Returns it, or an
+/// error if no server answered, in which case the wall clock is untouched.
+pub fn synchronize(instance: &Instance,
+                   config: &SntpConfig) -> Result<Sample, ()> {
+    let best = config.servers.iter()
+                             .filter_map(|s| query(instance, s, config.timeout).ok())
+                             .min_by_key(|s| s.delay);
+
+    let sample = try!(best.ok_or(()));
+
+    adjust_wall_time(sample.offset);
+
+    set_sync_status(SyncStatus::Synchronized(SyncInfo {
+        last_sync: Instant::now(),
+        offset: sample.offset,
+        delay: sample.delay,
+        stratum: sample.stratum,
+    }));
+
+    Ok(sample)
+}
+
+/// Spawn a thread synchronizing the wall clock every poll interval
+///
+/// The poll interval is raised to 15 seconds if lower, as required by
+/// RFC 4330.
+pub fn start(instance: Instance, mut config: SntpConfig) {
+    let min = Duration::from_secs(MIN_POLL_INTERVAL_SECS);
+
+    if config.poll_interval < min {
+        config.poll_interval = min;
+    }
+
+    Scheduler::spawn(move || {
+        loop {
+            if synchronize(&instance, &config).is_err() {
+                println!("Warning: No NTP server answered");
+            }
+
+            Scheduler::sleep(config.poll_interval);
+        }
+    });
+}
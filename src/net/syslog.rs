@@ -0,0 +1,390 @@
+//! Remote logging over UDP
+//!
+//! Records are sent to a remote collector either using the syslog protocol
+//! (RFC 5424, over UDP as described in RFC 5426) or as raw lines, like Linux's
+//! netconsole. Everything printed on the standard output is mirrored as well.
+//!
+//! Records are queued in a bounded backlog and sent by a dedicated thread, so
+//! logging never blocks: when the link is down or the network not configured
+//! yet, records are held until they can be sent, and the oldest ones are
+//! dropped when the backlog is full.
+
+use core::fmt;
+use core::str::FromStr;
+
+use sync::Arc;
+
+use string::String;
+use vec_deque::VecDeque;
+
+use sync::spin::InterruptSpinLock;
+
+use hal::cmd_line;
+
+use io::set_print_mirror;
+
+use thread::{Scheduler, WaitQueue};
+
+use time::{sync_status, Duration, SyncStatus, SystemTime};
+
+use net::{Instance, UdpSocket, ipv4};
+use net::defs::{Ipv4Addr, PortType};
+
+/// Default port of syslog collectors
+pub const SYSLOG_PORT: PortType = 514;
+/// Default port of netconsole collectors
+pub const NETCONSOLE_PORT: PortType = 6666;
+
+/// Messages are truncated to this size so that records are not fragmented
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// Delay between two attempts to send when the network is unreachable
+const RETRY_DELAY_MS: u64 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+/// Severity of a record (RFC 5424 section 6.2.1)
+pub enum Severity {
+    Emergency = 0,
+    Alert = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Informational = 6,
+    Debug = 7,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// How records are framed
+pub enum Framing {
+    /// RFC 5424 syslog messages
+    Syslog,
+    /// Raw lines
+    Netconsole,
+}
+
+#[derive(Clone)]
+/// Configuration of the remote log sink
+pub struct RemoteLogConfig {
+    /// Address of the collector
+    pub server: Ipv4Addr,
+    /// Port of the collector
+    pub port: PortType,
+    /// Framing of the records
+    pub framing: Framing,
+    /// Syslog facility, local0 (16) by default
+    pub facility: u8,
+    /// Host name reported in syslog records
+    pub hostname: String,
+    /// Application name reported in syslog records
+    pub app_name: String,
+    /// Severity of the lines printed on the standard output
+    pub print_severity: Severity,
+    /// Maximum number of records held while they cannot be sent
+    pub backlog: usize,
+}
+
+impl RemoteLogConfig {
+    /// Create a configuration sending records to `server` with `framing`
+    ///
+    /// The port is the default one of the framing.
+    pub fn new(server: Ipv4Addr, framing: Framing) -> Self {
+        RemoteLogConfig {
+            server: server,
+            port: match framing {
+                Framing::Syslog => SYSLOG_PORT,
+                Framing::Netconsole => NETCONSOLE_PORT,
+            },
+            framing: framing,
+            facility: 16,
+            hostname: String::from("-"),
+            app_name: String::from("uni"),
+            print_severity: Severity::Informational,
+            backlog: 256,
+        }
+    }
+
+    /// Create a configuration from the command line
+    ///
+    /// The options are `syslog=<ip>[:<port>]` and `netconsole=<ip>[:<port>]`.
+    /// Returns None if neither is present or valid.
+    pub fn from_cmd_line() -> Option<Self> {
+        for opt in cmd_line().split(' ') {
+            let (framing, value) = if opt.starts_with("syslog=") {
+                (Framing::Syslog, &opt[7..])
+            } else if opt.starts_with("netconsole=") {
+                (Framing::Netconsole, &opt[11..])
+            } else {
+                continue;
+            };
+
+            let mut fields = value.split(':');
+
+            let server = match fields.next().map(Ipv4Addr::from_str) {
+                Some(Ok(server)) => server,
+                _ => {
+                    println!("Warning: Invalid option {}", opt);
+                    continue;
+                }
+            };
+
+            let mut config = Self::new(server, framing);
+
+            if let Some(port) = fields.next() {
+                match PortType::from_str(port) {
+                    Ok(port) => config.port = port,
+                    Err(_) => {
+                        println!("Warning: Invalid option {}", opt);
+                        continue;
+                    }
+                }
+            }
+
+            return Some(config);
+        }
+
+        None
+    }
+}
+
+struct Record {
+    severity: Severity,
+    /// Time elapsed since the UNIX epoch, if the wall clock is synchronized
+    timestamp: Option<SystemTime>,
+    message: String,
+}
+
+struct Sink {
+    config: RemoteLogConfig,
+    backlog: VecDeque<Record>,
+    /// Printed text not terminated by a new line yet
+    partial: String,
+    dropped: usize,
+    /// Used to wake up the sending thread
+    wait: Arc<WaitQueue>,
+}
+
+impl Sink {
+    fn push(&mut self, severity: Severity, mut message: String) {
+        if message.len() > MAX_MESSAGE_SIZE {
+            let mut end = MAX_MESSAGE_SIZE;
+
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            message.truncate(end);
+        }
+
+        let timestamp = match sync_status() {
+            SyncStatus::Unsynchronized => None,
+            SyncStatus::Synchronized(..) => Some(SystemTime::now()),
+        };
+
+        if self.backlog.len() >= self.config.backlog {
+            self.backlog.pop_front();
+            self.dropped += 1;
+        }
+
+        self.backlog.push_back(Record {
+            severity: severity,
+            timestamp: timestamp,
+            message: message,
+        });
+    }
+}
+
+static SINK: InterruptSpinLock<Option<Sink>> = InterruptSpinLock::new(None);
+
+fn frame(config: &RemoteLogConfig, record: &Record) -> String {
+    match config.framing {
+        Framing::Netconsole => format!("{}\n", record.message),
+        Framing::Syslog => {
+            let pri = config.facility as usize * 8 + record.severity as usize;
+            let timestamp = match record.timestamp {
+                Some(ts) => format!("{}", ts.rfc3339()),
+                None => String::from("-"),
+            };
+
+            format!("<{}>1 {} {} {} - - - {}", pri, timestamp,
+                    config.hostname, config.app_name, record.message)
+        }
+    }
+}
+
+/// Mirror of the standard output
+///
+/// Complete lines are turned into records, the rest is kept until the end of
+/// the line is printed.
+fn mirror_print(args: fmt::Arguments) {
+    let text = format!("{}", args);
+
+    let wait = match *SINK.lock() {
+        None => return,
+        Some(ref mut sink) => {
+            sink.partial.push_str(&text);
+
+            while let Some(pos) = sink.partial.find('\n') {
+                let rest = sink.partial.split_off(pos + 1);
+                let line = String::from(sink.partial
+                                            .trim_right_matches(|c| c == '\r' || c == '\n'));
+                let severity = sink.config.print_severity;
+
+                sink.partial = rest;
+                sink.push(severity, line);
+            }
+
+            sink.wait.clone()
+        }
+    };
+
+    wait.unblock();
+}
+
+/// Log a record with `severity` to the remote collector
+///
+/// This never blocks. The record is dropped if remote logging is not
+/// started.
+pub fn log(severity: Severity, args: fmt::Arguments) {
+    let message = format!("{}", args);
+
+    let wait = match *SINK.lock() {
+        None => return,
+        Some(ref mut sink) => {
+            sink.push(severity, message);
+            sink.wait.clone()
+        }
+    };
+
+    wait.unblock();
+}
+
+/// Returns the number of records dropped because the backlog was full
+pub fn dropped() -> usize {
+    SINK.lock().as_ref().map_or(0, |s| s.dropped)
+}
+
+/// Returns the number of records waiting to be sent
+pub fn pending() -> usize {
+    SINK.lock().as_ref().map_or(0, |s| s.backlog.len())
+}
+
+/// Start mirroring logs to the collector configured in `config`
+///
+/// Fails if remote logging is already started.
+pub fn start(instance: Instance, config: RemoteLogConfig) -> Result<(), ()> {
+    let wait = Arc::new(WaitQueue::new());
+
+    {
+        let mut sink = SINK.lock();
+
+        if sink.is_some() {
+            return Err(());
+        }
+
+        *sink = Some(Sink {
+            config: config,
+            backlog: VecDeque::new(),
+            partial: String::new(),
+            dropped: 0,
+            wait: wait.clone(),
+        });
+    }
+
+    set_print_mirror(Some(mirror_print));
+
+    Scheduler::spawn(move || {
+        sender_thread(instance, wait);
+    });
+
+    Ok(())
+}
+
+/// Stop remote logging
+///
+/// Records not sent yet are discarded.
+pub fn stop() {
+    set_print_mirror(None);
+
+    let sink = SINK.lock().take();
+
+    if let Some(sink) = sink {
+        sink.wait.unblock();
+    }
+}
+
+/// Returns true if remote logging is running with the sending thread owning
+/// `wait`
+///
+/// This is false once stopped, even if started again since.
+fn is_current(wait: &Arc<WaitQueue>) -> bool {
+    SINK.lock().as_ref().map_or(false, |s| {
+        &*s.wait as *const WaitQueue == &**wait as *const WaitQueue
+    })
+}
+
+fn has_records(wait: &Arc<WaitQueue>) -> bool {
+    !is_current(wait) || SINK.lock().as_ref().map_or(false, |s| !s.backlog.is_empty())
+}
+
+/// Thread sending queued records
+///
+/// A record is only dequeued once the collector is reachable, so that
+/// records are held while the link is down or the network not configured.
+fn sender_thread(instance: Instance, wait: Arc<WaitQueue>) {
+    let mut sock: Option<UdpSocket> = None;
+
+    loop {
+        wait_event!(wait, has_records(&wait));
+
+        if !is_current(&wait) {
+            return;
+        }
+
+        let config = match *SINK.lock() {
+            Some(ref sink) => sink.config.clone(),
+            None => return,
+        };
+
+        if ipv4::route(&instance, &config.server).is_none() {
+            Scheduler::sleep(Duration::from_millis(RETRY_DELAY_MS));
+            continue;
+        }
+
+        if sock.is_none() {
+            sock = UdpSocket::bind(&instance, 0).ok();
+        }
+
+        let record = match *SINK.lock() {
+            Some(ref mut sink) => sink.backlog.pop_front(),
+            None => None,
+        };
+
+        let record = match record {
+            Some(record) => record,
+            None => continue,
+        };
+
+        let sent = match sock {
+            None => false,
+            Some(ref sock) => {
+                let data = frame(&config, &record);
+
+                sock.send_to(data.as_bytes(), &config.server, config.port).is_ok()
+            }
+        };
+
+        if !sent {
+            // Put the record back, unless newer records filled the backlog
+            if let Some(ref mut sink) = *SINK.lock() {
+                if sink.backlog.len() < sink.config.backlog {
+                    sink.backlog.push_front(record);
+                } else {
+                    sink.dropped += 1;
+                }
+            }
+
+            Scheduler::sleep(Duration::from_millis(RETRY_DELAY_MS));
+        }
+    }
+}
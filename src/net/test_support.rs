@@ -0,0 +1,128 @@
+//! Frame factories shared by the unit tests of the network stack
+//!
+//! Frames are built as byte vectors, with valid lengths and checksums, so
+//! that they can be given both to the parsers working on raw frames and,
+//! through `packet()`, to the code working on packets. Interfaces built by
+//! `interface()` keep what they transmit instead of sending it.
+
+use boxed::Box;
+use vec::Vec;
+
+use hal::net::HwInterface;
+
+use net::{Instance, Interface, Packet, PacketBuilder};
+use net::defs::{checksum, put16, EtherType, HwAddr, Ipv4Addr, PortType};
+use net::defs::{ProtocolIdType, ETHERTYPE_IPV4, ETH_HDR_SIZE, PROTOCOL_UDP};
+
+/// Hardware address 00:16:3e:00:00:`last`
+pub fn hw(last: u8) -> HwAddr {
+    unsafe { HwAddr::from_bytes(&[0x00, 0x16, 0x3e, 0, 0, last]) }
+}
+
+/// Interface of `instance` with hardware address `hw(last)`, up and keeping
+/// the frames it transmits
+pub fn interface(instance: &Instance, last: u8) -> Interface {
+    let intf = Interface::new(instance);
+
+    *intf.write().hw_addr_mut() = hw(last);
+    intf.write().pv_device_set(Box::new(HwInterface::new()));
+    intf.set_oper_up(true);
+
+    intf
+}
+
+/// Frames transmitted by `intf`, built by `interface()`
+pub fn sent(intf: &Interface) -> Vec<Vec<u8>> {
+    intf.read().pv_device_ref().unwrap().sent.clone()
+}
+
+/// Packet holding `frame`, as received from an interface
+pub fn packet(frame: &[u8]) -> Packet {
+    let mut builder = PacketBuilder::new().unwrap();
+
+    builder.write(frame).unwrap();
+    builder.finalize_raw()
+}
+
+/// Build an ethernet frame
+pub fn eth(dest: &HwAddr, src: &HwAddr, ether_type: EtherType,
+           payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HDR_SIZE + payload.len());
+
+    frame.extend_from_slice(dest.as_bytes());
+    frame.extend_from_slice(src.as_bytes());
+    frame.extend_from_slice(&[(ether_type >> 8) as u8, ether_type as u8]);
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+/// Build an IPv4 datagram, without options, with a valid header checksum
+pub fn ipv4(src: &Ipv4Addr, dst: &Ipv4Addr, protocol: ProtocolIdType,
+            payload: &[u8]) -> Vec<u8> {
+    let len = 20 + payload.len();
+    let (s, d) = (src.octets(), dst.octets());
+    let mut ip = vec![0x45, 0, (len >> 8) as u8, len as u8, 0, 1, 0x40, 0,
+                      64, protocol, 0, 0, s[0], s[1], s[2], s[3],
+                      d[0], d[1], d[2], d[3]];
+    let sum = checksum(&ip);
+
+    put16(&mut ip, 10, sum);
+    ip.extend_from_slice(payload);
+
+    ip
+}
+
+/// Build an ethernet frame from `hw(1)` to `hw(2)` carrying an IPv4
+/// datagram
+pub fn ipv4_frame(src: &Ipv4Addr, dst: &Ipv4Addr, protocol: ProtocolIdType,
+                  payload: &[u8]) -> Vec<u8> {
+    eth(&hw(2), &hw(1), ETHERTYPE_IPV4, &ipv4(src, dst, protocol, payload))
+}
+
+/// Compute the checksum of the UDP datagram `udp` carried from `src` to
+/// `dst`
+///
+/// The result is 0 if the checksum in `udp` is valid.
+pub fn udp_checksum(src: &Ipv4Addr, dst: &Ipv4Addr, udp: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(12 + udp.len());
+
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.extend_from_slice(&[0, PROTOCOL_UDP, (udp.len() >> 8) as u8,
+                               udp.len() as u8]);
+    pseudo.extend_from_slice(udp);
+
+    checksum(&pseudo)
+}
+
+/// Build an UDP datagram with a valid checksum
+pub fn udp(src: &Ipv4Addr, src_port: PortType, dst: &Ipv4Addr,
+           dst_port: PortType, payload: &[u8]) -> Vec<u8> {
+    let len = 8 + payload.len();
+    let mut udp = vec![0; 8];
+
+    put16(&mut udp, 0, src_port);
+    put16(&mut udp, 2, dst_port);
+    put16(&mut udp, 4, len as u16);
+    udp.extend_from_slice(payload);
+
+    // A null checksum means that there is none
+    let sum = match udp_checksum(src, dst, &udp) {
+        0 => 0xffff,
+        sum => sum,
+    };
+
+    put16(&mut udp, 6, sum);
+
+    udp
+}
+
+/// Build an ethernet frame from `hw(1)` to `hw(2)` carrying an UDP datagram
+/// with valid checksums
+pub fn udp_frame(src: &Ipv4Addr, src_port: PortType, dst: &Ipv4Addr,
+                 dst_port: PortType, payload: &[u8]) -> Vec<u8> {
+    let udp = udp(src, src_port, dst, dst_port, payload);
+
+    ipv4_frame(src, dst, PROTOCOL_UDP, &udp)
+}
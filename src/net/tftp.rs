@@ -0,0 +1,830 @@
+//! Trivial File Transfer Protocol read client
+//!
+//! This implements RFC 1350 in octet mode along with the option extension
+//! (RFC 2347) for the block size (RFC 2348), timeout and transfer size
+//! (RFC 2349) options.
+
+use core::str::{self, FromStr};
+
+use string::String;
+use vec::Vec;
+
+use io::Write;
+
+use time::{Duration, Instant};
+
+use net::{Instance, UdpSocket, Datagram};
+use net::defs::{be16, Ipv4Addr, PortType};
+
+/// Port of TFTP servers
+pub const TFTP_PORT: PortType = 69;
+
+/// Block size used when the server does not support the option
+const DEFAULT_BLKSIZE: usize = 512;
+/// Smallest block size allowed by RFC 2348
+const MIN_BLKSIZE: u16 = 8;
+/// Largest block size allowed by RFC 2348
+const MAX_BLKSIZE: u16 = 65464;
+/// Range of the timeout option in seconds (RFC 2349)
+const MIN_TIMEOUT: u64 = 1;
+const MAX_TIMEOUT: u64 = 255;
+
+const OP_RRQ: u16 = 1;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERR_UNDEFINED: u16 = 0;
+const ERR_DISK_FULL: u16 = 3;
+const ERR_ILLEGAL_OP: u16 = 4;
+const ERR_UNKNOWN_TID: u16 = 5;
+const ERR_OPTION: u16 = 8;
+
+#[derive(Debug)]
+/// Errors of a transfer
+pub enum Error {
+    /// The server did not answer after all retransmissions
+    Timeout,
+    /// The server aborted the transfer with a code and a message
+    Remote(u16, String),
+    /// The server sent an unexpected or malformed packet
+    Protocol,
+    /// The file is larger than allowed
+    TooLarge,
+    /// The writer failed
+    Write,
+    /// The request could not be sent
+    Network,
+}
+
+/// Result of a transfer
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// Datagram transport used by the client
+///
+/// This is implemented by `UdpSocket`. Other implementations allow the
+/// client to run against a server stand-in.
+pub trait Transport {
+    /// Send `data` to `port` of `dst`
+    fn send_to(&self, data: &[u8], dst: &Ipv4Addr,
+               port: PortType) -> ::core::result::Result<(), ()>;
+
+    /// Wait at most `timeout` for a datagram
+    fn recv_from_timeout(&self, timeout: Duration) -> Option<Datagram>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, data: &[u8], dst: &Ipv4Addr,
+               port: PortType) -> ::core::result::Result<(), ()> {
+        UdpSocket::send_to(self, data, dst, port)
+    }
+
+    fn recv_from_timeout(&self, timeout: Duration) -> Option<Datagram> {
+        UdpSocket::recv_from_timeout(self, timeout)
+    }
+}
+
+#[derive(Clone)]
+/// Options of a transfer
+pub struct TftpOptions {
+    /// Block size to negotiate, None to use the default 512 bytes blocks
+    pub blksize: Option<u16>,
+    /// Ask the server for the size of the file
+    pub tsize: bool,
+    /// Time to wait for a packet before retransmitting
+    ///
+    /// The server is asked to use the same timeout if it is a whole number
+    /// of seconds between 1 and 255.
+    pub timeout: Duration,
+    /// Number of retransmissions before giving up
+    pub retries: usize,
+    /// Largest file accepted
+    pub max_size: Option<usize>,
+}
+
+impl TftpOptions {
+    /// Default options
+    ///
+    /// The block size is negotiated so that a block fits in an ethernet frame
+    /// without fragmentation.
+    pub fn new() -> Self {
+        TftpOptions {
+            blksize: Some(1432),
+            tsize: true,
+            timeout: Duration::from_secs(1),
+            retries: 5,
+            max_size: None,
+        }
+    }
+}
+
+struct VecWriter<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for VecWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> ::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> ::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn push16(buf: &mut Vec<u8>, v: u16) {
+    buf.push((v >> 8) as u8);
+    buf.push(v as u8);
+}
+
+fn ack(block: u16) -> Vec<u8> {
+    let mut pkt = Vec::with_capacity(4);
+
+    push16(&mut pkt, OP_ACK);
+    push16(&mut pkt, block);
+
+    pkt
+}
+
+fn error(code: u16, msg: &str) -> Vec<u8> {
+    let mut pkt = Vec::with_capacity(5 + msg.len());
+
+    push16(&mut pkt, OP_ERROR);
+    push16(&mut pkt, code);
+    pkt.extend_from_slice(msg.as_bytes());
+    pkt.push(0);
+
+    pkt
+}
+
+/// Write the whole `data` to `writer`
+fn write_all<W: Write>(writer: &mut W, data: &[u8]) -> ::io::Result<()> {
+    let mut written = 0;
+
+    while written < data.len() {
+        match try!(writer.write(&data[written..])) {
+            0 => return Err(()),
+            n => written += n,
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if the option `name` is `expected`, ignoring case
+fn option_is(name: &str, expected: &str) -> bool {
+    name.len() == expected.len() &&
+    name.bytes().zip(expected.bytes()).all(|(a, b)| {
+        let a = if a >= b'A' && a <= b'Z' { a + (b'a' - b'A') } else { a };
+
+        a == b
+    })
+}
+
+/// Split the NUL terminated strings of `data`
+fn strings(data: &[u8]) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut rest = data;
+
+    while let Some(pos) = rest.iter().position(|&b| b == 0) {
+        res.push(str::from_utf8(&rest[..pos]).unwrap_or(""));
+        rest = &rest[pos + 1..];
+    }
+
+    res
+}
+
+/// A TFTP client fetching files from a server
+pub struct TftpClient<T: Transport> {
+    transport: T,
+    server: Ipv4Addr,
+    port: PortType,
+    options: TftpOptions,
+}
+
+impl TftpClient<UdpSocket> {
+    /// Create a client fetching files from `server` on the network stack
+    /// `instance`
+    pub fn new(instance: &Instance, server: Ipv4Addr) -> Result<Self> {
+        let sock = try!(UdpSocket::bind(instance, 0).map_err(|_| Error::Network));
+
+        Ok(Self::with_transport(sock, server, TFTP_PORT))
+    }
+}
+
+impl<T: Transport> TftpClient<T> {
+    /// Create a client sending requests to `port` of `server` over
+    /// `transport`
+    pub fn with_transport(transport: T, server: Ipv4Addr,
+                          port: PortType) -> Self {
+        TftpClient {
+            transport: transport,
+            server: server,
+            port: port,
+            options: TftpOptions::new(),
+        }
+    }
+
+    #[inline]
+    /// Returns the options of the transfers
+    pub fn options_mut(&mut self) -> &mut TftpOptions {
+        &mut self.options
+    }
+
+    /// Fetch `filename` in memory
+    pub fn get(&self, filename: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        try!(self.get_into(filename, &mut VecWriter(&mut data)));
+
+        Ok(data)
+    }
+
+    /// Fetch `filename` and stream it to `writer`
+    ///
+    /// Returns the size of the file.
+    pub fn get_into<W: Write>(&self, filename: &str,
+                              writer: &mut W) -> Result<usize> {
+        let request = self.request(filename);
+
+        // Last packet sent, retransmitted on timeout
+        let mut last = request;
+        // Transfer identifier (port) of the server, known after its first
+        // answer
+        let mut tid: Option<PortType> = None;
+        let mut blksize = DEFAULT_BLKSIZE;
+        let mut expected: u16 = 1;
+        let mut size = 0;
+        let mut retries = 0;
+
+        try!(self.send(&last, self.port));
+
+        let mut deadline = Instant::now() + self.options.timeout;
+
+        loop {
+            let now = Instant::now();
+
+            let dgram = if now < deadline {
+                self.transport.recv_from_timeout(deadline.duration_since(now))
+            } else {
+                None
+            };
+
+            let dgram = match dgram {
+                Some(dgram) => dgram,
+                None => {
+                    if retries == self.options.retries {
+                        return Err(Error::Timeout);
+                    }
+
+                    retries += 1;
+
+                    try!(self.send(&last, tid.unwrap_or(self.port)));
+
+                    deadline = Instant::now() + self.options.timeout;
+                    continue;
+                }
+            };
+
+            if dgram.src != self.server {
+                continue;
+            }
+
+            match tid {
+                None => tid = Some(dgram.src_port),
+                Some(tid) if tid != dgram.src_port => {
+                    let _ = self.send(&error(ERR_UNKNOWN_TID, "Unknown transfer ID"),
+                                      dgram.src_port);
+                    continue;
+                }
+                _ => {}
+            }
+
+            let port = dgram.src_port;
+            let pkt = &dgram.data;
+
+            if pkt.len() < 4 {
+                return self.abort(port, Error::Protocol);
+            }
+
+            match be16(pkt, 0) {
+                OP_ERROR => {
+                    let msg = strings(&pkt[4..]).first().map_or(String::new(),
+                                                                |m| String::from(*m));
+
+                    return Err(Error::Remote(be16(pkt, 2), msg));
+                }
+                OP_OACK if expected == 1 && size == 0 => {
+                    match self.negotiated(&pkt[2..]) {
+                        Ok(b) => blksize = b,
+                        Err(Error::TooLarge) => return self.abort(port, Error::TooLarge),
+                        Err(err) => {
+                            let _ = self.send(&error(ERR_OPTION, "Option negotiation failed"),
+                                              port);
+                            return Err(err);
+                        }
+                    }
+
+                    last = ack(0);
+                }
+                OP_DATA => {
+                    let block = be16(pkt, 2);
+                    let data = &pkt[4..];
+
+                    if block == expected.wrapping_sub(1) {
+                        // Our ACK was lost, send it again
+                        try!(self.send(&last, port));
+                        continue;
+                    }
+
+                    if block != expected || data.len() > blksize {
+                        return self.abort(port, Error::Protocol);
+                    }
+
+                    if self.options.max_size.map_or(false, |m| size + data.len() > m) {
+                        return self.abort(port, Error::TooLarge);
+                    }
+
+                    if write_all(writer, data).is_err() {
+                        return self.abort(port, Error::Write);
+                    }
+
+                    size += data.len();
+                    last = ack(block);
+
+                    if data.len() < blksize {
+                        // Last block, the final ACK is not retransmitted.
+                        // The server will resend the block if it is lost
+                        try!(self.send(&last, port));
+                        let _ = writer.flush();
+
+                        return Ok(size);
+                    }
+
+                    // Block numbers wrap around for large files
+                    expected = expected.wrapping_add(1);
+                }
+                _ => return self.abort(port, Error::Protocol),
+            }
+
+            try!(self.send(&last, port));
+
+            retries = 0;
+            deadline = Instant::now() + self.options.timeout;
+        }
+    }
+
+    fn request(&self, filename: &str) -> Vec<u8> {
+        let mut pkt = Vec::new();
+
+        push16(&mut pkt, OP_RRQ);
+        pkt.extend_from_slice(filename.as_bytes());
+        pkt.push(0);
+        pkt.extend_from_slice(b"octet\0");
+
+        if let Some(blksize) = self.options.blksize {
+            pkt.extend_from_slice(b"blksize\0");
+            pkt.extend_from_slice(format!("{}", blksize).as_bytes());
+            pkt.push(0);
+        }
+
+        if let Some(timeout) = self.timeout_option() {
+            pkt.extend_from_slice(b"timeout\0");
+            pkt.extend_from_slice(format!("{}", timeout).as_bytes());
+            pkt.push(0);
+        }
+
+        if self.options.tsize {
+            pkt.extend_from_slice(b"tsize\0");
+            pkt.extend_from_slice(b"0\0");
+        }
+
+        pkt
+    }
+
+    /// Returns the value of the timeout option, None if the timeout cannot
+    /// be expressed with it
+    fn timeout_option(&self) -> Option<u64> {
+        let timeout = &self.options.timeout;
+
+        if timeout.subsec_nanos() == 0 && timeout.as_secs() >= MIN_TIMEOUT &&
+           timeout.as_secs() <= MAX_TIMEOUT {
+            Some(timeout.as_secs())
+        } else {
+            None
+        }
+    }
+
+    /// Parse the options acknowledged by the server
+    ///
+    /// Returns the block size to use.
+    fn negotiated(&self, options: &[u8]) -> Result<usize> {
+        let strings = strings(options);
+        let mut blksize = DEFAULT_BLKSIZE;
+
+        for pair in strings.chunks(2) {
+            if pair.len() != 2 {
+                return Err(Error::Protocol);
+            }
+
+            let value = try!(usize::from_str(pair[1]).map_err(|_| Error::Protocol));
+
+            if option_is(pair[0], "blksize") {
+                // The server can only lower the block size we asked for
+                let requested = self.options.blksize.unwrap_or(MAX_BLKSIZE);
+
+                if value < MIN_BLKSIZE as usize || value > requested as usize {
+                    return Err(Error::Protocol);
+                }
+
+                blksize = value;
+            } else if option_is(pair[0], "timeout") {
+                // The server must accept the timeout as is
+                if self.timeout_option() != Some(value as u64) {
+                    return Err(Error::Protocol);
+                }
+            } else if option_is(pair[0], "tsize") {
+                if self.options.max_size.map_or(false, |m| value > m) {
+                    return Err(Error::TooLarge);
+                }
+            } else {
+                // Options we did not ask for
+                return Err(Error::Protocol);
+            }
+        }
+
+        Ok(blksize)
+    }
+
+    fn send(&self, pkt: &[u8], port: PortType) -> Result<()> {
+        self.transport.send_to(pkt, &self.server, port).map_err(|_| Error::Network)
+    }
+
+    /// Tell the server that the transfer is aborted and return `err`
+    fn abort<R>(&self, tid: PortType, err: Error) -> Result<R> {
+        let pkt = match err {
+            Error::TooLarge => error(ERR_DISK_FULL, "File too large"),
+            Error::Protocol => error(ERR_ILLEGAL_OP, "Illegal TFTP operation"),
+            _ => error(ERR_UNDEFINED, "Transfer aborted"),
+        };
+
+        let _ = self.send(&pkt, tid);
+
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::RefCell;
+
+    use vec::Vec;
+    use vec_deque::VecDeque;
+
+    use io::Write;
+
+    use time::Duration;
+
+    use net::Datagram;
+    use net::defs::{be16, Ipv4Addr, PortType};
+
+    use super::{push16, ack, error, Error, TftpClient, Transport};
+    use super::{OP_RRQ, OP_DATA, OP_ACK, OP_ERROR, OP_OACK, ERR_OPTION};
+
+    /// Port the stand-in serves the transfer from
+    const TID: PortType = 3000;
+
+    fn server() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 1)
+    }
+
+    /// In-process server stand-in
+    ///
+    /// `handler` is given every packet sent by the client and returns the
+    /// answers of the server. An empty answer is a lost packet.
+    struct Server<F> {
+        handler: RefCell<F>,
+        /// Packets sent by the client with their destination port
+        sent: RefCell<Vec<(Vec<u8>, PortType)>>,
+        pending: RefCell<VecDeque<Vec<u8>>>,
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<Vec<u8>>> Transport for Server<F> {
+        fn send_to(&self, data: &[u8], _dst: &Ipv4Addr,
+                   port: PortType) -> Result<(), ()> {
+            self.sent.borrow_mut().push((data.to_vec(), port));
+
+            let answers = (&mut *self.handler.borrow_mut())(data);
+
+            self.pending.borrow_mut().extend(answers);
+
+            Ok(())
+        }
+
+        fn recv_from_timeout(&self, _timeout: Duration) -> Option<Datagram> {
+            self.pending.borrow_mut().pop_front().map(|data| {
+                Datagram {
+                    data: data,
+                    src: server(),
+                    src_port: TID,
+                    dst: Ipv4Addr::new(10, 0, 0, 2),
+                }
+            })
+        }
+    }
+
+    fn new_client<F>(handler: F) -> TftpClient<Server<F>>
+        where F: FnMut(&[u8]) -> Vec<Vec<u8>> {
+        let stand_in = Server {
+            handler: RefCell::new(handler),
+            sent: RefCell::new(Vec::new()),
+            pending: RefCell::new(VecDeque::new()),
+        };
+
+        TftpClient::with_transport(stand_in, server(), 69)
+    }
+
+    fn file(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Returns the DATA packet of block `block` of `file`
+    fn data(file: &[u8], blksize: usize, block: u16) -> Vec<u8> {
+        let start = (block as usize - 1) * blksize;
+        let end = if start + blksize < file.len() {
+            start + blksize
+        } else {
+            file.len()
+        };
+        let mut pkt = Vec::new();
+
+        push16(&mut pkt, OP_DATA);
+        push16(&mut pkt, block);
+        pkt.extend_from_slice(&file[start..end]);
+
+        pkt
+    }
+
+    /// Answer a request with the first block and each ACK with the next one
+    fn serve(file: &[u8], blksize: usize, pkt: &[u8]) -> Vec<Vec<u8>> {
+        match be16(pkt, 0) {
+            OP_RRQ => vec![data(file, blksize, 1)],
+            OP_ACK => {
+                let block = be16(pkt, 2);
+
+                if block as usize * blksize <= file.len() {
+                    vec![data(file, blksize, block + 1)]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn oack(options: &[(&str, &str)]) -> Vec<u8> {
+        let mut pkt = Vec::new();
+
+        push16(&mut pkt, OP_OACK);
+
+        for &(name, value) in options {
+            pkt.extend_from_slice(name.as_bytes());
+            pkt.push(0);
+            pkt.extend_from_slice(value.as_bytes());
+            pkt.push(0);
+        }
+
+        pkt
+    }
+
+    /// Returns true if the client sent `pkt`
+    fn has_sent<F>(client: &TftpClient<Server<F>>, pkt: &[u8]) -> bool
+        where F: FnMut(&[u8]) -> Vec<Vec<u8>> {
+        client.transport.sent.borrow().iter().any(|&(ref p, _)| &p[..] == pkt)
+    }
+
+    fn count_sent<F>(client: &TftpClient<Server<F>>, pkt: &[u8]) -> usize
+        where F: FnMut(&[u8]) -> Vec<Vec<u8>> {
+        client.transport.sent.borrow().iter()
+                                      .filter(|&&(ref p, _)| &p[..] == pkt)
+                                      .count()
+    }
+
+    #[test]
+    fn test_multi_block() {
+        for &len in &[0, 100, 512, 1300, 1536] {
+            let content = file(len);
+            let expected = content.clone();
+            let mut client = new_client(move |pkt| serve(&content, 512, pkt));
+
+            client.options_mut().blksize = None;
+            client.options_mut().tsize = false;
+
+            assert_eq!(client.get("file").unwrap(), expected);
+
+            // The last block is acknowledged, the transfer then goes on with
+            // the port of the server
+            let blocks = len / 512 + 1;
+            let sent = client.transport.sent.borrow();
+
+            assert_eq!(sent.len(), blocks + 1);
+            assert_eq!(sent[0].1, 69);
+            assert_eq!(sent[blocks], (ack(blocks as u16), TID));
+        }
+    }
+
+    #[test]
+    fn test_request() {
+        let client = new_client(|_| Vec::new());
+        let mut expected = Vec::new();
+
+        push16(&mut expected, OP_RRQ);
+        expected.extend_from_slice(b"boot.cfg\0octet\0blksize\01432\0\
+                                     timeout\01\0tsize\00\0");
+
+        assert_eq!(client.request("boot.cfg"), expected);
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let content = file(2500);
+        let expected = content.clone();
+        let client = new_client(move |pkt| {
+            if be16(pkt, 0) == OP_RRQ {
+                vec![oack(&[("blksize", "1000"), ("TIMEOUT", "1"),
+                            ("tsize", "2500")])]
+            } else {
+                serve(&content, 1000, pkt)
+            }
+        });
+
+        assert_eq!(client.get("file").unwrap(), expected);
+        assert!(has_sent(&client, &ack(0)));
+        assert!(has_sent(&client, &ack(3)));
+    }
+
+    #[test]
+    fn test_negotiation_failure() {
+        let options: [&[(&str, &str)]; 4] = [
+            // Larger block size than requested
+            &[("blksize", "2000")],
+            // Another timeout than requested
+            &[("timeout", "5")],
+            // Option that was not requested
+            &[("windowsize", "4")],
+            &[("blksize", "")],
+        ];
+
+        for options in &options {
+            let pkt = oack(options);
+            let client = new_client(move |p| {
+                if be16(p, 0) == OP_RRQ {
+                    vec![pkt.clone()]
+                } else {
+                    Vec::new()
+                }
+            });
+
+            match client.get("file") {
+                Err(Error::Protocol) => {}
+                res => panic!("Unexpected result {:?}", res),
+            }
+
+            let sent = client.transport.sent.borrow();
+
+            assert_eq!(be16(&sent[1].0, 0), OP_ERROR);
+            assert_eq!(be16(&sent[1].0, 2), ERR_OPTION);
+        }
+
+        // The file is announced larger than allowed
+        let mut client = new_client(|p| {
+            if be16(p, 0) == OP_RRQ {
+                vec![oack(&[("tsize", "100000")])]
+            } else {
+                Vec::new()
+            }
+        });
+
+        client.options_mut().max_size = Some(65536);
+
+        match client.get("file") {
+            Err(Error::TooLarge) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_data() {
+        let content = file(1200);
+        let expected = content.clone();
+        let mut duplicated = false;
+        let mut client = new_client(move |pkt| {
+            // The first ACK is lost and the server sends the block again
+            if be16(pkt, 0) == OP_ACK && be16(pkt, 2) == 1 && !duplicated {
+                duplicated = true;
+                return vec![data(&content, 512, 1)];
+            }
+
+            serve(&content, 512, pkt)
+        });
+
+        client.options_mut().blksize = None;
+        client.options_mut().tsize = false;
+
+        // The block is written once and acknowledged again
+        assert_eq!(client.get("file").unwrap(), expected);
+        assert_eq!(count_sent(&client, &ack(1)), 2);
+        assert_eq!(count_sent(&client, &ack(2)), 1);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let content = file(700);
+        let expected = content.clone();
+        let (mut request_lost, mut ack_lost) = (false, false);
+        let mut client = new_client(move |pkt| {
+            // The first answers to the request and to the first ACK are lost
+            let lost = match be16(pkt, 0) {
+                OP_RRQ => &mut request_lost,
+                _ => &mut ack_lost,
+            };
+
+            if !*lost {
+                *lost = true;
+                return Vec::new();
+            }
+
+            serve(&content, 512, pkt)
+        });
+
+        client.options_mut().blksize = None;
+        client.options_mut().tsize = false;
+
+        assert_eq!(client.get("file").unwrap(), expected);
+        assert_eq!(count_sent(&client, &ack(1)), 2);
+        assert_eq!(count_sent(&client, &ack(2)), 1);
+        assert_eq!(client.transport.sent.borrow()[1].1, 69);
+
+        // The server never answers
+        let mut client = new_client(|_| Vec::new());
+
+        client.options_mut().retries = 3;
+
+        match client.get("file") {
+            Err(Error::Timeout) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        assert_eq!(client.transport.sent.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_error() {
+        let client = new_client(|pkt| {
+            if be16(pkt, 0) == OP_RRQ {
+                vec![error(1, "File not found")]
+            } else {
+                Vec::new()
+            }
+        });
+
+        match client.get("missing") {
+            Err(Error::Remote(1, ref msg)) if msg == "File not found" => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // The client does not answer an error
+        assert_eq!(client.transport.sent.borrow().len(), 1);
+    }
+
+    /// Writer accepting at most 100 bytes per call
+    struct ShortWriter(Vec<u8>);
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> ::io::Result<usize> {
+            let len = if buf.len() < 100 { buf.len() } else { 100 };
+
+            self.0.extend_from_slice(&buf[..len]);
+
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> ::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_short_writes() {
+        let content = file(1300);
+        let expected = content.clone();
+        let mut client = new_client(move |pkt| serve(&content, 512, pkt));
+        let mut writer = ShortWriter(Vec::new());
+
+        client.options_mut().blksize = None;
+        client.options_mut().tsize = false;
+
+        assert_eq!(client.get_into("file", &mut writer).unwrap(), 1300);
+        assert_eq!(writer.0, expected);
+    }
+}
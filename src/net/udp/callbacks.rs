@@ -0,0 +1,36 @@
+//! Useful callbacks used by UDP's specific filter
+
+use boxed::Box;
+
+use net::Packet;
+
+use net::defs::{Rule, TransportRule, PortType};
+
+use net::conn::filter::{SpecificCallbacks, GenericFilterTrait};
+
+use super::defs::Header;
+
+/// Defines specific callbacks for UDP protocol
+pub struct UdpCallbacks;
+
+impl SpecificCallbacks<PortType> for UdpCallbacks {
+    /// UDP is the last layer, there is no filter above it
+    fn filter_from_generic_parameter(_port: PortType) -> Option<Box<GenericFilterTrait>> {
+        None
+    }
+
+    #[inline]
+    /// UDP is the last layer, there is no filter above it
+    fn has_upper_filter(_rule: &Rule) -> bool {
+        false
+    }
+
+    /// Set UDP part of the rule with information gathered from the packet
+    fn set_layer_rule(rule: &mut Rule, pkt: &Packet) {
+        let hdr = pkt.tspt_header::<Header>().unwrap();
+
+        rule.tspt_rule = Some(TransportRule {
+            port: hdr.src_port.as_host(),
+        });
+    }
+}
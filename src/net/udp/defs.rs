@@ -0,0 +1,10 @@
+use net::defs::Int as NetInt;
+
+#[repr(C, packed)]
+/// UDP header
+pub struct Header {
+    pub src_port: NetInt<u16>,
+    pub dst_port: NetInt<u16>,
+    pub length: NetInt<u16>,
+    pub checksum: NetInt<u16>,
+}
@@ -0,0 +1,39 @@
+//! Implementation of UDP related Extractors
+
+use net::Packet;
+
+use net::defs::{Rule, PortType};
+
+use net::conn::filter::Extractor;
+
+use super::defs::Header;
+
+/// Type responsible for destination port extraction
+pub struct DestinationPortExtractor;
+
+impl Extractor<PortType> for DestinationPortExtractor {
+    /// Extract the local port from a rule
+    fn from_rule(rule: &Rule) -> Option<PortType> {
+        rule.tspt_rule.as_ref().map(|tspt_rule| tspt_rule.port)
+    }
+
+    /// Extract the destination port from a packet
+    fn from_packet(pkt: &Packet) -> Option<PortType> {
+        pkt.tspt_header::<Header>().map(|hdr| hdr.dst_port.as_host())
+    }
+}
+
+/// Type responsible for source port extraction
+pub struct SourcePortExtractor;
+
+impl Extractor<PortType> for SourcePortExtractor {
+    /// Rules only carry the local port, the source port cannot be extracted
+    fn from_rule(_rule: &Rule) -> Option<PortType> {
+        None
+    }
+
+    /// Extract the source port from a packet
+    fn from_packet(pkt: &Packet) -> Option<PortType> {
+        pkt.tspt_header::<Header>().map(|hdr| hdr.src_port.as_host())
+    }
+}
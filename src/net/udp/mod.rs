@@ -0,0 +1,404 @@
+//! UDP sockets over IPv4
+//!
+//! Received datagrams are routed to sockets by two filters, based on the
+//! destination port and on the source port. A socket is a multi connexion
+//! registered for its local port.
+
+use core::mem;
+
+use sync::Arc;
+
+use vec::Vec;
+use btree_map::BTreeMap;
+
+use sync::spin::SpinLock;
+
+use thread::{Notify, Pollable, Ready};
+
+use time::{Duration, Instant};
+
+use net::{Instance, InstanceWeak, Packet, MultiConn, ipv4};
+use net::defs::{checksum, Rule, EthernetRule, NetworkRule, TransportRule};
+use net::defs::{Ipv4Addr, PortType, ETHERTYPE_IPV4, PROTOCOL_UDP};
+
+use net::ipv4::defs::Header as Ipv4Header;
+
+use net::conn::Sharing;
+use net::conn::filter::{GenericFilter, SpecificFilter};
+
+mod defs;
+mod sanitizer;
+mod extractor;
+mod callbacks;
+
+use self::defs::Header;
+use self::sanitizer::UdpPacketSanitizer;
+use self::extractor::{DestinationPortExtractor, SourcePortExtractor};
+use self::callbacks::UdpCallbacks;
+
+/// Filter routing UDP datagrams on their source port
+pub type UdpSpecificFilter = SpecificFilter<PortType, PortType,
+                                            SourcePortExtractor,
+                                            UdpCallbacks>;
+
+/// Filter routing UDP datagrams on their destination port
+pub type UdpGenericFilter = GenericFilter<PortType, UdpSpecificFilter,
+                                          DestinationPortExtractor,
+                                          UdpPacketSanitizer>;
+
+/// Size of an UDP header
+pub const HDR_SIZE: usize = 8;
+
+/// First port used for ephemeral bindings (RFC 6335)
+const EPHEMERAL_START: PortType = 49152;
+
+/// A received datagram
+pub struct Datagram {
+    /// Content of the datagram
+    pub data: Vec<u8>,
+    /// Address of the sender
+    pub src: Ipv4Addr,
+    /// Port of the sender
+    pub src_port: PortType,
+    /// Address the datagram was sent to
+    pub dst: Ipv4Addr,
+}
+
+impl Datagram {
+    /// Extract the datagram carried by a packet that went through the filters
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        let ip = match pkt.net_header::<Ipv4Header>() {
+            Some(ip) => ip,
+            None => return None,
+        };
+
+        let hdr = match pkt.tspt_header::<Header>() {
+            Some(hdr) => hdr,
+            None => return None,
+        };
+
+        // The length was checked when the packet was sanitized
+        let len = hdr.length.as_host() as usize - mem::size_of::<Header>();
+        let data = pkt.payload().map_or(Vec::new(), |p| p[..len].to_vec());
+
+        Some(Datagram {
+            data: data,
+            src: ip.src.clone(),
+            src_port: hdr.src_port.as_host(),
+            dst: ip.dst.clone(),
+        })
+    }
+}
+
+/// Returns the rule matching datagrams sent to the local port `port`
+fn rule(port: PortType) -> Rule {
+    Rule {
+        eth_rule: Some(EthernetRule {
+            ether_type: ETHERTYPE_IPV4,
+            hw_in: None,
+        }),
+        net_rule: Some(NetworkRule {
+            protocol_id: PROTOCOL_UDP,
+            ip_in: None,
+        }),
+        tspt_rule: Some(TransportRule {
+            port: port,
+        }),
+    }
+}
+
+/// Ports bound by sockets of a network stack
+pub struct UdpTable {
+    /// Number of sockets bound to each port
+    ports: SpinLock<BTreeMap<PortType, usize>>,
+    next_ephemeral: SpinLock<PortType>,
+}
+
+impl UdpTable {
+    /// Create a table with no port bound
+    pub fn new() -> Self {
+        UdpTable {
+            ports: SpinLock::new(BTreeMap::new()),
+            next_ephemeral: SpinLock::new(EPHEMERAL_START),
+        }
+    }
+
+    /// Register `conn` for datagrams sent to `port` on `instance`
+    ///
+    /// A port of 0 binds an unused ephemeral port. Returns the bound port.
+    fn bind(&self, instance: &Instance, port: PortType,
+            conn: &Arc<MultiConn>) -> Result<PortType, ()> {
+        let mut ports = self.ports.lock();
+
+        let port = if port != 0 {
+            port
+        } else {
+            let mut next = self.next_ephemeral.lock();
+            let count = (!0 - EPHEMERAL_START) as usize + 1;
+            let mut found = None;
+
+            for _ in 0..count {
+                let candidate = *next;
+
+                *next = if candidate == !0 { EPHEMERAL_START } else { candidate + 1 };
+
+                if !ports.contains_key(&candidate) {
+                    found = Some(candidate);
+                    break;
+                }
+            }
+
+            try!(found.ok_or(()))
+        };
+
+        // This fails if the port is bound by a socket that does not share it
+        try!(instance.insert_multi(conn.clone(), &rule(port)));
+
+        *ports.entry(port).or_insert(0) += 1;
+
+        Ok(port)
+    }
+
+    fn unbind(&self, instance: &Instance, port: PortType,
+              conn: &Arc<MultiConn>) {
+        let mut ports = self.ports.lock();
+
+        instance.remove_multi(conn, &rule(port));
+
+        let unused = match ports.get_mut(&port) {
+            None => false,
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+        };
+
+        if unused {
+            ports.remove(&port);
+        }
+    }
+}
+
+/// Compute the checksum of the UDP datagram `dgram` sent from `src` to `dst`
+///
+/// The checksum field of `dgram` is part of the sum, which is therefore 0 for
+/// a datagram carrying a valid checksum.
+fn checksum_v4(src: &Ipv4Addr, dst: &Ipv4Addr, dgram: &[u8]) -> u16 {
+    let len = dgram.len();
+    let mut pseudo = Vec::with_capacity(12 + len);
+
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.extend_from_slice(&[0, PROTOCOL_UDP, (len >> 8) as u8, len as u8]);
+    pseudo.extend_from_slice(dgram);
+
+    checksum(&pseudo)
+}
+
+/// Build an UDP datagram, including its checksum
+fn datagram(src: &Ipv4Addr, dst: &Ipv4Addr, src_port: PortType,
+            dst_port: PortType, data: &[u8]) -> Vec<u8> {
+    let len = HDR_SIZE + data.len();
+    let mut dgram = Vec::with_capacity(len);
+
+    dgram.extend_from_slice(&[(src_port >> 8) as u8, src_port as u8,
+                              (dst_port >> 8) as u8, dst_port as u8,
+                              (len >> 8) as u8, len as u8, 0, 0]);
+    dgram.extend_from_slice(data);
+
+    // A computed checksum of zero is transmitted as all ones (RFC 768)
+    let sum = match checksum_v4(src, dst, &dgram) {
+        0 => 0xffff,
+        sum => sum,
+    };
+
+    dgram[6] = (sum >> 8) as u8;
+    dgram[7] = sum as u8;
+
+    dgram
+}
+
+/// An UDP socket bound to a local port
+///
+/// The socket receives datagrams sent to its port on any interface of the
+/// network stack. The port is released when the socket is dropped.
+pub struct UdpSocket {
+    conn: Arc<MultiConn>,
+    port: PortType,
+    instance: InstanceWeak,
+}
+
+impl UdpSocket {
+    /// Bind a socket to `port` on the network stack `instance`
+    ///
+    /// A port of 0 binds an ephemeral port. Fails if the port is already
+    /// bound.
+    pub fn bind(instance: &Instance, port: PortType) -> Result<Self, ()> {
+        Self::bind_shared(instance, port, Sharing::Exclusive)
+    }
+
+    /// Bind a socket to `port`, sharing it with the other sockets bound to
+    /// it with the same `sharing` policy
+    ///
+    /// Datagrams sent to the port are spread across these sockets, e.g. to
+    /// serve it from a pool of worker threads. A port of 0 binds an unused
+    /// ephemeral port. Fails if the port is bound with another policy or
+    /// exclusively.
+    pub fn bind_shared(instance: &Instance, port: PortType,
+                       sharing: Sharing) -> Result<Self, ()> {
+        let conn = Arc::new(MultiConn::any_interface(sharing));
+        let port = try!(instance.udp().bind(instance, port, &conn));
+
+        Ok(UdpSocket {
+            conn: conn,
+            port: port,
+            instance: instance.downgrade(),
+        })
+    }
+
+    #[inline]
+    /// Returns the local port of the socket
+    pub fn local_port(&self) -> PortType {
+        self.port
+    }
+
+    /// Send `data` to `port` of `dst`
+    ///
+    /// The hardware address of the next hop is resolved if needed, which
+    /// might block. This *MUST NOT* be called from the network thread.
+    pub fn send_to(&self, data: &[u8], dst: &Ipv4Addr,
+                   port: PortType) -> Result<(), ()> {
+        let instance = try!(self.instance.upgrade().ok_or(()));
+        let route = try!(ipv4::route(&instance, dst).ok_or(()));
+        let dgram = datagram(&route.src, dst, self.port, port, data);
+
+        ipv4::send(&instance, dst, PROTOCOL_UDP, &dgram)
+    }
+
+    #[inline]
+    /// Set the blocking mode of the socket, used by `recv()`
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.conn.set_nonblocking(nonblocking);
+    }
+
+    #[inline]
+    /// Returns true if the socket is in non-blocking mode
+    pub fn is_nonblocking(&self) -> bool {
+        self.conn.is_nonblocking()
+    }
+
+    /// Returns the next received datagram honouring the blocking mode of the
+    /// socket
+    ///
+    /// In blocking mode this behaves like `recv_from()`, otherwise like
+    /// `try_recv_from()`.
+    pub fn recv(&self) -> Option<Datagram> {
+        while let Some((pkt, _)) = self.conn.receive() {
+            if let Some(dgram) = Datagram::from_packet(&pkt) {
+                return Some(dgram);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the next received datagram without blocking
+    pub fn try_recv_from(&self) -> Option<Datagram> {
+        while let Some((pkt, _)) = self.conn.try_pop_packet() {
+            if let Some(dgram) = Datagram::from_packet(&pkt) {
+                return Some(dgram);
+            }
+        }
+
+        None
+    }
+
+    /// Block until a datagram is received and return it
+    pub fn recv_from(&self) -> Datagram {
+        loop {
+            let (pkt, _) = self.conn.pop_packet();
+
+            if let Some(dgram) = Datagram::from_packet(&pkt) {
+                return dgram;
+            }
+        }
+    }
+
+    /// Wait at most `timeout` for a datagram to be received
+    ///
+    /// Returns None if the timeout expired.
+    pub fn recv_from_timeout(&self, timeout: Duration) -> Option<Datagram> {
+        let deadline = Instant::now() + timeout;
+
+        // Invalid packets do not end the wait, only the deadline does
+        loop {
+            let remaining = deadline - Instant::now();
+            let (pkt, _) = match self.conn.pop_packet_timeout(remaining) {
+                Some(res) => res,
+                None => return None,
+            };
+
+            if let Some(dgram) = Datagram::from_packet(&pkt) {
+                return Some(dgram);
+            }
+        }
+    }
+}
+
+impl Pollable for UdpSocket {
+    fn readiness(&self) -> Ready {
+        self.conn.readiness()
+    }
+
+    fn watch(&self, notifier: Arc<Notify>) {
+        self.conn.watch(notifier);
+    }
+
+    fn unwatch(&self, notifier: &Notify) {
+        self.conn.unwatch(notifier);
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(instance) = self.instance.upgrade() {
+            instance.udp().unbind(&instance, self.port, &self.conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use net::defs::Ipv4Addr;
+
+    use super::{checksum_v4, datagram, HDR_SIZE};
+
+    fn src() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 2)
+    }
+
+    fn dst() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 1)
+    }
+
+    #[test]
+    fn test_checksum_v4() {
+        let dgram = datagram(&src(), &dst(), 49152, 53, b"ping");
+
+        assert_eq!(&dgram[..HDR_SIZE],
+                   &[0xc0, 0, 0, 53, 0, 12, 0x4c, 0xcd][..]);
+        assert_eq!(&dgram[HDR_SIZE..], b"ping");
+
+        // Valid datagrams sum to 0, whatever changes breaks that
+        assert_eq!(checksum_v4(&src(), &dst(), &dgram), 0);
+        assert!(checksum_v4(&src(), &dst(), &dgram[..11]) != 0);
+        assert!(checksum_v4(&Ipv4Addr::new(10, 0, 0, 3), &dst(),
+                            &dgram) != 0);
+
+        // A computed checksum of 0 is transmitted as all ones
+        let dgram = datagram(&src(), &dst(), 49152, 53, &[43, 162]);
+
+        assert_eq!(&dgram[6..8], &[0xff, 0xff][..]);
+        assert_eq!(checksum_v4(&src(), &dst(), &dgram), 0);
+    }
+}
@@ -0,0 +1,49 @@
+//! Sanitize incoming packets at the UDP layer
+
+use core::mem;
+
+use net::Packet;
+
+use net::ipv4::defs::Header as Ipv4Header;
+
+use net::conn::filter::PacketSanitizer;
+
+use super::defs::Header;
+use super::checksum_v4;
+
+/// Sanitize a packet at the UDP level
+pub struct UdpPacketSanitizer;
+
+impl PacketSanitizer for UdpPacketSanitizer {
+    /// Determine if the packet carries a valid UDP datagram
+    fn sanitize(pkt: &mut Packet) -> Result<(), ()> {
+        {
+            let ip = try!(pkt.net_header::<Ipv4Header>().ok_or(()));
+            let hdr = try!(pkt.tspt_header::<Header>().ok_or(()));
+
+            // The frame might be padded beyond the end of the IP datagram
+            let available = ip.length.as_host() as usize - pkt.net_hdr_size();
+            let length = hdr.length.as_host() as usize;
+
+            if length < mem::size_of::<Header>() || length > available {
+                return Err(());
+            }
+
+            // A checksum of 0 means the sender did not compute one
+            let start = pkt.link_hdr_size() + pkt.net_hdr_size();
+            let dgram = &pkt.as_bytes()[start..start + length];
+
+            if hdr.checksum.as_net() != 0 &&
+               checksum_v4(&ip.src, &ip.dst, dgram) != 0 {
+                return Err(());
+            }
+        }
+
+        unsafe {
+            // Set the size of the transport layer header
+            *pkt.tspt_hdr_size_mut() = mem::size_of::<Header>();
+        }
+
+        Ok(())
+    }
+}
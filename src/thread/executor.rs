@@ -0,0 +1,568 @@
+//! Executor running futures on top of scheduler threads
+//!
+//! The `async`/`await` syntax is not available with the compiler Uni.rs
+//! targets, so futures are written by implementing the `Future` trait by
+//! hand. A future that cannot make progress returns `Poll::Pending` after
+//! arranging for its `Waker` to be woken up, typically by watching a
+//! `WaitQueue`. Waking up is interrupt safe so it can also be done from a Xen
+//! event handler.
+
+use core::mem;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::boxed::Box;
+
+use vec::Vec;
+use vec_deque::VecDeque;
+
+use sync::{Arc, Weak};
+use sync::spin::{SpinLock, InterruptSpinLock};
+
+use hal::{console, local_irq_disable, local_irq_enable};
+
+use io::{Read, stdin};
+
+use time::{Duration, Instant};
+
+use super::{Scheduler, WaitQueue, Notify, Pollable, Ready};
+
+/// Result of polling a future
+pub enum Poll<T> {
+    /// The future completed with a value
+    Ready(T),
+    /// The future cannot make progress for now. It will be polled again once
+    /// its waker is woken up
+    Pending,
+}
+
+/// An asynchronous computation
+pub trait Future {
+    /// Value produced by the future on completion
+    type Output;
+
+    /// Try to make progress.
+    ///
+    /// If `Poll::Pending` is returned, the future *MUST* have arranged for
+    /// `waker` to be woken up when progress becomes possible.
+    fn poll(&mut self, waker: &Waker) -> Poll<Self::Output>;
+}
+
+#[derive(Clone)]
+/// Handle used to tell an executor that a task should be polled again
+pub struct Waker(Arc<WakerInner>);
+
+struct WakerInner {
+    task: usize,
+    woken: AtomicBool,
+    executor: Weak<ExecutorInner>,
+}
+
+impl Waker {
+    #[inline]
+    /// Schedule the task owning the waker to be polled again
+    ///
+    /// This is safe to be called from interrupt context.
+    pub fn wake(&self) {
+        self.0.notify();
+    }
+
+    #[inline]
+    /// Returns the waker as an object that can watch a wait queue
+    ///
+    /// The waker is woken up every time the queue is signaled.
+    pub fn as_notify(&self) -> Arc<Notify> {
+        self.0.clone()
+    }
+
+    /// Wake up the task at `deadline`
+    pub fn wake_at(&self, deadline: Instant) {
+        if let Some(executor) = self.0.executor.upgrade() {
+            executor.timers.lock().push((deadline, self.clone()));
+            executor.wait.unblock();
+        }
+    }
+}
+
+impl Notify for WakerInner {
+    fn notify(&self) {
+        if let Some(executor) = self.executor.upgrade() {
+            // A task is present at most once in the ready queue whose
+            // capacity is reserved in advance, so no allocation can happen
+            // here
+            if !self.woken.swap(true, Ordering::SeqCst) {
+                executor.ready.lock().push_back(self.task);
+                executor.wait.unblock();
+            }
+        }
+    }
+}
+
+struct Task {
+    future: Box<Future<Output = ()> + Send>,
+    waker: Waker,
+}
+
+struct ExecutorInner {
+    /// Futures spawned but not yet adopted by the running thread
+    spawned: SpinLock<Vec<Box<Future<Output = ()> + Send>>>,
+    /// Tasks to be polled
+    ready: InterruptSpinLock<VecDeque<usize>>,
+    /// Tasks to be woken up at a given deadline
+    timers: InterruptSpinLock<Vec<(Instant, Waker)>>,
+    /// Used to wait for a task to be woken up
+    wait: WaitQueue,
+}
+
+// Every field is protected by a lock
+unsafe impl Sync for ExecutorInner {}
+unsafe impl Send for ExecutorInner {}
+
+#[derive(Clone)]
+/// Run futures on a scheduler thread
+///
+/// Futures are spawned on the executor, which polls them from the thread
+/// calling `run()`. Only one thread at a time should run a given executor,
+/// several executors can be used to spread futures across threads.
+pub struct Executor(Arc<ExecutorInner>);
+
+impl Executor {
+    /// Create a new executor with no task
+    pub fn new() -> Self {
+        Executor(Arc::new(ExecutorInner {
+            spawned: SpinLock::new(Vec::new()),
+            ready: InterruptSpinLock::new(VecDeque::new()),
+            timers: InterruptSpinLock::new(Vec::new()),
+            wait: WaitQueue::new(),
+        }))
+    }
+
+    /// Spawn a new future on the executor
+    pub fn spawn<F>(&self, future: F)
+        where F: Future<Output = ()> + Send + 'static {
+        self.0.spawned.lock().push(Box::new(future));
+        self.0.wait.unblock();
+    }
+
+    /// Run the executor on the current thread until every task completed
+    pub fn run(&self) {
+        let mut tasks: Vec<Option<Task>> = Vec::new();
+        let mut live = 0;
+
+        loop {
+            live += self.adopt_spawned(&mut tasks);
+
+            if live == 0 {
+                return;
+            }
+
+            self.fire_timers();
+
+            let next = self.0.ready.lock().pop_front();
+
+            match next {
+                None => self.idle(),
+                Some(id) => {
+                    let done = match tasks[id] {
+                        None => false,
+                        Some(ref mut task) => {
+                            task.waker.0.woken.store(false, Ordering::SeqCst);
+
+                            match task.future.poll(&task.waker) {
+                                Poll::Ready(()) => true,
+                                Poll::Pending => false,
+                            }
+                        }
+                    };
+
+                    if done {
+                        self.retire(&mut tasks[id], id);
+                        live -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move spawned futures to the task list and return how many were moved
+    fn adopt_spawned(&self, tasks: &mut Vec<Option<Task>>) -> usize {
+        let spawned = mem::replace(&mut *self.0.spawned.lock(), Vec::new());
+        let count = spawned.len();
+
+        for future in spawned {
+            let id = match tasks.iter().position(|t| t.is_none()) {
+                Some(id) => id,
+                None => {
+                    tasks.push(None);
+                    tasks.len() - 1
+                }
+            };
+
+            let waker = Waker(Arc::new(WakerInner {
+                task: id,
+                woken: AtomicBool::new(false),
+                executor: Arc::downgrade(&self.0),
+            }));
+
+            tasks[id] = Some(Task {
+                future: future,
+                waker: waker.clone(),
+            });
+
+            {
+                let mut ready = self.0.ready.lock();
+                let len = ready.len();
+
+                ready.reserve(tasks.len().saturating_sub(len));
+            }
+
+            // Poll the new task at least once
+            waker.wake();
+        }
+
+        count
+    }
+
+    /// Free the slot `id` of a completed task
+    ///
+    /// The slot is reused by tasks spawned later, so the wakers of the
+    /// completed task, which might still be held by wait queues or timers,
+    /// must never queue `id` again.
+    fn retire(&self, slot: &mut Option<Task>, id: usize) {
+        if let Some(task) = slot.take() {
+            // A woken up waker is never queued again. Nothing resets the flag
+            // once the task is gone
+            task.waker.0.woken.store(true, Ordering::SeqCst);
+        }
+
+        self.0.ready.lock().retain(|t| *t != id);
+    }
+
+    /// Wake up the tasks whose deadline expired
+    fn fire_timers(&self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+
+        {
+            let mut timers = self.0.timers.lock();
+            let mut i = 0;
+
+            while i < timers.len() {
+                if timers[i].0 <= now {
+                    expired.push(timers.swap_remove(i).1);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        for waker in expired {
+            waker.wake();
+        }
+    }
+
+    /// Block until a task is woken up or the next timer expires
+    fn idle(&self) {
+        let deadline = self.0.timers.lock().iter().map(|t| t.0).min();
+
+        local_irq_disable();
+
+        let locked_queue = self.0.wait.lock();
+
+        if !self.0.ready.lock().is_empty() ||
+           !self.0.spawned.lock().is_empty() {
+            local_irq_enable();
+            return;
+        }
+
+        match deadline {
+            None => Scheduler::block(locked_queue),
+            Some(deadline) => {
+                Scheduler::block_timeout(locked_queue, &self.0.wait, deadline);
+            }
+        }
+    }
+}
+
+struct Output<F: Future> {
+    future: F,
+    output: Arc<SpinLock<Option<F::Output>>>,
+}
+
+impl<F: Future> Future for Output<F> {
+    type Output = ();
+
+    fn poll(&mut self, waker: &Waker) -> Poll<()> {
+        match self.future.poll(waker) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(v) => {
+                *self.output.lock() = Some(v);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+/// Run a future to completion on the current thread and return its output
+pub fn block_on<F>(future: F) -> F::Output
+    where F: Future + Send + 'static, F::Output: Send {
+    let executor = Executor::new();
+    let output = Arc::new(SpinLock::new(None));
+
+    executor.spawn(Output {
+        future: future,
+        output: output.clone(),
+    });
+
+    executor.run();
+
+    let ret = output.lock().take();
+
+    ret.expect("Future did not complete")
+}
+
+/// Future that completes at a given deadline
+pub struct Delay {
+    deadline: Instant,
+    registered: bool,
+}
+
+impl Delay {
+    /// Create a future that completes after `duration`
+    pub fn new(duration: Duration) -> Self {
+        Delay::until(Instant::now() + duration)
+    }
+
+    /// Create a future that completes at `deadline`
+    pub fn until(deadline: Instant) -> Self {
+        Delay {
+            deadline: deadline,
+            registered: false,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(&mut self, waker: &Waker) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        if !self.registered {
+            waker.wake_at(self.deadline);
+            self.registered = true;
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Future that completes when a handle is ready
+///
+/// The output is the readiness of the handle restricted to the interest and
+/// hang up.
+pub struct Readiness<P: Pollable> {
+    handle: P,
+    interest: Ready,
+    watcher: Option<Arc<Notify>>,
+}
+
+impl<P: Pollable> Readiness<P> {
+    /// Create a future that completes once `handle` is ready for `interest`
+    pub fn new(handle: P, interest: Ready) -> Self {
+        Readiness {
+            handle: handle,
+            interest: interest,
+            watcher: None,
+        }
+    }
+}
+
+impl<P: Pollable> Future for Readiness<P> {
+    type Output = Ready;
+
+    fn poll(&mut self, waker: &Waker) -> Poll<Ready> {
+        if self.watcher.is_none() {
+            let watcher = waker.as_notify();
+
+            self.handle.watch(watcher.clone());
+            self.watcher = Some(watcher);
+        }
+
+        let ready = self.handle.readiness() & (self.interest | Ready::hangup());
+
+        if ready.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(ready)
+        }
+    }
+}
+
+impl<P: Pollable> Drop for Readiness<P> {
+    fn drop(&mut self) {
+        if let Some(ref watcher) = self.watcher {
+            self.handle.unwatch(&**watcher);
+        }
+    }
+}
+
+/// Future that reads at most `max` bytes from the console
+pub struct ReadStdin {
+    max: usize,
+    watching: Option<Arc<Notify>>,
+}
+
+impl ReadStdin {
+    /// Create a future reading at most `max` bytes from the console
+    pub fn new(max: usize) -> Self {
+        ReadStdin {
+            max: max,
+            watching: None,
+        }
+    }
+}
+
+impl Future for ReadStdin {
+    type Output = Vec<u8>;
+
+    fn poll(&mut self, waker: &Waker) -> Poll<Vec<u8>> {
+        if self.watching.is_none() {
+            let watcher = waker.as_notify();
+
+            console().input_queue().watch(watcher.clone());
+            self.watching = Some(watcher);
+        }
+
+        if !console().has_input() {
+            return Poll::Pending;
+        }
+
+        // Input is available so this does not block
+        let mut buf = vec![0; self.max];
+        let stdin = stdin();
+        let size = stdin.lock().read(&mut buf[..]).unwrap_or(0);
+
+        buf.truncate(size);
+
+        Poll::Ready(buf)
+    }
+}
+
+impl Drop for ReadStdin {
+    fn drop(&mut self) {
+        if let Some(ref watcher) = self.watching {
+            console().input_queue().unwatch(&**watcher);
+        }
+    }
+}
+
+struct BlockingShared<T> {
+    result: InterruptSpinLock<Option<T>>,
+    waker: InterruptSpinLock<Option<Waker>>,
+}
+
+/// Future completing with the result of a blocking function run on its own
+/// scheduler thread
+///
+/// See `spawn_blocking()`.
+pub struct Blocking<T> {
+    shared: Arc<BlockingShared<T>>,
+}
+
+/// Run a blocking function `f` on a new scheduler thread and return a future
+/// that completes with its result
+///
+/// This is used to wrap blocking APIs, such as the Xen store, in futures.
+pub fn spawn_blocking<F, T>(f: F) -> Blocking<T>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static {
+    let shared = Arc::new(BlockingShared {
+        result: InterruptSpinLock::new(None),
+        waker: InterruptSpinLock::new(None),
+    });
+    let thread_shared = shared.clone();
+    let mut f = Some(f);
+
+    Scheduler::spawn(move || {
+        if let Some(f) = f.take() {
+            let result = f();
+
+            *thread_shared.result.lock() = Some(result);
+
+            if let Some(waker) = thread_shared.waker.lock().take() {
+                waker.wake();
+            }
+        }
+    });
+
+    Blocking {
+        shared: shared,
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(&mut self, waker: &Waker) -> Poll<T> {
+        *self.shared.waker.lock() = Some(waker.clone());
+
+        match self.shared.result.lock().take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sync::Arc;
+    use sync::spin::SpinLock;
+
+    use super::{Executor, Future, Poll, Waker};
+
+    /// Future keeping its waker and completing right away, waking itself up
+    /// first if `wake` is set
+    struct Leak {
+        waker: Arc<SpinLock<Option<Waker>>>,
+        wake: bool,
+    }
+
+    impl Future for Leak {
+        type Output = ();
+
+        fn poll(&mut self, waker: &Waker) -> Poll<()> {
+            if self.wake {
+                waker.wake();
+            }
+
+            *self.waker.lock() = Some(waker.clone());
+
+            Poll::Ready(())
+        }
+    }
+
+    #[test]
+    fn test_stale_waker() {
+        for &wake in &[false, true] {
+            let executor = Executor::new();
+            let waker = Arc::new(SpinLock::new(None));
+
+            executor.spawn(Leak {
+                waker: waker.clone(),
+                wake: wake,
+            });
+            executor.run();
+
+            assert!(executor.0.ready.lock().is_empty());
+
+            // The waker of a completed task queues nothing, its slot may
+            // belong to another task
+            let waker = waker.lock().take().unwrap();
+
+            waker.wake();
+
+            assert!(executor.0.ready.lock().is_empty());
+        }
+    }
+}
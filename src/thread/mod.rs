@@ -9,10 +9,16 @@ use self::stack::Stack;
 use self::context::Context;
 
 pub use self::scheduler::Scheduler;
-pub use self::wait_queue::WaitQueue;
+pub use self::wait_queue::{WaitQueue, Notify};
+pub use self::poll::{Poller, Pollable, Ready, Event};
+pub use self::executor::{Executor, Future, Poll, Waker, Delay, Readiness};
+pub use self::executor::{Blocking, ReadStdin, block_on, spawn_blocking};
 
+mod poll;
+mod executor;
 mod stack;
 mod context;
+mod timeout;
 mod scheduler;
 
 #[macro_use]
@@ -75,6 +81,8 @@ enum State {
 pub struct ThreadImpl {
     context: Context,
     state: State,
+    // Set when the thread is woken up because its deadline expired
+    timed_out: bool,
     // On Drop stack is released
     #[allow(dead_code)]
     stack: Stack,
@@ -95,6 +103,7 @@ impl ThreadImpl {
         ThreadImpl {
             context: unsafe { Context::new(thread_wrapper, fun, &mut stack) },
             state: State::Ready,
+            timed_out: false,
             stack: stack,
             prev: Link::none(),
             next: Link::none(),
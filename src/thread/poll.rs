@@ -0,0 +1,254 @@
+//! Readiness polling across several handles
+
+use core::ops::{BitOr, BitAnd};
+
+use vec::Vec;
+
+use sync::Arc;
+
+use hal::{console, local_irq_disable, local_irq_enable};
+
+use io::Stdin;
+
+use time::{Duration, Instant};
+
+use super::{Scheduler, WaitQueue, Notify};
+
+const READABLE: u8 = 1 << 0;
+const WRITABLE: u8 = 1 << 1;
+const HANGUP: u8 = 1 << 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Readiness of a handle, also used to express an interest
+pub struct Ready(u8);
+
+impl Ready {
+    #[inline]
+    /// Neither readable nor writable
+    pub fn empty() -> Self {
+        Ready(0)
+    }
+
+    #[inline]
+    /// Readable
+    pub fn readable() -> Self {
+        Ready(READABLE)
+    }
+
+    #[inline]
+    /// Writable
+    pub fn writable() -> Self {
+        Ready(WRITABLE)
+    }
+
+    #[inline]
+    /// The other end of the handle went away
+    ///
+    /// This is always reported, whatever the interest.
+    pub fn hangup() -> Self {
+        Ready(HANGUP)
+    }
+
+    #[inline]
+    /// Both readable and writable
+    pub fn all() -> Self {
+        Ready(READABLE | WRITABLE)
+    }
+
+    #[inline]
+    /// Returns true if nothing is reported
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline]
+    /// Returns true if readable
+    pub fn is_readable(&self) -> bool {
+        self.0 & READABLE != 0
+    }
+
+    #[inline]
+    /// Returns true if writable
+    pub fn is_writable(&self) -> bool {
+        self.0 & WRITABLE != 0
+    }
+
+    #[inline]
+    /// Returns true if hung up
+    pub fn is_hangup(&self) -> bool {
+        self.0 & HANGUP != 0
+    }
+}
+
+impl BitOr for Ready {
+    type Output = Ready;
+
+    #[inline]
+    fn bitor(self, rhs: Ready) -> Ready {
+        Ready(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Ready {
+    type Output = Ready;
+
+    #[inline]
+    fn bitand(self, rhs: Ready) -> Ready {
+        Ready(self.0 & rhs.0)
+    }
+}
+
+/// Trait implemented by handles that can be registered in a `Poller`
+pub trait Pollable {
+    /// Returns the current readiness of the handle
+    fn readiness(&self) -> Ready;
+
+    /// Notify `notifier` every time the readiness of the handle might have
+    /// changed
+    fn watch(&self, notifier: Arc<Notify>);
+
+    /// Stop notifying `notifier`
+    fn unwatch(&self, notifier: &Notify);
+}
+
+impl<T: Pollable + ?Sized> Pollable for Arc<T> {
+    #[inline]
+    fn readiness(&self) -> Ready {
+        (**self).readiness()
+    }
+
+    #[inline]
+    fn watch(&self, notifier: Arc<Notify>) {
+        (**self).watch(notifier)
+    }
+
+    #[inline]
+    fn unwatch(&self, notifier: &Notify) {
+        (**self).unwatch(notifier)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// A readiness event reported by a `Poller`
+pub struct Event {
+    /// Token returned by `Poller::register()` for the handle
+    pub token: usize,
+    /// Readiness of the handle, restricted to the registered interest and
+    /// hang up
+    pub readiness: Ready,
+}
+
+/// Wait for several handles at once
+///
+/// Handles are registered with an interest (readable, writable or both).
+/// A single thread can then block until at least one of them is ready.
+pub struct Poller<'a> {
+    notifier: Arc<WaitQueue>,
+    handles: Vec<(&'a Pollable, Ready)>,
+}
+
+impl<'a> Poller<'a> {
+    /// Create a new poller with no handle registered
+    pub fn new() -> Self {
+        Poller {
+            notifier: Arc::new(WaitQueue::new()),
+            handles: Vec::new(),
+        }
+    }
+
+    /// Register a `handle` with a given `interest`.
+    ///
+    /// Returns a token that identifies the handle in reported events.
+    pub fn register(&mut self, handle: &'a Pollable, interest: Ready) -> usize {
+        handle.watch(self.notifier.clone());
+
+        self.handles.push((handle, interest));
+
+        self.handles.len() - 1
+    }
+
+    /// Returns the events of the handles that are currently ready
+    pub fn ready_events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for (token, &(handle, interest)) in self.handles.iter().enumerate() {
+            let readiness = handle.readiness() & (interest | Ready::hangup());
+
+            if !readiness.is_empty() {
+                events.push(Event {
+                    token: token,
+                    readiness: readiness,
+                });
+            }
+        }
+
+        events
+    }
+
+    /// Block until at least one handle is ready and return the events
+    pub fn poll(&self) -> Vec<Event> {
+        self.wait(None)
+    }
+
+    /// Block until at least one handle is ready or `timeout` expires.
+    ///
+    /// Returns an empty list if the timeout expired.
+    pub fn poll_timeout(&self, timeout: Duration) -> Vec<Event> {
+        self.wait(Some(Instant::now() + timeout))
+    }
+
+    fn wait(&self, deadline: Option<Instant>) -> Vec<Event> {
+        loop {
+            local_irq_disable();
+
+            let locked_queue = self.notifier.lock();
+
+            // Readiness is checked with the notifier locked so that a
+            // notification cannot be missed between the check and the block
+            let events = self.ready_events();
+
+            if !events.is_empty() {
+                local_irq_enable();
+                return events;
+            }
+
+            match deadline {
+                None => Scheduler::block(locked_queue),
+                Some(deadline) => {
+                    if !Scheduler::block_timeout(locked_queue, &self.notifier,
+                                                 deadline) {
+                        return self.ready_events();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Poller<'a> {
+    fn drop(&mut self) {
+        for &(handle, _) in &self.handles {
+            handle.unwatch(&*self.notifier);
+        }
+    }
+}
+
+impl Pollable for Stdin {
+    fn readiness(&self) -> Ready {
+        // The raw console is used as a thread reading stdin holds its lock
+        // while blocked. Output never blocks.
+        if console().has_input() {
+            Ready::all()
+        } else {
+            Ready::writable()
+        }
+    }
+
+    fn watch(&self, notifier: Arc<Notify>) {
+        console().input_queue().watch(notifier);
+    }
+
+    fn unwatch(&self, notifier: &Notify) {
+        console().input_queue().unwatch(notifier);
+    }
+}
@@ -3,7 +3,7 @@
 use core::ptr;
 use core::mem;
 
-use hal::app;
+use hal::{app, timer};
 use hal::{local_irq_disable, local_irq_enable};
 
 use alloc::boxed::Box;
@@ -11,8 +11,11 @@ use alloc::boxed::Box;
 use sync::spin::{InterruptSpinLock, InterruptSpinGuard};
 use intrusive::queue::Queue;
 
+use time::{Duration, Instant};
+
 use super::{Thread, Builder, ThreadImpl, State};
-use super::wait_queue::InternalQueue;
+use super::timeout::{Timeout, TimeoutList};
+use super::wait_queue::{InternalQueue, WaitQueue};
 
 static mut SCHEDULER: SchedulerImpl = SchedulerImpl::new();
 
@@ -35,6 +38,23 @@ impl Scheduler {
         }
     }
 
+    /// Block the current thread for at least `duration`
+    pub fn sleep(duration: Duration) {
+        Scheduler::sleep_until(Instant::now() + duration);
+    }
+
+    /// Block the current thread until `deadline`
+    pub fn sleep_until(deadline: Instant) {
+        let wait = WaitQueue::new();
+
+        // Nothing signals the queue, so the thread only wakes up once the
+        // deadline expired
+        while Instant::now() < deadline {
+            local_irq_disable();
+            Scheduler::block_timeout(wait.lock(), &wait, deadline);
+        }
+    }
+
     #[doc(hidden)]
     /// Block the current thread inside a wait queue.
     pub fn block(queue: InterruptSpinGuard<InternalQueue>) {
@@ -43,6 +63,20 @@ impl Scheduler {
         }
     }
 
+    #[doc(hidden)]
+    /// Block the current thread inside a wait queue until `deadline`.
+    ///
+    /// `wait_queue` must be the queue `queue` was locked from.
+    ///
+    /// Returns true if the thread was unblocked before the deadline, false
+    /// if the deadline expired.
+    pub fn block_timeout(queue: InterruptSpinGuard<InternalQueue>,
+                         wait_queue: &WaitQueue, deadline: Instant) -> bool {
+        unsafe {
+            SCHEDULER.block_timeout(queue, wait_queue, deadline)
+        }
+    }
+
     #[doc(hidden)]
     /// Terminate the execution of the current thread
     pub fn terminate() -> ! {
@@ -67,6 +101,7 @@ struct SchedulerImpl {
     running: Option<ptr::Unique<ThreadImpl>>,
     ready_list: InterruptSpinLock<Queue<Box<ThreadImpl>, ThreadImpl>>,
     cleanup_queue: InterruptSpinLock<Queue<Box<ThreadImpl>, ThreadImpl>>,
+    timeouts: InterruptSpinLock<TimeoutList>,
 }
 
 impl SchedulerImpl {
@@ -75,6 +110,7 @@ impl SchedulerImpl {
             running: None,
             ready_list: InterruptSpinLock::new(Queue::new()),
             cleanup_queue: InterruptSpinLock::new(Queue::new()),
+            timeouts: InterruptSpinLock::new(TimeoutList::new()),
         }
     }
 
@@ -94,6 +130,51 @@ impl SchedulerImpl {
         }
     }
 
+    // Wake up the threads whose deadline expired
+    unsafe fn wake_expired(&mut self) {
+        let expired = {
+            let mut timeouts = self.timeouts.lock();
+
+            if timeouts.is_empty() {
+                return;
+            }
+
+            timeouts.take_expired(Instant::now())
+        };
+
+        for timeout in expired {
+            // A thread that is not blocked anymore was already woken up by an
+            // event. Its wait queue might even be gone so it must not be
+            // touched.
+            if (*timeout.thread).state != State::Blocked {
+                continue;
+            }
+
+            if let Some(mut t) = (*timeout.queue).remove(timeout.thread) {
+                t.timed_out = true;
+
+                self.ready(Thread {
+                    t_impl: t,
+                });
+            }
+        }
+    }
+
+    // Wait for something to happen when no thread is ready to run
+    unsafe fn idle(&self) {
+        // The timer is armed for the earliest deadline so that blocking is
+        // interrupted in time to wake up the thread waiting for it
+        match self.timeouts.lock().next_deadline() {
+            Some(deadline) => timer::arm(deadline.as_nanos()),
+            None => timer::disarm(),
+        }
+
+        // An interruption might wake up a thread for us to run
+        app::block();
+
+        local_irq_enable();
+    }
+
     // Return the next thread to run. If None is returned then it means that no
     // thread is ready to be scheduled and the current thread was just blocked
     // or terminated
@@ -166,6 +247,8 @@ impl SchedulerImpl {
         loop {
             local_irq_disable();
 
+            self.wake_expired();
+
             let next = self.elect_next();
 
             // The only time elect_next() leaves self.running not None is when
@@ -178,10 +261,9 @@ impl SchedulerImpl {
 
             match next {
                 None => {
-                    // If no threads can be run wait for an interruption. An
-                    // interruption might wake up a thread for us to run
-                    app::block();
-                    local_irq_enable();
+                    // If no threads can be run wait for an interruption or
+                    // a deadline
+                    self.idle();
                 }
                 Some(next) => {
                     self.switch_to_next(next);
@@ -221,8 +303,7 @@ impl SchedulerImpl {
         match next {
             None => {
                 // See comment in schedule()
-                app::block();
-                local_irq_enable();
+                self.idle();
             }
             Some(next) => {
                 self.switch_to_next(next);
@@ -235,6 +316,33 @@ impl SchedulerImpl {
         self.schedule(true);
     }
 
+    pub unsafe fn block_timeout(&mut self,
+                                queue: InterruptSpinGuard<InternalQueue>,
+                                wait_queue: &WaitQueue,
+                                deadline: Instant) -> bool {
+        let thread: *const ThreadImpl = match self.running {
+            Some(ref mut r) => {
+                r.get_mut().timed_out = false;
+                r.get()
+            }
+            None => return false,
+        };
+
+        self.timeouts.lock().insert(Timeout {
+            deadline: deadline,
+            thread: thread,
+            queue: wait_queue,
+        });
+
+        self.block(queue);
+
+        // We were either unblocked by an event or because the deadline
+        // expired. In the first case the timeout is still registered.
+        self.timeouts.lock().remove(thread);
+
+        !(*thread).timed_out
+    }
+
     pub unsafe fn terminate(&mut self) -> ! {
         if let Some(ref mut t) = self.running {
             t.get_mut().state = State::Terminated;
@@ -2,7 +2,7 @@
 
 use core::cmp;
 
-use alloc_uni::{__rust_allocate, __rust_deallocate};
+use alloc::heap::{allocate, deallocate};
 
 const MIN_STACK_SIZE: usize = 4096;
 
@@ -26,7 +26,7 @@ impl Stack {
 
         // We use a buddy allocator so we know the stack size is gonna be a
         // power of two
-        let stack_ptr = __rust_allocate(stack_size, 4096);
+        let stack_ptr = allocate(stack_size, 4096);
 
         if stack_ptr.is_null() {
             Stack::null()
@@ -58,7 +58,7 @@ impl Stack {
 impl Drop for Stack {
     fn drop(&mut self) {
         if let Some(ptr) = self.ptr {
-            __rust_deallocate(ptr, self.size, 4096);
+            deallocate(ptr, self.size, 4096);
         }
     }
 }
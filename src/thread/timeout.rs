@@ -0,0 +1,74 @@
+//! Bookkeeping of threads blocked until a deadline
+
+use vec::Vec;
+
+use time::Instant;
+
+use super::ThreadImpl;
+use super::wait_queue::WaitQueue;
+
+/// A thread blocked inside a wait queue until a deadline
+pub struct Timeout {
+    pub deadline: Instant,
+    pub thread: *const ThreadImpl,
+    pub queue: *const WaitQueue,
+}
+
+/// List of pending timeouts
+pub struct TimeoutList {
+    // Vec::new() is not a const fn so the vector is created on first use
+    timeouts: Option<Vec<Timeout>>,
+}
+
+impl TimeoutList {
+    pub const fn new() -> Self {
+        TimeoutList {
+            timeouts: None,
+        }
+    }
+
+    /// Returns true if no timeout is pending
+    pub fn is_empty(&self) -> bool {
+        self.timeouts.as_ref().map_or(true, |t| t.is_empty())
+    }
+
+    /// Returns the earliest deadline if any timeout is pending
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timeouts.as_ref().and_then(|t| t.iter().map(|t| t.deadline).min())
+    }
+
+    /// Register a new timeout
+    pub fn insert(&mut self, timeout: Timeout) {
+        if self.timeouts.is_none() {
+            self.timeouts = Some(Vec::new());
+        }
+
+        self.timeouts.as_mut().unwrap().push(timeout);
+    }
+
+    /// Remove the timeout registered by `thread` if there is one
+    pub fn remove(&mut self, thread: *const ThreadImpl) {
+        if let Some(ref mut timeouts) = self.timeouts {
+            timeouts.retain(|t| t.thread != thread);
+        }
+    }
+
+    /// Remove and return every timeout whose deadline is before `now`
+    pub fn take_expired(&mut self, now: Instant) -> Vec<Timeout> {
+        let mut expired = Vec::new();
+
+        if let Some(ref mut timeouts) = self.timeouts {
+            let mut i = 0;
+
+            while i < timeouts.len() {
+                if timeouts[i].deadline <= now {
+                    expired.push(timeouts.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        expired
+    }
+}
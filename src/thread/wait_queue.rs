@@ -1,17 +1,34 @@
+use core::mem;
+
 use alloc::boxed::Box;
 
+use vec::Vec;
+
+use sync::Arc;
+
 use intrusive::queue::Queue;
 
 use hal::local_irq_disable;
 
 use sync::spin::{InterruptSpinLock, InterruptSpinGuard};
 
+use time::Instant;
+
 use super::{Thread, ThreadImpl, Scheduler};
 
 pub type InternalQueue = Queue<Box<ThreadImpl>, ThreadImpl>;
 
+/// Trait implemented by objects that can watch a wait queue
+pub trait Notify {
+    /// Called every time the watched wait queue is signaled
+    ///
+    /// This might be called from interrupt context.
+    fn notify(&self);
+}
+
 pub struct WaitQueue {
     queue: InterruptSpinLock<InternalQueue>,
+    watchers: InterruptSpinLock<Vec<Arc<Notify>>>,
 }
 
 impl WaitQueue {
@@ -19,6 +36,31 @@ impl WaitQueue {
     pub fn new() -> Self {
         WaitQueue {
             queue: InterruptSpinLock::new(Queue::new()),
+            watchers: InterruptSpinLock::new(Vec::new()),
+        }
+    }
+
+    /// Register a `watcher`.
+    ///
+    /// `watcher` is notified each time this queue is signaled, whether or not
+    /// threads are blocked on this queue. When the watcher is another wait
+    /// queue, every thread blocked on it is unblocked. This lets a thread
+    /// wait for several queues at once.
+    pub fn watch(&self, watcher: Arc<Notify>) {
+        self.watchers.lock().push(watcher);
+    }
+
+    /// Unregister a `watcher` previously registered with `watch()`
+    pub fn unwatch<T: Notify + ?Sized>(&self, watcher: &T) {
+        let ptr = watcher as *const T as *const u8;
+
+        self.watchers.lock().retain(|w| &**w as *const Notify as *const u8 != ptr);
+    }
+
+    #[inline]
+    fn notify_watchers(&self) {
+        for w in self.watchers.lock().iter() {
+            w.notify();
         }
     }
 
@@ -27,6 +69,14 @@ impl WaitQueue {
         self.queue.lock()
     }
 
+    #[doc(hidden)]
+    /// Remove a blocked thread from the queue
+    ///
+    /// Returns None if the thread is not part of the queue
+    pub fn remove(&self, thread: *const ThreadImpl) -> Option<Box<ThreadImpl>> {
+        self.queue.lock().remove(thread)
+    }
+
     #[inline]
     /// Block the current thread
     pub fn block(&self) {
@@ -34,6 +84,16 @@ impl WaitQueue {
         Scheduler::block(self.queue.lock());
     }
 
+    #[inline]
+    /// Block the current thread until it is unblocked or `deadline` expires
+    ///
+    /// Returns true if the thread was unblocked, false if the deadline
+    /// expired first.
+    pub fn block_timeout(&self, deadline: Instant) -> bool {
+        local_irq_disable();
+        Scheduler::block_timeout(self.queue.lock(), self, deadline)
+    }
+
     #[inline]
     fn unblock_thread(imp: Box<ThreadImpl>) {
         let thread = Thread {
@@ -49,6 +109,8 @@ impl WaitQueue {
         if let Some(t) = self.queue.lock().dequeue() {
             WaitQueue::unblock_thread(t);
         }
+
+        self.notify_watchers();
     }
 
     /// Unblock all threads in the queue
@@ -58,6 +120,17 @@ impl WaitQueue {
         while let Some(t) = queue.dequeue() {
             WaitQueue::unblock_thread(t);
         }
+
+        mem::drop(queue);
+
+        self.notify_watchers();
+    }
+}
+
+impl Notify for WaitQueue {
+    #[inline]
+    fn notify(&self) {
+        self.unblock_all();
     }
 }
 
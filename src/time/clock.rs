@@ -0,0 +1,171 @@
+//! System wall clock
+//!
+//! The wall clock is the monotonic clock shifted by an offset. Small
+//! corrections are slewed, i.e. applied progressively so that the wall clock
+//! never jumps nor goes backward. Large ones are stepped.
+
+use sync::spin::InterruptSpinLock;
+
+use hal::{monotonic_time, wall_clock_origin};
+
+use super::{Duration, Instant};
+
+/// Corrections larger than this (in nanoseconds) are stepped
+const STEP_THRESHOLD_NS: u64 = 128_000_000;
+
+/// Maximum slew rate in parts per million
+const MAX_SLEW_PPM: u64 = 500;
+
+#[derive(Clone, Copy, Debug)]
+/// Information about the last synchronization of the wall clock
+pub struct SyncInfo {
+    /// When the synchronization happened
+    pub last_sync: Instant,
+    /// Offset measured between the wall clock and the reference, in
+    /// nanoseconds. Positive if the wall clock was late
+    pub offset: i64,
+    /// Round trip delay to the reference
+    pub delay: Duration,
+    /// Stratum of the reference
+    pub stratum: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Synchronization status of the wall clock
+pub enum SyncStatus {
+    /// The wall clock was never synchronized with a reference
+    Unsynchronized,
+    /// The wall clock follows a reference
+    Synchronized(SyncInfo),
+}
+
+struct ClockState {
+    /// Offset in nanoseconds from the monotonic clock to the UNIX epoch
+    offset: i64,
+    /// Correction in nanoseconds being slewed
+    slew: i64,
+    /// Monotonic time at which the slew started
+    slew_start: u64,
+    status: SyncStatus,
+}
+
+impl ClockState {
+    const fn new() -> Self {
+        ClockState {
+            offset: 0,
+            slew: 0,
+            slew_start: 0,
+            status: SyncStatus::Unsynchronized,
+        }
+    }
+
+    /// Returns the part of the slew already applied at `mono`
+    fn slewed(&self, mono: u64) -> i64 {
+        let max = (mono.saturating_sub(self.slew_start) / 1_000_000) *
+                  MAX_SLEW_PPM;
+        let abs = self.slew.abs() as u64;
+
+        if abs <= max {
+            self.slew
+        } else if self.slew < 0 {
+            -(max as i64)
+        } else {
+            max as i64
+        }
+    }
+
+    fn now(&self, mono: u64) -> i64 {
+        mono as i64 + self.offset + self.slewed(mono)
+    }
+
+    /// Fold the slew applied so far into the offset
+    fn settle(&mut self, mono: u64) {
+        let slewed = self.slewed(mono);
+
+        self.offset += slewed;
+        self.slew -= slewed;
+        self.slew_start = mono;
+    }
+}
+
+static CLOCK: InterruptSpinLock<ClockState> =
+    InterruptSpinLock::new(ClockState::new());
+
+#[doc(hidden)]
+/// Start the wall clock from the time given by the platform
+pub fn init() {
+    CLOCK.lock().offset = wall_clock_origin() as i64;
+}
+
+#[doc(hidden)]
+/// Step the wall clock to the time given by the platform after the system
+/// was suspended
+///
+/// The monotonic clock does not count the time spent suspended, the
+/// synchronization with a reference is lost as well.
+pub fn resume() {
+    let mut clock = CLOCK.lock();
+
+    clock.offset = wall_clock_origin() as i64;
+    clock.slew = 0;
+    clock.slew_start = monotonic_time();
+    clock.status = SyncStatus::Unsynchronized;
+}
+
+/// Returns the time elapsed since the UNIX epoch according to the wall clock
+pub fn wall_time() -> Duration {
+    let ns = CLOCK.lock().now(monotonic_time());
+
+    if ns < 0 {
+        Duration::from_secs(0)
+    } else {
+        Duration::from_nanos(ns as u64)
+    }
+}
+
+/// Set the wall clock to `since_epoch`, the time elapsed since the UNIX epoch
+///
+/// The clock is stepped and any slew in progress is cancelled.
+pub fn set_wall_time(since_epoch: Duration) {
+    let mono = monotonic_time();
+    let mut clock = CLOCK.lock();
+
+    clock.offset = since_epoch.as_nanos() as i64 - mono as i64;
+    clock.slew = 0;
+    clock.slew_start = mono;
+}
+
+/// Correct the wall clock by `offset` nanoseconds
+///
+/// Small corrections are slewed at 500 ppm at most, large ones are stepped.
+pub fn adjust_wall_time(offset: i64) {
+    let mono = monotonic_time();
+    let mut clock = CLOCK.lock();
+
+    clock.settle(mono);
+
+    if offset.abs() as u64 > STEP_THRESHOLD_NS {
+        clock.offset += offset;
+        clock.slew = 0;
+    } else {
+        clock.slew = offset;
+    }
+}
+
+/// Returns the correction still to be slewed, in nanoseconds
+pub fn pending_adjustment() -> i64 {
+    let mono = monotonic_time();
+    let clock = CLOCK.lock();
+
+    clock.slew - clock.slewed(mono)
+}
+
+/// Record a synchronization of the wall clock with a reference
+pub fn set_sync_status(status: SyncStatus) {
+    CLOCK.lock().status = status;
+}
+
+/// Returns the synchronization status of the wall clock
+pub fn sync_status() -> SyncStatus {
+    CLOCK.lock().status
+}
@@ -0,0 +1,148 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+const NANOS_PER_MILLI: u32 = 1_000_000;
+const NANOS_PER_MICRO: u32 = 1_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+/// A span of time
+///
+/// A duration is made of a number of seconds and a fractional part expressed
+/// in nanoseconds.
+pub struct Duration {
+    secs: u64,
+    nanos: u32,
+}
+
+impl Duration {
+    /// Creates a new duration from a number of seconds and nanoseconds
+    ///
+    /// Nanoseconds in excess of a second are carried over to `secs`.
+    pub fn new(secs: u64, nanos: u32) -> Self {
+        Duration {
+            secs: secs + (nanos / NANOS_PER_SEC) as u64,
+            nanos: nanos % NANOS_PER_SEC,
+        }
+    }
+
+    #[inline]
+    /// Creates a duration of zero
+    pub const fn zero() -> Self {
+        Duration {
+            secs: 0,
+            nanos: 0,
+        }
+    }
+
+    #[inline]
+    /// Creates a new duration from a number of seconds
+    pub fn from_secs(secs: u64) -> Self {
+        Duration::new(secs, 0)
+    }
+
+    #[inline]
+    /// Creates a new duration from a number of milliseconds
+    pub fn from_millis(millis: u64) -> Self {
+        Duration::new(millis / 1000,
+                      (millis % 1000) as u32 * NANOS_PER_MILLI)
+    }
+
+    #[inline]
+    /// Creates a new duration from a number of microseconds
+    pub fn from_micros(micros: u64) -> Self {
+        Duration::new(micros / 1_000_000,
+                      (micros % 1_000_000) as u32 * NANOS_PER_MICRO)
+    }
+
+    #[inline]
+    /// Creates a new duration from a number of nanoseconds
+    pub fn from_nanos(nanos: u64) -> Self {
+        Duration::new(nanos / NANOS_PER_SEC as u64,
+                      (nanos % NANOS_PER_SEC as u64) as u32)
+    }
+
+    #[inline]
+    /// Returns the number of whole seconds of the duration
+    pub fn as_secs(&self) -> u64 {
+        self.secs
+    }
+
+    #[inline]
+    /// Returns the fractional part of the duration in nanoseconds
+    pub fn subsec_nanos(&self) -> u32 {
+        self.nanos
+    }
+
+    /// Returns the total number of nanoseconds of the duration
+    ///
+    /// The result saturates at `u64::MAX` (roughly 584 years).
+    pub fn as_nanos(&self) -> u64 {
+        self.secs.checked_mul(NANOS_PER_SEC as u64)
+                 .and_then(|n| n.checked_add(self.nanos as u64))
+                 .unwrap_or(u64::max_value())
+    }
+
+    /// Add two durations, returning None on overflow
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        self.secs.checked_add(rhs.secs).and_then(|secs| {
+            let nanos = self.nanos + rhs.nanos;
+
+            if nanos >= NANOS_PER_SEC {
+                secs.checked_add(1).map(|secs| Duration {
+                    secs: secs,
+                    nanos: nanos - NANOS_PER_SEC,
+                })
+            } else {
+                Some(Duration {
+                    secs: secs,
+                    nanos: nanos,
+                })
+            }
+        })
+    }
+
+    /// Subtract two durations, returning None if `rhs` is bigger than `self`
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.secs.checked_sub(rhs.secs).and_then(|secs| {
+            if self.nanos >= rhs.nanos {
+                Some(Duration {
+                    secs: secs,
+                    nanos: self.nanos - rhs.nanos,
+                })
+            } else {
+                secs.checked_sub(1).map(|secs| Duration {
+                    secs: secs,
+                    nanos: self.nanos + NANOS_PER_SEC - rhs.nanos,
+                })
+            }
+        })
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        self.checked_add(rhs).expect("overflow when adding durations")
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        self.checked_sub(rhs).expect("overflow when subtracting durations")
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
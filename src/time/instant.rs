@@ -0,0 +1,75 @@
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use hal::monotonic_time;
+
+use super::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+/// A measurement of the monotonic clock
+///
+/// Instants are opaque and only useful when compared to one another or
+/// combined with a `Duration`.
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the instant corresponding to "now"
+    pub fn now() -> Self {
+        Instant(monotonic_time())
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to `self`
+    ///
+    /// This returns a zero duration if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    /// Returns the raw value of the monotonic clock for this instant
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    /// Returns the amount of time elapsed since this instant was created
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(rhs.as_nanos()))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(rhs.as_nanos()))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
@@ -0,0 +1,17 @@
+//! Temporal quantification for Uni.rs
+
+pub use self::duration::Duration;
+pub use self::instant::Instant;
+pub use self::system::{SystemTime, SystemTimeError, UNIX_EPOCH};
+pub use self::system::{DateTime, Rfc3339, HttpDate};
+pub use self::clock::{SyncInfo, SyncStatus};
+pub use self::clock::{wall_time, set_wall_time, adjust_wall_time};
+pub use self::clock::{pending_adjustment, set_sync_status, sync_status};
+
+#[doc(hidden)]
+pub use self::clock::{init, resume};
+
+mod duration;
+mod instant;
+mod system;
+mod clock;
@@ -0,0 +1,276 @@
+//! Calendar time
+
+use core::fmt::{Display, Formatter, Error};
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use super::{Duration, wall_time};
+
+const SECS_PER_DAY: u64 = 86400;
+
+const WEEKDAYS: [&'static str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat",
+                                     "Sun"];
+
+const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// The UNIX epoch, 1970-01-01 00:00:00 UTC
+pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::zero());
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+/// A measurement of the wall clock
+///
+/// Unlike an `Instant`, a system time can go backward when the wall clock is
+/// stepped, e.g. when it is synchronized with a time server.
+pub struct SystemTime(Duration);
+
+#[derive(Clone, Copy, Debug)]
+/// Error returned by `SystemTime::duration_since()` when the time given is
+/// later than `self`
+pub struct SystemTimeError(Duration);
+
+impl SystemTimeError {
+    #[inline]
+    /// Returns how much later the time given was
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl SystemTime {
+    /// Returns the system time corresponding to "now"
+    pub fn now() -> Self {
+        SystemTime(wall_time())
+    }
+
+    #[inline]
+    /// Returns the system time `secs` seconds after the UNIX epoch
+    pub fn from_unix(secs: u64) -> Self {
+        SystemTime(Duration::from_secs(secs))
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to `self`
+    ///
+    /// This fails if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: SystemTime)
+        -> Result<Duration, SystemTimeError> {
+        match self.0.checked_sub(earlier.0) {
+            Some(d) => Ok(d),
+            None => Err(SystemTimeError(earlier.0 - self.0)),
+        }
+    }
+
+    #[inline]
+    /// Returns the amount of time elapsed since this system time
+    ///
+    /// This fails if the wall clock was stepped backward in the meantime.
+    pub fn elapsed(&self) -> Result<Duration, SystemTimeError> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    #[inline]
+    /// Returns the amount of time elapsed since the UNIX epoch
+    pub fn since_epoch(&self) -> Duration {
+        self.0
+    }
+
+    /// Break the system time down into UTC calendar fields
+    pub fn to_utc(&self) -> DateTime {
+        let secs = self.0.as_secs();
+        let days = (secs / SECS_PER_DAY) as i64;
+        let rem = secs % SECS_PER_DAY;
+
+        // Civil date from days since the epoch, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u32,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: ((rem / 60) % 60) as u8,
+            second: (rem % 60) as u8,
+            nanosecond: self.0.subsec_nanos(),
+            // The epoch was a thursday
+            weekday: ((days + 3) % 7) as u8,
+        }
+    }
+
+    #[inline]
+    /// Returns a formatter for the RFC 3339 representation of the time with
+    /// microsecond precision (e.g. `2017-03-14T15:09:26.535897Z`)
+    pub fn rfc3339(&self) -> Rfc3339 {
+        Rfc3339(self.to_utc())
+    }
+
+    #[inline]
+    /// Returns a formatter for the representation of the time used by HTTP
+    /// headers (e.g. `Tue, 14 Mar 2017 15:09:26 GMT`)
+    pub fn http_date(&self) -> HttpDate {
+        HttpDate(self.to_utc())
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, rhs: Duration) -> SystemTime {
+        SystemTime(self.0 + rhs)
+    }
+}
+
+impl AddAssign<Duration> for SystemTime {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, rhs: Duration) -> SystemTime {
+        SystemTime(self.0 - rhs)
+    }
+}
+
+impl SubAssign<Duration> for SystemTime {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// UTC calendar fields of a system time
+pub struct DateTime {
+    /// Year (e.g. 2017)
+    pub year: u32,
+    /// Month, from 1 to 12
+    pub month: u8,
+    /// Day of the month, from 1 to 31
+    pub day: u8,
+    /// Hour, from 0 to 23
+    pub hour: u8,
+    /// Minute, from 0 to 59
+    pub minute: u8,
+    /// Second, from 0 to 59
+    pub second: u8,
+    /// Nanoseconds elapsed in the second
+    pub nanosecond: u32,
+    /// Day of the week, from 0 (monday) to 6 (sunday)
+    pub weekday: u8,
+}
+
+/// RFC 3339 representation of a time, obtained with `SystemTime::rfc3339()`
+pub struct Rfc3339(DateTime);
+
+impl Display for Rfc3339 {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let dt = &self.0;
+
+        f.write_fmt(format_args!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+                                 dt.year, dt.month, dt.day, dt.hour, dt.minute,
+                                 dt.second, dt.nanosecond / 1000))
+    }
+}
+
+/// HTTP representation of a time, obtained with `SystemTime::http_date()`
+pub struct HttpDate(DateTime);
+
+impl Display for HttpDate {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let dt = &self.0;
+
+        f.write_fmt(format_args!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+                                 WEEKDAYS[dt.weekday as usize], dt.day,
+                                 MONTHS[dt.month as usize - 1], dt.year,
+                                 dt.hour, dt.minute, dt.second))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::Duration;
+
+    use super::{DateTime, SystemTime, UNIX_EPOCH};
+
+    fn date(year: u32, month: u8, day: u8, hour: u8, minute: u8, second: u8,
+            weekday: u8) -> DateTime {
+        DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: hour,
+            minute: minute,
+            second: second,
+            nanosecond: 0,
+            weekday: weekday,
+        }
+    }
+
+    #[test]
+    fn test_to_utc() {
+        let cases = [
+            (0, date(1970, 1, 1, 0, 0, 0, 3)),
+            (86399, date(1970, 1, 1, 23, 59, 59, 3)),
+            // 2000 is a leap year, 2100 is not
+            (951782400, date(2000, 2, 29, 0, 0, 0, 1)),
+            (951868800, date(2000, 3, 1, 0, 0, 0, 2)),
+            (4107456000, date(2100, 2, 28, 0, 0, 0, 6)),
+            (4107542400, date(2100, 3, 1, 0, 0, 0, 0)),
+            (253402300799, date(9999, 12, 31, 23, 59, 59, 4)),
+        ];
+
+        for &(secs, expected) in &cases {
+            assert_eq!(SystemTime::from_unix(secs).to_utc(), expected);
+        }
+
+        let time = UNIX_EPOCH + Duration::new(1489504166, 535897932);
+        let mut expected = date(2017, 3, 14, 15, 9, 26, 1);
+
+        expected.nanosecond = 535897932;
+
+        assert_eq!(time.to_utc(), expected);
+    }
+
+    #[test]
+    fn test_formats() {
+        let time = UNIX_EPOCH + Duration::new(1489504166, 535897932);
+
+        assert_eq!(format!("{}", time.rfc3339()),
+                   "2017-03-14T15:09:26.535897Z");
+        assert_eq!(format!("{}", time.http_date()),
+                   "Tue, 14 Mar 2017 15:09:26 GMT");
+        assert_eq!(format!("{}", UNIX_EPOCH.http_date()),
+                   "Thu, 01 Jan 1970 00:00:00 GMT");
+
+        let time = SystemTime::from_unix(4107456000);
+
+        assert_eq!(format!("{}", time.http_date()),
+                   "Sun, 28 Feb 2100 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let earlier = SystemTime::from_unix(1000);
+        let mut later = earlier + Duration::from_secs(30);
+
+        assert_eq!(later.since_epoch(), Duration::from_secs(1030));
+        assert_eq!(later.duration_since(earlier).unwrap(),
+                   Duration::from_secs(30));
+        assert_eq!(earlier.duration_since(later).unwrap_err().duration(),
+                   Duration::from_secs(30));
+
+        later -= Duration::from_secs(30);
+
+        assert_eq!(later, earlier);
+        assert_eq!(earlier - Duration::from_secs(1000), UNIX_EPOCH);
+    }
+}
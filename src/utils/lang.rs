@@ -1,8 +1,8 @@
-use core::fmt;
+#[cfg(not(test))] use core::fmt;
 
-use io::Write;
+#[cfg(not(test))] use io::Write;
 
-use hal::{app, console};
+#[cfg(not(test))] use hal::{app, console};
 
 #[cfg(not(test))]
 #[lang = "stack_exhausted"]
@@ -30,6 +30,7 @@ pub extern fn panic_impl(msg: fmt::Arguments, file: &'static str, line: u32) {
     loop {}
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 #[allow(non_snake_case)]
 pub fn _Unwind_Resume(_: *mut u8) -> ! {